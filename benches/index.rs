@@ -0,0 +1,112 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use reverything::ntfs::synthetic::{SyntheticOptions, SyntheticVolume};
+use reverything::ntfs::volume::VolumeRead;
+use reverything::query::SearchOptions;
+use reverything::search;
+use std::sync::Mutex;
+
+const QUERIES: &[&str] = &[
+    "report",
+    "ext:pdf;docx",
+    "size:>100mb",
+    "node_modules src",
+    "resume",
+];
+
+fn build_index(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build_index");
+    group.sample_size(10);
+    for (files, fragments) in [(100_000, 1), (100_000, 32), (1_000_000, 8)] {
+        let volume = SyntheticVolume::generate(&SyntheticOptions {
+            files,
+            directories: files / 10,
+            mft_fragments: fragments,
+            ..Default::default()
+        });
+        group.throughput(Throughput::Elements(volume.file_count() as u64));
+        group.bench_function(format!("{}_files_{}_runs", files, fragments), |b| {
+            b.iter(|| volume.build_index().unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn search(c: &mut Criterion) {
+    let volume = SyntheticVolume::generate(&SyntheticOptions {
+        files: 1_000_000,
+        directories: 100_000,
+        ..Default::default()
+    });
+    let mut index = Mutex::new(volume.build_index().unwrap());
+
+    let mut group = c.benchmark_group("search");
+    for trigrams in [false, true] {
+        if trigrams {
+            index.get_mut().unwrap().build_trigram_index();
+        }
+
+        for query in QUERIES {
+            for options in [
+                SearchOptions::default(),
+                SearchOptions {
+                    fold: true,
+                    match_path: true,
+                    ..Default::default()
+                },
+            ] {
+                let mut name = query.to_string();
+                if options.match_path {
+                    name.push_str(" (path, fold)");
+                }
+                if trigrams {
+                    name.push_str(" (trigrams)");
+                }
+                group.bench_function(name, |b| {
+                    b.iter(|| search::filter(&index, query, options).unwrap())
+                });
+            }
+        }
+    }
+    group.finish();
+
+    c.bench_function("build_trigram_index", |b| {
+        b.iter(|| index.get_mut().unwrap().build_trigram_index())
+    });
+}
+
+fn apply_journal(c: &mut Criterion) {
+    const CHANGES: usize = 20_000;
+
+    let mut group = c.benchmark_group("apply_journal");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(CHANGES as u64));
+    group.bench_function(format!("{}_changes", CHANGES), |b| {
+        b.iter_batched(
+            || {
+                let mut volume = SyntheticVolume::generate(&SyntheticOptions {
+                    files: 100_000,
+                    directories: 10_000,
+                    free_records: CHANGES,
+                    ..Default::default()
+                });
+                let index = volume.build_index().unwrap();
+                let journal = volume.open_journal().unwrap();
+                volume.generate_changes(CHANGES);
+                let end = volume.reader().query_journal().unwrap().NextUsn;
+                (index, journal, end)
+            },
+            |(mut index, mut journal, end)| {
+                while journal.next_usn() < end {
+                    let entries = journal.read_entries().unwrap();
+                    index.process_journal_entries(&entries);
+                }
+                index
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, build_index, search, apply_journal);
+criterion_main!(benches);
@@ -0,0 +1,52 @@
+/* C interface of reverything.dll, see src/ffi.rs. All strings are UTF-8. */
+#ifndef REVERYTHING_H
+#define REVERYTHING_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define REVERYTHING_WHOLE_WORD 0x1
+#define REVERYTHING_MATCH_PATH 0x2
+#define REVERYTHING_IGNORE_CASE 0x4
+
+#define REVERYTHING_CREATED 0
+#define REVERYTHING_RENAMED 1
+#define REVERYTHING_DELETED 2
+#define REVERYTHING_MOVED 3
+
+typedef struct ReverythingIndex ReverythingIndex;
+typedef struct ReverythingResults ReverythingResults;
+
+/* Called on the journal thread. `path` is only valid during the call. */
+typedef void (*ReverythingChangeCallback)(void *user_data, uint32_t kind, const char *path);
+
+/* Builds the index of the first volume. Requires an elevated process. Returns NULL on failure. */
+ReverythingIndex *reverything_index_create(void);
+/* Stops following the journal and waits for it. Another index can be created afterwards. */
+void reverything_index_destroy(ReverythingIndex *index);
+
+/* Uses the search syntax of the app. Returns NULL if the query is invalid. */
+ReverythingResults *reverything_search(const ReverythingIndex *index, const char *query, uint32_t flags);
+size_t reverything_results_count(const ReverythingResults *results);
+/* Valid until the results are freed */
+const char *reverything_results_path(const ReverythingResults *results, size_t i);
+uint64_t reverything_results_size(const ReverythingResults *results, size_t i);
+bool reverything_results_is_directory(const ReverythingResults *results, size_t i);
+void reverything_results_free(ReverythingResults *results);
+
+uint64_t reverything_subscribe(const ReverythingIndex *index, ReverythingChangeCallback callback, void *user_data);
+void reverything_unsubscribe(const ReverythingIndex *index, uint64_t id);
+
+/* Message of the last error on the calling thread, or NULL */
+const char *reverything_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,49 @@
+use std::process::Command;
+
+use eyre::{eyre, Context, Result};
+
+/// Name of the scheduled task which starts the UI when the user logs in
+const TASK_NAME: &str = "reverything";
+
+/// Registers or removes the scheduled task which starts the UI minimized when the current user
+/// logs in. A task is used instead of the `Run` registry key, because it can start the app with
+/// the highest privileges without showing a UAC prompt. Creating it requires an elevated process.
+pub fn set_enabled(enabled: bool) -> Result<()> {
+    if enabled == is_enabled() {
+        return Ok(());
+    }
+
+    let command = if enabled {
+        let action = format!("\"{}\" ui --minimized", std::env::current_exe()?.display());
+        schtasks(&[
+            "/Create", "/F", "/TN", TASK_NAME, "/TR", &action, "/SC", "ONLOGON", "/RL", "HIGHEST",
+        ])
+    } else {
+        schtasks(&["/Delete", "/F", "/TN", TASK_NAME])
+    };
+    command.with_context(|| {
+        format!(
+            "Failed to {} the autostart task",
+            if enabled { "create" } else { "delete" }
+        )
+    })
+}
+
+pub fn is_enabled() -> bool {
+    schtasks(&["/Query", "/TN", TASK_NAME]).is_ok()
+}
+
+fn schtasks(args: &[&str]) -> Result<()> {
+    let output = Command::new("schtasks.exe")
+        .args(args)
+        .output()
+        .with_context(|| "Failed to run schtasks.exe")?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "{}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,458 @@
+//! Where the UI gets its results and the state of the index from. The UI is a client of the
+//! service if it is running, so that the index is only kept once. Otherwise it builds an index of
+//! its own.
+
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use eyre::{eyre, Result};
+use rustc_hash::{FxHashMap, FxHashSet};
+use tracing::{error, warn};
+
+use crate::changelog::ChangeLog;
+use crate::config::{SizeFormat, SortOrder};
+use crate::format;
+use crate::ipc::{self, Client, ServiceStatus};
+use crate::metrics::FilterMetrics;
+use crate::ntfs::index::{FileId, NtfsVolumeIndex};
+use crate::query::SearchOptions;
+use crate::search::{self, RefinementCache, ResultEntry};
+use crate::stats::Statistics;
+
+/// Rows fetched from the service at once while scrolling
+const PAGE_SIZE: usize = 256;
+/// Pages kept by [ServiceResults], older ones are fetched again when scrolling back
+const MAX_CACHED_PAGES: usize = 64;
+/// Rows fetched from the service at once for actions on all results, like selecting them
+const BULK_SIZE: usize = 10_000;
+/// How long to wait before following the changes of the service again after the connection broke
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub enum Backend {
+    /// The index which the UI built, because the service is not running
+    Local(Arc<Mutex<NtfsVolumeIndex>>),
+    /// The index of the service, which is used over its named pipe
+    Service,
+}
+
+impl Backend {
+    pub fn results(&self) -> Box<dyn ResultSource> {
+        match self {
+            Backend::Local(index) => Box::new(LocalResults::new(index.clone())),
+            Backend::Service => Box::new(ServiceResults::default()),
+        }
+    }
+
+    /// Results can only be grouped by folder with a local index, as the service only knows the
+    /// results themselves.
+    pub fn can_group(&self) -> bool {
+        matches!(self, Backend::Local(_))
+    }
+
+    pub fn status(&self) -> Result<ServiceStatus> {
+        match self {
+            Backend::Local(index) => {
+                let index = index.lock().unwrap();
+                Ok(ServiceStatus {
+                    generation: index.generation(),
+                    paused: reverything::is_paused(),
+                    unreadable_records: index.unreadable_records(),
+                    journal_error: reverything::journal_error(),
+                })
+            }
+            Backend::Service => Client::connect()?.status(),
+        }
+    }
+
+    pub fn set_paused(&self, paused: bool) -> Result<()> {
+        match self {
+            Backend::Local(_) => {
+                reverything::set_paused(paused);
+                Ok(())
+            }
+            Backend::Service => Client::connect()?.set_paused(paused),
+        }
+    }
+
+    pub fn retry_journal(&self) -> Result<()> {
+        match self {
+            Backend::Local(_) => {
+                reverything::retry_journal();
+                Ok(())
+            }
+            Backend::Service => Client::connect()?.retry_journal(),
+        }
+    }
+
+    /// Labels and values describing the volume, see [format::format_volume_info]
+    pub fn volume_info(&self, size_format: SizeFormat) -> Result<Vec<(String, String)>> {
+        match self {
+            Backend::Local(index) => Ok(format::format_volume_info(
+                &reverything::volume_info(index)?,
+                size_format,
+            )
+            .into_iter()
+            .map(|(label, value)| (label.to_string(), value))
+            .collect()),
+            Backend::Service => Client::connect()?.info(),
+        }
+    }
+
+    pub fn statistics(&self) -> Result<Statistics> {
+        match self {
+            Backend::Local(index) => Ok(Statistics::compute(&index.lock().unwrap())),
+            Backend::Service => Client::connect()?.stats(),
+        }
+    }
+
+    /// Returns the number of bytes which were freed, see [reverything::compact]
+    pub fn compact(&self) -> Result<usize> {
+        match self {
+            Backend::Local(index) => Ok(reverything::compact(index)),
+            Backend::Service => Client::connect()?.compact(),
+        }
+    }
+}
+
+/// Records the changes reported by the service in `change_log`, like the local index does for
+/// the changes it reads from the journal. Connects again if the service is restarted.
+pub fn start_change_thread(change_log: Arc<Mutex<ChangeLog>>) {
+    std::thread::spawn(move || loop {
+        let res = Client::connect().and_then(|mut client| {
+            client.watch(|changes| {
+                let mut change_log = change_log.lock().unwrap();
+                for change in changes {
+                    change_log.push(change.time, change.kind, change.path, change.old_path);
+                }
+            })
+        });
+        if let Err(e) = res {
+            warn!("Following the changes of the service failed: {:?}", e);
+        }
+        std::thread::sleep(RECONNECT_INTERVAL);
+    });
+}
+
+/// The rows of the results table. Rows are either results, or the folders containing them if the
+/// results are grouped by folder.
+pub trait ResultSource {
+    /// Runs a search, keeping the previous results if it fails. With `expanded_groups`, the results
+    /// are grouped by folder and those of the given folders follow their folder.
+    fn search(
+        &mut self,
+        query: &str,
+        options: SearchOptions,
+        sort: Option<SortOrder>,
+        expanded_groups: Option<&FxHashSet<FileId>>,
+    ) -> Result<()>;
+
+    fn row_count(&self) -> usize;
+
+    /// Number of results in the folder of the given row, `None` if it is not a group
+    fn group_count(&self, row: usize) -> Option<usize>;
+
+    fn entry(&mut self, row: usize) -> Option<ResultEntry>;
+
+    /// The entries in the given rows with their row, skipping files which were deleted
+    fn entries(&mut self, rows: Range<usize>) -> Vec<(usize, ResultEntry)>;
+
+    /// The ids of the files in the given rows with their row, skipping files which were deleted
+    fn ids(&mut self, rows: Range<usize>) -> Vec<(usize, FileId)> {
+        self.entries(rows)
+            .into_iter()
+            .map(|(row, entry)| (row, entry.id))
+            .collect()
+    }
+
+    /// The file with the given id, wherever it is in the results. `None` if it was deleted.
+    fn find(&mut self, id: FileId) -> Option<ResultEntry>;
+
+    /// Timings of the last search, if it was run locally
+    fn last_metrics(&self) -> Option<FilterMetrics> {
+        None
+    }
+}
+
+/// Results searched in the index of the UI
+pub struct LocalResults {
+    index: Arc<Mutex<NtfsVolumeIndex>>,
+    /// MFT index of the file in every row. If the results are grouped, these are the folders
+    /// followed by the results inside of them if the folder is expanded.
+    rows: Vec<u64>,
+    /// Number of results in the folder of every row which is a group
+    groups: FxHashMap<usize, usize>,
+    /// Results of the previous queries, which makes typing and refreshing an unchanged index cheap
+    cache: RefinementCache,
+}
+
+impl LocalResults {
+    pub fn new(index: Arc<Mutex<NtfsVolumeIndex>>) -> Self {
+        Self {
+            index,
+            rows: Vec::new(),
+            groups: FxHashMap::default(),
+            cache: RefinementCache::default(),
+        }
+    }
+
+    /// Replaces the results by one row per folder containing them. The results of expanded
+    /// folders follow their folder.
+    fn group_rows(
+        &mut self,
+        ntfs_index: &NtfsVolumeIndex,
+        files: &[u64],
+        expanded_groups: &FxHashSet<FileId>,
+    ) -> Vec<u64> {
+        self.groups.clear();
+
+        let mut rows = Vec::new();
+        for (folder, files) in search::group_by_folder(ntfs_index, files) {
+            self.groups.insert(rows.len(), files.len());
+            rows.push(folder);
+            if ntfs_index
+                .file_id(folder)
+                .is_some_and(|id| expanded_groups.contains(&id))
+            {
+                rows.extend(files);
+            }
+        }
+        rows
+    }
+}
+
+impl ResultSource for LocalResults {
+    fn search(
+        &mut self,
+        query: &str,
+        options: SearchOptions,
+        sort: Option<SortOrder>,
+        expanded_groups: Option<&FxHashSet<FileId>>,
+    ) -> Result<()> {
+        let mut files = self.cache.filter(&self.index, query, options)?;
+        if let Some(sort) = sort {
+            search::sort(&self.index, &mut files, sort.column, sort.ascending);
+        }
+        let index = self.index.clone();
+        let ntfs_index = index.lock().unwrap();
+        self.rows = match expanded_groups {
+            Some(expanded_groups) => self.group_rows(&ntfs_index, &files, expanded_groups),
+            None => {
+                self.groups.clear();
+                files
+            }
+        };
+        Ok(())
+    }
+
+    fn row_count(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn group_count(&self, row: usize) -> Option<usize> {
+        self.groups.get(&row).copied()
+    }
+
+    fn entry(&mut self, row: usize) -> Option<ResultEntry> {
+        let ntfs_index = self.index.lock().unwrap();
+        ResultEntry::new(&ntfs_index, *self.rows.get(row)?)
+    }
+
+    fn entries(&mut self, rows: Range<usize>) -> Vec<(usize, ResultEntry)> {
+        let ntfs_index = self.index.lock().unwrap();
+        let end = rows.end.min(self.rows.len());
+        (rows.start.min(end)..end)
+            .filter_map(|row| ResultEntry::new(&ntfs_index, self.rows[row]).map(|e| (row, e)))
+            .collect()
+    }
+
+    fn ids(&mut self, rows: Range<usize>) -> Vec<(usize, FileId)> {
+        let ntfs_index = self.index.lock().unwrap();
+        let end = rows.end.min(self.rows.len());
+        (rows.start.min(end)..end)
+            .filter_map(|row| ntfs_index.file_id(self.rows[row]).map(|id| (row, id)))
+            .collect()
+    }
+
+    fn find(&mut self, id: FileId) -> Option<ResultEntry> {
+        let ntfs_index = self.index.lock().unwrap();
+        ResultEntry::new(&ntfs_index, ntfs_index.resolve(id)?)
+    }
+
+    fn last_metrics(&self) -> Option<FilterMetrics> {
+        self.cache.last_metrics()
+    }
+}
+
+/// A search as it was opened in a cursor of the service
+#[derive(PartialEq)]
+struct OpenedSearch {
+    query: String,
+    options: SearchOptions,
+    sort: Option<SortOrder>,
+    /// [ServiceStatus::generation] at the time of the search
+    generation: u64,
+}
+
+/// Results searched by the service, which are kept in a cursor of the service and fetched page by
+/// page as they are shown.
+#[derive(Default)]
+pub struct ServiceResults {
+    /// `None` until connected, and again after the connection broke
+    client: Option<Client>,
+    /// The cursor holding the results and the search it was opened for
+    cursor: Option<(u64, OpenedSearch)>,
+    row_count: usize,
+    /// Rows fetched so far by their page, `None` for files which were deleted
+    pages: FxHashMap<usize, Vec<Option<ResultEntry>>>,
+}
+
+impl ServiceResults {
+    /// Sends requests over the connection to the service, connecting first if necessary. The
+    /// cursor is lost along with a broken connection.
+    fn request<R>(&mut self, f: impl FnOnce(&mut Client) -> Result<R>) -> Result<R> {
+        let client = match &mut self.client {
+            Some(client) => client,
+            None => self.client.insert(Client::connect()?),
+        };
+
+        let res = f(client);
+        if let Err(e) = &res {
+            if ipc::is_disconnected(e) {
+                warn!("Lost the connection to the service: {:?}", e);
+                self.client = None;
+                self.cursor = None;
+                self.row_count = 0;
+                self.pages.clear();
+            }
+        }
+        res
+    }
+
+    fn cursor(&self) -> Option<u64> {
+        self.cursor.as_ref().map(|(cursor, _)| *cursor)
+    }
+
+    fn fetch(&mut self, start: usize, count: usize) -> Vec<Option<ResultEntry>> {
+        let Some(cursor) = self.cursor() else {
+            return Vec::new();
+        };
+        match self.request(|client| client.rows(cursor, start, count)) {
+            Ok(rows) => rows,
+            Err(e) => {
+                error!("Fetching results from the service failed: {:?}", e);
+                Vec::new()
+            }
+        }
+    }
+}
+
+impl ResultSource for ServiceResults {
+    fn search(
+        &mut self,
+        query: &str,
+        options: SearchOptions,
+        sort: Option<SortOrder>,
+        expanded_groups: Option<&FxHashSet<FileId>>,
+    ) -> Result<()> {
+        if expanded_groups.is_some() {
+            return Err(eyre!("Results of the service cannot be grouped by folder"));
+        }
+
+        // The results are refreshed every second, so searching again only if the index changed
+        // keeps the service idle
+        let generation = self.request(|client| client.status())?.generation;
+        let search = OpenedSearch {
+            query: query.to_string(),
+            options,
+            sort,
+            generation,
+        };
+        if self
+            .cursor
+            .as_ref()
+            .is_some_and(|(_, opened)| *opened == search)
+        {
+            return Ok(());
+        }
+
+        let (cursor, row_count) = self.request(|client| {
+            client.set_options(options)?;
+            client.open(query, sort)
+        })?;
+        if let Some((previous, _)) = self.cursor.replace((cursor, search)) {
+            if let Err(e) = self.request(|client| client.close(previous)) {
+                error!("{:?}", e);
+            }
+        }
+        self.row_count = row_count;
+        self.pages.clear();
+        Ok(())
+    }
+
+    fn row_count(&self) -> usize {
+        self.row_count
+    }
+
+    fn group_count(&self, _row: usize) -> Option<usize> {
+        None
+    }
+
+    fn entry(&mut self, row: usize) -> Option<ResultEntry> {
+        if row >= self.row_count {
+            return None;
+        }
+
+        let page = row / PAGE_SIZE;
+        if !self.pages.contains_key(&page) {
+            let rows = self.fetch(page * PAGE_SIZE, PAGE_SIZE);
+            // Dropping everything is crude, but scrolling back is rare compared to scrolling on
+            if self.pages.len() >= MAX_CACHED_PAGES {
+                self.pages.clear();
+            }
+            self.pages.insert(page, rows);
+        }
+        self.pages[&page].get(row % PAGE_SIZE).cloned().flatten()
+    }
+
+    fn entries(&mut self, rows: Range<usize>) -> Vec<(usize, ResultEntry)> {
+        // Single rows are looked up for every click, so these come from the cached pages
+        if rows.len() <= PAGE_SIZE {
+            return rows
+                .filter_map(|row| self.entry(row).map(|entry| (row, entry)))
+                .collect();
+        }
+
+        let end = rows.end.min(self.row_count);
+        let mut entries = Vec::new();
+        let mut start = rows.start;
+        while start < end {
+            let count = (end - start).min(BULK_SIZE);
+            let fetched = self.fetch(start, count);
+            if fetched.is_empty() {
+                break;
+            }
+            // The service may send fewer rows than requested
+            let fetched_rows = fetched.len();
+            entries.extend(
+                fetched
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(i, entry)| entry.map(|entry| (start + i, entry))),
+            );
+            start += fetched_rows;
+        }
+        entries
+    }
+
+    fn find(&mut self, id: FileId) -> Option<ResultEntry> {
+        match self.request(|client| client.entry(id)) {
+            Ok(entry) => entry,
+            Err(e) => {
+                error!("{:?}", e);
+                None
+            }
+        }
+    }
+}
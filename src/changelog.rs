@@ -0,0 +1,69 @@
+use std::collections::VecDeque;
+
+use crate::filetime;
+use crate::ntfs::index::{ChangeKind, FileChange};
+
+const MAX_ENTRIES: usize = 5000;
+
+/// The most recent changes to the index. Old entries are dropped once it is full.
+#[derive(Debug, Default)]
+pub struct ChangeLog {
+    entries: VecDeque<ChangeLogEntry>,
+    next_sequence: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChangeLogEntry {
+    /// Increases by one for every recorded change, which lets readers poll for new entries
+    pub sequence: u64,
+    /// FILETIME at which the change was processed
+    pub time: u64,
+    pub kind: ChangeKind,
+    pub path: String,
+    /// Path before a rename or move
+    pub old_path: Option<String>,
+}
+
+impl ChangeLog {
+    pub fn record(&mut self, changes: &[FileChange]) {
+        let time = filetime::now();
+        for change in changes {
+            self.push(
+                time,
+                change.kind,
+                change.path.clone(),
+                change.old_path.clone(),
+            );
+        }
+    }
+
+    /// Adds a change which was recorded elsewhere, like the changes reported by the service.
+    pub fn push(&mut self, time: u64, kind: ChangeKind, path: String, old_path: Option<String>) {
+        if self.entries.len() >= MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(ChangeLogEntry {
+            sequence: self.next_sequence,
+            time,
+            kind,
+            path,
+            old_path,
+        });
+        self.next_sequence += 1;
+    }
+
+    /// The sequence number which the next recorded change will get.
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    /// Returns all entries which are still stored and have a sequence number of at least
+    /// `sequence`, oldest first.
+    pub fn since(&self, sequence: u64) -> impl Iterator<Item = &ChangeLogEntry> {
+        let skip = self
+            .entries
+            .partition_point(|entry| entry.sequence < sequence);
+        self.entries.range(skip..)
+    }
+}
@@ -0,0 +1,137 @@
+use std::fs::File;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use eyre::{eyre, Context, Result};
+use windows::Win32::Security::Cryptography::{
+    BCryptCreateHash, BCryptDestroyHash, BCryptFinishHash, BCryptHashData, BCRYPT_HASH_HANDLE,
+    BCRYPT_MD5_ALG_HANDLE, BCRYPT_SHA1_ALG_HANDLE, BCRYPT_SHA256_ALG_HANDLE,
+};
+
+const READ_BUFFER_SIZE: usize = 256 * 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Algorithm {
+    Md5,
+    Sha1,
+    Sha256,
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 3] = [Algorithm::Md5, Algorithm::Sha1, Algorithm::Sha256];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "MD5",
+            Algorithm::Sha1 => "SHA-1",
+            Algorithm::Sha256 => "SHA-256",
+        }
+    }
+}
+
+/// An incremental hash computed by the Windows cryptography API.
+pub struct Hasher {
+    handle: BCRYPT_HASH_HANDLE,
+    length: usize,
+}
+
+impl Hasher {
+    pub fn new(algorithm: Algorithm) -> Result<Self> {
+        let (provider, length) = match algorithm {
+            Algorithm::Md5 => (BCRYPT_MD5_ALG_HANDLE, 16),
+            Algorithm::Sha1 => (BCRYPT_SHA1_ALG_HANDLE, 20),
+            Algorithm::Sha256 => (BCRYPT_SHA256_ALG_HANDLE, 32),
+        };
+
+        let mut handle = BCRYPT_HASH_HANDLE::default();
+        unsafe { BCryptCreateHash(provider, &mut handle, None, None, 0) }
+            .ok()
+            .with_context(|| "BCryptCreateHash failed")?;
+
+        Ok(Self { handle, length })
+    }
+
+    pub fn update(&mut self, data: &[u8]) -> Result<()> {
+        unsafe { BCryptHashData(self.handle, data, 0) }
+            .ok()
+            .with_context(|| "BCryptHashData failed")
+    }
+
+    pub fn finish(self) -> Result<Vec<u8>> {
+        let mut digest = vec![0u8; self.length];
+        unsafe { BCryptFinishHash(self.handle, &mut digest, 0) }
+            .ok()
+            .with_context(|| "BCryptFinishHash failed")?;
+
+        Ok(digest)
+    }
+}
+
+impl Drop for Hasher {
+    fn drop(&mut self) {
+        let _ = unsafe { BCryptDestroyHash(self.handle) };
+    }
+}
+
+/// Hashes the content of the file at `path`. If `limit` is given, only that many bytes from the
+/// start of the file are hashed.
+pub fn hash_file(path: &str, mut hasher: Hasher, limit: Option<u64>) -> Result<Vec<u8>> {
+    read_chunks(path, limit, |chunk| hasher.update(chunk))?;
+    hasher.finish()
+}
+
+/// Computes the hex encoded digests of all [Algorithm]s while reading the file only once.
+/// `on_progress` is called with the number of bytes read after each chunk. Fails if `cancel` is set
+/// in the meantime.
+pub fn checksums(
+    path: &str,
+    cancel: &AtomicBool,
+    mut on_progress: impl FnMut(u64),
+) -> Result<Vec<(Algorithm, String)>> {
+    let mut hashers = Algorithm::ALL
+        .into_iter()
+        .map(|algorithm| Ok((algorithm, Hasher::new(algorithm)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    read_chunks(path, None, |chunk| {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(eyre!("Cancelled"));
+        }
+
+        for (_, hasher) in &mut hashers {
+            hasher.update(chunk)?;
+        }
+        on_progress(chunk.len() as u64);
+        Ok(())
+    })?;
+
+    hashers
+        .into_iter()
+        .map(|(algorithm, hasher)| Ok((algorithm, to_hex(&hasher.finish()?))))
+        .collect()
+}
+
+fn read_chunks(
+    path: &str,
+    limit: Option<u64>,
+    mut f: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    let file = File::open(path).with_context(|| format!("Failed to open '{}'", path))?;
+    let mut reader = file.take(limit.unwrap_or(u64::MAX));
+
+    let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+    loop {
+        let read = reader
+            .read(&mut buffer)
+            .with_context(|| format!("Failed to read '{}'", path))?;
+        if read == 0 {
+            return Ok(());
+        }
+
+        f(&buffer[..read])?;
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
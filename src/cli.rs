@@ -0,0 +1,471 @@
+use eyre::{eyre, ContextCompat, Result};
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+use crate::config::SortOrder;
+use crate::ipc::Client;
+use crate::ntfs::index::{
+    set_read_queue_depth, ChangeKind, DiscrepancyKind, FileId, NtfsVolumeIndex,
+};
+use crate::ntfs::volume::get_volumes;
+use crate::{checksum, content_search, duplicates, format};
+
+/// Progress is only shown while hashing files of at least this size.
+const CHECKSUM_PROGRESS_MIN_SIZE: u64 = 64 * 1024 * 1024;
+/// Queue depths `benchmark-index` reads the MFT with, see [set_read_queue_depth]
+const BENCHMARK_QUEUE_DEPTHS: [usize; 5] = [1, 4, 16, 32, 64];
+
+/// Sub commands which can be passed as the first argument. Running without any arguments starts
+/// the UI.
+#[derive(Debug)]
+pub enum Command {
+    /// Starts the UI, or shows the running one, and searches for the query unless it is empty
+    Ui {
+        query: String,
+        /// Set when started at login, see [crate::autostart]
+        minimized: bool,
+    },
+    /// Entry point used by the service control manager
+    Service,
+    InstallService,
+    UninstallService,
+    /// Queries the running service and prints the results as they arrive, sorted by the service if
+    /// `--sort` is given
+    Search {
+        query: String,
+        sort: Option<SortOrder>,
+        /// Print the id of every result, see [Command::Resolve]
+        ids: bool,
+    },
+    /// Prints the current path of the file with this id, as shown in the details of a result
+    Resolve(FileId),
+    /// Prints changes reported by the running service as they happen, only the given kinds if any
+    Watch(Vec<ChangeKind>),
+    /// Prints files with identical content among the results of a query
+    Duplicates(String),
+    /// Prints lines matching a regex in the files found by a query
+    Grep {
+        pattern: String,
+        query: String,
+    },
+    /// Prints the MD5, SHA-1 and SHA-256 digests of all files found by a query
+    Checksum(String),
+    /// Builds the index with every index mode and prints how long each took
+    BenchmarkIndex,
+    /// Suspends or resumes following the journal in the running service
+    SetPaused(bool),
+    /// Prints information about the volume indexed by the running service
+    Info,
+    /// Prints how long searches and applying journal entries take in the running service
+    Metrics,
+    /// Prints the number and size of files by extension and top-level directory
+    Stats,
+    /// Repairs entries of the running service's index which don't lead to the root directory, or
+    /// with `--deep` compares the index with the directories on the volume
+    Verify {
+        deep: bool,
+    },
+    /// Prints the results of a saved search of the running service, or lists them if no name is
+    /// given
+    Saved(String),
+    /// Runs a Rhai script against the running service, see `src/script.rs`
+    Script {
+        path: PathBuf,
+        /// Passed to the script as `ARGS`
+        args: Vec<String>,
+        allow_write: bool,
+    },
+}
+
+impl Command {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let Some(command) = args.next() else {
+            return Ok(Command::Ui {
+                query: String::new(),
+                minimized: false,
+            });
+        };
+
+        match command.as_str() {
+            "ui" => {
+                let mut args = args.peekable();
+                let mut minimized = false;
+                let mut scope = None;
+                while let Some(arg) = args.next_if(|arg| arg.starts_with("--")) {
+                    match arg.as_str() {
+                        "--minimized" => minimized = true,
+                        "--in" => {
+                            let folder = args
+                                .next()
+                                .ok_or_else(|| eyre!("Usage: reverything ui --in <folder>"))?;
+                            scope = Some(scope_query(&folder));
+                        }
+                        _ => {
+                            return Err(eyre!(
+                                "Unknown argument '{}', expected --minimized or --in",
+                                arg
+                            ))
+                        }
+                    }
+                }
+                let query = args.collect::<Vec<_>>().join(" ");
+                Ok(Command::Ui {
+                    query: match scope {
+                        Some(scope) => format!("{} {}", scope, query),
+                        None => query,
+                    },
+                    minimized,
+                })
+            }
+            "service" => Ok(Command::Service),
+            "install-service" => Ok(Command::InstallService),
+            "uninstall-service" => Ok(Command::UninstallService),
+            "search" => {
+                let mut args = args.peekable();
+                let sort = match args.next_if(|arg| arg == "--sort") {
+                    Some(_) => Some(SortOrder::parse(&args.next().ok_or_else(|| {
+                        eyre!("Usage: reverything search [--sort [-]<column>] [--ids] <query>")
+                    })?)?),
+                    None => None,
+                };
+                let ids = args.next_if(|arg| arg == "--ids").is_some();
+                Ok(Command::Search {
+                    query: args.collect::<Vec<_>>().join(" "),
+                    sort,
+                    ids,
+                })
+            }
+            "resolve" => Ok(Command::Resolve(
+                args.next()
+                    .ok_or_else(|| eyre!("Usage: reverything resolve <file id>"))?
+                    .parse()?,
+            )),
+            "watch" => Ok(Command::Watch(
+                args.map(|kind| {
+                    ChangeKind::ALL
+                        .into_iter()
+                        .find(|k| k.name().eq_ignore_ascii_case(&kind))
+                        .ok_or_else(|| {
+                            eyre!(
+                                "Unknown change kind '{}', expected one of: created, renamed, moved, deleted",
+                                kind
+                            )
+                        })
+                })
+                .collect::<Result<_>>()?,
+            )),
+            "duplicates" => Ok(Command::Duplicates(args.collect::<Vec<_>>().join(" "))),
+            "grep" => Ok(Command::Grep {
+                pattern: args
+                    .next()
+                    .ok_or_else(|| eyre!("Usage: reverything grep <pattern> <query>"))?,
+                query: args.collect::<Vec<_>>().join(" "),
+            }),
+            "checksum" => Ok(Command::Checksum(args.collect::<Vec<_>>().join(" "))),
+            "benchmark-index" => Ok(Command::BenchmarkIndex),
+            "pause" => Ok(Command::SetPaused(true)),
+            "resume" => Ok(Command::SetPaused(false)),
+            "info" => Ok(Command::Info),
+            "metrics" => Ok(Command::Metrics),
+            "stats" => Ok(Command::Stats),
+            "verify" => match args.next().as_deref() {
+                None => Ok(Command::Verify { deep: false }),
+                Some("--deep") => Ok(Command::Verify { deep: true }),
+                Some(arg) => Err(eyre!("Unknown argument '{}', expected --deep", arg)),
+            },
+            "saved" => Ok(Command::Saved(args.collect::<Vec<_>>().join(" "))),
+            "script" => {
+                let mut args = args.peekable();
+                let allow_write = args.next_if(|arg| arg == "--allow-write").is_some();
+                Ok(Command::Script {
+                    path: args.next().map(PathBuf::from).ok_or_else(|| {
+                        eyre!("Usage: reverything script [--allow-write] <script> [args...]")
+                    })?,
+                    args: args.collect(),
+                    allow_write,
+                })
+            }
+            _ => Err(eyre!(
+                "Unknown command '{}', expected one of: ui, service, install-service, uninstall-service, search, resolve, watch, duplicates, grep, checksum, benchmark-index, pause, resume, info, metrics, stats, verify, saved, script",
+                command
+            )),
+        }
+    }
+}
+
+/// Query limiting the search to `folder`, as passed by the Explorer menu. The backslash of drive
+/// roots like `"C:\"` escapes the closing quote, so Windows passes them as `C:"`.
+fn scope_query(folder: &str) -> String {
+    let folder = folder.trim_end_matches('"');
+    let separator = if folder.ends_with(':') { "\\" } else { "" };
+    format!("path:\"{}{}\"", folder, separator)
+}
+
+pub fn search(query: &str, sort: Option<SortOrder>, ids: bool) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    Client::connect()?.stream(query, sort, |results| {
+        for result in results {
+            if ids {
+                write!(stdout, "{}\t", result.id)?;
+            }
+            writeln!(stdout, "{}\t{}", result.size, result.path)?;
+        }
+        Ok(())
+    })
+}
+
+/// Files are hashed by this process instead of the service, so only files which the current user
+/// can read are compared.
+pub fn duplicates(query: &str) -> Result<()> {
+    let files = Client::connect()?
+        .search(query)?
+        .into_iter()
+        .map(|result| (result.path, result.size))
+        .collect();
+
+    let sets = duplicates::find_duplicates(files, &AtomicBool::new(false)).unwrap_or_default();
+    for set in sets {
+        for path in set.paths {
+            println!("{}\t{}", set.size, path);
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+pub fn grep(pattern: &str, query: &str) -> Result<()> {
+    let pattern = content_search::build_pattern(pattern, true, false)?;
+    let files = Client::connect()?
+        .search(query)?
+        .into_iter()
+        .map(|result| (result.path, result.size))
+        .collect::<Vec<_>>();
+
+    let matches = content_search::search_contents(&files, &pattern, &AtomicBool::new(false))
+        .unwrap_or_default();
+    for m in matches {
+        println!("{}:{}:{}", m.path, m.line_number, m.line);
+    }
+
+    Ok(())
+}
+
+/// Files are hashed one after another, so that the progress shown for large files is meaningful.
+pub fn checksum(query: &str) -> Result<()> {
+    let results = Client::connect()?.search(query)?;
+    for result in results {
+        let mut done = 0u64;
+        let mut last_percent = None;
+        let checksums = checksum::checksums(&result.path, &AtomicBool::new(false), |read| {
+            done += read;
+            let percent = done * 100 / result.size.max(1);
+            if result.size >= CHECKSUM_PROGRESS_MIN_SIZE && last_percent != Some(percent) {
+                eprint!("\r{:>3}% {}", percent, result.path);
+                last_percent = Some(percent);
+            }
+        });
+        if last_percent.is_some() {
+            eprint!("\r\x1b[2K");
+        }
+
+        println!("{}", result.path);
+        match checksums {
+            Ok(checksums) => {
+                for (algorithm, digest) in checksums {
+                    println!("  {:<9}{}", algorithm.name(), digest);
+                }
+            }
+            Err(e) => println!("  {:#}", e),
+        }
+    }
+
+    Ok(())
+}
+
+pub fn info() -> Result<()> {
+    for (label, value) in Client::connect()?.info()? {
+        println!("{:<18}{}", label, value);
+    }
+
+    Ok(())
+}
+
+pub fn metrics() -> Result<()> {
+    for (label, value) in Client::connect()?.metrics()? {
+        println!("{:<18}{}", label, value);
+    }
+
+    Ok(())
+}
+
+/// Prints tab separated raw values, so that the output can be processed by other programs.
+pub fn stats() -> Result<()> {
+    let stats = Client::connect()?.stats()?;
+    for (kind, groups) in [
+        ("extension", &stats.by_extension),
+        ("directory", &stats.by_directory),
+    ] {
+        for group in groups {
+            println!("{}\t{}\t{}\t{}", kind, group.files, group.size, group.name);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn verify(deep: bool) -> Result<()> {
+    if deep {
+        return verify_directories();
+    }
+
+    let orphans = Client::connect()?.verify()?;
+    for orphan in &orphans {
+        let status = if orphan.resolved {
+            "resolved"
+        } else {
+            "quarantined"
+        };
+        println!("{}\t{}\t{}", orphan.mft_index, status, orphan.name);
+    }
+
+    let quarantined = orphans.iter().filter(|orphan| !orphan.resolved).count();
+    println!(
+        "{} orphaned entries, {} resolved, {} quarantined",
+        orphans.len(),
+        orphans.len() - quarantined,
+        quarantined
+    );
+    Ok(())
+}
+
+/// Prints the discrepancies between the index and the directories on the volume, nothing is
+/// repaired.
+fn verify_directories() -> Result<()> {
+    let check = Client::connect()?.verify_directories()?;
+    for discrepancy in &check.discrepancies {
+        let kind = match discrepancy.kind {
+            DiscrepancyKind::Missing => "missing",
+            DiscrepancyKind::Unlisted => "unlisted",
+        };
+        println!("{}\t{}\t{}", discrepancy.mft_index, kind, discrepancy.path);
+    }
+
+    println!(
+        "{} discrepancies in {} directories, {} directories could not be read",
+        check.discrepancies.len(),
+        check.directories,
+        check.skipped
+    );
+    Ok(())
+}
+
+pub fn resolve(id: FileId) -> Result<()> {
+    let result = Client::connect()?
+        .resolve(id)?
+        .with_context(|| format!("File {} doesn't exist anymore", id))?;
+    println!("{}\t{}", result.size, result.path);
+    Ok(())
+}
+
+pub fn saved(name: &str) -> Result<()> {
+    let mut client = Client::connect()?;
+    if name.is_empty() {
+        for preset in client.saved_searches()? {
+            println!("{}\t{}", preset.name, preset.query);
+        }
+        return Ok(());
+    }
+
+    for result in client.saved(name)? {
+        println!("{}\t{}", result.size, result.path);
+    }
+
+    Ok(())
+}
+
+pub fn set_paused(paused: bool) -> Result<()> {
+    Client::connect()?.set_paused(paused)
+}
+
+pub fn watch(kinds: &[ChangeKind]) -> Result<()> {
+    let mut client = Client::connect()?;
+    client.watch(|changes| {
+        for change in changes {
+            if !kinds.is_empty() && !kinds.contains(&change.kind) {
+                continue;
+            }
+
+            println!(
+                "{}\t{}\t{}",
+                format::format_time_precise(change.time),
+                change.kind.name(),
+                format::format_change_path(&change.path, change.old_path.as_deref())
+            );
+        }
+    })
+}
+
+/// Helps choosing the `index_mode` in the config. Requires an elevated shell.
+pub fn benchmark_index() -> Result<()> {
+    let volume = get_volumes()
+        .into_iter()
+        .next()
+        .with_context(|| "Cannot find first volume")?;
+
+    for (name, build) in [
+        ("mft", NtfsVolumeIndex::new as fn(_) -> _),
+        ("usn_enumeration", NtfsVolumeIndex::from_usn_data),
+    ] {
+        let t = Instant::now();
+        match build(volume) {
+            Ok(index) => println!(
+                "{}\t{:?}\t{} files",
+                name,
+                t.elapsed(),
+                index.real_file_count()
+            ),
+            Err(e) => println!("{}\tfailed: {:#}", name, e),
+        }
+    }
+
+    // The first read of the MFT also fills the disk cache, so every depth is read after it
+    let mut fastest: Option<(usize, Duration)> = None;
+    for depth in BENCHMARK_QUEUE_DEPTHS {
+        set_read_queue_depth(depth);
+        let t = Instant::now();
+        match NtfsVolumeIndex::new(volume) {
+            Ok(_) => {
+                let elapsed = t.elapsed();
+                println!("mft, queue depth {}\t{:?}", depth, elapsed);
+                if fastest.is_none_or(|(_, fastest)| elapsed < fastest) {
+                    fastest = Some((depth, elapsed));
+                }
+            }
+            Err(e) => println!("mft, queue depth {}\tfailed: {:#}", depth, e),
+        }
+    }
+    if let Some((depth, _)) = fastest {
+        println!(
+            "Fastest queue depth: {}, set mft_read_queue_depth to use it",
+            depth
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "scripting")]
+pub fn script(path: &Path, args: &[String], allow_write: bool) -> Result<()> {
+    crate::script::run(path, args, allow_write)
+}
+
+#[cfg(not(feature = "scripting"))]
+pub fn script(_path: &Path, _args: &[String], _allow_write: bool) -> Result<()> {
+    Err(eyre!(
+        "Scripts are not supported by this build, build with `--features scripting` to run them"
+    ))
+}
@@ -0,0 +1,475 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use eyre::{eyre, Context, ContextCompat, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use tracing::level_filters::LevelFilter;
+
+use crate::history::SearchHistory;
+use crate::JournalOptions;
+
+const APP_DIR_NAME: &str = "reverything";
+const CONFIG_FILE_NAME: &str = "config.toml";
+const STATE_FILE_NAME: &str = "state.toml";
+const CALIBRATION_FILE_NAME: &str = "calibration.toml";
+
+/// User editable settings, stored in `%APPDATA%\reverything\config.toml`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub presets: Vec<Preset>,
+    pub size_format: SizeFormat,
+    pub watch_rules: Vec<WatchRule>,
+    /// Programs listed under "Open with" in the context menu of the results
+    pub external_tools: Vec<ExternalTool>,
+    /// Answer queries of tools built with the Everything SDK, like `es.exe`, while the UI is
+    /// running
+    pub everything_ipc: bool,
+    /// How the index is built on startup
+    pub index_mode: IndexMode,
+    /// Least severe level of messages which are logged
+    pub log_level: LogLevel,
+    /// Hours between reading the volume again to correct changes the journal missed. 0 disables
+    /// the verification.
+    pub verify_interval_hours: u64,
+    /// Keep an index of the trigrams in all file names, which makes searching for text faster on
+    /// large volumes but needs more memory
+    pub trigram_index: bool,
+    /// Show the original name and path of deleted files in the recycle bin instead of the names
+    /// Windows gave them, read from the `$I` files next to them
+    pub resolve_recycle_bin: bool,
+    /// Leave NTFS metafiles like `$MFT`, everything inside `$Extend` and empty system files out of
+    /// the results
+    pub hide_metafiles: bool,
+    /// Milliseconds between checking the journal for changes
+    pub journal_poll_interval_ms: u64,
+    /// Most journal entries which are applied to the index at once, see
+    /// [JournalOptions::batch_size]
+    pub journal_batch_size: usize,
+    /// Start the UI minimized when logging in, toggled in the UI
+    pub autostart: bool,
+    /// Add "Search with reverything" to the context menu of folders in Explorer, toggled in the UI
+    pub explorer_menu: bool,
+    /// Number of reads which are queued at the same time while reading the MFT. 0 measures it
+    /// while the MFT is read for the first time, see [Calibration].
+    pub mft_read_queue_depth: usize,
+    /// Build the index with background I/O priority, so that other applications stay responsive
+    /// while it is read, especially on hard disks
+    pub low_io_priority: bool,
+    /// Save the index and load it on the next start instead of reading the MFT again, see
+    /// [JournalOptions::persist]
+    pub persist_index: bool,
+    /// UNC paths like `\\server\share` or mapped drives like `Z:\`, whose files are listed and
+    /// searched together with the volume
+    pub network_locations: Vec<String>,
+    /// Zoom factor of the whole UI, applied on top of the display scaling
+    pub ui_zoom: f32,
+    /// Scale the UI like the display settings of Windows ask for. If disabled, only [Self::ui_zoom]
+    /// is applied.
+    pub follow_system_dpi: bool,
+    /// Font size of the results table in logical pixels, 0 for the default size
+    pub results_font_size: f32,
+    /// Show how long the last search and the last batch of journal entries took below the results
+    pub performance_overlay: bool,
+    /// Minutes between listing the network locations again, as there is no journal telling what
+    /// changed. 0 only lists them on startup.
+    pub network_rescan_minutes: u64,
+}
+
+/// Where the initial index is read from. The journal is used to keep it up to date either way.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexMode {
+    /// Reads the MFT and falls back to USN enumeration if that fails
+    #[default]
+    Auto,
+    /// Reads the MFT directly from the volume, which is the fastest and includes sizes and dates
+    Mft,
+    /// Enumerates files through `FSCTL_ENUM_USN_DATA`, which works even if raw volume reads are
+    /// restricted, but doesn't provide sizes and dates
+    UsnEnumeration,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub fn filter(self) -> LevelFilter {
+        match self {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+/// How file sizes are displayed in the results.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SizeFormat {
+    /// Exact byte count, e.g. `1,234,567`
+    Bytes,
+    /// Scaled to the largest fitting unit, e.g. `1.2 MB`
+    #[default]
+    HumanReadable,
+}
+
+/// A named query which is combined with the search text when selected in the UI.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Preset {
+    pub name: String,
+    pub query: String,
+}
+
+/// Shows a notification whenever a file matching `pattern` is created, renamed or deleted below
+/// `path_prefix`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchRule {
+    pub name: String,
+    /// Directory to watch, e.g. `C:\Users\me\Downloads`. Empty to watch the whole volume.
+    #[serde(default)]
+    pub path_prefix: String,
+    /// Query which changed files have to match, using the same syntax as the search box
+    #[serde(default)]
+    pub pattern: String,
+}
+
+/// A program which can be run for a file from the context menu of the results.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternalTool {
+    pub name: String,
+    /// Command line to run, with `%path%`, `%dir%` and `%name%` replaced by the full path of the
+    /// file, its directory and its name, e.g. `notepad.exe "%path%"`. The placeholders are replaced
+    /// after splitting the command into arguments.
+    pub command: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let preset = |name: &str, query: &str| Preset {
+            name: name.to_string(),
+            query: query.to_string(),
+        };
+        let tool = |name: &str, command: &str| ExternalTool {
+            name: name.to_string(),
+            command: command.to_string(),
+        };
+
+        Self {
+            presets: vec![
+                preset("Videos", "ext:mp4;mkv;avi;mov;wmv;webm"),
+                preset("Audio", "ext:mp3;flac;wav;ogg;m4a"),
+                preset("Pictures", "ext:jpg;jpeg;png;gif;bmp;webp"),
+                preset(
+                    "Documents",
+                    "ext:pdf;doc;docx;xls;xlsx;ppt;pptx;odt;ods;txt;md",
+                ),
+                preset("Archives", "ext:zip;rar;7z;tar;gz"),
+                preset("Large files (>1GB)", "size:>1gb"),
+            ],
+            size_format: SizeFormat::default(),
+            watch_rules: Vec::new(),
+            external_tools: vec![
+                tool("Notepad", r#"notepad.exe "%path%""#),
+                tool("Terminal here", r#"cmd.exe /K cd /D "%dir%""#),
+                tool(
+                    "SHA-256 hash",
+                    r#"cmd.exe /K certutil -hashfile "%path%" SHA256"#,
+                ),
+            ],
+            everything_ipc: true,
+            index_mode: IndexMode::default(),
+            verify_interval_hours: 0,
+            log_level: LogLevel::default(),
+            trigram_index: false,
+            resolve_recycle_bin: true,
+            hide_metafiles: true,
+            journal_poll_interval_ms: 1000,
+            journal_batch_size: 1000,
+            autostart: false,
+            explorer_menu: false,
+            mft_read_queue_depth: 0,
+            low_io_priority: false,
+            persist_index: false,
+            ui_zoom: 1.0,
+            follow_system_dpi: true,
+            results_font_size: 0.0,
+            performance_overlay: false,
+            network_locations: Vec::new(),
+            network_rescan_minutes: 60,
+        }
+    }
+}
+
+impl Config {
+    pub fn journal_options(&self) -> JournalOptions {
+        JournalOptions {
+            poll_interval: Duration::from_millis(self.journal_poll_interval_ms),
+            batch_size: self.journal_batch_size,
+            persist: self.persist_index,
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        save(CONFIG_FILE_NAME, self)
+    }
+
+    /// Loads the config. If there is none yet, the default config is written to disk so it can
+    /// be edited. Falls back to the default config if that fails.
+    pub fn load() -> Self {
+        Self::try_load().unwrap_or_else(|e| {
+            error!("{:?}", e);
+            Self::default()
+        })
+    }
+
+    /// Like [Self::load], but returns the error instead of logging it, as logging is only set up
+    /// once the config was loaded.
+    pub fn try_load() -> Result<Self> {
+        match load(CONFIG_FILE_NAME)? {
+            Some(config) => Ok(config),
+            None => {
+                let config = Self::default();
+                save(CONFIG_FILE_NAME, &config)?;
+                Ok(config)
+            }
+        }
+    }
+}
+
+/// Everything the app remembers between runs. Unlike the config, this is written by the app
+/// itself and not meant to be edited by hand.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    pub history: SearchHistory,
+    /// Visible columns of the results table, in display order
+    pub columns: Vec<ColumnLayout>,
+    pub show_preview: bool,
+    pub group_by_folder: bool,
+    /// `None` if the results were not sorted
+    pub sort: Option<SortOrder>,
+    /// Search text when the app was closed, shown again unless another query is passed on start
+    pub last_query: String,
+    /// `None` until the app was closed once
+    pub window: Option<WindowGeometry>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        let column = |column, width| ColumnLayout { column, width };
+
+        Self {
+            history: SearchHistory::default(),
+            columns: vec![
+                column(Column::Name, 320.0),
+                column(Column::Path, 480.0),
+                column(Column::Size, 120.0),
+                column(Column::DateModified, 160.0),
+            ],
+            show_preview: false,
+            group_by_folder: false,
+            sort: None,
+            last_query: String::new(),
+            window: None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Column {
+    Name,
+    Path,
+    Size,
+    SizeOnDisk,
+    DateModified,
+    DateCreated,
+    DateAccessed,
+    Attributes,
+    Extension,
+    /// Queried from Windows for the visible rows, see [crate::owner]
+    Owner,
+}
+
+impl Column {
+    pub const ALL: [Column; 10] = [
+        Column::Name,
+        Column::Path,
+        Column::Size,
+        Column::SizeOnDisk,
+        Column::DateModified,
+        Column::DateCreated,
+        Column::DateAccessed,
+        Column::Attributes,
+        Column::Extension,
+        Column::Owner,
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Column::Name => "Name",
+            Column::Path => "Path",
+            Column::Size => "Size",
+            Column::SizeOnDisk => "Size on disk",
+            Column::DateModified => "Date modified",
+            Column::DateCreated => "Date created",
+            Column::DateAccessed => "Date accessed",
+            Column::Attributes => "Attributes",
+            Column::Extension => "Extension",
+            Column::Owner => "Owner",
+        }
+    }
+
+    /// Name of the column in the config and on the command line, e.g. `date_modified`
+    pub fn name(self) -> &'static str {
+        match self {
+            Column::Name => "name",
+            Column::Path => "path",
+            Column::Size => "size",
+            Column::SizeOnDisk => "size_on_disk",
+            Column::DateModified => "date_modified",
+            Column::DateCreated => "date_created",
+            Column::DateAccessed => "date_accessed",
+            Column::Attributes => "attributes",
+            Column::Extension => "extension",
+            Column::Owner => "owner",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct ColumnLayout {
+    pub column: Column,
+    /// Width in logical pixels
+    pub width: f32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SortOrder {
+    pub column: Column,
+    pub ascending: bool,
+}
+
+impl SortOrder {
+    /// Parses the name of a column like `size`, prefixed with `-` to sort in descending order.
+    pub fn parse(value: &str) -> Result<Self> {
+        let (name, ascending) = match value.strip_prefix('-') {
+            Some(name) => (name, false),
+            None => (value, true),
+        };
+        let column = Column::ALL
+            .into_iter()
+            .find(|column| column.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                let names = Column::ALL.map(Column::name).join(", ");
+                eyre!("Unknown column '{}', expected one of: {}", name, names)
+            })?;
+        Ok(Self { column, ascending })
+    }
+
+    /// Formats the order like [Self::parse] expects it.
+    pub fn to_arg(self) -> String {
+        match self.ascending {
+            true => self.column.name().to_string(),
+            false => format!("-{}", self.column.name()),
+        }
+    }
+}
+
+/// Position and size of the main window in physical pixels. If the window was maximized, these are
+/// its maximized bounds.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub maximized: bool,
+}
+
+impl State {
+    /// Loads the state, falling back to the default state if it is missing or broken.
+    pub fn load() -> Self {
+        load_or_default(STATE_FILE_NAME)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        save(STATE_FILE_NAME, self)
+    }
+}
+
+/// Measurements taken while the index is built for the first time. Written by the app itself like
+/// the state, but kept apart from it as the service measures as well. Deleting the file measures
+/// again.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Calibration {
+    /// Fastest number of reads in flight while reading the MFT, by volume letter
+    pub mft_read_queue_depth: BTreeMap<String, usize>,
+}
+
+impl Calibration {
+    /// Loads the measurements, falling back to none if they are missing or broken.
+    pub fn load() -> Self {
+        load_or_default(CALIBRATION_FILE_NAME)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        save(CALIBRATION_FILE_NAME, self)
+    }
+}
+
+/// Returns `%APPDATA%\reverything`, creating it if necessary.
+pub fn app_dir() -> Result<PathBuf> {
+    let dir = PathBuf::from(std::env::var_os("APPDATA").with_context(|| "APPDATA is not set")?)
+        .join(APP_DIR_NAME);
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create directory '{}'", dir.display()))?;
+
+    Ok(dir)
+}
+
+fn load_or_default<T: DeserializeOwned + Default>(file_name: &str) -> T {
+    match load(file_name) {
+        Ok(Some(value)) => value,
+        Ok(None) => T::default(),
+        Err(e) => {
+            error!("{:?}", e);
+            T::default()
+        }
+    }
+}
+
+/// Returns `None` if the file does not exist yet.
+fn load<T: DeserializeOwned>(file_name: &str) -> Result<Option<T>> {
+    let path = app_dir()?.join(file_name);
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read '{}'", path.display())),
+    };
+
+    toml::from_str(&content)
+        .map(Some)
+        .with_context(|| format!("Failed to parse '{}'", path.display()))
+}
+
+fn save<T: Serialize>(file_name: &str, value: &T) -> Result<()> {
+    let path = app_dir()?.join(file_name);
+    std::fs::write(&path, toml::to_string_pretty(value)?)
+        .with_context(|| format!("Failed to write '{}'", path.display()))
+}
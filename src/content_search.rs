@@ -0,0 +1,89 @@
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use eyre::{Context, Result};
+use rayon::prelude::*;
+use regex::Regex;
+use tracing::error;
+
+/// Larger files are skipped, they are rarely text and would take too long to search.
+pub const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+/// Only this many matching lines are reported per file.
+const MAX_MATCHES_PER_FILE: usize = 100;
+/// Lines are cut off after this many characters, minified files can have huge lines.
+const MAX_LINE_LENGTH: usize = 500;
+/// Files containing a null byte in this many bytes at the start are treated as binary.
+const BINARY_CHECK_SIZE: usize = 8 * 1024;
+
+#[derive(Debug)]
+pub struct ContentMatch {
+    pub path: String,
+    /// 1-based line number
+    pub line_number: usize,
+    pub line: String,
+}
+
+/// Builds the pattern to search for. Unless `regex` is set, `pattern` is matched literally.
+pub fn build_pattern(pattern: &str, regex: bool, ignore_case: bool) -> Result<Regex> {
+    let pattern = if regex {
+        pattern.to_string()
+    } else {
+        regex::escape(pattern)
+    };
+    let pattern = if ignore_case {
+        format!("(?i){}", pattern)
+    } else {
+        pattern
+    };
+
+    Regex::new(&pattern).with_context(|| format!("Invalid pattern '{}'", pattern))
+}
+
+/// Searches the content of the given `(path, size)` files in parallel and returns every matching
+/// line, grouped by file in the order of `files`. Binary, unreadable and files larger than
+/// [MAX_FILE_SIZE] are skipped. Returns `None` if `cancel` was set in the meantime.
+pub fn search_contents(
+    files: &[(String, u64)],
+    pattern: &Regex,
+    cancel: &AtomicBool,
+) -> Option<Vec<ContentMatch>> {
+    let matches = files
+        .par_iter()
+        .filter(|(_, size)| *size > 0 && *size <= MAX_FILE_SIZE)
+        .flat_map_iter(|(path, _)| {
+            if cancel.load(Ordering::Relaxed) {
+                return Vec::new();
+            }
+
+            search_file(path, pattern).unwrap_or_else(|e| {
+                error!("{:?}", e);
+                Vec::new()
+            })
+        })
+        .collect();
+
+    (!cancel.load(Ordering::Relaxed)).then_some(matches)
+}
+
+fn search_file(path: &str, pattern: &Regex) -> Result<Vec<ContentMatch>> {
+    let mut content = Vec::new();
+    std::fs::File::open(path)
+        .and_then(|file| file.take(MAX_FILE_SIZE).read_to_end(&mut content))
+        .with_context(|| format!("Failed to read '{}'", path))?;
+
+    if content[..content.len().min(BINARY_CHECK_SIZE)].contains(&0) {
+        return Ok(Vec::new());
+    }
+
+    Ok(String::from_utf8_lossy(&content)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| pattern.is_match(line))
+        .take(MAX_MATCHES_PER_FILE)
+        .map(|(i, line)| ContentMatch {
+            path: path.to_string(),
+            line_number: i + 1,
+            line: line.trim().chars().take(MAX_LINE_LENGTH).collect(),
+        })
+        .collect())
+}
@@ -0,0 +1,108 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+use tracing::error;
+
+use crate::checksum::{self, Algorithm, Hasher};
+
+/// Number of bytes hashed in the first pass. Most files which only have the same size already
+/// differ in here, so they don't have to be read completely.
+const PARTIAL_HASH_SIZE: u64 = 64 * 1024;
+
+/// Files which all have the same content.
+#[derive(Debug)]
+pub struct DuplicateSet {
+    pub size: u64,
+    pub paths: Vec<String>,
+}
+
+impl DuplicateSet {
+    /// Space which could be freed by only keeping one of the files
+    pub fn wasted_size(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+/// Finds files with identical content among the given `(path, size)` pairs. Files are grouped by
+/// size first, then by a hash of their first bytes and only then by a hash of their full content.
+/// Empty and unreadable files are skipped. Returns `None` if `cancel` was set in the meantime.
+///
+/// The sets are sorted by the space they waste, largest first.
+pub fn find_duplicates(
+    files: Vec<(String, u64)>,
+    cancel: &AtomicBool,
+) -> Option<Vec<DuplicateSet>> {
+    let mut by_size = FxHashMap::<u64, Vec<String>>::default();
+    for (path, size) in files.into_iter().filter(|(_, size)| *size > 0) {
+        by_size.entry(size).or_default().push(path);
+    }
+
+    let candidates = by_size
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|(size, paths)| DuplicateSet { size, paths })
+        .collect();
+
+    // Small files were already hashed completely in the first pass
+    let (mut sets, partial): (Vec<_>, Vec<_>) =
+        split_by_hash(candidates, Some(PARTIAL_HASH_SIZE), cancel)?
+            .into_iter()
+            .partition(|set| set.size <= PARTIAL_HASH_SIZE);
+    sets.extend(split_by_hash(partial, None, cancel)?);
+
+    for set in &mut sets {
+        set.paths.sort();
+    }
+    sets.sort_by_key(|set| std::cmp::Reverse(set.wasted_size()));
+    Some(sets)
+}
+
+/// Splits every set into sets of files with the same hash, dropping files which are unique.
+fn split_by_hash(
+    sets: Vec<DuplicateSet>,
+    limit: Option<u64>,
+    cancel: &AtomicBool,
+) -> Option<Vec<DuplicateSet>> {
+    // Hashing per file instead of per set keeps all threads busy even if one set is huge
+    let hashes = sets
+        .par_iter()
+        .enumerate()
+        .flat_map_iter(|(i, set)| set.paths.iter().map(move |path| (i, path)))
+        .filter_map(|(i, path)| {
+            if cancel.load(Ordering::Relaxed) {
+                return None;
+            }
+
+            let hash = Hasher::new(Algorithm::Sha256)
+                .and_then(|hasher| checksum::hash_file(path, hasher, limit));
+            match hash {
+                Ok(hash) => Some(((i, hash), path.clone())),
+                Err(e) => {
+                    error!("{:?}", e);
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if cancel.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    let mut by_hash = FxHashMap::<(usize, Vec<u8>), Vec<String>>::default();
+    for (key, path) in hashes {
+        by_hash.entry(key).or_default().push(path);
+    }
+
+    Some(
+        by_hash
+            .into_iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .map(|((i, _), paths)| DuplicateSet {
+                size: sets[i].size,
+                paths,
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,439 @@
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::sync::{Arc, Mutex};
+
+use eyre::{eyre, Context, Result};
+use tracing::error;
+use windows::core::{w, PCWSTR};
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::System::DataExchange::COPYDATASTRUCT;
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::WindowsAndMessaging::{
+    ChangeWindowMessageFilterEx, CreateWindowExW, DefWindowProcW, DispatchMessageW, FindWindowW,
+    GetMessageW, RegisterClassW, SendMessageW, TranslateMessage, HMENU, MSG, MSGFLT_ALLOW,
+    WINDOW_EX_STYLE, WM_COPYDATA, WM_USER, WNDCLASSW, WS_POPUP,
+};
+
+use crate::config::Column;
+use crate::ntfs::index::{FileInfo, NtfsVolumeIndex};
+use crate::query::SearchOptions;
+use crate::search;
+
+// Constants from Everything_IPC.h of the Everything SDK, https://www.voidtools.com/support/everything/sdk/
+/// Clients look for a window of this class to find Everything
+const EVERYTHING_IPC_WNDCLASS: PCWSTR = w!("EVERYTHING_TASKBAR_NOTIFICATION");
+
+const EVERYTHING_IPC_GET_MAJOR_VERSION: usize = 0;
+const EVERYTHING_IPC_GET_MINOR_VERSION: usize = 1;
+const EVERYTHING_IPC_GET_REVISION: usize = 2;
+const EVERYTHING_IPC_GET_BUILD_NUMBER: usize = 3;
+const EVERYTHING_IPC_GET_TARGET_MACHINE: usize = 5;
+const EVERYTHING_IPC_IS_DB_LOADED: usize = 401;
+const EVERYTHING_IPC_IS_DB_BUSY: usize = 402;
+
+const EVERYTHING_IPC_COPYDATAQUERYW: usize = 2;
+const EVERYTHING_IPC_COPYDATA_QUERY2W: usize = 18;
+
+const EVERYTHING_IPC_MATCHCASE: u32 = 0x1;
+const EVERYTHING_IPC_MATCHWHOLEWORD: u32 = 0x2;
+const EVERYTHING_IPC_MATCHPATH: u32 = 0x4;
+
+const EVERYTHING_IPC_FOLDER: u32 = 0x1;
+
+const EVERYTHING_IPC_QUERY2_REQUEST_NAME: u32 = 0x1;
+const EVERYTHING_IPC_QUERY2_REQUEST_PATH: u32 = 0x2;
+const EVERYTHING_IPC_QUERY2_REQUEST_FULL_PATH_AND_NAME: u32 = 0x4;
+const EVERYTHING_IPC_QUERY2_REQUEST_EXTENSION: u32 = 0x8;
+const EVERYTHING_IPC_QUERY2_REQUEST_SIZE: u32 = 0x10;
+const EVERYTHING_IPC_QUERY2_REQUEST_DATE_CREATED: u32 = 0x20;
+const EVERYTHING_IPC_QUERY2_REQUEST_DATE_MODIFIED: u32 = 0x40;
+const EVERYTHING_IPC_QUERY2_REQUEST_DATE_ACCESSED: u32 = 0x80;
+const EVERYTHING_IPC_QUERY2_REQUEST_ATTRIBUTES: u32 = 0x100;
+/// Everything else, like run counts or highlighted names, is not available in the index
+const SUPPORTED_REQUEST_FLAGS: u32 = 0x1FF;
+
+const EVERYTHING_IPC_SORT_NAME_ASCENDING: u32 = 1;
+
+/// The version reported to clients. Clients only use the version to check which features are
+/// available, and everything up to 1.4 is supported.
+const REPORTED_VERSION: (usize, usize, usize, usize) = (1, 4, 1, 0);
+
+thread_local! {
+    /// The index which is queried by the window procedure
+    static INDEX: RefCell<Option<Arc<Mutex<NtfsVolumeIndex>>>> = const { RefCell::new(None) };
+}
+
+/// Answers queries sent through the IPC protocol of Everything, so tools built with the
+/// Everything SDK (like `es.exe`) can search the index. Does nothing if Everything itself is
+/// running, because clients can only talk to one of both.
+pub fn start(index: Arc<Mutex<NtfsVolumeIndex>>) {
+    std::thread::spawn(move || {
+        if let Err(e) = unsafe { run(index) } {
+            error!("{:?}", e);
+        }
+    });
+}
+
+unsafe fn run(index: Arc<Mutex<NtfsVolumeIndex>>) -> Result<()> {
+    if FindWindowW(EVERYTHING_IPC_WNDCLASS, PCWSTR::null()).is_ok() {
+        return Err(eyre!(
+            "Everything is running, not answering queries of Everything SDK clients"
+        ));
+    }
+
+    INDEX.set(Some(index));
+    create_window().with_context(|| "Failed to create the Everything IPC window")?;
+
+    let mut msg = MSG::default();
+    while GetMessageW(&mut msg, HWND::default(), 0, 0).as_bool() {
+        let _ = TranslateMessage(&msg);
+        DispatchMessageW(&msg);
+    }
+
+    Ok(())
+}
+
+/// Clients find the window using `FindWindow`, so unlike a message-only window this has to be a
+/// (hidden) top level window.
+///
+/// The app usually runs elevated, which makes Windows drop messages from clients with a lower
+/// integrity level, so the messages of the protocol are allowed explicitly.
+unsafe fn create_window() -> Result<HWND> {
+    let instance = GetModuleHandleW(None)?;
+    let class = WNDCLASSW {
+        lpfnWndProc: Some(window_proc),
+        hInstance: instance.into(),
+        lpszClassName: EVERYTHING_IPC_WNDCLASS,
+        ..Default::default()
+    };
+    RegisterClassW(&class);
+
+    let hwnd = CreateWindowExW(
+        WINDOW_EX_STYLE::default(),
+        EVERYTHING_IPC_WNDCLASS,
+        PCWSTR::null(),
+        WS_POPUP,
+        0,
+        0,
+        0,
+        0,
+        HWND::default(),
+        HMENU::default(),
+        instance,
+        None,
+    )?;
+    for msg in [WM_COPYDATA, WM_USER] {
+        ChangeWindowMessageFilterEx(hwnd, msg, MSGFLT_ALLOW, None)
+            .with_context(|| format!("Failed to allow message {:#x}", msg))?;
+    }
+
+    Ok(hwnd)
+}
+
+unsafe extern "system" fn window_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        WM_USER => LRESULT(match wparam.0 {
+            EVERYTHING_IPC_GET_MAJOR_VERSION => REPORTED_VERSION.0,
+            EVERYTHING_IPC_GET_MINOR_VERSION => REPORTED_VERSION.1,
+            EVERYTHING_IPC_GET_REVISION => REPORTED_VERSION.2,
+            EVERYTHING_IPC_GET_BUILD_NUMBER => REPORTED_VERSION.3,
+            // 2 is x64, which is the only supported target
+            EVERYTHING_IPC_GET_TARGET_MACHINE => 2,
+            EVERYTHING_IPC_IS_DB_LOADED => 1,
+            EVERYTHING_IPC_IS_DB_BUSY => 0,
+            _ => 0,
+        } as isize),
+        WM_COPYDATA => {
+            let data = &*(lparam.0 as *const COPYDATASTRUCT);
+            let bytes = std::slice::from_raw_parts(data.lpData as *const u8, data.cbData as usize);
+
+            let res = match data.dwData {
+                EVERYTHING_IPC_COPYDATAQUERYW => Query::parse(bytes, false),
+                EVERYTHING_IPC_COPYDATA_QUERY2W => Query::parse(bytes, true),
+                // The ANSI variants are not supported, every client built in the last decade uses
+                // the wide ones
+                _ => return LRESULT(0),
+            }
+            .and_then(|query| query.answer(hwnd));
+
+            match res {
+                Ok(()) => LRESULT(1),
+                Err(e) => {
+                    error!("{:?}", e);
+                    LRESULT(0)
+                }
+            }
+        }
+        _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+}
+
+/// `EVERYTHING_IPC_QUERYW` or `EVERYTHING_IPC_QUERY2`, depending on `version2`.
+struct Query {
+    version2: bool,
+    /// Window handles are always 32 bit values
+    reply_hwnd: u32,
+    reply_copydata_message: u32,
+    search_flags: u32,
+    offset: u32,
+    max_results: u32,
+    request_flags: u32,
+    sort_type: u32,
+    search: String,
+}
+
+impl Query {
+    fn parse(bytes: &[u8], version2: bool) -> Result<Self> {
+        let header_len = if version2 { 7 } else { 5 };
+        let dword = |i: usize| {
+            bytes
+                .get(i * 4..i * 4 + 4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                .ok_or_else(|| eyre!("Everything IPC query is too short"))
+        };
+
+        // The search string is null terminated
+        let search = bytes
+            .get(header_len * 4..)
+            .unwrap_or_default()
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0)
+            .collect::<Vec<_>>();
+
+        Ok(Self {
+            version2,
+            reply_hwnd: dword(0)?,
+            reply_copydata_message: dword(1)?,
+            search_flags: dword(2)?,
+            offset: dword(3)?,
+            max_results: dword(4)?,
+            request_flags: if version2 { dword(5)? } else { 0 },
+            sort_type: if version2 {
+                dword(6)?
+            } else {
+                EVERYTHING_IPC_SORT_NAME_ASCENDING
+            },
+            search: String::from_utf16_lossy(&search),
+        })
+    }
+
+    /// Runs the query and sends the results to the reply window of the client.
+    fn answer(&self, hwnd: HWND) -> Result<()> {
+        let index = INDEX
+            .with_borrow(|index| index.clone())
+            .ok_or_else(|| eyre!("Everything IPC index not set"))?;
+
+        let options = SearchOptions {
+            whole_word: self.search_flags & EVERYTHING_IPC_MATCHWHOLEWORD != 0,
+            match_path: self.search_flags & EVERYTHING_IPC_MATCHPATH != 0,
+            // Everything ignores case by default
+            fold: self.search_flags & EVERYTHING_IPC_MATCHCASE == 0,
+            ..Default::default()
+        };
+        // Clients can't handle errors, so invalid queries simply don't match anything
+        let mut files = search::filter(&index, &self.search, options).unwrap_or_default();
+
+        let (column, ascending, sort_type) = sort_column(self.sort_type);
+        search::sort(&index, &mut files, column, ascending);
+        let index = index.lock().unwrap();
+
+        let visible = files
+            .iter()
+            .skip(self.offset as usize)
+            .take(self.max_results as usize)
+            .filter_map(|&i| index.find_by_index(i))
+            .collect::<Vec<_>>();
+
+        let mut reply = if self.version2 {
+            self.list2(&index, files.len(), &visible, sort_type)
+        } else {
+            self.list(&index, &files, &visible)
+        };
+
+        let data = COPYDATASTRUCT {
+            dwData: self.reply_copydata_message as usize,
+            cbData: reply.len() as u32,
+            lpData: reply.as_mut_ptr() as *mut c_void,
+        };
+        unsafe {
+            SendMessageW(
+                HWND(self.reply_hwnd as usize as *mut c_void),
+                WM_COPYDATA,
+                WPARAM(hwnd.0 as usize),
+                LPARAM(&data as *const _ as isize),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Builds an `EVERYTHING_IPC_LISTW`. Items store offsets from the start of the list to their
+    /// name and path, which follow after all items.
+    fn list(&self, index: &NtfsVolumeIndex, files: &[u64], visible: &[&FileInfo]) -> Vec<u8> {
+        let total_folders = files
+            .iter()
+            .filter_map(|&i| index.find_by_index(i))
+            .filter(|info| info.is_directory())
+            .count();
+        let visible_folders = visible.iter().filter(|info| info.is_directory()).count();
+
+        let mut list = Vec::new();
+        for value in [
+            total_folders,
+            files.len() - total_folders,
+            files.len(),
+            visible_folders,
+            visible.len() - visible_folders,
+            visible.len(),
+            self.offset as usize,
+        ] {
+            push_dword(&mut list, value as u32);
+        }
+
+        let mut strings = Vec::new();
+        let strings_start = list.len() + visible.len() * 3 * 4;
+        for info in visible {
+            let (path, name) = split_path(index, info);
+
+            push_dword(
+                &mut list,
+                if info.is_directory() {
+                    EVERYTHING_IPC_FOLDER
+                } else {
+                    0
+                },
+            );
+            push_dword(&mut list, (strings_start + strings.len()) as u32);
+            push_string(&mut strings, name);
+            push_dword(&mut list, (strings_start + strings.len()) as u32);
+            push_string(&mut strings, &path);
+        }
+
+        list.extend(strings);
+        list
+    }
+
+    /// Builds an `EVERYTHING_IPC_LIST2`. Items store an offset from the start of the list to their
+    /// data, which contains the requested properties in the order of their flags.
+    fn list2(
+        &self,
+        index: &NtfsVolumeIndex,
+        total: usize,
+        visible: &[&FileInfo],
+        sort_type: u32,
+    ) -> Vec<u8> {
+        let request_flags = self.request_flags & SUPPORTED_REQUEST_FLAGS;
+
+        let mut list = Vec::new();
+        for value in [
+            total as u32,
+            visible.len() as u32,
+            self.offset,
+            request_flags,
+            sort_type,
+        ] {
+            push_dword(&mut list, value);
+        }
+
+        let mut data = Vec::new();
+        let data_start = list.len() + visible.len() * 2 * 4;
+        for info in visible {
+            push_dword(
+                &mut list,
+                if info.is_directory() {
+                    EVERYTHING_IPC_FOLDER
+                } else {
+                    0
+                },
+            );
+            push_dword(&mut list, (data_start + data.len()) as u32);
+
+            let (path, name) = split_path(index, info);
+            let times = info.times();
+            let flag = |flag: u32| request_flags & flag != 0;
+            if flag(EVERYTHING_IPC_QUERY2_REQUEST_NAME) {
+                push_counted_string(&mut data, name);
+            }
+            if flag(EVERYTHING_IPC_QUERY2_REQUEST_PATH) {
+                push_counted_string(&mut data, &path);
+            }
+            if flag(EVERYTHING_IPC_QUERY2_REQUEST_FULL_PATH_AND_NAME) {
+                push_counted_string(&mut data, &format!("{}\\{}", path, name));
+            }
+            if flag(EVERYTHING_IPC_QUERY2_REQUEST_EXTENSION) {
+                push_counted_string(&mut data, info.extension().unwrap_or_default());
+            }
+            if flag(EVERYTHING_IPC_QUERY2_REQUEST_SIZE) {
+                data.extend(info.size().to_le_bytes());
+            }
+            if flag(EVERYTHING_IPC_QUERY2_REQUEST_DATE_CREATED) {
+                data.extend(times.created.to_le_bytes());
+            }
+            if flag(EVERYTHING_IPC_QUERY2_REQUEST_DATE_MODIFIED) {
+                data.extend(times.modified.to_le_bytes());
+            }
+            if flag(EVERYTHING_IPC_QUERY2_REQUEST_DATE_ACCESSED) {
+                data.extend(times.accessed.to_le_bytes());
+            }
+            if flag(EVERYTHING_IPC_QUERY2_REQUEST_ATTRIBUTES) {
+                push_dword(&mut data, info.attributes());
+            }
+        }
+
+        list.extend(data);
+        list
+    }
+}
+
+/// Maps an `EVERYTHING_IPC_SORT_*` value to a column. Sort orders which are not supported fall back
+/// to sorting by name, which is reported back to the client.
+fn sort_column(sort_type: u32) -> (Column, bool, u32) {
+    let column = match sort_type {
+        1 | 2 => Column::Name,
+        3 | 4 => Column::Path,
+        5 | 6 => Column::Size,
+        7 | 8 => Column::Extension,
+        11 | 12 => Column::DateCreated,
+        13 | 14 => Column::DateModified,
+        15 | 16 => Column::Attributes,
+        23 | 24 => Column::DateAccessed,
+        _ => return (Column::Name, true, EVERYTHING_IPC_SORT_NAME_ASCENDING),
+    };
+
+    // Odd values are ascending
+    (column, sort_type % 2 == 1, sort_type)
+}
+
+/// Returns the path of the parent directory and the name of the file, which Everything reports
+/// separately.
+fn split_path<'a>(index: &NtfsVolumeIndex, info: &'a FileInfo) -> (String, &'a str) {
+    let full_path = index.compute_full_path(info);
+    let parent = full_path
+        .rsplit_once('\\')
+        .map_or(full_path.as_str(), |(parent, _)| parent);
+
+    (parent.to_string(), info.name.as_str())
+}
+
+fn push_dword(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend(value.to_le_bytes());
+}
+
+/// Null terminated UTF-16
+fn push_string(buffer: &mut Vec<u8>, value: &str) {
+    for c in value.encode_utf16().chain([0]) {
+        buffer.extend(c.to_le_bytes());
+    }
+}
+
+/// Length in characters without the null terminator, followed by the null terminated string
+fn push_counted_string(buffer: &mut Vec<u8>, value: &str) {
+    push_dword(buffer, value.encode_utf16().count() as u32);
+    push_string(buffer, value);
+}
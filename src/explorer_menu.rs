@@ -0,0 +1,104 @@
+//! "Search with reverything" in the context menu of folders and drives in Explorer. It starts the
+//! UI, or shows the running one, with the search limited to the folder.
+
+use eyre::{eyre, Result};
+use windows::core::{HSTRING, PCWSTR};
+use windows::Win32::Foundation::{ERROR_FILE_NOT_FOUND, ERROR_SUCCESS, WIN32_ERROR};
+use windows::Win32::System::Registry::{
+    RegCloseKey, RegCreateKeyExW, RegDeleteTreeW, RegOpenKeyExW, RegSetValueExW, HKEY,
+    HKEY_CURRENT_USER, KEY_READ, KEY_WRITE, REG_OPTION_NON_VOLATILE, REG_SZ,
+};
+
+/// Keys below `HKEY_CURRENT_USER` of the menu entries for folders, the background of an open
+/// folder and drives. Registering them for the current user doesn't need elevation.
+const MENU_KEYS: [&str; 3] = [
+    r"Software\Classes\Directory\shell\reverything",
+    r"Software\Classes\Directory\Background\shell\reverything",
+    r"Software\Classes\Drive\shell\reverything",
+];
+const MENU_TEXT: &str = "Search with reverything";
+
+/// Adds or removes the menu entries. Adding them again updates the path of the executable in case
+/// it was moved.
+pub fn set_enabled(enabled: bool) -> Result<()> {
+    if enabled {
+        let exe = std::env::current_exe()?;
+        // `%V` is the folder, see `ui --in` in `src/cli.rs`
+        let command = format!("\"{}\" ui --in \"%V\"", exe.display());
+        let icon = format!("\"{}\",0", exe.display());
+        for key in MENU_KEYS {
+            set_value(key, "", MENU_TEXT)?;
+            set_value(key, "Icon", &icon)?;
+            set_value(&format!(r"{}\command", key), "", &command)?;
+        }
+    } else {
+        for key in MENU_KEYS {
+            match unsafe { RegDeleteTreeW(HKEY_CURRENT_USER, &HSTRING::from(key)) } {
+                ERROR_SUCCESS | ERROR_FILE_NOT_FOUND => {}
+                e => return Err(registry_error(e, "delete", key)),
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    let mut key = HKEY::default();
+    let res = unsafe {
+        RegOpenKeyExW(
+            HKEY_CURRENT_USER,
+            &HSTRING::from(MENU_KEYS[0]),
+            0,
+            KEY_READ,
+            &mut key,
+        )
+    };
+    if res != ERROR_SUCCESS {
+        return false;
+    }
+    let _ = unsafe { RegCloseKey(key) };
+    true
+}
+
+/// Sets the string value `name` of the key at `path`, creating the key if needed. An empty name
+/// sets the default value.
+fn set_value(path: &str, name: &str, value: &str) -> Result<()> {
+    let mut key = HKEY::default();
+    let res = unsafe {
+        RegCreateKeyExW(
+            HKEY_CURRENT_USER,
+            &HSTRING::from(path),
+            0,
+            PCWSTR::null(),
+            REG_OPTION_NON_VOLATILE,
+            KEY_WRITE,
+            None,
+            &mut key,
+            None,
+        )
+    };
+    if res != ERROR_SUCCESS {
+        return Err(registry_error(res, "create", path));
+    }
+
+    let data = value
+        .encode_utf16()
+        .chain([0])
+        .flat_map(u16::to_le_bytes)
+        .collect::<Vec<_>>();
+    let res = unsafe { RegSetValueExW(key, &HSTRING::from(name), 0, REG_SZ, Some(&data)) };
+    let _ = unsafe { RegCloseKey(key) };
+    if res != ERROR_SUCCESS {
+        return Err(registry_error(res, "write", path));
+    }
+    Ok(())
+}
+
+fn registry_error(error: WIN32_ERROR, action: &str, path: &str) -> eyre::Report {
+    eyre!(
+        "Failed to {} registry key HKCU\\{}: {}",
+        action,
+        path,
+        windows::core::Error::from(error.to_hresult())
+    )
+}
@@ -0,0 +1,140 @@
+//! Runs the external tools from the "Open with" menu. Their command lines are configured in
+//! [Config::external_tools](crate::config::Config::external_tools) and can refer to the selected
+//! file through placeholders:
+//! - `%path%` is the full path of the file
+//! - `%dir%` is the directory containing it, or the directory itself if a directory was selected
+//! - `%name%` is the file name
+
+use std::os::windows::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+
+use eyre::{eyre, Context, Result};
+use tracing::info;
+
+use crate::config::ExternalTool;
+
+/// Starts `tool` for the file at `path` without waiting for it to exit.
+pub fn run(tool: &ExternalTool, path: &str) -> Result<()> {
+    let dir = directory_of(path);
+    let name = path.rsplit_once('\\').map_or(path, |(_, name)| name);
+    // The command is split before the placeholders are replaced, so that a file name can't add
+    // arguments or commands of its own
+    let mut args = split_args(&tool.command)
+        .into_iter()
+        .map(|arg| expand(&arg, path, dir, name));
+    let Some((program, _)) = args.next().filter(|(program, _)| !program.is_empty()) else {
+        return Err(eyre!("The command of '{}' is empty", tool.name));
+    };
+
+    let mut command = Command::new(&program);
+    if is_cmd(&program) {
+        // cmd.exe parses its command line on its own, so the arguments are passed as written
+        for (arg, expanded) in args {
+            command.raw_arg(quote_for_cmd(&arg, expanded)?);
+        }
+    } else {
+        command.args(args.map(|(arg, _)| arg));
+    }
+
+    info!("Running {}: {:?}", tool.name, command);
+    command
+        .current_dir(dir)
+        .spawn()
+        .with_context(|| format!("Failed to run '{}'", tool.name))?;
+    Ok(())
+}
+
+/// Splits `command` into its arguments like the C runtime does: arguments are separated by
+/// whitespace unless it is quoted, and backslashes only escape quotes.
+fn split_args(command: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut in_arg = false;
+    let mut quoted = false;
+    let mut backslashes = 0;
+
+    for c in command.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+                in_arg = true;
+                continue;
+            }
+            '"' => {
+                arg.extend(std::iter::repeat_n('\\', backslashes / 2));
+                if backslashes % 2 == 1 {
+                    arg.push('"');
+                } else {
+                    quoted = !quoted;
+                }
+                in_arg = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                arg.extend(std::iter::repeat_n('\\', backslashes));
+                if in_arg {
+                    args.push(std::mem::take(&mut arg));
+                }
+                in_arg = false;
+            }
+            c => {
+                arg.extend(std::iter::repeat_n('\\', backslashes));
+                arg.push(c);
+                in_arg = true;
+            }
+        }
+        backslashes = 0;
+    }
+    arg.extend(std::iter::repeat_n('\\', backslashes));
+    if in_arg {
+        args.push(arg);
+    }
+    args
+}
+
+/// Replaces the placeholders in a single argument. Returns whether any were replaced.
+fn expand(arg: &str, path: &str, dir: &str, name: &str) -> (String, bool) {
+    let replaced = ["%path%", "%dir%", "%name%"]
+        .iter()
+        .any(|placeholder| arg.contains(placeholder));
+    let expanded = arg
+        .replace("%path%", path)
+        .replace("%dir%", dir)
+        .replace("%name%", name);
+    (expanded, replaced)
+}
+
+fn is_cmd(program: &str) -> bool {
+    Path::new(program)
+        .file_stem()
+        .is_some_and(|stem| stem.eq_ignore_ascii_case("cmd"))
+}
+
+/// Quotes an argument for cmd.exe. Inside quotes, cmd.exe takes `&`, `|`, `<`, `>` and `^`
+/// literally, but still expands variables like `%TEMP%`, which can't be escaped there. Arguments
+/// holding a path are therefore always quoted and rejected if they contain a `%`.
+fn quote_for_cmd(arg: &str, expanded: bool) -> Result<String> {
+    if expanded && arg.contains(['%', '"']) {
+        return Err(eyre!("'{}' can't be passed to cmd.exe safely", arg));
+    }
+    let needs_quotes =
+        arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || "&|<>^()".contains(c));
+    if expanded || needs_quotes {
+        Ok(format!("\"{}\"", arg))
+    } else {
+        Ok(arg.to_string())
+    }
+}
+
+/// Directory in which the tool runs, `path` itself if it is a directory.
+fn directory_of(path: &str) -> &str {
+    if Path::new(path).is_dir() {
+        return path;
+    }
+    match path.rsplit_once('\\') {
+        // Keep the backslash of volume roots like `C:\`
+        Some((parent, _)) if parent.ends_with(':') => &path[..parent.len() + 1],
+        Some((parent, _)) => parent,
+        None => path,
+    }
+}
@@ -0,0 +1,248 @@
+//! C interface of `reverything.dll`, declared in `include/reverything.h`. All strings are UTF-8.
+//! Functions which can fail return null and store an error message which can be retrieved with
+//! [`reverything_last_error`].
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::sync::{Arc, Mutex};
+
+use eyre::eyre;
+
+use crate::config::IndexMode;
+use crate::ntfs::index::{ChangeKind, NtfsVolumeIndex};
+use crate::query::SearchOptions;
+use crate::{search, JournalOptions};
+
+pub const REVERYTHING_WHOLE_WORD: u32 = 0x1;
+pub const REVERYTHING_MATCH_PATH: u32 = 0x2;
+pub const REVERYTHING_IGNORE_CASE: u32 = 0x4;
+
+pub const REVERYTHING_CREATED: u32 = 0;
+pub const REVERYTHING_RENAMED: u32 = 1;
+pub const REVERYTHING_DELETED: u32 = 2;
+pub const REVERYTHING_MOVED: u32 = 3;
+
+/// Called on the journal thread with the kind and path of a changed file. The path is only valid
+/// during the call.
+pub type ReverythingChangeCallback =
+    extern "C" fn(user_data: *mut c_void, kind: u32, path: *const c_char);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Index of the first volume, which is kept up to date by following the journal.
+pub struct ReverythingIndex {
+    index: Arc<Mutex<NtfsVolumeIndex>>,
+    subscribers: Arc<Mutex<Subscribers>>,
+}
+
+#[derive(Default)]
+struct Subscribers {
+    list: Vec<Subscriber>,
+    next_id: u64,
+}
+
+struct Subscriber {
+    id: u64,
+    callback: ReverythingChangeCallback,
+    user_data: *mut c_void,
+}
+
+// The caller promises that `user_data` can be used from the journal thread by subscribing
+unsafe impl Send for Subscriber {}
+
+/// Results of a search, ordered by MFT index.
+pub struct ReverythingResults {
+    files: Vec<(CString, u64, bool)>,
+}
+
+/// Builds the index of the first volume. Requires the process to be elevated. Returns null on
+/// failure.
+#[no_mangle]
+pub extern "C" fn reverything_index_create() -> *mut ReverythingIndex {
+    let subscribers = Arc::new(Mutex::new(Subscribers::default()));
+    let index = crate::build_index(IndexMode::Auto, JournalOptions::default(), {
+        let subscribers = subscribers.clone();
+        move |_, changes| {
+            let subscribers = subscribers.lock().unwrap();
+            if subscribers.list.is_empty() {
+                return;
+            }
+
+            for change in changes {
+                let kind = match change.kind {
+                    ChangeKind::Created => REVERYTHING_CREATED,
+                    ChangeKind::Renamed => REVERYTHING_RENAMED,
+                    ChangeKind::Deleted => REVERYTHING_DELETED,
+                    ChangeKind::Moved => REVERYTHING_MOVED,
+                };
+                let Ok(path) = CString::new(change.path.as_str()) else {
+                    continue;
+                };
+                for subscriber in &subscribers.list {
+                    (subscriber.callback)(subscriber.user_data, kind, path.as_ptr());
+                }
+            }
+        }
+    });
+
+    match index {
+        Ok(index) => Box::into_raw(Box::new(ReverythingIndex { index, subscribers })),
+        Err(e) => fail(e),
+    }
+}
+
+/// Destroys an index created by [`reverything_index_create`], waiting for the journal thread to
+/// stop. No callbacks are called once this returns, and another index can be created afterwards.
+///
+/// # Safety
+/// `index` has to be a pointer returned by [`reverything_index_create`] or null.
+#[no_mangle]
+pub unsafe extern "C" fn reverything_index_destroy(index: *mut ReverythingIndex) {
+    if index.is_null() {
+        return;
+    }
+
+    let index = Box::from_raw(index);
+    // The journal thread may still apply a batch until it notices the shutdown
+    index.subscribers.lock().unwrap().list.clear();
+    crate::shutdown(&index.index);
+}
+
+/// Searches the index using the same syntax as the search box of the app. `flags` is a
+/// combination of `REVERYTHING_WHOLE_WORD`, `REVERYTHING_MATCH_PATH` and
+/// `REVERYTHING_IGNORE_CASE`. Returns null if the query is invalid.
+///
+/// # Safety
+/// `index` has to be a valid index and `query` a null terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn reverything_search(
+    index: *const ReverythingIndex,
+    query: *const c_char,
+    flags: u32,
+) -> *mut ReverythingResults {
+    let Ok(query) = CStr::from_ptr(query).to_str() else {
+        return fail(eyre!("Query is not valid UTF-8"));
+    };
+    let options = SearchOptions {
+        whole_word: flags & REVERYTHING_WHOLE_WORD != 0,
+        match_path: flags & REVERYTHING_MATCH_PATH != 0,
+        fold: flags & REVERYTHING_IGNORE_CASE != 0,
+        ..Default::default()
+    };
+
+    let index = &(*index).index;
+    let files = match search::filter(index, query, options) {
+        Ok(files) => files,
+        Err(e) => return fail(e),
+    };
+    let index = index.lock().unwrap();
+
+    let files = files
+        .into_iter()
+        .filter_map(|i| index.find_by_index(i))
+        .filter_map(|info| {
+            let path = CString::new(index.compute_full_path(info)).ok()?;
+            Some((path, info.size(), info.is_directory()))
+        })
+        .collect();
+    Box::into_raw(Box::new(ReverythingResults { files }))
+}
+
+/// # Safety
+/// `results` has to be a pointer returned by [`reverything_search`].
+#[no_mangle]
+pub unsafe extern "C" fn reverything_results_count(results: *const ReverythingResults) -> usize {
+    (*results).files.len()
+}
+
+/// Returns the full path of the result at `i`, which stays valid until the results are freed.
+///
+/// # Safety
+/// `results` has to be a pointer returned by [`reverything_search`] and `i` less than its count.
+#[no_mangle]
+pub unsafe extern "C" fn reverything_results_path(
+    results: *const ReverythingResults,
+    i: usize,
+) -> *const c_char {
+    (&(*results).files)[i].0.as_ptr()
+}
+
+/// # Safety
+/// `results` has to be a pointer returned by [`reverything_search`] and `i` less than its count.
+#[no_mangle]
+pub unsafe extern "C" fn reverything_results_size(
+    results: *const ReverythingResults,
+    i: usize,
+) -> u64 {
+    (&(*results).files)[i].1
+}
+
+/// # Safety
+/// `results` has to be a pointer returned by [`reverything_search`] and `i` less than its count.
+#[no_mangle]
+pub unsafe extern "C" fn reverything_results_is_directory(
+    results: *const ReverythingResults,
+    i: usize,
+) -> bool {
+    (&(*results).files)[i].2
+}
+
+/// # Safety
+/// `results` has to be a pointer returned by [`reverything_search`] or null.
+#[no_mangle]
+pub unsafe extern "C" fn reverything_results_free(results: *mut ReverythingResults) {
+    if !results.is_null() {
+        drop(Box::from_raw(results));
+    }
+}
+
+/// Registers a callback which is called for every file that is created, renamed or deleted.
+/// Returns an id for [`reverything_unsubscribe`].
+///
+/// # Safety
+/// `index` has to be a valid index and `user_data` has to be usable from another thread.
+#[no_mangle]
+pub unsafe extern "C" fn reverything_subscribe(
+    index: *const ReverythingIndex,
+    callback: ReverythingChangeCallback,
+    user_data: *mut c_void,
+) -> u64 {
+    let mut subscribers = (*index).subscribers.lock().unwrap();
+    let id = subscribers.next_id;
+    subscribers.next_id += 1;
+    subscribers.list.push(Subscriber {
+        id,
+        callback,
+        user_data,
+    });
+    id
+}
+
+/// Removes a callback. It is not called anymore once this returns.
+///
+/// # Safety
+/// `index` has to be a valid index.
+#[no_mangle]
+pub unsafe extern "C" fn reverything_unsubscribe(index: *const ReverythingIndex, id: u64) {
+    (*index)
+        .subscribers
+        .lock()
+        .unwrap()
+        .list
+        .retain(|subscriber| subscriber.id != id);
+}
+
+/// Returns the message of the last error on this thread, or null if there was none. The message
+/// stays valid until the next failing call on this thread.
+#[no_mangle]
+pub extern "C" fn reverything_last_error() -> *const c_char {
+    LAST_ERROR.with_borrow(|error| error.as_ref().map_or(std::ptr::null(), |e| e.as_ptr()))
+}
+
+fn fail<T>(error: eyre::Report) -> *mut T {
+    let message = CString::new(format!("{:#}", error).replace('\0', "")).unwrap();
+    LAST_ERROR.set(Some(message));
+    std::ptr::null_mut()
+}
@@ -0,0 +1,105 @@
+use std::fs::OpenOptions;
+use std::os::windows::fs::OpenOptionsExt;
+use std::os::windows::io::AsRawHandle;
+
+use eyre::{eyre, Context, Result};
+use windows::core::{HSTRING, PCWSTR};
+use windows::Win32::Foundation::HANDLE;
+use windows::Win32::Storage::FileSystem::{
+    GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION, FILE_FLAG_BACKUP_SEMANTICS,
+};
+use windows::Win32::System::Com::{CoCreateInstance, CLSCTX_ALL};
+use windows::Win32::UI::Shell::{
+    FileOperation, IFileOperation, IFileOperationProgressSink, IShellItem,
+    SHCreateItemFromParsingName, FOFX_ADDUNDORECORD, FOFX_RECYCLEONDELETE, FOF_ALLOWUNDO,
+    FOF_NOCONFIRMMKDIR,
+};
+
+use crate::shell::with_com;
+
+/// Renames the file at `path`. `new_name` is the new file name, not a full path.
+pub fn rename(path: &str, new_name: &str) -> Result<()> {
+    perform(|op| unsafe {
+        op.RenameItem(
+            &create_shell_item(path)?,
+            &HSTRING::from(new_name),
+            None::<&IFileOperationProgressSink>,
+        )
+    })
+    .with_context(|| format!("Failed to rename '{}' to '{}'", path, new_name))
+}
+
+/// Moves all files in `paths` to the Recycle Bin using a single operation.
+pub fn recycle(paths: &[String]) -> Result<()> {
+    perform(|op| unsafe {
+        paths.iter().try_for_each(|path| {
+            op.DeleteItem(
+                &create_shell_item(path)?,
+                None::<&IFileOperationProgressSink>,
+            )
+        })
+    })
+    .with_context(|| format!("Failed to delete {} file(s)", paths.len()))
+}
+
+/// Returns the number of names the file at `path` has. Only needed for the details dialog, so it is
+/// queried on demand instead of being stored in the index.
+pub fn hard_link_count(path: &str) -> Result<u32> {
+    let file = OpenOptions::new()
+        // Only query metadata, which works even for files opened by other processes
+        .access_mode(0)
+        // Required to open directories
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS.0)
+        .open(path)
+        .with_context(|| format!("Failed to open '{}'", path))?;
+
+    let mut info = BY_HANDLE_FILE_INFORMATION::default();
+    unsafe { GetFileInformationByHandle(HANDLE(file.as_raw_handle()), &mut info) }
+        .with_context(|| format!("Failed to query file information of '{}'", path))?;
+
+    Ok(info.nNumberOfLinks)
+}
+
+/// Moves the file at `path` into the `destination` directory, keeping its name.
+pub fn move_to(path: &str, destination: &str) -> Result<()> {
+    perform(|op| unsafe {
+        op.MoveItem(
+            &create_shell_item(path)?,
+            &create_shell_item(destination)?,
+            PCWSTR::null(),
+            None::<&IFileOperationProgressSink>,
+        )
+    })
+    .with_context(|| format!("Failed to move '{}' to '{}'", path, destination))
+}
+
+/// Runs a single shell file operation. The shell takes care of confirmations, progress dialogs and
+/// undo support. The resulting changes end up in the USN journal, so the index does not need to be
+/// updated manually.
+fn perform(queue: impl FnOnce(&IFileOperation) -> windows::core::Result<()>) -> Result<()> {
+    with_com(|| {
+        if unsafe { perform_initialized(queue)? } {
+            return Err(eyre!("Operation was aborted"));
+        }
+
+        Ok(())
+    })
+}
+
+/// Returns whether any of the queued operations were aborted by the user.
+unsafe fn perform_initialized(
+    queue: impl FnOnce(&IFileOperation) -> windows::core::Result<()>,
+) -> windows::core::Result<bool> {
+    let op: IFileOperation = CoCreateInstance(&FileOperation, None, CLSCTX_ALL)?;
+    op.SetOperationFlags(
+        FOF_ALLOWUNDO | FOF_NOCONFIRMMKDIR | FOFX_RECYCLEONDELETE | FOFX_ADDUNDORECORD,
+    )?;
+    queue(&op)?;
+    op.PerformOperations()?;
+
+    Ok(op.GetAnyOperationsAborted()?.as_bool())
+}
+
+fn create_shell_item(path: &str) -> windows::core::Result<IShellItem> {
+    unsafe { SHCreateItemFromParsingName(&HSTRING::from(path), None) }
+}
@@ -0,0 +1,67 @@
+use eyre::{eyre, Result};
+use windows::Win32::Foundation::{FILETIME, SYSTEMTIME};
+use windows::Win32::System::SystemInformation::{GetLocalTime, GetSystemTimeAsFileTime};
+use windows::Win32::System::Time::{
+    FileTimeToSystemTime, SystemTimeToFileTime, SystemTimeToTzSpecificLocalTime,
+    TzSpecificLocalTimeToSystemTime,
+};
+
+pub const HOUR: u64 = 60 * 60 * 10_000_000;
+pub const DAY: u64 = 24 * HOUR;
+
+/// Returns the current time as a FILETIME, see [crate::ntfs::index::FileTimes].
+pub fn now() -> u64 {
+    from_filetime(unsafe { GetSystemTimeAsFileTime() })
+}
+
+/// Returns the local date of today as `(year, month, day)`.
+pub fn today() -> (u16, u16, u16) {
+    let time = unsafe { GetLocalTime() };
+    (time.wYear, time.wMonth, time.wDay)
+}
+
+/// Converts the start of the given local date to a FILETIME.
+pub fn local_midnight(year: u16, month: u16, day: u16) -> Result<u64> {
+    let local = SYSTEMTIME {
+        wYear: year,
+        wMonth: month,
+        wDay: day,
+        ..Default::default()
+    };
+
+    unsafe {
+        let mut utc = SYSTEMTIME::default();
+        let mut filetime = FILETIME::default();
+        TzSpecificLocalTimeToSystemTime(None, &local, &mut utc)
+            .and_then(|_| SystemTimeToFileTime(&utc, &mut filetime))
+            .map_err(|_| eyre!("Invalid date {:04}-{:02}-{:02}", year, month, day))?;
+
+        Ok(from_filetime(filetime))
+    }
+}
+
+/// Converts a FILETIME to the local time zone. Returns `None` for timestamps which cannot be
+/// represented, like 0 for files whose timestamps are unknown.
+pub fn to_local(time: u64) -> Option<SYSTEMTIME> {
+    if time == 0 {
+        return None;
+    }
+
+    let filetime = FILETIME {
+        dwLowDateTime: time as u32,
+        dwHighDateTime: (time >> 32) as u32,
+    };
+
+    unsafe {
+        let mut utc = SYSTEMTIME::default();
+        let mut local = SYSTEMTIME::default();
+        FileTimeToSystemTime(&filetime, &mut utc).ok()?;
+        SystemTimeToTzSpecificLocalTime(None, &utc, &mut local).ok()?;
+
+        Some(local)
+    }
+}
+
+fn from_filetime(filetime: FILETIME) -> u64 {
+    (filetime.dwHighDateTime as u64) << 32 | filetime.dwLowDateTime as u64
+}
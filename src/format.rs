@@ -0,0 +1,410 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use windows::core::PCWSTR;
+use windows::Win32::Globalization::{GetLocaleInfoEx, LOCALE_SDECIMAL, LOCALE_STHOUSAND};
+use windows::Win32::Storage::FileSystem::{
+    FILE_ATTRIBUTE_ARCHIVE, FILE_ATTRIBUTE_COMPRESSED, FILE_ATTRIBUTE_ENCRYPTED,
+    FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_OFFLINE, FILE_ATTRIBUTE_READONLY, FILE_ATTRIBUTE_SYSTEM,
+};
+
+use crate::config::SizeFormat;
+use crate::filetime;
+use crate::stats::GroupStats;
+use reverything::metrics::{BatchMetrics, FilterMetrics, Metrics};
+use reverything::VolumeInfo;
+
+const UNITS: [&str; 5] = ["KB", "MB", "GB", "TB", "PB"];
+/// Width of the bar drawn by [format_share], in characters
+const SHARE_BAR_WIDTH: usize = 20;
+
+/// Decimal and thousands separators of the user's locale.
+struct Separators {
+    decimal: String,
+    thousands: String,
+}
+
+pub fn format_size(size: u64, format: SizeFormat) -> String {
+    match format {
+        SizeFormat::Bytes => group_thousands(size),
+        SizeFormat::HumanReadable => {
+            if size < 1024 {
+                return format!("{} B", size);
+            }
+
+            let mut value = size as f64 / 1024.0;
+            let mut unit = 0;
+            while value >= 1024.0 && unit < UNITS.len() - 1 {
+                value /= 1024.0;
+                unit += 1;
+            }
+
+            let tenths = (value * 10.0).round() as u64;
+            format!(
+                "{}{}{} {}",
+                group_thousands(tenths / 10),
+                separators().decimal,
+                tenths % 10,
+                UNITS[unit]
+            )
+        }
+    }
+}
+
+/// Labels and formatted values of the volume info, in display order.
+pub fn format_volume_info(
+    info: &VolumeInfo,
+    size_format: SizeFormat,
+) -> Vec<(&'static str, String)> {
+    let mut rows = vec![
+        ("Volume", format!("{}:", info.volume.to_ascii_uppercase())),
+        ("File system", info.file_system.clone()),
+        ("Total size", format_size(info.total_bytes, size_format)),
+        ("Free space", format_size(info.free_bytes, size_format)),
+        (
+            "Cluster size",
+            format_size(info.cluster_size as u64, size_format),
+        ),
+    ];
+    // Only NTFS volumes have an MFT
+    if let Some(file_record_size) = info.file_record_size {
+        rows.push((
+            "File record size",
+            format_size(file_record_size as u64, size_format),
+        ));
+    }
+    if let Some(mft_size) = info.mft_size {
+        rows.push(("MFT size", format_size(mft_size, size_format)));
+    }
+    rows.push(("Files", group_thousands(info.files as u64)));
+    rows.push(("Directories", group_thousands(info.directories as u64)));
+    if let Some(journal_id) = info.journal_id {
+        rows.push(("Journal ID", format!("{:#x}", journal_id)));
+        rows.push((
+            "Journal lag",
+            match info.journal_lag {
+                Some(lag) => format_size(lag, size_format),
+                None => "Unknown".to_string(),
+            },
+        ));
+    }
+    rows.push((
+        "Removed entries",
+        group_thousands(info.removed_entries as u64),
+    ));
+    rows
+}
+
+/// Labels and formatted values of the search and journal timings, in display order.
+pub fn format_metrics(metrics: &Metrics) -> Vec<(&'static str, String)> {
+    let last_filter = metrics.last_filter.unwrap_or_default();
+    let last_batch = metrics.last_batch.unwrap_or_default();
+    vec![
+        ("Searches", group_thousands(metrics.filters)),
+        ("Last search", format_duration(last_filter.duration)),
+        ("Last scanned", group_thousands(last_filter.scanned as u64)),
+        ("Last matched", group_thousands(last_filter.matched as u64)),
+        (
+            "Average search",
+            format_duration(metrics.average_filter_time()),
+        ),
+        ("Journal batches", group_thousands(metrics.batches)),
+        ("Journal entries", group_thousands(metrics.batch_entries)),
+        ("Last batch", format_batch(&last_batch)),
+        (
+            "Average batch",
+            format_duration(metrics.average_batch_time()),
+        ),
+    ]
+}
+
+/// Single line summary of the last search and journal batch for the performance overlay, e.g.
+/// `Search 12.3 ms, 1,024 scanned, 17 matched · Journal 25 entries in 1.5 ms`.
+pub fn format_performance(filter: Option<FilterMetrics>, batch: Option<BatchMetrics>) -> String {
+    let mut parts = Vec::new();
+    if let Some(filter) = filter {
+        parts.push(format!(
+            "Search {}, {} scanned, {} matched",
+            format_duration(filter.duration),
+            group_thousands(filter.scanned as u64),
+            group_thousands(filter.matched as u64)
+        ));
+    }
+    if let Some(batch) = batch {
+        parts.push(format!("Journal {}", format_batch(&batch)));
+    }
+    parts.join(" · ")
+}
+
+fn format_batch(batch: &BatchMetrics) -> String {
+    format!(
+        "{} entries in {}",
+        group_thousands(batch.entries as u64),
+        format_duration(batch.duration)
+    )
+}
+
+/// Formats a duration in milliseconds with one decimal, e.g. `12.3 ms`.
+fn format_duration(duration: Duration) -> String {
+    let tenths = duration.as_micros() / 100;
+    format!(
+        "{}{}{} ms",
+        group_thousands((tenths / 10) as u64),
+        separators().decimal,
+        tenths % 10
+    )
+}
+
+/// Formats the path of a change, e.g. `C:\foo\a.txt -> C:\bar\a.txt` for a move.
+pub fn format_change_path(path: &str, old_path: Option<&str>) -> String {
+    match old_path {
+        Some(old_path) => format!("{} -> {}", old_path, path),
+        None => path.to_string(),
+    }
+}
+
+/// Name, file count, size and share of the total size of each group, in display order. `unnamed`
+/// is shown for the group without a name, e.g. the files without an extension.
+pub fn format_group_stats(
+    groups: &[GroupStats],
+    total_size: u64,
+    unnamed: &str,
+    size_format: SizeFormat,
+) -> Vec<[String; 4]> {
+    groups
+        .iter()
+        .map(|group| {
+            [
+                if group.name.is_empty() {
+                    unnamed.to_string()
+                } else {
+                    group.name.clone()
+                },
+                group_thousands(group.files as u64),
+                format_size(group.size, size_format),
+                format_share(group.size, total_size),
+            ]
+        })
+        .collect()
+}
+
+/// Draws the share of `part` in `total` as a bar followed by the percentage, e.g. `████░░ 67.0%`.
+pub fn format_share(part: u64, total: u64) -> String {
+    let share = if total == 0 {
+        0.0
+    } else {
+        part as f64 / total as f64
+    };
+    let filled = ((share * SHARE_BAR_WIDTH as f64).round() as usize).min(SHARE_BAR_WIDTH);
+    let tenths = (share * 1000.0).round() as u64;
+    format!(
+        "{}{} {}{}{}%",
+        "█".repeat(filled),
+        "░".repeat(SHARE_BAR_WIDTH - filled),
+        tenths / 10,
+        separators().decimal,
+        tenths % 10
+    )
+}
+
+/// Formats a FILETIME in the local time zone, e.g. `2024-01-31 14:05`. Unknown times are empty.
+pub fn format_time(time: u64) -> String {
+    let Some(time) = filetime::to_local(time) else {
+        return String::new();
+    };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        time.wYear, time.wMonth, time.wDay, time.wHour, time.wMinute
+    )
+}
+
+/// Like [format_time], but including seconds, e.g. `2024-01-31 14:05:09`.
+pub fn format_time_precise(time: u64) -> String {
+    let Some(time) = filetime::to_local(time) else {
+        return String::new();
+    };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        time.wYear, time.wMonth, time.wDay, time.wHour, time.wMinute, time.wSecond
+    )
+}
+
+/// Formats attribute flags like the explorer does, e.g. `RHA` for a read-only, hidden file which is
+/// ready for archiving.
+pub fn format_attributes(attributes: u32, is_directory: bool) -> String {
+    const FLAGS: [(u32, char); 7] = [
+        (FILE_ATTRIBUTE_READONLY.0, 'R'),
+        (FILE_ATTRIBUTE_HIDDEN.0, 'H'),
+        (FILE_ATTRIBUTE_SYSTEM.0, 'S'),
+        (FILE_ATTRIBUTE_ARCHIVE.0, 'A'),
+        (FILE_ATTRIBUTE_COMPRESSED.0, 'C'),
+        (FILE_ATTRIBUTE_ENCRYPTED.0, 'E'),
+        (FILE_ATTRIBUTE_OFFLINE.0, 'O'),
+    ];
+
+    let mut out = String::with_capacity(FLAGS.len() + 1);
+    if is_directory {
+        out.push('D');
+    }
+    out.extend(
+        FLAGS
+            .iter()
+            .filter(|(flag, _)| attributes & flag != 0)
+            .map(|&(_, c)| c),
+    );
+
+    out
+}
+
+pub fn group_thousands(value: u64) -> String {
+    let digits = value.to_string();
+    let separator = &separators().thousands;
+
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3 * separator.len());
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push_str(separator);
+        }
+        out.push(c);
+    }
+
+    out
+}
+
+fn separators() -> &'static Separators {
+    static SEPARATORS: OnceLock<Separators> = OnceLock::new();
+    SEPARATORS.get_or_init(|| Separators {
+        decimal: locale_info(LOCALE_SDECIMAL).unwrap_or_else(|| ".".to_string()),
+        thousands: locale_info(LOCALE_STHOUSAND).unwrap_or_else(|| ",".to_string()),
+    })
+}
+
+fn locale_info(lctype: u32) -> Option<String> {
+    let mut buffer = [0u16; 16];
+    // A null locale name refers to the user's default locale
+    let len = unsafe { GetLocaleInfoEx(PCWSTR::null(), lctype, Some(&mut buffer)) };
+    // The returned length includes the null terminator
+    (len > 0).then(|| String::from_utf16_lossy(&buffer[..len as usize - 1]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replaces `,` and `.` with the separators of the user's locale
+    fn localized(expected: &str) -> String {
+        expected
+            .chars()
+            .map(|c| match c {
+                ',' => separators().thousands.clone(),
+                '.' => separators().decimal.clone(),
+                c => c.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn thousands_are_grouped() {
+        assert_eq!(group_thousands(0), "0");
+        assert_eq!(group_thousands(999), "999");
+        assert_eq!(group_thousands(1000), localized("1,000"));
+        assert_eq!(group_thousands(1234567), localized("1,234,567"));
+        assert_eq!(group_thousands(123456), localized("123,456"));
+    }
+
+    #[test]
+    fn sizes_are_scaled_to_the_largest_unit() {
+        let human = |size| format_size(size, SizeFormat::HumanReadable);
+        assert_eq!(human(0), "0 B");
+        assert_eq!(human(1023), "1023 B");
+        assert_eq!(human(1024), localized("1.0 KB"));
+        assert_eq!(human(1536), localized("1.5 KB"));
+        assert_eq!(human(5 << 30), localized("5.0 GB"));
+        // Sizes beyond the largest unit keep growing instead of switching units
+        assert_eq!(human(2048 << 50), localized("2,048.0 PB"));
+        assert_eq!(
+            format_size(1234567, SizeFormat::Bytes),
+            localized("1,234,567")
+        );
+    }
+
+    #[test]
+    fn durations_have_one_decimal() {
+        assert_eq!(format_duration(Duration::ZERO), localized("0.0 ms"));
+        assert_eq!(
+            format_duration(Duration::from_micros(12_345)),
+            localized("12.3 ms")
+        );
+        assert_eq!(
+            format_duration(Duration::from_secs(2)),
+            localized("2,000.0 ms")
+        );
+    }
+
+    #[test]
+    fn shares_are_drawn_as_bars() {
+        assert_eq!(
+            format_share(1, 4),
+            format!("{}{} {}", "█".repeat(5), "░".repeat(15), localized("25.0%"))
+        );
+        assert_eq!(
+            format_share(4, 4),
+            format!("{} {}", "█".repeat(20), localized("100.0%"))
+        );
+        assert_eq!(
+            format_share(0, 0),
+            format!("{} {}", "░".repeat(20), localized("0.0%"))
+        );
+    }
+
+    #[test]
+    fn attributes_are_listed_like_the_explorer_does() {
+        assert_eq!(format_attributes(0, false), "");
+        assert_eq!(format_attributes(0, true), "D");
+        assert_eq!(
+            format_attributes(
+                FILE_ATTRIBUTE_ARCHIVE.0 | FILE_ATTRIBUTE_READONLY.0 | FILE_ATTRIBUTE_HIDDEN.0,
+                false
+            ),
+            "RHA"
+        );
+        assert_eq!(
+            format_attributes(FILE_ATTRIBUTE_SYSTEM.0 | FILE_ATTRIBUTE_HIDDEN.0, true),
+            "DHS"
+        );
+    }
+
+    #[test]
+    fn groups_without_a_name_are_labeled() {
+        let groups = [
+            GroupStats {
+                name: "pdf".to_string(),
+                files: 1200,
+                size: 3 << 20,
+            },
+            GroupStats {
+                name: String::new(),
+                files: 1,
+                size: 1 << 20,
+            },
+        ];
+        let rows = format_group_stats(&groups, 4 << 20, "(none)", SizeFormat::HumanReadable);
+        assert_eq!(rows[0][0], "pdf");
+        assert_eq!(rows[0][1], localized("1,200"));
+        assert_eq!(rows[0][2], localized("3.0 MB"));
+        assert!(rows[0][3].ends_with(&localized(" 75.0%")));
+        assert_eq!(rows[1][0], "(none)");
+        assert!(rows[1][3].ends_with(&localized(" 25.0%")));
+    }
+
+    #[test]
+    fn moves_show_the_old_path() {
+        assert_eq!(format_change_path(r"C:\a.txt", None), r"C:\a.txt");
+        assert_eq!(
+            format_change_path(r"C:\bar\a.txt", Some(r"C:\foo\a.txt")),
+            r"C:\foo\a.txt -> C:\bar\a.txt"
+        );
+    }
+}
@@ -0,0 +1,223 @@
+//! Wildcard patterns like `*.log` or `report_??.xlsx`. Unlike the plain search text, which can
+//! appear anywhere in a name, a pattern has to match the whole name. `*` matches any number of
+//! characters and `?` exactly one. Windows doesn't allow either in names, so they can't be meant
+//! literally.
+
+use std::iter;
+
+/// A pattern for a single name. Compiled into the parts between the `*`, so that matching never
+/// has to backtrack: the first part has to start the name, the last one has to end it and the
+/// ones in between are found from left to right.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob {
+    /// Never empty. `None` stands for a `?`.
+    segments: Vec<Vec<Option<char>>>,
+}
+
+/// A pattern for the end of a path, split at backslashes into one [Glob] per component. `src\*.rs`
+/// matches the `.rs` files directly inside every directory named `src`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathGlob {
+    components: Vec<Glob>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            segments: pattern
+                .split('*')
+                .map(|segment| segment.chars().map(|c| (c != '?').then_some(c)).collect())
+                .collect(),
+        }
+    }
+
+    /// Whether `text` contains wildcards, so that it should be matched as a pattern
+    pub fn is_pattern(text: &str) -> bool {
+        text.contains(['*', '?'])
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let (first, rest) = self.segments.split_first().unwrap();
+        let Some(name) = strip_segment(name, first) else {
+            return false;
+        };
+        // Without a `*`, the pattern has to cover the whole name
+        let Some((last, middle)) = rest.split_last() else {
+            return name.is_empty();
+        };
+
+        let Some(start) = suffix_start(name, last.len()) else {
+            return false;
+        };
+        let (mut name, suffix) = name.split_at(start);
+        if strip_segment(suffix, last).is_none() {
+            return false;
+        }
+        for segment in middle {
+            match find_segment(name, segment) {
+                Some(rest) => name = rest,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// The longest part of the pattern without wildcards, which every matching name contains
+    pub fn longest_literal(&self) -> String {
+        self.segments
+            .iter()
+            .flat_map(|segment| segment.split(Option::is_none))
+            .max_by_key(|literal| literal.len())
+            .map(|literal| literal.iter().flatten().collect())
+            .unwrap_or_default()
+    }
+}
+
+impl PathGlob {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            components: pattern.split('\\').map(Glob::new).collect(),
+        }
+    }
+
+    /// Whether the pattern only applies to the name, as it has no backslashes
+    pub fn is_name_only(&self) -> bool {
+        self.components.len() == 1
+    }
+
+    /// Whether the last components of `path` match the components of the pattern
+    pub fn matches(&self, path: &str) -> bool {
+        let mut components = path.rsplit('\\');
+        self.components.iter().rev().all(|glob| {
+            components
+                .next()
+                .is_some_and(|component| glob.matches(component))
+        })
+    }
+
+    /// The pattern for the file name itself
+    pub fn name(&self) -> &Glob {
+        self.components.last().unwrap()
+    }
+}
+
+/// Returns what is left of `name` if it starts with `segment`.
+fn strip_segment<'a>(name: &'a str, segment: &[Option<char>]) -> Option<&'a str> {
+    let mut chars = name.chars();
+    for token in segment {
+        let c = chars.next()?;
+        if token.is_some_and(|token| token != c) {
+            return None;
+        }
+    }
+    Some(chars.as_str())
+}
+
+/// Returns what is left of `name` after the first occurrence of `segment`.
+fn find_segment<'a>(name: &'a str, segment: &[Option<char>]) -> Option<&'a str> {
+    name.char_indices()
+        .map(|(i, _)| i)
+        .chain(iter::once(name.len()))
+        .find_map(|i| strip_segment(&name[i..], segment))
+}
+
+/// Byte offset of the last `chars` characters of `name`, `None` if it is shorter.
+fn suffix_start(name: &str, chars: usize) -> Option<usize> {
+    if chars == 0 {
+        return Some(name.len());
+    }
+    name.char_indices().nth_back(chars - 1).map(|(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::fold;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        Glob::new(pattern).matches(name)
+    }
+
+    #[test]
+    fn star_matches_any_number_of_characters() {
+        assert!(matches("*.log", "server.log"));
+        assert!(matches("*.log", ".log"));
+        assert!(!matches("*.log", "server.log.1"));
+        assert!(matches("report*", "report"));
+        assert!(matches("report*", "report 2024.pdf"));
+        assert!(matches("a*b*c", "abc"));
+        assert!(matches("a*b*c", "a-b-b-c"));
+        assert!(!matches("a*b*c", "a-c-b"));
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(matches("report_??.xlsx", "report_01.xlsx"));
+        assert!(!matches("report_??.xlsx", "report_1.xlsx"));
+        assert!(!matches("report_??.xlsx", "report_001.xlsx"));
+        // Characters, not bytes
+        assert!(matches("r?sum?.pdf", "résumé.pdf"));
+        assert!(matches("*?", "é"));
+        assert!(!matches("*?", ""));
+        assert!(matches("?*?", "ab"));
+        assert!(!matches("?*?", "a"));
+    }
+
+    #[test]
+    fn double_star_is_the_same_as_one() {
+        assert_eq!(
+            Glob::new("a**b").matches("axyzb"),
+            Glob::new("a*b").matches("axyzb")
+        );
+        assert!(matches("**.rs", "main.rs"));
+        assert!(matches("**", ""));
+        // Stars never match across components of a path
+        assert!(!PathGlob::new("src\\**.rs").matches("C:\\src\\ntfs\\index.rs"));
+        assert!(PathGlob::new("src\\**.rs").matches("C:\\src\\main.rs"));
+    }
+
+    #[test]
+    fn without_wildcards_the_whole_name_has_to_match() {
+        assert!(matches("notes.txt", "notes.txt"));
+        assert!(!matches("notes.txt", "notes.txt.bak"));
+        assert!(!matches("notes.txt", "my notes.txt"));
+        assert!(!Glob::is_pattern("notes.txt"));
+        assert!(Glob::is_pattern("notes?.txt"));
+        assert!(Glob::is_pattern("*.txt"));
+    }
+
+    #[test]
+    fn matching_is_case_sensitive_unless_both_sides_are_folded() {
+        assert!(!matches("*.LOG", "server.log"));
+        assert!(matches(&fold("*.LOG"), &fold("Server.Log")));
+        assert!(matches(&fold("R?SUM?.PDF"), &fold("Résumé.pdf")));
+        assert!(PathGlob::new(&fold("SRC\\*.RS")).matches(&fold("C:\\Src\\Main.rs")));
+    }
+
+    #[test]
+    fn path_patterns_match_the_last_components() {
+        let glob = PathGlob::new("src\\*.rs");
+        assert!(!glob.is_name_only());
+        assert!(glob.matches("C:\\project\\src\\main.rs"));
+        assert!(!glob.matches("C:\\project\\source\\main.rs"));
+        assert!(!glob.matches("main.rs"));
+        assert_eq!(glob.name(), &Glob::new("*.rs"));
+
+        let glob = PathGlob::new("*.rs");
+        assert!(glob.is_name_only());
+        assert!(glob.matches("C:\\project\\src\\main.rs"));
+    }
+
+    #[test]
+    fn longest_literal() {
+        assert_eq!(Glob::new("*.log").longest_literal(), ".log");
+        assert_eq!(Glob::new("report_??.xlsx").longest_literal(), "report_");
+        assert_eq!(Glob::new("ab?cdef*gh").longest_literal(), "cdef");
+        assert_eq!(Glob::new("*é*").longest_literal(), "é");
+        assert_eq!(Glob::new("*").longest_literal(), "");
+        assert_eq!(Glob::new("???").longest_literal(), "");
+        assert_eq!(Glob::new("**").longest_literal(), "");
+    }
+}
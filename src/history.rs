@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+
+const MAX_RECENT_QUERIES: usize = 50;
+const MAX_OPENED_FILES: usize = 10;
+
+/// Recently used and pinned search queries, and the results which were opened recently. Pinned
+/// queries are never evicted.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchHistory {
+    /// Most recent query first
+    recent: Vec<String>,
+    pinned: Vec<String>,
+    /// Paths of the opened results, most recent first
+    opened: Vec<String>,
+}
+
+impl SearchHistory {
+    /// Moves the query to the front of the recent queries.
+    pub fn remember(&mut self, query: &str) {
+        let query = query.trim();
+        if query.is_empty() {
+            return;
+        }
+
+        self.recent.retain(|q| q != query);
+        self.recent.insert(0, query.to_string());
+        self.recent.truncate(MAX_RECENT_QUERIES);
+    }
+
+    /// Moves the path to the front of the recently opened results.
+    pub fn remember_opened(&mut self, path: &str) {
+        self.opened.retain(|p| p != path);
+        self.opened.insert(0, path.to_string());
+        self.opened.truncate(MAX_OPENED_FILES);
+    }
+
+    pub fn opened(&self) -> &[String] {
+        &self.opened
+    }
+
+    pub fn pinned(&self) -> &[String] {
+        &self.pinned
+    }
+
+    pub fn toggle_pinned(&mut self, query: &str) {
+        if let Some(pos) = self.pinned.iter().position(|q| q == query) {
+            self.pinned.remove(pos);
+        } else {
+            self.pinned.push(query.to_string());
+        }
+    }
+
+    /// Returns all queries together with whether they are pinned. Pinned queries come first and
+    /// are not repeated in the recent queries.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, bool)> {
+        self.pinned.iter().map(|q| (q.as_str(), true)).chain(
+            self.recent
+                .iter()
+                .filter(|q| !self.pinned.contains(q))
+                .map(|q| (q.as_str(), false)),
+        )
+    }
+}
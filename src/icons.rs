@@ -0,0 +1,231 @@
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::num::NonZeroUsize;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use lru::LruCache;
+use rustc_hash::FxHashSet;
+use slint::{Image, Rgba8Pixel, SharedPixelBuffer};
+use tracing::error;
+use windows::core::HSTRING;
+use windows::Win32::Foundation::HWND;
+use windows::Win32::Graphics::Gdi::{
+    DeleteObject, GetDC, GetDIBits, GetObjectW, ReleaseDC, BITMAP, BITMAPINFO, BITMAPINFOHEADER,
+    BI_RGB, DIB_RGB_COLORS, HBITMAP,
+};
+use windows::Win32::Storage::FileSystem::{
+    FILE_ATTRIBUTE_DIRECTORY, FILE_ATTRIBUTE_NORMAL, FILE_FLAGS_AND_ATTRIBUTES,
+};
+use windows::Win32::UI::Shell::{
+    SHGetFileInfoW, SHFILEINFOW, SHGFI_FLAGS, SHGFI_ICON, SHGFI_SMALLICON, SHGFI_USEFILEATTRIBUTES,
+};
+use windows::Win32::UI::WindowsAndMessaging::{DestroyIcon, GetIconInfo, HICON, ICONINFO};
+
+use crate::ntfs::index::FileInfo;
+use crate::shell;
+
+/// Enough for a few screens of results with distinct icons
+const CACHE_SIZE: usize = 512;
+
+pub type IconPixels = SharedPixelBuffer<Rgba8Pixel>;
+
+/// Identifies the icon of a file. Most files share the icon of their extension, but executables
+/// and shortcuts have their own.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum IconKey {
+    Folder,
+    Extension(String),
+    File(String),
+}
+
+impl IconKey {
+    /// `path` is only computed for files with their own icon.
+    pub fn new(info: &FileInfo, path: impl FnOnce() -> String) -> Self {
+        if info.is_directory() {
+            return IconKey::Folder;
+        }
+
+        let extension = info.extension().unwrap_or_default().to_ascii_lowercase();
+        match extension.as_str() {
+            "exe" | "lnk" | "ico" => IconKey::File(path()),
+            _ => IconKey::Extension(extension),
+        }
+    }
+}
+
+/// Small icons of the files shown in the results. Missing icons are loaded on a background thread,
+/// an empty image is shown until then.
+pub struct IconCache {
+    cache: RefCell<LruCache<IconKey, Image>>,
+    /// Icons which were requested but not loaded yet
+    pending: RefCell<FxHashSet<IconKey>>,
+    requests: Sender<IconKey>,
+}
+
+impl IconCache {
+    /// `on_loaded` is called on the UI thread with each batch of loaded icons, which should be
+    /// passed to [IconCache::insert].
+    pub fn new(
+        on_loaded: impl Fn(Vec<(IconKey, Option<IconPixels>)>) + Send + Clone + 'static,
+    ) -> Self {
+        let (requests, receiver) = channel();
+        std::thread::spawn(move || {
+            if let Err(e) = shell::with_com(|| {
+                load_icons(receiver, on_loaded);
+                Ok(())
+            }) {
+                error!("Icon loader failed: {:?}", e);
+            }
+        });
+
+        Self {
+            cache: RefCell::new(LruCache::new(NonZeroUsize::new(CACHE_SIZE).unwrap())),
+            pending: RefCell::new(FxHashSet::default()),
+            requests,
+        }
+    }
+
+    /// Returns the cached icon or requests it to be loaded.
+    pub fn get(&self, key: IconKey) -> Image {
+        if let Some(image) = self.cache.borrow_mut().get(&key) {
+            return image.clone();
+        }
+
+        if self.pending.borrow_mut().insert(key.clone()) {
+            let _ = self.requests.send(key);
+        }
+        Image::default()
+    }
+
+    pub fn insert(&self, key: IconKey, pixels: Option<IconPixels>) {
+        self.pending.borrow_mut().remove(&key);
+        // Icons which failed to load are cached as well, otherwise they would be requested again
+        // on every repaint
+        self.cache
+            .borrow_mut()
+            .put(key, pixels.map(Image::from_rgba8).unwrap_or_default());
+    }
+}
+
+fn load_icons(
+    receiver: Receiver<IconKey>,
+    on_loaded: impl Fn(Vec<(IconKey, Option<IconPixels>)>) + Send + Clone + 'static,
+) {
+    while let Ok(key) = receiver.recv() {
+        // Scrolling requests many icons at once, batch them to avoid updating the UI for each one
+        let batch = std::iter::once(key)
+            .chain(receiver.try_iter())
+            .map(|key| {
+                let pixels = load_icon(&key);
+                (key, pixels)
+            })
+            .collect::<Vec<_>>();
+
+        let on_loaded = on_loaded.clone();
+        if slint::invoke_from_event_loop(move || on_loaded(batch)).is_err() {
+            // The event loop is gone
+            return;
+        }
+    }
+}
+
+fn load_icon(key: &IconKey) -> Option<IconPixels> {
+    // With SHGFI_USEFILEATTRIBUTES the file does not have to exist, which is a lot faster
+    let (path, attributes, flags) = match key {
+        IconKey::Folder => (
+            "folder".to_string(),
+            FILE_ATTRIBUTE_DIRECTORY,
+            SHGFI_USEFILEATTRIBUTES,
+        ),
+        IconKey::Extension(extension) => (
+            format!("file.{}", extension),
+            FILE_ATTRIBUTE_NORMAL,
+            SHGFI_USEFILEATTRIBUTES,
+        ),
+        IconKey::File(path) => (path.clone(), FILE_FLAGS_AND_ATTRIBUTES(0), SHGFI_FLAGS(0)),
+    };
+
+    unsafe {
+        let mut info = SHFILEINFOW::default();
+        let res = SHGetFileInfoW(
+            &HSTRING::from(path),
+            attributes,
+            Some(&mut info),
+            size_of::<SHFILEINFOW>() as u32,
+            flags | SHGFI_ICON | SHGFI_SMALLICON,
+        );
+        if res == 0 || info.hIcon.is_invalid() {
+            return None;
+        }
+
+        let pixels = icon_to_pixels(info.hIcon);
+        let _ = DestroyIcon(info.hIcon);
+
+        pixels
+    }
+}
+
+unsafe fn icon_to_pixels(icon: HICON) -> Option<IconPixels> {
+    let mut icon_info = ICONINFO::default();
+    GetIconInfo(icon, &mut icon_info).ok()?;
+
+    let pixels = bitmap_to_pixels(icon_info.hbmColor);
+    let _ = DeleteObject(icon_info.hbmColor);
+    let _ = DeleteObject(icon_info.hbmMask);
+
+    pixels
+}
+
+unsafe fn bitmap_to_pixels(bitmap: HBITMAP) -> Option<IconPixels> {
+    let mut bitmap_info = BITMAP::default();
+    if GetObjectW(
+        bitmap,
+        size_of::<BITMAP>() as i32,
+        Some(&mut bitmap_info as *mut _ as *mut c_void),
+    ) == 0
+    {
+        return None;
+    }
+
+    let (width, height) = (bitmap_info.bmWidth as u32, bitmap_info.bmHeight as u32);
+    let mut info = BITMAPINFO {
+        bmiHeader: BITMAPINFOHEADER {
+            biSize: size_of::<BITMAPINFOHEADER>() as u32,
+            biWidth: width as i32,
+            // Negative height means top-down rows
+            biHeight: -(height as i32),
+            biPlanes: 1,
+            biBitCount: 32,
+            biCompression: BI_RGB.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let mut pixels = IconPixels::new(width, height);
+    let dc = GetDC(HWND::default());
+    let lines = GetDIBits(
+        dc,
+        bitmap,
+        0,
+        height,
+        Some(pixels.make_mut_bytes().as_mut_ptr() as *mut c_void),
+        &mut info,
+        DIB_RGB_COLORS,
+    );
+    ReleaseDC(HWND::default(), dc);
+    if lines == 0 {
+        return None;
+    }
+
+    // GDI uses BGRA. Old icons don't have an alpha channel, which makes them fully transparent.
+    let has_alpha = pixels.as_slice().iter().any(|p| p.a != 0);
+    for pixel in pixels.make_mut_slice() {
+        std::mem::swap(&mut pixel.r, &mut pixel.b);
+        if !has_alpha {
+            pixel.a = 255;
+        }
+    }
+
+    Some(pixels)
+}
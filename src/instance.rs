@@ -0,0 +1,165 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::windows::io::FromRawHandle;
+use std::time::{Duration, Instant};
+
+use eyre::{eyre, Context, Report, Result};
+use tracing::error;
+use windows::core::{w, HSTRING, PCWSTR};
+use windows::Win32::Foundation::{
+    GetLastError, BOOL, ERROR_ALREADY_EXISTS, ERROR_PIPE_CONNECTED, HANDLE, HWND, LPARAM,
+};
+use windows::Win32::Storage::FileSystem::PIPE_ACCESS_DUPLEX;
+use windows::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_TYPE_BYTE,
+    PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+};
+use windows::Win32::System::Threading::{CreateMutexW, GetCurrentProcessId};
+use windows::Win32::UI::WindowsAndMessaging::{
+    AllowSetForegroundWindow, EnumWindows, GetWindowThreadProcessId, IsIconic, IsWindowVisible,
+    SetForegroundWindow, ShowWindow, ASFW_ANY, SW_RESTORE,
+};
+
+use crate::ntfs::try_close_handle;
+
+/// Owned by the running UI. `Local\` makes it per session, so that every logged in user can run
+/// their own instance.
+const MUTEX_NAME: PCWSTR = w!("Local\\reverything-ui");
+const PIPE_BUFFER_SIZE: u32 = 4 * 1024;
+/// How long a second instance waits for the first one to finish building its index
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(120);
+const CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Held by the first instance of the UI while it runs, see [acquire].
+pub struct InstanceLock(HANDLE);
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        if let Err(e) = try_close_handle(self.0) {
+            error!("{:?}", e);
+        }
+    }
+}
+
+/// Returns `None` if another instance of the UI is already running in this session.
+pub fn acquire() -> Result<Option<InstanceLock>> {
+    let handle = unsafe { CreateMutexW(None, true, MUTEX_NAME) }
+        .with_context(|| "Failed to create the instance mutex")?;
+    if unsafe { GetLastError() } == ERROR_ALREADY_EXISTS {
+        try_close_handle(handle)?;
+        return Ok(None);
+    }
+
+    Ok(Some(InstanceLock(handle)))
+}
+
+/// Asks the running instance to show its window and search for `query`, unless it is empty. Waits
+/// for the running instance if it is still building its index.
+pub fn forward(query: &str) -> Result<()> {
+    // Windows only lets the process which received the last input take the foreground
+    let _ = unsafe { AllowSetForegroundWindow(ASFW_ANY) };
+
+    let start = Instant::now();
+    let pipe = loop {
+        match OpenOptions::new().read(true).write(true).open(pipe_name()) {
+            Ok(pipe) => break pipe,
+            Err(_) if start.elapsed() < STARTUP_TIMEOUT => {
+                std::thread::sleep(CONNECT_RETRY_INTERVAL)
+            }
+            Err(e) => {
+                return Err(e).with_context(|| "Failed to connect to the running instance");
+            }
+        }
+    };
+
+    let mut reader = BufReader::new(pipe.try_clone()?);
+    writeln!(&pipe, "show {}", query.trim())?;
+    let mut response = String::new();
+    reader.read_line(&mut response)?;
+    match response.trim_end().split_once(' ') {
+        Some(("ok", _)) => Ok(()),
+        Some(("err", message)) => Err(eyre!("Running instance returned an error: {}", message)),
+        _ => Err(eyre!("Malformed response '{}'", response.trim_end())),
+    }
+}
+
+/// Accepts requests of other instances on a background thread. `on_show` is called with the
+/// query which should be searched for, empty if the current search should be kept.
+pub fn listen(on_show: impl Fn(String) + Send + 'static) {
+    std::thread::spawn(move || loop {
+        if let Err(e) = accept(&on_show) {
+            error!("Instance pipe failed: {:?}", e);
+            // Don't spin if the pipe can't be created at all
+            std::thread::sleep(CONNECT_RETRY_INTERVAL);
+        }
+    });
+}
+
+fn accept(on_show: &impl Fn(String)) -> Result<()> {
+    let handle = unsafe {
+        // The default security only lets the current user and administrators connect
+        let handle = CreateNamedPipeW(
+            &HSTRING::from(pipe_name()),
+            PIPE_ACCESS_DUPLEX,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT,
+            PIPE_UNLIMITED_INSTANCES,
+            PIPE_BUFFER_SIZE,
+            PIPE_BUFFER_SIZE,
+            0,
+            None,
+        );
+        if handle.is_invalid() {
+            return Err(Report::new(std::io::Error::last_os_error()))
+                .with_context(|| "CreateNamedPipeW failed");
+        }
+
+        // ERROR_PIPE_CONNECTED means the client connected before we started waiting
+        if ConnectNamedPipe(handle, None).is_err() && GetLastError() != ERROR_PIPE_CONNECTED {
+            try_close_handle(handle)?;
+            return Ok(());
+        }
+        handle
+    };
+
+    let mut pipe = unsafe { File::from_raw_handle(handle.0) };
+    let mut line = String::new();
+    BufReader::new(&pipe).read_line(&mut line)?;
+    match line
+        .trim_end()
+        .split_once(' ')
+        .unwrap_or((line.trim_end(), ""))
+    {
+        ("show", query) => {
+            writeln!(pipe, "ok 0")?;
+            on_show(query.to_string());
+        }
+        (command, _) => writeln!(pipe, "err Unknown command '{}'", command)?,
+    }
+
+    Ok(())
+}
+
+/// Restores the windows of this process if they are minimized and brings them to the foreground.
+pub fn bring_to_foreground() {
+    unsafe extern "system" fn activate(hwnd: HWND, _: LPARAM) -> BOOL {
+        let mut process_id = 0;
+        GetWindowThreadProcessId(hwnd, Some(&mut process_id));
+        if process_id == GetCurrentProcessId() && IsWindowVisible(hwnd).as_bool() {
+            if IsIconic(hwnd).as_bool() {
+                let _ = ShowWindow(hwnd, SW_RESTORE);
+            }
+            let _ = SetForegroundWindow(hwnd);
+        }
+        true.into()
+    }
+
+    let _ = unsafe { EnumWindows(Some(activate), LPARAM(0)) };
+}
+
+/// Pipes are not separated by session, so the name includes the user instead.
+fn pipe_name() -> String {
+    format!(
+        r"\\.\pipe\reverything-ui-{}",
+        std::env::var("USERNAME").unwrap_or_default()
+    )
+}
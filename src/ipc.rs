@@ -0,0 +1,1286 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::os::windows::io::{AsRawHandle, FromRawHandle};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use eyre::{eyre, Context, ContextCompat, Report, Result};
+use rustc_hash::FxHashMap;
+use tracing::error;
+use windows::core::HSTRING;
+use windows::Win32::Foundation::{
+    GetLastError, LocalFree, BOOL, ERROR_PIPE_CONNECTED, HANDLE, HLOCAL,
+};
+use windows::Win32::Security::Authorization::{
+    ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+};
+use windows::Win32::Security::{
+    CheckTokenMembership, CreateWellKnownSid, RevertToSelf, WinBuiltinAdministratorsSid,
+    PSECURITY_DESCRIPTOR, PSID, SECURITY_ATTRIBUTES, SECURITY_MAX_SID_SIZE, TOKEN_QUERY,
+};
+use windows::Win32::Storage::FileSystem::PIPE_ACCESS_DUPLEX;
+use windows::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, ImpersonateNamedPipeClient, PIPE_READMODE_BYTE,
+    PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+};
+use windows::Win32::System::Threading::{GetCurrentThread, OpenThreadToken};
+
+use crate::changelog::ChangeLog;
+use crate::config::{Preset, SizeFormat, SortOrder};
+use crate::ntfs::index::{
+    ChangeKind, Discrepancy, DiscrepancyKind, FileId, FileInfo, FileTimes, NtfsVolumeIndex, Orphan,
+};
+use crate::ntfs::try_close_handle;
+use crate::query::SearchOptions;
+use crate::search::ResultEntry;
+use crate::stats::{GroupStats, Statistics};
+use crate::{format, metrics, search};
+use reverything::DirectoryCheck;
+
+pub const PIPE_NAME: &str = r"\\.\pipe\reverything";
+const PIPE_BUFFER_SIZE: u32 = 64 * 1024;
+/// Full access for SYSTEM and administrators, read/write for all authenticated users. Without
+/// this, the default DACL would only allow elevated clients to send requests to the service.
+/// Requests which change the state of the service are still limited to administrators, see
+/// [Request::requires_administrator].
+const PIPE_SECURITY_DESCRIPTOR: &str = "D:(A;;GA;;;SY)(A;;GA;;;BA)(A;;GRGW;;;AU)";
+const WATCH_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Most results in a single response of a [Request::Stream], [Request::Fetch] or [Request::Rows]
+const STREAM_CHUNK_SIZE: usize = 10_000;
+/// Answers of the client to each response of a [Request::Stream] but the last
+const STREAM_MORE: &str = "more";
+const STREAM_CANCEL: &str = "cancel";
+/// Most cursors a client can keep open at the same time, as each holds all of its results
+const MAX_CURSORS: usize = 16;
+/// Sort argument of [Request::Stream] and [Request::Open] for results in index order
+const UNSORTED: &str = "none";
+/// First column of the lines sent for a [Request::Stats], telling which list the group belongs to
+const GROUP_EXTENSION: &str = "extension";
+const GROUP_DIRECTORY: &str = "directory";
+/// Second column of the lines sent for a [Request::Verify]
+const ORPHAN_RESOLVED: &str = "resolved";
+const ORPHAN_QUARANTINED: &str = "quarantined";
+/// Names of the options of a [Request::SetOptions]
+const OPTION_WHOLE_WORD: &str = "whole_word";
+const OPTION_MATCH_PATH: &str = "match_path";
+const OPTION_FOLD: &str = "fold";
+const OPTION_HIDE_RECYCLE_BIN: &str = "hide_recycle_bin";
+/// Labels of the lines sent for a [Request::Status]
+const STATUS_GENERATION: &str = "generation";
+const STATUS_PAUSED: &str = "paused";
+const STATUS_UNREADABLE_RECORDS: &str = "unreadable_records";
+const STATUS_JOURNAL_ERROR: &str = "journal_error";
+/// Second column of the lines sent for a [Request::VerifyDirectories]
+const DISCREPANCY_MISSING: &str = "missing";
+const DISCREPANCY_UNLISTED: &str = "unlisted";
+
+/// A request sent by a client. Every request is a single line in the form `<command> <argument>`.
+/// Results are sent as lines holding the size, the [FileId] and the path of a file.
+#[derive(Debug)]
+pub enum Request {
+    Search(String),
+    /// The file with this id, answered like a [Request::Search] with no results if it was deleted.
+    /// Lets clients keep referring to a result while it is renamed or moved.
+    Resolve(FileId),
+    /// Streams changes to the index until the client disconnects. Instead of a single response,
+    /// the service periodically sends a response with the changes since the previous one.
+    Watch,
+    /// Suspends or resumes following the journal, see [reverything::set_paused]
+    SetPaused(bool),
+    /// Information about the indexed volume, see [reverything::volume_info]
+    Info,
+    /// Timings of searches and of applying journal entries, see [reverything::metrics]
+    Metrics,
+    /// Files and sizes by extension and top-level directory, see [Statistics::compute]
+    Stats,
+    /// Repairs entries whose parents don't lead to the root directory, see
+    /// [NtfsVolumeIndex::repair_orphans]
+    Verify,
+    /// Compares the index with the `$I30` index of every directory, see
+    /// [reverything::verify_directories]. The first line holds the number of checked and skipped
+    /// directories, every other line a discrepancy.
+    VerifyDirectories,
+    /// Names and queries of the saved searches, which are the presets of the service's config
+    ListSaved,
+    /// Results of the saved search with this name, compared ignoring case. Answered like a
+    /// [Request::Search], so clients don't need to know the query.
+    Saved(String),
+    /// Sends the results of a search in responses of up to [STREAM_CHUNK_SIZE] results, followed
+    /// by an empty one. The client answers every non-empty response with `more` to receive the
+    /// next one, or with `cancel` to end the stream early, which is confirmed by the empty
+    /// response. The index is only locked while a response is put together, so that it keeps up
+    /// with the journal while a slow client reads millions of results. Results which were deleted
+    /// in the meantime are left out. Disconnecting cancels the stream as well.
+    Stream {
+        query: String,
+        /// Sorted by the service, in index order if `None`
+        sort: Option<SortOrder>,
+    },
+    /// Runs a search and keeps its results in a cursor of this connection. Answered with a line
+    /// holding the id of the cursor and the number of results.
+    Open {
+        query: String,
+        sort: Option<SortOrder>,
+    },
+    /// The next results of a cursor, at most `count` and at most [STREAM_CHUNK_SIZE]. Fewer than
+    /// that means that the cursor reached the end. Results which were deleted since it was opened
+    /// are left out.
+    Fetch {
+        cursor: u64,
+        count: usize,
+    },
+    /// Drops a cursor. Cursors are dropped on their own when the client disconnects.
+    Close(u64),
+    /// Changes the search options of the following searches of this connection. The argument
+    /// lists the enabled options separated by commas, e.g. `fold,match_path`, and may be empty.
+    /// Metafiles are hidden as configured for the service.
+    SetOptions(SearchOptions),
+    /// Results of a cursor from `start` on, at most `count` and at most [STREAM_CHUNK_SIZE], for
+    /// tables which only fetch the visible rows. Unlike [Request::Fetch], every line describes a result in full, see
+    /// [write_entry], and is empty if the file was deleted since the cursor was opened.
+    Rows {
+        cursor: u64,
+        start: usize,
+        count: usize,
+    },
+    /// The file with this id described like by [Request::Rows], no lines if it was deleted
+    Entry(FileId),
+    /// Labels and values describing the state of the service, see [ServiceStatus]
+    Status,
+    /// Reads the journal again right away after reading it failed, see
+    /// [reverything::retry_journal]
+    RetryJournal,
+    /// Frees the memory of removed entries, answered with the number of freed bytes, see
+    /// [reverything::compact]
+    Compact,
+}
+
+impl Request {
+    /// Whether the request changes the state of the service instead of only reading the index.
+    /// Those requests are only accepted from administrators, see [is_administrator].
+    fn requires_administrator(&self) -> bool {
+        matches!(
+            self,
+            Request::SetPaused(_)
+                | Request::Compact
+                | Request::Verify
+                | Request::VerifyDirectories
+                | Request::RetryJournal
+        )
+    }
+
+    fn parse(line: &str) -> Result<Self> {
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            "search" => Ok(Request::Search(argument.to_string())),
+            "resolve" => Ok(Request::Resolve(argument.parse()?)),
+            "watch" => Ok(Request::Watch),
+            "pause" => Ok(Request::SetPaused(true)),
+            "resume" => Ok(Request::SetPaused(false)),
+            "info" => Ok(Request::Info),
+            "metrics" => Ok(Request::Metrics),
+            "stats" => Ok(Request::Stats),
+            "verify" if argument.is_empty() => Ok(Request::Verify),
+            "verify" if argument == "deep" => Ok(Request::VerifyDirectories),
+            "saved" if argument.is_empty() => Ok(Request::ListSaved),
+            "saved" => Ok(Request::Saved(argument.to_string())),
+            "stream" | "open" => {
+                let (sort, query) = argument.split_once(' ').unwrap_or((argument, ""));
+                let sort = match sort {
+                    UNSORTED => None,
+                    sort => Some(SortOrder::parse(sort)?),
+                };
+                let query = query.to_string();
+                Ok(match command {
+                    "stream" => Request::Stream { query, sort },
+                    _ => Request::Open { query, sort },
+                })
+            }
+            "fetch" => {
+                let (cursor, count) = argument
+                    .split_once(' ')
+                    .with_context(|| "Usage: fetch <cursor> <count>")?;
+                Ok(Request::Fetch {
+                    cursor: cursor.parse()?,
+                    count: count.parse()?,
+                })
+            }
+            "close" => Ok(Request::Close(argument.parse()?)),
+            "options" => Ok(Request::SetOptions(parse_options(argument)?)),
+            "rows" => {
+                let mut parts = argument.splitn(3, ' ');
+                let (Some(cursor), Some(start), Some(count)) =
+                    (parts.next(), parts.next(), parts.next())
+                else {
+                    return Err(eyre!("Usage: rows <cursor> <start> <count>"));
+                };
+                Ok(Request::Rows {
+                    cursor: cursor.parse()?,
+                    start: start.parse()?,
+                    count: count.parse()?,
+                })
+            }
+            "entry" => Ok(Request::Entry(argument.parse()?)),
+            "status" => Ok(Request::Status),
+            "retry" => Ok(Request::RetryJournal),
+            "compact" => Ok(Request::Compact),
+            _ => Err(eyre!("Unknown command '{}'", command)),
+        }
+    }
+
+    fn to_line(&self) -> String {
+        match self {
+            Request::Search(query) => format!("search {}", query),
+            Request::Resolve(id) => format!("resolve {}", id),
+            Request::Watch => "watch".to_string(),
+            Request::SetPaused(true) => "pause".to_string(),
+            Request::SetPaused(false) => "resume".to_string(),
+            Request::Info => "info".to_string(),
+            Request::Metrics => "metrics".to_string(),
+            Request::Stats => "stats".to_string(),
+            Request::Verify => "verify".to_string(),
+            Request::VerifyDirectories => "verify deep".to_string(),
+            Request::ListSaved => "saved".to_string(),
+            Request::Saved(name) => format!("saved {}", name),
+            Request::Stream { query, sort } => format!("stream {} {}", sort_arg(*sort), query),
+            Request::Open { query, sort } => format!("open {} {}", sort_arg(*sort), query),
+            Request::Fetch { cursor, count } => format!("fetch {} {}", cursor, count),
+            Request::Close(cursor) => format!("close {}", cursor),
+            Request::SetOptions(options) => format!("options {}", options_arg(*options)),
+            Request::Rows {
+                cursor,
+                start,
+                count,
+            } => format!("rows {} {} {}", cursor, start, count),
+            Request::Entry(id) => format!("entry {}", id),
+            Request::Status => "status".to_string(),
+            Request::RetryJournal => "retry".to_string(),
+            Request::Compact => "compact".to_string(),
+        }
+    }
+}
+
+/// Results of a search kept for a client, see [Request::Open].
+struct Cursor {
+    files: Vec<FileId>,
+    /// Number of results which were fetched already
+    position: usize,
+}
+
+#[derive(Debug)]
+pub struct SearchResult {
+    pub id: FileId,
+    pub path: String,
+    pub size: u64,
+}
+
+/// State of the service, see [Request::Status]
+#[derive(Debug, Default, Clone)]
+pub struct ServiceStatus {
+    /// Changes whenever the index changes, see [NtfsVolumeIndex::generation]
+    pub generation: u64,
+    pub paused: bool,
+    /// See [NtfsVolumeIndex::unreadable_records]
+    pub unreadable_records: usize,
+    /// See [reverything::journal_error]
+    pub journal_error: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Change {
+    /// FILETIME at which the service processed the change
+    pub time: u64,
+    pub kind: ChangeKind,
+    pub path: String,
+    /// Path before a rename or move
+    pub old_path: Option<String>,
+}
+
+/// Accepts clients on the named pipe forever. Every client is handled on its own thread. Searches
+/// are run with `options`, `saved` are the searches which clients can run by name.
+pub fn serve(
+    index: Arc<Mutex<NtfsVolumeIndex>>,
+    change_log: Arc<Mutex<ChangeLog>>,
+    options: SearchOptions,
+    saved: Vec<Preset>,
+) -> Result<()> {
+    let saved = Arc::new(saved);
+    loop {
+        let handle = create_pipe_instance()?;
+
+        unsafe {
+            // ERROR_PIPE_CONNECTED means the client connected before we started waiting
+            if ConnectNamedPipe(handle, None).is_err() && GetLastError() != ERROR_PIPE_CONNECTED {
+                try_close_handle(handle)?;
+                continue;
+            }
+        }
+
+        let pipe = unsafe { File::from_raw_handle(handle.0) };
+        let index = index.clone();
+        let change_log = change_log.clone();
+        let saved = saved.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = handle_client(pipe, &index, &change_log, options, &saved) {
+                error!("IPC client failed: {:?}", e);
+            }
+        });
+    }
+}
+
+fn create_pipe_instance() -> Result<HANDLE> {
+    unsafe {
+        let mut descriptor = PSECURITY_DESCRIPTOR::default();
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            &HSTRING::from(PIPE_SECURITY_DESCRIPTOR),
+            SDDL_REVISION_1,
+            &mut descriptor,
+            None,
+        )
+        .with_context(|| "Failed to create pipe security descriptor")?;
+
+        let attributes = SECURITY_ATTRIBUTES {
+            nLength: size_of::<SECURITY_ATTRIBUTES>() as u32,
+            lpSecurityDescriptor: descriptor.0,
+            bInheritHandle: false.into(),
+        };
+
+        let handle = CreateNamedPipeW(
+            &HSTRING::from(PIPE_NAME),
+            PIPE_ACCESS_DUPLEX,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT,
+            PIPE_UNLIMITED_INSTANCES,
+            PIPE_BUFFER_SIZE,
+            PIPE_BUFFER_SIZE,
+            0,
+            Some(&attributes),
+        );
+        let _ = LocalFree(HLOCAL(descriptor.0));
+
+        if handle.is_invalid() {
+            return Err(Report::new(std::io::Error::last_os_error()))
+                .with_context(|| "CreateNamedPipeW failed");
+        }
+
+        Ok(handle)
+    }
+}
+
+fn handle_client(
+    pipe: File,
+    index: &Mutex<NtfsVolumeIndex>,
+    change_log: &Mutex<ChangeLog>,
+    options: SearchOptions,
+    saved: &[Preset],
+) -> Result<()> {
+    let mut reader = BufReader::new(pipe.try_clone()?);
+    let mut writer = BufWriter::new(pipe);
+
+    let default_options = options;
+    let mut options = options;
+    let mut cursors = FxHashMap::<u64, Cursor>::default();
+    let mut next_cursor = 1;
+
+    let mut line = String::new();
+    while reader.read_line(&mut line)? != 0 {
+        match Request::parse(line.trim_end()) {
+            Ok(request)
+                if request.requires_administrator() && !is_administrator(writer.get_ref())? =>
+            {
+                writeln!(
+                    writer,
+                    "err Only administrators can send '{}'",
+                    request.to_line()
+                )?
+            }
+            Ok(Request::Search(query)) => write_results(&mut writer, index, &query, options)?,
+            Ok(Request::Resolve(id)) => {
+                let response = {
+                    let index = index.lock().unwrap();
+                    let infos = find_by_id(&index, id).into_iter().collect::<Vec<_>>();
+                    format_infos(&index, &infos)
+                };
+                writer.write_all(&response)?;
+            }
+            Ok(Request::Stream { query, sort }) => {
+                if !stream_results(&mut reader, &mut writer, index, &query, sort, options)? {
+                    return Ok(());
+                }
+            }
+            Ok(Request::Open { .. }) if cursors.len() >= MAX_CURSORS => {
+                writeln!(writer, "err Too many open cursors, close some first")?
+            }
+            Ok(Request::Open { query, sort }) => match sorted_results(index, &query, sort, options)
+            {
+                Ok(files) => {
+                    writeln!(writer, "ok 1")?;
+                    writeln!(writer, "{}\t{}", next_cursor, files.len())?;
+                    cursors.insert(next_cursor, Cursor { files, position: 0 });
+                    next_cursor += 1;
+                }
+                Err(e) => writeln!(writer, "err {}", e)?,
+            },
+            Ok(Request::Fetch { cursor, count }) => match cursors.get_mut(&cursor) {
+                Some(open) => {
+                    let count = count.min(STREAM_CHUNK_SIZE);
+                    let response = {
+                        let index = index.lock().unwrap();
+                        let mut infos = Vec::new();
+                        while infos.len() < count && open.position < open.files.len() {
+                            infos.extend(find_by_id(&index, open.files[open.position]));
+                            open.position += 1;
+                        }
+                        format_infos(&index, &infos)
+                    };
+                    writer.write_all(&response)?;
+                }
+                None => writeln!(writer, "err Unknown cursor {}", cursor)?,
+            },
+            Ok(Request::Close(cursor)) => match cursors.remove(&cursor) {
+                Some(_) => writeln!(writer, "ok 0")?,
+                None => writeln!(writer, "err Unknown cursor {}", cursor)?,
+            },
+            Ok(Request::SetOptions(new_options)) => {
+                options = SearchOptions {
+                    hide_metafiles: default_options.hide_metafiles,
+                    ..new_options
+                };
+                writeln!(writer, "ok 0")?;
+            }
+            Ok(Request::Rows {
+                cursor,
+                start,
+                count,
+            }) => match cursors.get(&cursor) {
+                Some(open) => {
+                    let ids = open
+                        .files
+                        .iter()
+                        .skip(start)
+                        .take(count.min(STREAM_CHUNK_SIZE));
+                    let mut response = Vec::new();
+                    {
+                        let index = index.lock().unwrap();
+                        writeln!(response, "ok {}", ids.len())?;
+                        for &id in ids {
+                            let entry = index.resolve(id).and_then(|i| ResultEntry::new(&index, i));
+                            write_entry(&mut response, entry)?;
+                        }
+                    }
+                    writer.write_all(&response)?;
+                }
+                None => writeln!(writer, "err Unknown cursor {}", cursor)?,
+            },
+            Ok(Request::Entry(id)) => {
+                let index = index.lock().unwrap();
+                match index.resolve(id).and_then(|i| ResultEntry::new(&index, i)) {
+                    Some(entry) => {
+                        writeln!(writer, "ok 1")?;
+                        write_entry(&mut writer, Some(entry))?;
+                    }
+                    None => writeln!(writer, "ok 0")?,
+                }
+            }
+            Ok(Request::Status) => {
+                let (generation, unreadable_records) = {
+                    let index = index.lock().unwrap();
+                    (index.generation(), index.unreadable_records())
+                };
+                writeln!(writer, "ok 4")?;
+                writeln!(writer, "{}\t{}", STATUS_GENERATION, generation)?;
+                writeln!(
+                    writer,
+                    "{}\t{}",
+                    STATUS_PAUSED,
+                    reverything::is_paused() as u8
+                )?;
+                writeln!(
+                    writer,
+                    "{}\t{}",
+                    STATUS_UNREADABLE_RECORDS, unreadable_records
+                )?;
+                let journal_error = reverything::journal_error().unwrap_or_default();
+                writeln!(
+                    writer,
+                    "{}\t{}",
+                    STATUS_JOURNAL_ERROR,
+                    journal_error.replace(['\r', '\n'], " ")
+                )?;
+            }
+            Ok(Request::RetryJournal) => {
+                reverything::retry_journal();
+                writeln!(writer, "ok 0")?;
+            }
+            Ok(Request::Compact) => {
+                let freed = reverything::compact(index);
+                writeln!(writer, "ok 1")?;
+                writeln!(writer, "{}", freed)?;
+            }
+            Ok(Request::Watch) => return stream_changes(&mut writer, change_log),
+            Ok(Request::SetPaused(paused)) => {
+                reverything::set_paused(paused);
+                writeln!(writer, "ok 0")?;
+            }
+            Ok(Request::Info) => match reverything::volume_info(index) {
+                Ok(info) => {
+                    let rows = format::format_volume_info(&info, SizeFormat::default());
+                    writeln!(writer, "ok {}", rows.len())?;
+                    for (label, value) in rows {
+                        writeln!(writer, "{}\t{}", label, value)?;
+                    }
+                }
+                Err(e) => writeln!(writer, "err {:#}", e)?,
+            },
+            Ok(Request::Metrics) => {
+                let rows = format::format_metrics(&metrics::get());
+                writeln!(writer, "ok {}", rows.len())?;
+                for (label, value) in rows {
+                    writeln!(writer, "{}\t{}", label, value)?;
+                }
+            }
+            Ok(Request::Stats) => {
+                let stats = Statistics::compute(&index.lock().unwrap());
+                writeln!(
+                    writer,
+                    "ok {}",
+                    stats.by_extension.len() + stats.by_directory.len()
+                )?;
+                for (kind, groups) in [
+                    (GROUP_EXTENSION, &stats.by_extension),
+                    (GROUP_DIRECTORY, &stats.by_directory),
+                ] {
+                    for group in groups {
+                        writeln!(
+                            writer,
+                            "{}\t{}\t{}\t{}",
+                            kind, group.files, group.size, group.name
+                        )?;
+                    }
+                }
+            }
+            Ok(Request::Verify) => {
+                let orphans = reverything::repair_orphans(&mut index.lock().unwrap());
+                writeln!(writer, "ok {}", orphans.len())?;
+                for orphan in orphans {
+                    let status = if orphan.resolved {
+                        ORPHAN_RESOLVED
+                    } else {
+                        ORPHAN_QUARANTINED
+                    };
+                    writeln!(writer, "{}\t{}\t{}", orphan.mft_index, status, orphan.name)?;
+                }
+            }
+            Ok(Request::VerifyDirectories) => match reverything::verify_directories(index) {
+                Ok(check) => {
+                    writeln!(writer, "ok {}", check.discrepancies.len() + 1)?;
+                    writeln!(writer, "{}\t{}", check.directories, check.skipped)?;
+                    for discrepancy in check.discrepancies {
+                        let kind = match discrepancy.kind {
+                            DiscrepancyKind::Missing => DISCREPANCY_MISSING,
+                            DiscrepancyKind::Unlisted => DISCREPANCY_UNLISTED,
+                        };
+                        writeln!(
+                            writer,
+                            "{}\t{}\t{}",
+                            discrepancy.mft_index, kind, discrepancy.path
+                        )?;
+                    }
+                }
+                Err(e) => writeln!(writer, "err {:#}", e)?,
+            },
+            Ok(Request::ListSaved) => {
+                writeln!(writer, "ok {}", saved.len())?;
+                for preset in saved {
+                    writeln!(writer, "{}\t{}", preset.name, preset.query)?;
+                }
+            }
+            Ok(Request::Saved(name)) => {
+                match saved
+                    .iter()
+                    .find(|preset| preset.name.eq_ignore_ascii_case(&name))
+                {
+                    Some(preset) => write_results(&mut writer, index, &preset.query, options)?,
+                    None => writeln!(writer, "err No saved search named '{}'", name)?,
+                }
+            }
+            Err(e) => writeln!(writer, "err {}", e)?,
+        }
+
+        writer.flush()?;
+        line.clear();
+    }
+
+    Ok(())
+}
+
+/// Whether the client connected to `pipe` is an elevated administrator. Checked by impersonating
+/// the client, which is only possible after something was read from the pipe.
+fn is_administrator(pipe: &File) -> Result<bool> {
+    unsafe {
+        let mut sid_buffer = [0u8; SECURITY_MAX_SID_SIZE as usize];
+        let mut sid_size = sid_buffer.len() as u32;
+        let administrators = PSID(sid_buffer.as_mut_ptr().cast());
+        CreateWellKnownSid(
+            WinBuiltinAdministratorsSid,
+            PSID::default(),
+            administrators,
+            &mut sid_size,
+        )
+        .with_context(|| "CreateWellKnownSid failed")?;
+
+        ImpersonateNamedPipeClient(HANDLE(pipe.as_raw_handle()))
+            .with_context(|| "ImpersonateNamedPipeClient failed")?;
+        let mut token = HANDLE::default();
+        let opened = OpenThreadToken(GetCurrentThread(), TOKEN_QUERY, true, &mut token);
+        // Has to happen before anything else, the thread must not keep the rights of the client
+        RevertToSelf().with_context(|| "RevertToSelf failed")?;
+        opened.with_context(|| "OpenThreadToken failed")?;
+
+        let mut is_member = BOOL::default();
+        let checked = CheckTokenMembership(token, administrators, &mut is_member);
+        try_close_handle(token)?;
+        checked.with_context(|| "CheckTokenMembership failed")?;
+        Ok(is_member.as_bool())
+    }
+}
+
+/// Sends every file matching the query. The response is put together before it is sent, so that
+/// the index isn't locked while a slow client reads it.
+fn write_results(
+    writer: &mut impl Write,
+    index: &Mutex<NtfsVolumeIndex>,
+    query: &str,
+    options: SearchOptions,
+) -> Result<()> {
+    match search::filter(index, query, options) {
+        Ok(results) => {
+            let response = {
+                let index = index.lock().unwrap();
+                let infos = results
+                    .into_iter()
+                    .filter_map(|i| Some((i, index.find_by_index(i)?)))
+                    .collect::<Vec<_>>();
+                format_infos(&index, &infos)
+            };
+            writer.write_all(&response)?;
+        }
+        Err(e) => writeln!(writer, "err {}", e)?,
+    }
+
+    Ok(())
+}
+
+/// Runs a search and sorts its results, see [Request::Open]. The results are returned by id, as
+/// they are sent after the index was unlocked and the journal may have reused their MFT records.
+fn sorted_results(
+    index: &Mutex<NtfsVolumeIndex>,
+    query: &str,
+    sort: Option<SortOrder>,
+    options: SearchOptions,
+) -> Result<Vec<FileId>> {
+    let mut files = search::filter(index, query, options)?;
+    if let Some(sort) = sort {
+        search::sort(index, &mut files, sort.column, sort.ascending);
+    }
+    let index = index.lock().unwrap();
+    Ok(files.into_iter().filter_map(|i| index.file_id(i)).collect())
+}
+
+fn find_by_id(index: &NtfsVolumeIndex, id: FileId) -> Option<(u64, &FileInfo)> {
+    let i = index.resolve(id)?;
+    Some((i, index.find_by_index(i)?))
+}
+
+/// Puts the given files together as a single response, which is sent once the index was unlocked,
+/// so that a client which doesn't read can't block the index.
+fn format_infos(index: &NtfsVolumeIndex, infos: &[(u64, &FileInfo)]) -> Vec<u8> {
+    let mut response = Vec::new();
+    // Writing to a Vec can't fail
+    let _ = writeln!(response, "ok {}", infos.len());
+    for &(i, info) in infos {
+        let _ = write_info(&mut response, index, i, info);
+    }
+    response
+}
+
+/// Sends a single result, see [Request].
+fn write_info(
+    writer: &mut impl Write,
+    index: &NtfsVolumeIndex,
+    i: u64,
+    info: &FileInfo,
+) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        "{}\t{}\t{}",
+        info.size(),
+        index.file_id(i).unwrap(),
+        index.compute_full_path(info)
+    )
+}
+
+/// Sends the results of a search in chunks, see [Request::Stream]. Returns `false` if the client
+/// disconnected before all of them were sent.
+fn stream_results(
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+    index: &Mutex<NtfsVolumeIndex>,
+    query: &str,
+    sort: Option<SortOrder>,
+    options: SearchOptions,
+) -> Result<bool> {
+    let files = match sorted_results(index, query, sort, options) {
+        Ok(files) => files,
+        Err(e) => {
+            writeln!(writer, "err {}", e)?;
+            return Ok(true);
+        }
+    };
+
+    let res = (|| {
+        for chunk in files.chunks(STREAM_CHUNK_SIZE) {
+            let response = {
+                let index = index.lock().unwrap();
+                let infos = chunk
+                    .iter()
+                    .filter_map(|&id| find_by_id(&index, id))
+                    .collect::<Vec<_>>();
+                // An empty response would end the stream
+                if infos.is_empty() {
+                    continue;
+                }
+                format_infos(&index, &infos)
+            };
+            writer.write_all(&response)?;
+            writer.flush()?;
+
+            let mut answer = String::new();
+            if reader.read_line(&mut answer)? == 0 {
+                return Err(ErrorKind::BrokenPipe.into());
+            }
+            match answer.trim_end() {
+                STREAM_MORE => {}
+                STREAM_CANCEL => break,
+                answer => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Unexpected answer '{}' to a stream", answer),
+                    ))
+                }
+            }
+        }
+        writeln!(writer, "ok 0")?;
+        writer.flush()
+    })();
+    match res {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Sends a result of a [Request::Rows] or [Request::Entry], or an empty line if it was deleted.
+/// The columns are the id, size, size on disk, the creation, modification and access times, the
+/// attributes, `d` for directories or `f` for files and the path.
+fn write_entry(writer: &mut impl Write, entry: Option<ResultEntry>) -> std::io::Result<()> {
+    let Some(ResultEntry { id, path, info }) = entry else {
+        return writeln!(writer);
+    };
+    let times = info.times();
+    writeln!(
+        writer,
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+        id,
+        info.size(),
+        info.size_on_disk(),
+        times.created,
+        times.modified,
+        times.accessed,
+        info.attributes(),
+        if info.is_directory() { 'd' } else { 'f' },
+        path
+    )
+}
+
+fn parse_entry(line: &str) -> Result<Option<ResultEntry>> {
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let parts = line.splitn(9, '\t').collect::<Vec<_>>();
+    let &[id, size, size_on_disk, created, modified, accessed, attributes, kind, path] =
+        parts.as_slice()
+    else {
+        return Err(eyre!("Malformed entry '{}'", line));
+    };
+
+    let id = id.parse::<FileId>()?;
+    let name = path.rsplit_once('\\').map_or(path, |(_, name)| name);
+    let times = FileTimes {
+        created: created.parse()?,
+        modified: modified.parse()?,
+        accessed: accessed.parse()?,
+    };
+    let info = FileInfo::new(
+        size.parse()?,
+        size_on_disk.parse()?,
+        kind == "d",
+        // The parent is only known to the service
+        0,
+        name.into(),
+        times,
+        attributes.parse()?,
+    )
+    .with_sequence(id.sequence);
+    Ok(Some(ResultEntry {
+        id,
+        path: path.to_string(),
+        info,
+    }))
+}
+
+fn options_arg(options: SearchOptions) -> String {
+    [
+        (options.whole_word, OPTION_WHOLE_WORD),
+        (options.match_path, OPTION_MATCH_PATH),
+        (options.fold, OPTION_FOLD),
+        (options.hide_recycle_bin, OPTION_HIDE_RECYCLE_BIN),
+    ]
+    .into_iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, name)| name)
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+fn parse_options(arg: &str) -> Result<SearchOptions> {
+    let mut options = SearchOptions::default();
+    for name in arg.split(',').filter(|name| !name.is_empty()) {
+        match name {
+            OPTION_WHOLE_WORD => options.whole_word = true,
+            OPTION_MATCH_PATH => options.match_path = true,
+            OPTION_FOLD => options.fold = true,
+            OPTION_HIDE_RECYCLE_BIN => options.hide_recycle_bin = true,
+            _ => return Err(eyre!("Unknown search option '{}'", name)),
+        }
+    }
+    Ok(options)
+}
+
+/// Whether a request failed because the connection to the service broke, rather than because the
+/// service rejected it. The connection has to be opened again after such errors.
+pub fn is_disconnected(error: &Report) -> bool {
+    error.downcast_ref::<std::io::Error>().is_some()
+}
+
+fn sort_arg(sort: Option<SortOrder>) -> String {
+    sort.map_or_else(|| UNSORTED.to_string(), SortOrder::to_arg)
+}
+
+/// Sends all changes which happen from now on. Returns once the client disconnected, which is
+/// noticed because empty responses are sent even if nothing changed.
+fn stream_changes(writer: &mut impl Write, change_log: &Mutex<ChangeLog>) -> Result<()> {
+    let mut next_sequence = change_log.lock().unwrap().next_sequence();
+    loop {
+        let entries = {
+            let change_log = change_log.lock().unwrap();
+            let entries = change_log.since(next_sequence).cloned().collect::<Vec<_>>();
+            next_sequence = change_log.next_sequence();
+            entries
+        };
+
+        let res = (|| {
+            writeln!(writer, "ok {}", entries.len())?;
+            for entry in entries {
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\t{}",
+                    entry.time,
+                    entry.kind.name(),
+                    entry.path,
+                    entry.old_path.as_deref().unwrap_or_default()
+                )?;
+            }
+            writer.flush()
+        })();
+        match res {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+
+        std::thread::sleep(WATCH_POLL_INTERVAL);
+    }
+}
+
+/// Connection to a running reverything service.
+pub struct Client {
+    reader: BufReader<File>,
+    writer: File,
+}
+
+impl Client {
+    pub fn connect() -> Result<Self> {
+        let pipe = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(PIPE_NAME)
+            .with_context(|| "Failed to connect to the reverything service, is it running?")?;
+
+        Ok(Self {
+            reader: BufReader::new(pipe.try_clone()?),
+            writer: pipe,
+        })
+    }
+
+    pub fn search(&mut self, query: &str) -> Result<Vec<SearchResult>> {
+        let count = self.send(&Request::Search(query.to_string()))?;
+        self.read_results(count)
+    }
+
+    /// Returns the file with the given id, `None` if it was deleted.
+    pub fn resolve(&mut self, id: FileId) -> Result<Option<SearchResult>> {
+        let count = self.send(&Request::Resolve(id))?;
+        Ok(self.read_results(count)?.pop())
+    }
+
+    /// Returns the names and queries of the searches saved in the service's config.
+    pub fn saved_searches(&mut self) -> Result<Vec<Preset>> {
+        let count = self.send(&Request::ListSaved)?;
+
+        (0..count)
+            .map(|_| {
+                let line = self.read_line()?;
+                let (name, query) = line
+                    .split_once('\t')
+                    .with_context(|| format!("Malformed saved search '{}'", line))?;
+                Ok(Preset {
+                    name: name.to_string(),
+                    query: query.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    /// Runs a search and passes its results to `on_results` in chunks as they arrive, without
+    /// keeping all of them in memory. If `on_results` fails, the stream is cancelled and the error
+    /// returned.
+    pub fn stream(
+        &mut self,
+        query: &str,
+        sort: Option<SortOrder>,
+        mut on_results: impl FnMut(Vec<SearchResult>) -> Result<()>,
+    ) -> Result<()> {
+        let request = Request::Stream {
+            query: query.to_string(),
+            sort,
+        };
+        writeln!(self.writer, "{}", request.to_line())?;
+
+        loop {
+            let count = match self.read_header()? {
+                0 => return Ok(()),
+                count => count,
+            };
+            if let Err(e) = on_results(self.read_results(count)?) {
+                writeln!(self.writer, "{}", STREAM_CANCEL)?;
+                // Confirmed by the empty response which ends every stream
+                self.read_header()?;
+                return Err(e);
+            }
+            writeln!(self.writer, "{}", STREAM_MORE)?;
+        }
+    }
+
+    /// Runs a search and keeps its results in a cursor of the service. Returns the id of the cursor
+    /// and the number of results.
+    pub fn open(&mut self, query: &str, sort: Option<SortOrder>) -> Result<(u64, usize)> {
+        let count = self.send(&Request::Open {
+            query: query.to_string(),
+            sort,
+        })?;
+        let (cursor, results) = self
+            .read_rows(count)?
+            .pop()
+            .with_context(|| "Missing cursor in the response")?;
+        Ok((cursor.parse()?, results.parse()?))
+    }
+
+    pub fn close(&mut self, cursor: u64) -> Result<()> {
+        self.send(&Request::Close(cursor))?;
+        Ok(())
+    }
+
+    /// Returns the results of a cursor from `start` on, `None` for files which were deleted.
+    pub fn rows(
+        &mut self,
+        cursor: u64,
+        start: usize,
+        count: usize,
+    ) -> Result<Vec<Option<ResultEntry>>> {
+        let count = self.send(&Request::Rows {
+            cursor,
+            start,
+            count,
+        })?;
+        (0..count)
+            .map(|_| parse_entry(&self.read_line()?))
+            .collect()
+    }
+
+    /// Returns the file with the given id, `None` if it was deleted.
+    pub fn entry(&mut self, id: FileId) -> Result<Option<ResultEntry>> {
+        let count = self.send(&Request::Entry(id))?;
+        let lines = (0..count)
+            .map(|_| self.read_line())
+            .collect::<Result<Vec<_>>>()?;
+        match lines.first() {
+            Some(line) => parse_entry(line),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_options(&mut self, options: SearchOptions) -> Result<()> {
+        self.send(&Request::SetOptions(options))?;
+        Ok(())
+    }
+
+    pub fn status(&mut self) -> Result<ServiceStatus> {
+        let count = self.send(&Request::Status)?;
+        let mut status = ServiceStatus::default();
+        for (label, value) in self.read_rows(count)? {
+            match label.as_str() {
+                STATUS_GENERATION => status.generation = value.parse()?,
+                STATUS_PAUSED => status.paused = value == "1",
+                STATUS_UNREADABLE_RECORDS => status.unreadable_records = value.parse()?,
+                STATUS_JOURNAL_ERROR => status.journal_error = (!value.is_empty()).then_some(value),
+                // Added by newer versions of the service
+                _ => {}
+            }
+        }
+        Ok(status)
+    }
+
+    pub fn retry_journal(&mut self) -> Result<()> {
+        self.send(&Request::RetryJournal)?;
+        Ok(())
+    }
+
+    /// Returns the number of bytes which were freed.
+    pub fn compact(&mut self) -> Result<usize> {
+        let count = self.send(&Request::Compact)?;
+        let lines = (0..count)
+            .map(|_| self.read_line())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(lines
+            .first()
+            .with_context(|| "Missing size in the response")?
+            .parse()?)
+    }
+
+    /// Runs the saved search with the given name.
+    pub fn saved(&mut self, name: &str) -> Result<Vec<SearchResult>> {
+        let count = self.send(&Request::Saved(name.to_string()))?;
+        self.read_results(count)
+    }
+
+    fn read_results(&mut self, count: usize) -> Result<Vec<SearchResult>> {
+        (0..count)
+            .map(|_| {
+                let line = self.read_line()?;
+                let mut parts = line.splitn(3, '\t');
+                let (Some(size), Some(id), Some(path)) = (parts.next(), parts.next(), parts.next())
+                else {
+                    return Err(eyre!("Malformed search result '{}'", line));
+                };
+
+                Ok(SearchResult {
+                    id: id.parse()?,
+                    path: path.to_string(),
+                    size: size.parse()?,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the labels and values of the volume info, formatted by the service.
+    pub fn info(&mut self) -> Result<Vec<(String, String)>> {
+        let count = self.send(&Request::Info)?;
+        self.read_rows(count)
+    }
+
+    /// Returns the labels and values of the search and journal timings, formatted by the service.
+    pub fn metrics(&mut self) -> Result<Vec<(String, String)>> {
+        let count = self.send(&Request::Metrics)?;
+        self.read_rows(count)
+    }
+
+    /// Reads `count` lines of tab separated labels and values.
+    fn read_rows(&mut self, count: usize) -> Result<Vec<(String, String)>> {
+        (0..count)
+            .map(|_| {
+                let line = self.read_line()?;
+                let (label, value) = line
+                    .split_once('\t')
+                    .with_context(|| format!("Malformed row '{}'", line))?;
+                Ok((label.to_string(), value.to_string()))
+            })
+            .collect()
+    }
+
+    pub fn stats(&mut self) -> Result<Statistics> {
+        let count = self.send(&Request::Stats)?;
+
+        let mut stats = Statistics::default();
+        for _ in 0..count {
+            let line = self.read_line()?;
+            let mut parts = line.splitn(4, '\t');
+            let (Some(kind), Some(files), Some(size), Some(name)) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                return Err(eyre!("Malformed stats line '{}'", line));
+            };
+
+            let group = GroupStats {
+                name: name.to_string(),
+                files: files.parse()?,
+                size: size.parse()?,
+            };
+            match kind {
+                GROUP_EXTENSION => stats.by_extension.push(group),
+                GROUP_DIRECTORY => stats.by_directory.push(group),
+                _ => return Err(eyre!("Unknown stats group '{}'", kind)),
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Asks the service to repair orphaned entries and returns the ones it found.
+    pub fn verify(&mut self) -> Result<Vec<Orphan>> {
+        let count = self.send(&Request::Verify)?;
+
+        (0..count)
+            .map(|_| {
+                let line = self.read_line()?;
+                let mut parts = line.splitn(3, '\t');
+                let (Some(mft_index), Some(status), Some(name)) =
+                    (parts.next(), parts.next(), parts.next())
+                else {
+                    return Err(eyre!("Malformed orphan line '{}'", line));
+                };
+
+                Ok(Orphan {
+                    mft_index: mft_index.parse()?,
+                    name: name.to_string(),
+                    resolved: match status {
+                        ORPHAN_RESOLVED => true,
+                        ORPHAN_QUARANTINED => false,
+                        _ => return Err(eyre!("Unknown orphan status '{}'", status)),
+                    },
+                })
+            })
+            .collect()
+    }
+
+    /// Asks the service to compare its index with the directories on the volume.
+    pub fn verify_directories(&mut self) -> Result<DirectoryCheck> {
+        let count = self.send(&Request::VerifyDirectories)?;
+        if count == 0 {
+            return Err(eyre!("Missing the number of checked directories"));
+        }
+
+        let line = self.read_line()?;
+        let (directories, skipped) = line
+            .split_once('\t')
+            .with_context(|| format!("Malformed directory count line '{}'", line))?;
+        let mut check = DirectoryCheck {
+            directories: directories.parse()?,
+            skipped: skipped.parse()?,
+            discrepancies: Vec::with_capacity(count - 1),
+        };
+        for _ in 1..count {
+            let line = self.read_line()?;
+            let mut parts = line.splitn(3, '\t');
+            let (Some(mft_index), Some(kind), Some(path)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                return Err(eyre!("Malformed discrepancy line '{}'", line));
+            };
+
+            check.discrepancies.push(Discrepancy {
+                mft_index: mft_index.parse()?,
+                kind: match kind {
+                    DISCREPANCY_MISSING => DiscrepancyKind::Missing,
+                    DISCREPANCY_UNLISTED => DiscrepancyKind::Unlisted,
+                    _ => return Err(eyre!("Unknown discrepancy kind '{}'", kind)),
+                },
+                path: path.to_string(),
+            });
+        }
+        Ok(check)
+    }
+
+    pub fn set_paused(&mut self, paused: bool) -> Result<()> {
+        self.send(&Request::SetPaused(paused))?;
+        Ok(())
+    }
+
+    /// Calls `on_changes` with every batch of changes which the service reports. Only returns
+    /// if the connection fails.
+    pub fn watch(&mut self, mut on_changes: impl FnMut(Vec<Change>)) -> Result<()> {
+        writeln!(self.writer, "{}", Request::Watch.to_line())?;
+
+        loop {
+            let count = self.read_header()?;
+            let changes = (0..count)
+                .map(|_| {
+                    let line = self.read_line()?;
+                    let mut parts = line.splitn(4, '\t');
+                    let (Some(time), Some(kind), Some(path), Some(old_path)) =
+                        (parts.next(), parts.next(), parts.next(), parts.next())
+                    else {
+                        return Err(eyre!("Malformed change '{}'", line));
+                    };
+
+                    Ok(Change {
+                        time: time.parse()?,
+                        kind: ChangeKind::ALL
+                            .into_iter()
+                            .find(|k| k.name() == kind)
+                            .with_context(|| format!("Unknown change kind '{}'", kind))?,
+                        path: path.to_string(),
+                        old_path: (!old_path.is_empty()).then(|| old_path.to_string()),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            on_changes(changes);
+        }
+    }
+
+    /// Sends a request and returns the number of lines that follow the response header.
+    fn send(&mut self, request: &Request) -> Result<usize> {
+        writeln!(self.writer, "{}", request.to_line())?;
+        self.read_header()
+    }
+
+    /// Reads a response header and returns the number of lines that follow it.
+    fn read_header(&mut self) -> Result<usize> {
+        let header = self.read_line()?;
+        match header.split_once(' ') {
+            Some(("ok", count)) => Ok(count.parse()?),
+            Some(("err", message)) => Err(eyre!("Service returned an error: {}", message)),
+            _ => Err(eyre!("Malformed response header '{}'", header)),
+        }
+    }
+
+    fn read_line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(std::io::Error::from(ErrorKind::UnexpectedEof))
+                .with_context(|| "Service closed the connection");
+        }
+
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
@@ -0,0 +1,111 @@
+use eyre::{Context, Result};
+use windows::core::{w, Interface, HSTRING, PROPVARIANT};
+use windows::Win32::Storage::EnhancedStorage::PKEY_Title;
+use windows::Win32::System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER};
+use windows::Win32::UI::Shell::Common::{IObjectArray, IObjectCollection};
+use windows::Win32::UI::Shell::PropertiesSystem::IPropertyStore;
+use windows::Win32::UI::Shell::{
+    DestinationList, EnumerableObjectCollection, ICustomDestinationList, IShellLinkW, ShellLink,
+};
+
+use crate::shell;
+
+/// Replaces the jump list of the taskbar button with the recently opened files, most recent first,
+/// and the pinned search queries. Opening a pinned query starts the UI with it, or forwards it to
+/// the running one.
+pub fn update(recent_files: &[String], pinned_queries: &[String]) -> Result<()> {
+    let exe = std::env::current_exe()?;
+
+    shell::with_com(|| unsafe {
+        let list: ICustomDestinationList =
+            CoCreateInstance(&DestinationList, None, CLSCTX_INPROC_SERVER)?;
+        let mut max_slots = 0u32;
+        let _removed: IObjectArray = list.BeginList(&mut max_slots)?;
+
+        let res = (|| -> Result<()> {
+            // Every category and task counts against the slots
+            let file_slots = (max_slots as usize).saturating_sub(pinned_queries.len());
+            let files = recent_files
+                .iter()
+                .take(file_slots)
+                .map(|path| {
+                    let name = path
+                        .rsplit_once('\\')
+                        .map_or(path.as_str(), |(_, name)| name);
+                    create_link(path, "", name, path)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            if !files.is_empty() {
+                list.AppendCategory(w!("Recent files"), &collection(files)?)?;
+            }
+
+            let exe = exe.to_string_lossy();
+            let searches = pinned_queries
+                .iter()
+                .map(|query| {
+                    let arguments = format!("ui {}", quote_argument(query));
+                    create_link(&exe, &arguments, query, &format!("Search for {}", query))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            if !searches.is_empty() {
+                list.AppendCategory(w!("Pinned searches"), &collection(searches)?)?;
+            }
+
+            list.CommitList()?;
+            Ok(())
+        })();
+        if res.is_err() {
+            let _ = list.AbortList();
+        }
+
+        res.with_context(|| "Failed to update the jump list")
+    })
+}
+
+/// Quotes an argument so that the C runtime splits it off the command line unchanged. Backslashes
+/// are only special in front of a quote, so only those in front of a quote or the closing quote are
+/// doubled.
+fn quote_argument(arg: &str) -> String {
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        if c == '"' {
+            quoted.extend(std::iter::repeat_n('\\', backslashes + 1));
+        }
+        backslashes = if c == '\\' { backslashes + 1 } else { 0 };
+        quoted.push(c);
+    }
+    quoted.extend(std::iter::repeat_n('\\', backslashes));
+    quoted.push('"');
+    quoted
+}
+
+unsafe fn create_link(
+    target: &str,
+    arguments: &str,
+    title: &str,
+    description: &str,
+) -> Result<IShellLinkW> {
+    let link: IShellLinkW = CoCreateInstance(&ShellLink, None, CLSCTX_INPROC_SERVER)?;
+    link.SetPath(&HSTRING::from(target))?;
+    link.SetArguments(&HSTRING::from(arguments))?;
+    link.SetDescription(&HSTRING::from(description))?;
+
+    // Jump lists show the title instead of the name of the link
+    let properties: IPropertyStore = link.cast()?;
+    properties.SetValue(&PKEY_Title, &PROPVARIANT::from(title))?;
+    properties.Commit()?;
+
+    Ok(link)
+}
+
+unsafe fn collection(links: Vec<IShellLinkW>) -> Result<IObjectArray> {
+    let collection: IObjectCollection =
+        CoCreateInstance(&EnumerableObjectCollection, None, CLSCTX_INPROC_SERVER)?;
+    for link in links {
+        collection.AddObject(&link)?;
+    }
+
+    Ok(collection.cast()?)
+}
@@ -0,0 +1,994 @@
+//! The index and search used by the reverything app, which can also be used from other languages
+//! through the C interface in [`ffi`].
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::config::IndexMode;
+use crate::metrics::BatchMetrics;
+use crate::ntfs::fallback::DirectoryWatcher;
+use crate::ntfs::index::{
+    is_low_io_priority, Discrepancy, FileChange, FileInfo, NtfsVolumeIndex, Orphan,
+};
+use crate::ntfs::journal::{query_journal, Journal, JournalEntry};
+use crate::ntfs::volume::{get_volumes, Volume};
+use crate::snapshot::WriteAheadLog;
+use eyre::{eyre, ContextCompat, Result};
+use rayon::prelude::*;
+use tracing::{debug, error, info, warn};
+use windows::Win32::System::Threading::{
+    GetCurrentThread, SetThreadPriority, THREAD_MODE_BACKGROUND_BEGIN, THREAD_MODE_BACKGROUND_END,
+};
+
+pub mod changelog;
+pub mod config;
+pub mod ffi;
+pub mod filetime;
+pub mod glob;
+pub mod history;
+pub mod metrics;
+pub mod network;
+pub mod ntfs;
+pub mod owner;
+pub mod query;
+pub mod recycle_bin;
+pub mod search;
+pub mod snapshot;
+pub mod stats;
+
+/// How often sleeping threads check whether a retry or shutdown was requested
+const RETRY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// The journal is opened again after this many failed attempts in a row
+const REOPEN_JOURNAL_AFTER_FAILURES: u32 = 3;
+/// Directories of network locations which are listed at once. Listing mostly waits for the
+/// server, so this is independent of the number of cores.
+const NETWORK_SCAN_THREADS: usize = 16;
+/// Directories whose `$I30` index is read before comparing them with the index, see
+/// [verify_directories]
+const DIRECTORY_CHECK_CHUNK_SIZE: usize = 1024;
+
+/// Set while the journal is not followed, see [set_paused].
+static PAUSED: AtomicBool = AtomicBool::new(false);
+/// Why the journal cannot be read, until reading it succeeds again
+static JOURNAL_ERROR: Mutex<Option<String>> = Mutex::new(None);
+/// Set to attempt reading the journal again without waiting
+static RETRY_JOURNAL: AtomicBool = AtomicBool::new(false);
+/// Set by [shutdown] to make the background threads return
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+/// Journal, verification, network and volume threads, joined by [shutdown]
+static BACKGROUND_THREADS: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+/// Id of the journal which is followed and the USN of the next record which will be applied
+static JOURNAL_POSITION: Mutex<Option<(u64, i64)>> = Mutex::new(None);
+/// Set while the index is saved, see [JournalOptions::persist]
+static WRITE_AHEAD_LOG: Mutex<Option<WriteAheadLog>> = Mutex::new(None);
+/// Entries of the network locations and the other volumes, see [update_locations]
+static LOCATIONS: Mutex<Locations> = Mutex::new(Locations {
+    network: Vec::new(),
+    volumes: Vec::new(),
+});
+
+/// How the journal is followed after the index was built, see [build_index].
+#[derive(Debug, Copy, Clone)]
+pub struct JournalOptions {
+    /// How often the journal is checked for new entries. Doubled for every failed attempt to read
+    /// it.
+    pub poll_interval: Duration,
+    /// Most entries which are applied while holding the lock of the index. Larger bursts are split
+    /// into several batches, so that searches can run in between.
+    pub batch_size: usize,
+    /// Saves the index on shutdown and logs the applied journal entries, so that the next start
+    /// only has to catch up with the journal instead of reading the MFT. Only applies to indices
+    /// read from the MFT, see [snapshot].
+    pub persist: bool,
+}
+
+impl Default for JournalOptions {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(1),
+            batch_size: 1000,
+            persist: false,
+        }
+    }
+}
+
+/// Size and state of the indexed volume, see [volume_info].
+#[derive(Debug, Clone)]
+pub struct VolumeInfo {
+    pub volume: char,
+    /// Like `NTFS` or `FAT32`, followed by the version for ReFS
+    pub file_system: String,
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+    pub cluster_size: u32,
+    /// `None` unless the volume is formatted with NTFS
+    pub file_record_size: Option<u32>,
+    /// `None` unless the volume is formatted with NTFS
+    pub mft_size: Option<u64>,
+    pub files: usize,
+    pub directories: usize,
+    /// `None` if the volume has no journal, like FAT32 and exFAT volumes
+    pub journal_id: Option<u64>,
+    /// Size of the journal records which were written but not yet applied to the index. `None` if
+    /// the journal is not followed.
+    pub journal_lag: Option<u64>,
+    /// Entries which were removed but still take memory until the index is compacted, see
+    /// [compact]
+    pub removed_entries: usize,
+}
+
+/// Suspends or resumes applying journal entries to the index. The journal keeps the position it
+/// read up to, so everything that changed in the meantime is applied once resumed.
+pub fn set_paused(paused: bool) {
+    PAUSED.store(paused, Ordering::Relaxed);
+}
+
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::Relaxed)
+}
+
+/// Returns why the journal cannot be read. Changes to the volume are not applied to the index
+/// while this is set. Reading is retried with increasing delays.
+pub fn journal_error() -> Option<String> {
+    JOURNAL_ERROR.lock().unwrap().clone()
+}
+
+/// Collects information about the volume of the index and how far behind the journal the index is.
+pub fn volume_info(index: &Mutex<NtfsVolumeIndex>) -> Result<VolumeInfo> {
+    let (volume, (files, directories), removed_entries) = {
+        let index = index.lock().unwrap();
+        (
+            index.volume(),
+            index.count_files_and_directories(),
+            index.removed_entries(),
+        )
+    };
+    let mut file_system = volume.file_system()?;
+    let (total_bytes, free_bytes, cluster_size) = volume.query_space()?;
+    let (file_record_size, mft_size) = match file_system.as_str() {
+        "NTFS" => {
+            let data = volume.query_volume_data()?;
+            (
+                Some(data.BytesPerFileRecordSegment),
+                Some(data.MftValidDataLength as u64),
+            )
+        }
+        _ => (None, None),
+    };
+    let journal = match file_system.as_str() {
+        "NTFS" | "ReFS" => Some(query_journal(volume)?),
+        _ => None,
+    };
+    if file_system == "ReFS" {
+        let data = volume.query_refs_volume_data()?;
+        file_system = format!("ReFS {}.{}", data.MajorVersion, data.MinorVersion);
+    }
+    let journal_lag = match (&journal, *JOURNAL_POSITION.lock().unwrap()) {
+        (Some(journal), Some((id, next_usn))) if id == journal.UsnJournalID => {
+            Some(journal.NextUsn.saturating_sub(next_usn).max(0) as u64)
+        }
+        _ => None,
+    };
+
+    Ok(VolumeInfo {
+        volume: volume.id,
+        file_system,
+        total_bytes,
+        free_bytes,
+        cluster_size,
+        file_record_size,
+        mft_size,
+        files,
+        directories,
+        journal_id: journal.map(|journal| journal.UsnJournalID),
+        journal_lag,
+        removed_entries,
+    })
+}
+
+/// Frees the memory of entries which were removed from the index. Returns the number of bytes
+/// which were freed. Also happens automatically once enough entries were removed.
+pub fn compact(index: &Mutex<NtfsVolumeIndex>) -> usize {
+    let t = Instant::now();
+    let freed = index.lock().unwrap().compact();
+    info!(
+        "Compacting the index freed {} bytes in {:?}",
+        freed,
+        t.elapsed()
+    );
+    freed
+}
+
+fn compact_if_needed(index: &Mutex<NtfsVolumeIndex>) {
+    if index.lock().unwrap().needs_compaction() {
+        compact(index);
+    }
+}
+
+/// Attempts to read the journal again right away instead of waiting for the next retry.
+pub fn retry_journal() {
+    RETRY_JOURNAL.store(true, Ordering::Relaxed);
+}
+
+/// Stops the journal, verification, network and volume threads, waiting for them to finish what
+/// they are doing, and closes the handle which the index keeps to read the MFT. The index can still
+/// be searched afterwards, but is no longer updated.
+pub fn shutdown(index: &Mutex<NtfsVolumeIndex>) {
+    SHUTTING_DOWN.store(true, Ordering::Relaxed);
+    let threads = std::mem::take(&mut *BACKGROUND_THREADS.lock().unwrap());
+    for thread in threads {
+        if thread.join().is_err() {
+            error!("A background thread panicked");
+        }
+    }
+    // The log is only needed until the next snapshot was written
+    if WRITE_AHEAD_LOG.lock().unwrap().take().is_some() {
+        if let Some((journal_id, next_usn)) = *JOURNAL_POSITION.lock().unwrap() {
+            if let Err(e) = save_snapshot(&index.lock().unwrap(), journal_id, next_usn) {
+                error!("Failed to save the index: {:?}", e);
+            }
+        }
+    }
+    index.lock().unwrap().close();
+    *LOCATIONS.lock().unwrap() = Locations::default();
+    // Allows building another index, which the C interface may do after destroying one
+    SHUTTING_DOWN.store(false, Ordering::Relaxed);
+
+    if let Some((journal_id, next_usn)) = *JOURNAL_POSITION.lock().unwrap() {
+        info!(
+            "Shut down, journal {:x} was applied up to USN {}",
+            journal_id, next_usn
+        );
+    }
+}
+
+fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::Relaxed)
+}
+
+/// Builds the index for the first volume and keeps it up to date by following the journal.
+/// `on_changes` is called with the index still locked after each batch of journal entries. The
+/// other volumes without an MFT are indexed in the background, see [start_volume_thread].
+pub fn build_index(
+    mode: IndexMode,
+    journal_options: JournalOptions,
+    on_changes: impl Fn(&NtfsVolumeIndex, &[FileChange]) + Send + 'static,
+) -> Result<Arc<Mutex<NtfsVolumeIndex>>> {
+    // Lowers the I/O and CPU priority of the reads on this thread until the index was built
+    let _background = if is_low_io_priority() {
+        BackgroundMode::begin()
+    } else {
+        None
+    };
+    let vol = get_volumes()
+        .into_iter()
+        .next()
+        .with_context(|| "Cannot find first volume")?;
+    let file_system = vol.file_system().unwrap_or_else(|e| {
+        warn!("Failed to query the file system: {:?}", e);
+        "NTFS".to_string()
+    });
+    if !matches!(file_system.as_str(), "NTFS" | "ReFS") {
+        let index = build_watched_index(vol, &file_system, journal_options, on_changes)?;
+        start_volume_thread(index.clone(), vol, journal_options);
+        return Ok(index);
+    }
+
+    let t = Instant::now();
+    let is_refs = file_system == "ReFS";
+    let restored = if journal_options.persist && !is_refs && mode != IndexMode::UsnEnumeration {
+        restore_index(vol).unwrap_or_else(|e| {
+            warn!("Failed to load the saved index: {:?}", e);
+            None
+        })
+    } else {
+        None
+    };
+    let (mut index, mut journal, log) = match restored {
+        Some((index, journal, log)) => (index, journal, Some(log)),
+        None => {
+            let journal = Journal::new(vol)?;
+            let index = match mode {
+                // There is no MFT, so the index mode doesn't matter
+                _ if is_refs => {
+                    info!(
+                        "Volume {} is formatted with ReFS, using USN enumeration",
+                        vol.id
+                    );
+                    NtfsVolumeIndex::from_refs(vol, None)?
+                }
+                IndexMode::Mft => NtfsVolumeIndex::new(vol)?,
+                IndexMode::UsnEnumeration => NtfsVolumeIndex::from_usn_data(vol)?,
+                IndexMode::Auto => NtfsVolumeIndex::new(vol).or_else(|e| {
+                    warn!(
+                        "Reading the MFT failed, using USN enumeration instead: {:?}",
+                        e
+                    );
+                    NtfsVolumeIndex::from_usn_data(vol)
+                })?,
+            };
+            (index, journal, None)
+        }
+    };
+    if index.unreadable_records() > 0 {
+        warn!(
+            "{} MFT records could not be read and are missing from the index",
+            index.unreadable_records()
+        );
+    }
+    repair_orphans(&mut index);
+    journal.set_file_ids(index.file_ids());
+    *JOURNAL_POSITION.lock().unwrap() = Some((journal.journal_id(), journal.next_usn()));
+    if journal_options.persist {
+        // Without a snapshot of this index, the entries in the log couldn't be replayed
+        let log = match log {
+            Some(log) => Ok(log),
+            None if index.is_from_mft() => {
+                save_snapshot(&index, journal.journal_id(), journal.next_usn())
+            }
+            None => Err(eyre!("Only indices read from the MFT can be saved")),
+        };
+        match log {
+            Ok(log) => *WRITE_AHEAD_LOG.lock().unwrap() = Some(log),
+            Err(e) => {
+                error!("Failed to save the index: {:?}", e);
+                discard_snapshot();
+            }
+        }
+    }
+    let index = Arc::new(Mutex::new(index));
+    info!("Building index took: {:?}", t.elapsed());
+
+    start_journal_thread(journal, index.clone(), journal_options, on_changes);
+    start_volume_thread(index.clone(), vol, journal_options);
+
+    Ok(index)
+}
+
+/// Loads the index saved by the last run and replays the journal entries logged after that.
+/// Returns `None` if there is no saved index or the journal can't be read from where it left off.
+fn restore_index(vol: Volume) -> Result<Option<(NtfsVolumeIndex, Journal, WriteAheadLog)>> {
+    let Some(snapshot) = snapshot::load(vol.id)? else {
+        return Ok(None);
+    };
+    let Some(journal) = Journal::resume(vol, snapshot.journal_id, snapshot.next_usn)? else {
+        info!("The journal was reset or overwritten since the index was saved, reading the MFT");
+        return Ok(None);
+    };
+
+    let mut index = NtfsVolumeIndex::from_snapshot(vol, snapshot.infos)?;
+    index.process_journal_entries(&snapshot.log);
+    let log = WriteAheadLog::open(snapshot.id, snapshot.log_len)?;
+    info!(
+        "Loaded the saved index and {} logged journal entries",
+        snapshot.log.len()
+    );
+    Ok(Some((index, journal, log)))
+}
+
+/// Writes a snapshot of `index` and starts a new log for it.
+fn save_snapshot(index: &NtfsVolumeIndex, journal_id: u64, next_usn: i64) -> Result<WriteAheadLog> {
+    let t = Instant::now();
+    let id = snapshot::save(index, journal_id, next_usn)?;
+    let log = WriteAheadLog::create(id)?;
+    info!("Saving the index took {:?}", t.elapsed());
+    Ok(log)
+}
+
+/// Deletes the saved index, which would load an outdated state otherwise.
+fn discard_snapshot() {
+    if let Err(e) = snapshot::discard() {
+        error!("{:?}", e);
+    }
+}
+
+/// Appends journal entries which were applied to the index to the log, if the index is saved. If
+/// that fails, the index isn't saved anymore.
+fn append_to_log(journal_id: u64, next_usn: i64, entries: &[JournalEntry]) {
+    let mut log = WRITE_AHEAD_LOG.lock().unwrap();
+    let Some(wal) = &mut *log else {
+        return;
+    };
+    if let Err(e) = wal.append(journal_id, next_usn, entries) {
+        error!("{:?}", e);
+        *log = None;
+        discard_snapshot();
+    }
+}
+
+/// Keeps the current thread in background mode until dropped, which gives its I/O the lowest
+/// priority. Threads can't enter background mode twice, so this isn't used on threads which stay
+/// in background mode anyway.
+struct BackgroundMode;
+
+impl BackgroundMode {
+    fn begin() -> Option<Self> {
+        match unsafe { SetThreadPriority(GetCurrentThread(), THREAD_MODE_BACKGROUND_BEGIN) } {
+            Ok(()) => Some(Self),
+            Err(e) => {
+                warn!("Failed to enter background mode: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for BackgroundMode {
+    fn drop(&mut self) {
+        let res = unsafe { SetThreadPriority(GetCurrentThread(), THREAD_MODE_BACKGROUND_END) };
+        if let Err(e) = res {
+            warn!("Failed to leave background mode: {:?}", e);
+        }
+    }
+}
+
+/// Builds the index of a volume without an MFT and a journal by listing all directories, and keeps
+/// it up to date by watching the volume for changes.
+fn build_watched_index(
+    vol: Volume,
+    file_system: &str,
+    options: JournalOptions,
+    on_changes: impl Fn(&NtfsVolumeIndex, &[FileChange]) + Send + 'static,
+) -> Result<Arc<Mutex<NtfsVolumeIndex>>> {
+    let t = Instant::now();
+    let (watcher, index) = watch_volume(vol, file_system)?;
+    let index = Arc::new(Mutex::new(index));
+    info!("Building index took: {:?}", t.elapsed());
+
+    start_watcher_thread(watcher, index.clone(), options, on_changes);
+
+    Ok(index)
+}
+
+/// Starts watching a volume without an MFT and a journal for changes, then lists its directories.
+fn watch_volume(vol: Volume, file_system: &str) -> Result<(DirectoryWatcher, NtfsVolumeIndex)> {
+    info!(
+        "Volume {} is formatted with {}, listing its directories",
+        vol.id, file_system
+    );
+    // Started first, so that changes made while listing are not missed
+    let watcher = DirectoryWatcher::new(vol)?;
+    let mut index = NtfsVolumeIndex::from_directory_walk(vol)?;
+    repair_orphans(&mut index);
+    Ok((watcher, index))
+}
+
+/// Lists a watched volume again after its watcher lost track of the changes. Returns `None` if
+/// that fails, in which case the volume keeps its outdated entries.
+fn rescan(vol: Volume) -> Option<NtfsVolumeIndex> {
+    warn!(
+        "Too many changes on volume {} at once, listing its directories again",
+        vol.id
+    );
+    let t = Instant::now();
+    match NtfsVolumeIndex::from_directory_walk(vol) {
+        Ok(mut index) => {
+            repair_orphans(&mut index);
+            info!("Listing volume {} again took {:?}", vol.id, t.elapsed());
+            Some(index)
+        }
+        Err(e) => {
+            error!("Failed to list volume {} again: {:?}", vol.id, e);
+            None
+        }
+    }
+}
+
+fn start_watcher_thread(
+    mut watcher: DirectoryWatcher,
+    index: Arc<Mutex<NtfsVolumeIndex>>,
+    options: JournalOptions,
+    on_changes: impl Fn(&NtfsVolumeIndex, &[FileChange]) + Send + 'static,
+) {
+    let thread = std::thread::spawn(move || loop {
+        wait_for_retry(options.poll_interval);
+        if is_shutting_down() {
+            break;
+        }
+        // The system keeps collecting changes in the meantime
+        if is_paused() {
+            continue;
+        }
+
+        let changes = match watcher.read_changes() {
+            Ok(Some(changes)) => {
+                *JOURNAL_ERROR.lock().unwrap() = None;
+                changes
+            }
+            Ok(None) => {
+                let volume = index.lock().unwrap().volume();
+                if let Some(fresh) = rescan(volume) {
+                    index.lock().unwrap().replace_entries(fresh);
+                }
+                continue;
+            }
+            Err(e) => {
+                error!("Failed to read directory changes: {:?}", e);
+                *JOURNAL_ERROR.lock().unwrap() = Some(format!("{:#}", e));
+                continue;
+            }
+        };
+
+        let start = Instant::now();
+        for changes in changes.chunks(options.batch_size.max(1)) {
+            let mut index = index.lock().unwrap();
+            let changes = index.process_path_changes(changes);
+            on_changes(&index, &changes);
+            owner::forget(&changes);
+            drop(index);
+            std::thread::yield_now();
+        }
+        if !changes.is_empty() {
+            record_batch(start, changes.len());
+        }
+        compact_if_needed(&index);
+    });
+    BACKGROUND_THREADS.lock().unwrap().push(thread);
+}
+
+fn start_journal_thread(
+    mut journal: Journal,
+    index: Arc<Mutex<NtfsVolumeIndex>>,
+    options: JournalOptions,
+    on_changes: impl Fn(&NtfsVolumeIndex, &[FileChange]) + Send + 'static,
+) {
+    let thread = std::thread::spawn(move || {
+        let mut failures = 0u32;
+        let mut caught_up = true;
+        loop {
+            if caught_up || failures > 0 {
+                wait_for_retry(options.poll_interval * 2u32.pow(failures.min(6)));
+            } else {
+                // Let searches which waited for the lock run before the next batch
+                std::thread::yield_now();
+            }
+            // Entries which were read are always applied, so the position is never ahead of the
+            // index
+            if is_shutting_down() {
+                break;
+            }
+            if is_paused() {
+                caught_up = true;
+                continue;
+            }
+
+            // Keep reading until the batch is full, so that bursts are applied quickly
+            let batch_size = options.batch_size.max(1);
+            let mut batch = Vec::new();
+            let mut read_error = None;
+            caught_up = false;
+            while batch.len() < batch_size {
+                match journal.read_entries() {
+                    Ok(entries) if entries.is_empty() => {
+                        caught_up = true;
+                        break;
+                    }
+                    Ok(entries) => batch.extend(entries),
+                    Err(e) => {
+                        read_error = Some(e);
+                        break;
+                    }
+                }
+            }
+
+            match &read_error {
+                Some(e) => {
+                    error!("Failed to read the journal: {:?}", e);
+                    *JOURNAL_ERROR.lock().unwrap() = Some(format!("{:#}", e));
+                    failures += 1;
+                }
+                None if failures > 0 => {
+                    info!("Reading the journal works again");
+                    *JOURNAL_ERROR.lock().unwrap() = None;
+                    failures = 0;
+                }
+                None => {}
+            }
+            *JOURNAL_POSITION.lock().unwrap() = Some((journal.journal_id(), journal.next_usn()));
+
+            // A single read can return more entries than fit into a batch
+            let start = Instant::now();
+            for entries in batch.chunks(batch_size) {
+                let mut index = index.lock().unwrap();
+                let changes = index.process_journal_entries(entries);
+                on_changes(&index, &changes);
+                owner::forget(&changes);
+                drop(index);
+                std::thread::yield_now();
+            }
+            if !batch.is_empty() {
+                record_batch(start, batch.len());
+                append_to_log(journal.journal_id(), journal.next_usn(), &batch);
+                compact_if_needed(&index);
+            }
+
+            if read_error.is_some() && failures >= REOPEN_JOURNAL_AFTER_FAILURES {
+                reopen_journal(&mut journal, &index);
+            }
+        }
+    });
+    BACKGROUND_THREADS.lock().unwrap().push(thread);
+}
+
+fn record_batch(start: Instant, entries: usize) {
+    metrics::record_batch(BatchMetrics {
+        duration: start.elapsed(),
+        entries,
+    });
+}
+
+/// Starts reading the journal from its current end, for example because it was deleted and
+/// recreated. Changes from before that are picked up by verifying the whole index.
+fn reopen_journal(journal: &mut Journal, index: &Mutex<NtfsVolumeIndex>) {
+    let volume = index.lock().unwrap().volume();
+    match Journal::new(volume) {
+        Ok(new_journal) => {
+            *journal = new_journal;
+            warn!("Reopened the journal, verifying the index to catch up on missed changes");
+            match verify(index) {
+                Ok(corrections) => info!("Verification corrected {} files", corrections),
+                Err(e) => error!("Verification failed: {:?}", e),
+            }
+        }
+        Err(e) => error!("Failed to reopen the journal: {:?}", e),
+    }
+}
+
+/// Sleeps for `duration`, or less if [retry_journal] or [shutdown] is called in the meantime.
+fn wait_for_retry(duration: Duration) {
+    let end = Instant::now() + duration;
+    while let Some(remaining) = end.checked_duration_since(Instant::now()) {
+        if RETRY_JOURNAL.swap(false, Ordering::Relaxed) || is_shutting_down() {
+            return;
+        }
+        std::thread::sleep(remaining.min(RETRY_CHECK_INTERVAL));
+    }
+}
+
+/// Reads the volume again every `interval` and corrects entries of the index which the journal
+/// failed to keep up to date. Runs with background priority, so that it does not slow down other
+/// programs.
+pub fn start_verification_thread(index: Arc<Mutex<NtfsVolumeIndex>>, interval: Duration) {
+    let thread = std::thread::spawn(move || {
+        let res = unsafe { SetThreadPriority(GetCurrentThread(), THREAD_MODE_BACKGROUND_BEGIN) };
+        if let Err(e) = res {
+            warn!("Failed to lower verification thread priority: {:?}", e);
+        }
+
+        loop {
+            if !sleep_unless_shutdown(interval) {
+                return;
+            }
+            if is_paused() {
+                continue;
+            }
+
+            let t = Instant::now();
+            match verify(&index) {
+                Ok(corrections) => info!(
+                    "Verifying index took {:?}, corrected {} files",
+                    t.elapsed(),
+                    corrections
+                ),
+                Err(e) => error!("Verification failed: {:?}", e),
+            }
+        }
+    });
+    BACKGROUND_THREADS.lock().unwrap().push(thread);
+}
+
+/// Lists the network locations right away and again every `interval` unless it is zero, replacing
+/// their previous entries in the index. Locations which can't be reached keep the entries of their
+/// last successful scan.
+pub fn start_network_thread(
+    index: Arc<Mutex<NtfsVolumeIndex>>,
+    locations: Vec<String>,
+    interval: Duration,
+) {
+    let thread = std::thread::spawn(move || {
+        let pool = match rayon::ThreadPoolBuilder::new()
+            .num_threads(NETWORK_SCAN_THREADS)
+            .thread_name(|i| format!("network-scan-{}", i))
+            .build()
+        {
+            Ok(pool) => pool,
+            Err(e) => {
+                error!("Failed to create network scan threads: {:?}", e);
+                return;
+            }
+        };
+
+        loop {
+            if !is_paused() {
+                let t = Instant::now();
+                let mut scanned = Vec::with_capacity(locations.len());
+                for location in &locations {
+                    match network::scan(location, &pool) {
+                        Ok(files) => scanned.push(Some(files)),
+                        Err(e) => {
+                            warn!("Failed to scan network location {}: {:?}", location, e);
+                            scanned.push(None);
+                        }
+                    }
+                    if is_shutting_down() {
+                        return;
+                    }
+                }
+                update_locations(&index, |all| {
+                    all.network.resize(locations.len(), Vec::new());
+                    for (files, scanned) in all.network.iter_mut().zip(scanned) {
+                        if let Some(scanned) = scanned {
+                            *files = scanned;
+                        }
+                    }
+                });
+                info!(
+                    "Scanning {} network locations took {:?}",
+                    locations.len(),
+                    t.elapsed()
+                );
+            }
+
+            if interval.is_zero() || !sleep_unless_shutdown(interval) {
+                return;
+            }
+        }
+    });
+    BACKGROUND_THREADS.lock().unwrap().push(thread);
+}
+
+/// Indexes the volumes other than `main_volume` which have no MFT, like USB sticks formatted with
+/// FAT32 or exFAT, and keeps them up to date by watching them for changes. They are searched
+/// together with the network locations, so their changes are not passed to the `on_changes` of
+/// [build_index]. Volumes which can't be watched anymore, for example because they were removed,
+/// are dropped from the index.
+fn start_volume_thread(
+    index: Arc<Mutex<NtfsVolumeIndex>>,
+    main_volume: Volume,
+    options: JournalOptions,
+) {
+    let volumes = get_volumes()
+        .into_iter()
+        .filter(|vol| vol.id != main_volume.id)
+        .filter_map(|vol| match vol.file_system() {
+            Ok(file_system) if !matches!(file_system.as_str(), "NTFS" | "ReFS") => {
+                Some((vol, file_system))
+            }
+            Ok(_) => None,
+            Err(e) => {
+                warn!("Failed to query the file system of {}: {:?}", vol.id, e);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    if volumes.is_empty() {
+        return;
+    }
+
+    let thread = std::thread::spawn(move || {
+        let mut watched = Vec::new();
+        for (vol, file_system) in volumes {
+            if is_shutting_down() {
+                return;
+            }
+            match watch_volume(vol, &file_system) {
+                Ok((watcher, volume_index)) => {
+                    publish_volume(&index, &volume_index);
+                    watched.push((watcher, volume_index));
+                }
+                Err(e) => warn!("Failed to index volume {}: {:?}", vol.id, e),
+            }
+        }
+
+        while sleep_unless_shutdown(options.poll_interval) {
+            // The system keeps collecting changes in the meantime
+            if is_paused() {
+                continue;
+            }
+
+            watched.retain_mut(|(watcher, volume_index)| {
+                let vol = volume_index.volume();
+                match watcher.read_changes() {
+                    Ok(Some(changes)) if changes.is_empty() => {}
+                    Ok(Some(changes)) => {
+                        let start = Instant::now();
+                        let changes = volume_index.process_path_changes(&changes);
+                        owner::forget(&changes);
+                        publish_volume(&index, volume_index);
+                        record_batch(start, changes.len());
+                    }
+                    Ok(None) => {
+                        if let Some(fresh) = rescan(vol) {
+                            volume_index.replace_entries(fresh);
+                            publish_volume(&index, volume_index);
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Stopped watching volume {}: {:?}", vol.id, e);
+                        update_locations(&index, |all| all.volumes.retain(|(id, _)| *id != vol.id));
+                        return false;
+                    }
+                }
+                true
+            });
+        }
+    });
+    BACKGROUND_THREADS.lock().unwrap().push(thread);
+}
+
+/// Replaces the entries of a volume indexed by [start_volume_thread] in the main index.
+fn publish_volume(index: &Mutex<NtfsVolumeIndex>, volume_index: &NtfsVolumeIndex) {
+    let id = volume_index.volume().id;
+    let files = volume_index.to_location();
+    update_locations(index, |all| {
+        match all.volumes.iter_mut().find(|(volume, _)| *volume == id) {
+            Some((_, location)) => *location = files,
+            None => all.volumes.push((id, files)),
+        }
+    });
+}
+
+/// Network locations and other volumes are all stored as network locations of the index, which
+/// are replaced at once, see [NtfsVolumeIndex::set_network_files].
+#[derive(Default)]
+struct Locations {
+    /// Entries of each network location, in the order of the configuration
+    network: Vec<Vec<FileInfo>>,
+    /// Entries of each volume without an MFT other than the indexed one
+    volumes: Vec<(char, Vec<FileInfo>)>,
+}
+
+/// Changes the entries of some locations and stores all of them in the index.
+fn update_locations(index: &Mutex<NtfsVolumeIndex>, update: impl FnOnce(&mut Locations)) {
+    let mut locations = LOCATIONS.lock().unwrap();
+    update(&mut locations);
+    let all = locations
+        .network
+        .iter()
+        .chain(locations.volumes.iter().map(|(_, files)| files))
+        .map(Vec::as_slice)
+        .collect::<Vec<_>>();
+    index.lock().unwrap().set_network_files(&all);
+}
+
+/// Sleeps for `duration`, but wakes up regularly to check for [shutdown], as the duration is
+/// usually hours. Returns false if the thread should return.
+fn sleep_unless_shutdown(duration: Duration) -> bool {
+    let end = Instant::now() + duration;
+    while let Some(remaining) = end.checked_duration_since(Instant::now()) {
+        if is_shutting_down() {
+            return false;
+        }
+        std::thread::sleep(remaining.min(RETRY_CHECK_INTERVAL));
+    }
+    !is_shutting_down()
+}
+
+/// Reads the volume again and corrects the entries of the index which differ. Returns the number
+/// of corrected files, see [NtfsVolumeIndex::reconcile].
+pub fn verify(index: &Mutex<NtfsVolumeIndex>) -> Result<usize> {
+    let (volume, from_mft, file_ids) = {
+        let mut index = index.lock().unwrap();
+        // Files don't have stable ids, so the indices of a fresh index wouldn't match
+        if index.is_from_directory_walk() {
+            return Err(eyre!(
+                "Volumes without a journal are watched for changes and can't be verified"
+            ));
+        }
+        index.begin_verification();
+        (index.volume(), index.is_from_mft(), index.file_ids())
+    };
+    let _verification = Verification(index);
+    // Read the volume the same way the index was built, without holding the lock
+    let fresh = if from_mft {
+        NtfsVolumeIndex::new(volume)?
+    } else if file_ids.is_some() {
+        NtfsVolumeIndex::from_refs(volume, file_ids)?
+    } else {
+        NtfsVolumeIndex::from_usn_data(volume)?
+    };
+    // Entries of unreadable records would be removed by mistake
+    if fresh.unreadable_records() > 0 {
+        return Err(eyre!("Some MFT records could not be read"));
+    }
+
+    let mut index = index.lock().unwrap();
+    let corrections = index.reconcile(fresh);
+    repair_orphans(&mut index);
+    Ok(corrections)
+}
+
+/// Ends the verification of the index when dropped, so that the records changed by the journal
+/// aren't collected forever if reading the volume again fails.
+struct Verification<'a>(&'a Mutex<NtfsVolumeIndex>);
+
+impl Drop for Verification<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut index) = self.0.lock() {
+            index.end_verification();
+        }
+    }
+}
+
+/// Result of [verify_directories]
+#[derive(Debug, Default)]
+pub struct DirectoryCheck {
+    pub directories: usize,
+    /// Directories whose index could not be read, see
+    /// [ntfs::directory_index::read_entries]
+    pub skipped: usize,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+/// Reads the `$I30` index of every directory and compares it with the children in the index,
+/// without changing anything. Directories are read in chunks without holding the index, so
+/// changes which the journal has not applied yet can show up as discrepancies.
+pub fn verify_directories(index: &Mutex<NtfsVolumeIndex>) -> Result<DirectoryCheck> {
+    let (location, directories) = {
+        let index = index.lock().unwrap();
+        let location = index.mft_location().with_context(|| {
+            "Only indices read from the MFT can be verified against their directories"
+        })?;
+        (location, index.directories())
+    };
+
+    let mut check = DirectoryCheck {
+        directories: directories.len(),
+        ..Default::default()
+    };
+    for chunk in directories.chunks(DIRECTORY_CHECK_CHUNK_SIZE) {
+        let entries = chunk
+            .par_iter()
+            .map(|&directory| (directory, location.read_directory(directory)))
+            .collect::<Vec<_>>();
+
+        let index = index.lock().unwrap();
+        for (directory, entries) in entries {
+            match entries {
+                Ok(entries) => {
+                    let discrepancies = index.compare_directory(directory, &entries);
+                    check.discrepancies.extend(discrepancies);
+                }
+                Err(e) => {
+                    debug!("{:?}", e);
+                    check.skipped += 1;
+                }
+            }
+        }
+    }
+
+    info!(
+        "Checked {} directories, skipped {}, found {} discrepancies",
+        check.directories,
+        check.skipped,
+        check.discrepancies.len()
+    );
+    for discrepancy in &check.discrepancies {
+        warn!(
+            "{:?}: {} ({})",
+            discrepancy.kind, discrepancy.path, discrepancy.mft_index
+        );
+    }
+    Ok(check)
+}
+
+/// Repairs entries which don't lead to the root directory and logs those which could not be
+/// repaired, see [NtfsVolumeIndex::repair_orphans].
+pub fn repair_orphans(index: &mut NtfsVolumeIndex) -> Vec<Orphan> {
+    let orphans = index.repair_orphans();
+    let quarantined = orphans.iter().filter(|orphan| !orphan.resolved).count();
+    if !orphans.is_empty() {
+        warn!(
+            "Found {} orphaned entries, repaired {}, quarantined {}",
+            orphans.len(),
+            orphans.len() - quarantined,
+            quarantined
+        );
+    }
+    for orphan in orphans.iter().filter(|orphan| !orphan.resolved) {
+        warn!("Quarantined {} ({})", orphan.name, orphan.mft_index);
+    }
+    orphans
+}
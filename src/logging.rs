@@ -0,0 +1,129 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, Layer};
+
+use crate::config::{self, LogLevel};
+use crate::filetime;
+
+const MAX_ENTRIES: usize = 2000;
+/// Log files older than this many days are deleted
+const MAX_LOG_FILES: usize = 7;
+
+/// The most recent log messages, shown in the log tab of the UI. Old entries are dropped once it is
+/// full.
+#[derive(Debug, Default)]
+pub struct RecentLog {
+    entries: VecDeque<LogEntry>,
+    next_sequence: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    /// Increases by one for every message, which lets readers poll for new entries
+    pub sequence: u64,
+    /// FILETIME at which the message was logged
+    pub time: u64,
+    pub level: Level,
+    pub message: String,
+}
+
+impl RecentLog {
+    fn push(&mut self, level: Level, message: String) {
+        if self.entries.len() >= MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(LogEntry {
+            sequence: self.next_sequence,
+            time: filetime::now(),
+            level,
+            message,
+        });
+        self.next_sequence += 1;
+    }
+
+    /// The sequence number which the next message will get.
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    /// Returns all entries which are still stored and have a sequence number of at least
+    /// `sequence`, oldest first.
+    pub fn since(&self, sequence: u64) -> impl Iterator<Item = &LogEntry> {
+        let skip = self
+            .entries
+            .partition_point(|entry| entry.sequence < sequence);
+        self.entries.range(skip..)
+    }
+}
+
+/// Sends log messages to stderr, to a log file in `%APPDATA%\reverything\logs` which is rotated
+/// daily and to the returned [RecentLog].
+pub fn init(level: LogLevel) -> Arc<Mutex<RecentLog>> {
+    let recent = Arc::new(Mutex::new(RecentLog::default()));
+
+    let file = config::app_dir().and_then(|dir| {
+        RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix("reverything")
+            .filename_suffix("log")
+            .max_log_files(MAX_LOG_FILES)
+            .build(dir.join("logs"))
+            .map_err(Into::into)
+    });
+    let (file, file_error) = match file {
+        Ok(file) => (Some(file), None),
+        Err(e) => (None, Some(e)),
+    };
+
+    tracing_subscriber::registry()
+        .with(level.filter())
+        .with(fmt::layer().with_writer(std::io::stderr))
+        .with(file.map(|file| fmt::layer().with_ansi(false).with_writer(file)))
+        .with(RecentLogLayer(recent.clone()))
+        .init();
+
+    if let Some(e) = file_error {
+        tracing::error!("Failed to open log file: {:?}", e);
+    }
+
+    recent
+}
+
+struct RecentLogLayer(Arc<Mutex<RecentLog>>);
+
+impl<S: Subscriber> Layer<S> for RecentLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        self.0
+            .lock()
+            .unwrap()
+            .push(*event.metadata().level(), visitor.0);
+    }
+}
+
+/// Formats the message of an event followed by its other fields.
+#[derive(Default)]
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+
+        if field.name() == "message" {
+            let _ = write!(self.0, "{:?}", value);
+        } else {
+            let _ = write!(self.0, "{}={:?}", field.name(), value);
+        }
+    }
+}
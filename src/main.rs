@@ -1,47 +1,159 @@
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::Duration;
 
-use crate::ntfs::index::NtfsVolumeIndex;
-use crate::ntfs::journal::Journal;
-use crate::ntfs::volume::get_volumes;
-use eyre::{ContextCompat, Result};
+use crate::backend::Backend;
+use crate::changelog::ChangeLog;
+use crate::cli::Command;
+use crate::config::{Config, LogLevel};
+use eyre::Result;
 use mimalloc_rust::GlobalMiMalloc;
+use reverything::{
+    build_index, changelog, config, filetime, history, metrics, ntfs, owner, query, recycle_bin,
+    search, stats,
+};
+use tracing::{error, info};
 
-mod ntfs;
+mod autostart;
+mod backend;
+mod checksum;
+mod cli;
+mod content_search;
+mod duplicates;
+mod everything_ipc;
+mod explorer_menu;
+mod external_tools;
+mod fileops;
+mod format;
+mod icons;
+mod instance;
+mod ipc;
+mod jump_list;
+mod logging;
+mod preview;
+#[cfg(feature = "scripting")]
+mod script;
+mod service;
+mod shell;
 mod ui;
+mod watchers;
 
 #[global_allocator]
 static GLOBAL: GlobalMiMalloc = GlobalMiMalloc;
 
 fn main() -> Result<()> {
-    let vol = get_volumes()
-        .into_iter()
-        .next()
-        .with_context(|| "Cannot find first volume")?;
-    let journal = Journal::new(vol)?;
-
-    let t = Instant::now();
-    let index = Arc::new(Mutex::new(NtfsVolumeIndex::new(vol)?));
-    println!("Building index took: {:?}", t.elapsed());
-
-    start_journal_thread(journal, index.clone());
-    
-    ui::run_ui(index.clone())?;
-    Ok(())
-}
+    let command = Command::parse(std::env::args().skip(1))?;
+    let config = Config::try_load();
+    let recent_log = logging::init(config.as_ref().map_or(LogLevel::default(), |c| c.log_level));
+    let config = config.unwrap_or_else(|e| {
+        error!("{:?}", e);
+        Config::default()
+    });
+    ntfs::index::set_read_queue_depth(config.mft_read_queue_depth);
+    ntfs::index::set_low_io_priority(config.low_io_priority);
 
-fn start_journal_thread(mut journal: Journal, index: Arc<Mutex<NtfsVolumeIndex>>) {
-    std::thread::spawn(move || {
-        loop {
-            std::thread::sleep(std::time::Duration::from_secs(1));
+    match command {
+        Command::Ui { query, minimized } => {
+            // Building a second index would need just as much memory, so reuse the running one
+            let Some(_instance) = instance::acquire()? else {
+                return instance::forward(&query);
+            };
 
-            let vec = journal.read_entries().unwrap();
-            if vec.is_empty() {
-                continue;
+            let change_log = Arc::new(Mutex::new(ChangeLog::default()));
+            // Search the index of the service if it runs, and only build one as a fallback
+            let backend = match ipc::Client::connect() {
+                Ok(_) => {
+                    info!("Using the index of the reverything service");
+                    backend::start_change_thread(change_log.clone());
+                    Backend::Service
+                }
+                Err(e) => {
+                    info!("Building a local index: {:#}", e);
+                    Backend::Local(build_local_index(&config, change_log.clone())?)
+                }
+            };
+            let res = ui::run_ui(
+                backend.clone(),
+                change_log,
+                recent_log,
+                config,
+                query,
+                minimized,
+            );
+            if let Backend::Local(index) = &backend {
+                reverything::shutdown(index);
             }
+            res?;
+        }
+        Command::Service => service::run()?,
+        Command::InstallService => service::install()?,
+        Command::UninstallService => service::uninstall()?,
+        Command::Search { query, sort, ids } => cli::search(&query, sort, ids)?,
+        Command::Resolve(id) => cli::resolve(id)?,
+        Command::Watch(kinds) => cli::watch(&kinds)?,
+        Command::Duplicates(query) => cli::duplicates(&query)?,
+        Command::Grep { pattern, query } => cli::grep(&pattern, &query)?,
+        Command::Checksum(query) => cli::checksum(&query)?,
+        Command::BenchmarkIndex => cli::benchmark_index()?,
+        Command::SetPaused(paused) => cli::set_paused(paused)?,
+        Command::Info => cli::info()?,
+        Command::Metrics => cli::metrics()?,
+        Command::Stats => cli::stats()?,
+        Command::Verify { deep } => cli::verify(deep)?,
+        Command::Saved(name) => cli::saved(&name)?,
+        Command::Script {
+            path,
+            args,
+            allow_write,
+        } => cli::script(&path, &args, allow_write)?,
+    }
+
+    Ok(())
+}
 
-            let mut index = index.lock().unwrap();
-            index.process_journal_entries(&vec);
+/// Builds the index which the UI uses while the service is not running, along with everything the
+/// service would otherwise keep up to date.
+fn build_local_index(
+    config: &Config,
+    change_log: Arc<Mutex<ChangeLog>>,
+) -> Result<Arc<Mutex<ntfs::index::NtfsVolumeIndex>>> {
+    let watchers = Arc::new(watchers::Watchers::new(config.watch_rules.clone()));
+    let index = loop {
+        let res = build_index(config.index_mode, config.journal_options(), {
+            let change_log = change_log.clone();
+            let watchers = watchers.clone();
+            move |index, changes| {
+                change_log.lock().unwrap().record(changes);
+                watchers.process(index, changes);
+            }
+        });
+        match res {
+            Ok(index) => break index,
+            Err(e) => {
+                error!("Building the index failed: {:?}", e);
+                if !shell::ask_retry(&format!("Building the index failed:\n\n{:#}", e)) {
+                    return Err(e);
+                }
+            }
         }
-    });
+    };
+    if config.trigram_index {
+        index.lock().unwrap().build_trigram_index();
+    }
+    if config.verify_interval_hours > 0 {
+        reverything::start_verification_thread(
+            index.clone(),
+            Duration::from_secs(config.verify_interval_hours * 60 * 60),
+        );
+    }
+    if !config.network_locations.is_empty() {
+        reverything::start_network_thread(
+            index.clone(),
+            config.network_locations.clone(),
+            Duration::from_secs(config.network_rescan_minutes * 60),
+        );
+    }
+    if config.everything_ipc {
+        everything_ipc::start(index.clone());
+    }
+    Ok(index)
 }
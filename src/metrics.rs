@@ -0,0 +1,88 @@
+//! How long searches and applying changes to the index take, shown by the UI and reported to IPC
+//! clients for monitoring.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics::new());
+
+/// Timing of a single search
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FilterMetrics {
+    pub duration: Duration,
+    /// Entries which were checked against the query. Fewer than the index holds if the trigram
+    /// index or the results of a previous query narrowed them down. Network locations are not
+    /// counted.
+    pub scanned: usize,
+    pub matched: usize,
+}
+
+/// Timing of a batch of journal entries or directory changes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BatchMetrics {
+    /// Time from reading the batch until it was applied, including waiting for the index lock
+    pub duration: Duration,
+    pub entries: usize,
+}
+
+/// The last search and batch since the start of the process, and totals over all of them
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Metrics {
+    pub last_filter: Option<FilterMetrics>,
+    pub filters: u64,
+    pub filter_time: Duration,
+    pub last_batch: Option<BatchMetrics>,
+    pub batches: u64,
+    pub batch_entries: u64,
+    pub batch_time: Duration,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            last_filter: None,
+            filters: 0,
+            filter_time: Duration::ZERO,
+            last_batch: None,
+            batches: 0,
+            batch_entries: 0,
+            batch_time: Duration::ZERO,
+        }
+    }
+
+    /// Average duration of a search, zero if there was none yet
+    pub fn average_filter_time(&self) -> Duration {
+        average(self.filter_time, self.filters)
+    }
+
+    /// Average duration of applying a batch, zero if there was none yet
+    pub fn average_batch_time(&self) -> Duration {
+        average(self.batch_time, self.batches)
+    }
+}
+
+pub fn record_filter(filter: FilterMetrics) {
+    let mut metrics = METRICS.lock().unwrap();
+    metrics.last_filter = Some(filter);
+    metrics.filters += 1;
+    metrics.filter_time += filter.duration;
+}
+
+pub fn record_batch(batch: BatchMetrics) {
+    let mut metrics = METRICS.lock().unwrap();
+    metrics.last_batch = Some(batch);
+    metrics.batches += 1;
+    metrics.batch_entries += batch.entries as u64;
+    metrics.batch_time += batch.duration;
+}
+
+pub fn get() -> Metrics {
+    *METRICS.lock().unwrap()
+}
+
+fn average(total: Duration, count: u64) -> Duration {
+    match count {
+        0 => Duration::ZERO,
+        count => Duration::from_nanos((total.as_nanos() / count as u128) as u64),
+    }
+}
@@ -0,0 +1,60 @@
+use eyre::Result;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use tracing::warn;
+use windows::Win32::Storage::FileSystem::{FILE_ATTRIBUTE_DIRECTORY, FILE_ATTRIBUTE_REPARSE_POINT};
+
+use crate::ntfs::fallback::{list_directory, to_file_info};
+use crate::ntfs::index::{FileInfo, FileTimes, ROOT_INDEX};
+
+/// Lists everything inside a network location, given as a UNC path like `\\server\share` or a
+/// mapped drive like `Z:\`. The directories of each level are listed in parallel on `pool`, as
+/// most of the time is spent waiting for the server.
+///
+/// Returns the entries in the layout expected by
+/// [NtfsVolumeIndex::set_network_files](crate::ntfs::index::NtfsVolumeIndex::set_network_files).
+/// Fails if the location itself can't be listed, subdirectories which can't be listed are skipped.
+pub fn scan(location: &str, pool: &ThreadPool) -> Result<Vec<FileInfo>> {
+    let root = location.trim_end_matches('\\');
+    let mut files = vec![FileInfo::new(
+        0,
+        0,
+        true,
+        ROOT_INDEX,
+        root.into(),
+        FileTimes::default(),
+        FILE_ATTRIBUTE_DIRECTORY.0,
+    )];
+
+    let mut level = vec![(0, root.to_string(), list_directory(root)?)];
+    while !level.is_empty() {
+        let mut pending = Vec::new();
+        for (parent, directory, entries) in level {
+            for data in entries {
+                let info = to_file_info(&data, parent);
+                // Junctions could lead into a cycle
+                if info.is_directory()
+                    && data.dwFileAttributes & FILE_ATTRIBUTE_REPARSE_POINT.0 == 0
+                {
+                    pending.push((files.len() as u64, format!("{}\\{}", directory, info.name)));
+                }
+                files.push(info);
+            }
+        }
+
+        level = pool.install(|| {
+            pending
+                .into_par_iter()
+                .filter_map(|(parent, directory)| match list_directory(&directory) {
+                    Ok(entries) => Some((parent, directory, entries)),
+                    Err(e) => {
+                        warn!("{:?}", e);
+                        None
+                    }
+                })
+                .collect()
+        });
+    }
+
+    Ok(files)
+}
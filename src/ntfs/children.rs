@@ -0,0 +1,88 @@
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+
+use crate::ntfs::file_infos::FileInfos;
+
+/// Number of records whose parents are collected into one map before the maps are merged
+const BUILD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Maps the MFT index of every directory to the MFT indices of the entries directly inside of it.
+/// Entries only store their parent, so without this, finding the contents of a directory requires
+/// checking every entry of the volume.
+#[derive(Debug, Default)]
+pub struct ChildrenMap {
+    /// u32 is enough for every real MFT and halves the memory needed
+    children: FxHashMap<u64, Vec<u32>>,
+}
+
+impl ChildrenMap {
+    pub fn build(infos: &FileInfos) -> Self {
+        let children = infos
+            .par_chunks(BUILD_CHUNK_SIZE)
+            .enumerate()
+            .map(|(chunk, infos)| {
+                let mut children = FxHashMap::<u64, Vec<u32>>::default();
+                for (i, info) in infos.enumerate() {
+                    if let Some(info) = info {
+                        let index = (chunk * BUILD_CHUNK_SIZE + i) as u32;
+                        children.entry(info.parent()).or_default().push(index);
+                    }
+                }
+                children
+            })
+            .reduce(FxHashMap::default, |mut left, right| {
+                for (parent, indices) in right {
+                    left.entry(parent).or_default().extend(indices);
+                }
+                left
+            });
+
+        Self { children }
+    }
+
+    pub fn insert(&mut self, parent: u64, index: u64) {
+        let indices = self.children.entry(parent).or_default();
+        if !indices.contains(&(index as u32)) {
+            indices.push(index as u32);
+        }
+    }
+
+    pub fn remove(&mut self, parent: u64, index: u64) {
+        let Some(indices) = self.children.get_mut(&parent) else {
+            return;
+        };
+        if let Some(pos) = indices.iter().position(|&i| i == index as u32) {
+            indices.swap_remove(pos);
+        }
+        if indices.is_empty() {
+            self.children.remove(&parent);
+        }
+    }
+
+    /// Frees unused capacity left behind by removed entries. Returns the number of bytes which were
+    /// freed.
+    pub fn shrink_to_fit(&mut self) -> usize {
+        let before = self.heap_size();
+        self.children.shrink_to_fit();
+        self.children.values_mut().for_each(Vec::shrink_to_fit);
+        before - self.heap_size()
+    }
+
+    fn heap_size(&self) -> usize {
+        self.children.capacity() * size_of::<(u64, Vec<u32>)>()
+            + self
+                .children
+                .values()
+                .map(|indices| indices.capacity() * size_of::<u32>())
+                .sum::<usize>()
+    }
+
+    /// Returns the MFT indices of the entries in the directory, in no particular order.
+    pub fn get(&self, parent: u64) -> impl Iterator<Item = u64> + '_ {
+        self.children
+            .get(&parent)
+            .into_iter()
+            .flatten()
+            .map(|&index| index as u64)
+    }
+}
@@ -0,0 +1,506 @@
+//! Parses the `$I30` index of directories, the B-tree in which NTFS stores the names of the files
+//! inside a directory. The index itself is built from the file name attributes of the files, so
+//! both can be compared to find entries whose parent is wrong.
+
+use eyre::{eyre, ContextCompat, Result};
+use windows::Win32::System::Ioctl::NTFS_VOLUME_DATA_BUFFER;
+
+use crate::ntfs::file_attribute::{Attribute, AttributeType};
+use crate::ntfs::file_record::FileRecord;
+use crate::ntfs::mft::read_runs;
+use crate::ntfs::volume::VolumeRead;
+
+/// Name of the index attributes of a directory, which index the file names
+const FILE_NAME_INDEX: &str = "$I30";
+/// Offset of the index header in the content of `$INDEX_ROOT`
+const ROOT_HEADER_OFFSET: usize = 0x10;
+/// Offset of the index header in a block of `$INDEX_ALLOCATION`
+const BLOCK_HEADER_OFFSET: usize = 0x18;
+/// Set in the index header of `$INDEX_ROOT` if the directory has index blocks in
+/// `$INDEX_ALLOCATION`
+const LARGE_INDEX: u8 = 0x1;
+/// Set for the entry which ends a node. It only points to a subnode and has no file name.
+const LAST_ENTRY: u32 = 0x2;
+/// Size of the header of an index entry, which is followed by the file name attribute
+const ENTRY_HEADER_SIZE: usize = 0x10;
+/// Offsets in the file name attribute stored as the key of an index entry
+const KEY_NAME_LENGTH: usize = 0x40;
+const KEY_NAMESPACE: usize = 0x41;
+const KEY_NAME: usize = 0x42;
+const NAMESPACE_DOS: u8 = 2;
+
+/// A file listed in the index of a directory.
+#[derive(Debug, Clone)]
+pub struct DirectoryEntry {
+    pub mft_index: u64,
+    /// Sequence number of the file's record when it was added to the directory
+    pub sequence: u16,
+    pub name: String,
+}
+
+/// Reads the entries of the directory stored in `record`, which must have been fixed up already.
+/// Short DOS names are left out, as the same file is listed with its long name as well. Fails for
+/// directories whose index attributes are stored in other records, which only happens for huge
+/// or very fragmented directories.
+pub fn read_entries(
+    record: &FileRecord,
+    vol: &dyn VolumeRead,
+    volume_data: NTFS_VOLUME_DATA_BUFFER,
+) -> Result<Vec<DirectoryEntry>> {
+    let root = find_index_attribute(record, AttributeType::IndexRoot)
+        .with_context(|| "Cannot find $INDEX_ROOT attribute")?;
+    let root = root
+        .resident_value()
+        .with_context(|| "$INDEX_ROOT is not resident")?;
+
+    let mut entries = Vec::new();
+    parse_node(root, ROOT_HEADER_OFFSET, &mut entries)?;
+    let flags = *root
+        .get(ROOT_HEADER_OFFSET + 0x0C)
+        .with_context(|| "$INDEX_ROOT is too short")?;
+    if flags & LARGE_INDEX == 0 {
+        return Ok(entries);
+    }
+
+    let block_size = read_u32(root, 0x08)? as usize;
+    if block_size < BLOCK_HEADER_OFFSET {
+        return Err(eyre!("Index block size {} is invalid", block_size));
+    }
+    let allocation = find_index_attribute(record, AttributeType::IndexAllocation)
+        .with_context(|| "Cannot find $INDEX_ALLOCATION attribute")?;
+    let (size, runs) = allocation
+        .decode_data_runs(volume_data.BytesPerCluster as usize)
+        .with_context(|| "$INDEX_ALLOCATION is not non-resident")?;
+    let mut blocks = vec![0u8; size];
+    read_runs(vol, &runs, 0, &mut blocks)?;
+
+    let bitmap = read_bitmap(record, vol, volume_data)?;
+    for (i, block) in blocks.chunks_exact_mut(block_size).enumerate() {
+        // Unused blocks still contain the entries they had before
+        if bitmap
+            .get(i / 8)
+            .is_none_or(|byte| byte & (1 << (i % 8)) == 0)
+        {
+            continue;
+        }
+        if &block[..4] != b"INDX" {
+            return Err(eyre!("Index block {} is invalid", i));
+        }
+
+        fixup(block)?;
+        parse_node(block, BLOCK_HEADER_OFFSET, &mut entries)?;
+    }
+
+    Ok(entries)
+}
+
+fn find_index_attribute<'a>(
+    record: &'a FileRecord,
+    attribute_type: AttributeType,
+) -> Option<Attribute<'a>> {
+    record.attributes().find(|attr| {
+        let t = attr.header.attribute_type;
+        t == attribute_type && attr.name() == FILE_NAME_INDEX
+    })
+}
+
+/// Reads the bitmap in which bit n is set if block n of `$INDEX_ALLOCATION` is in use.
+fn read_bitmap(
+    record: &FileRecord,
+    vol: &dyn VolumeRead,
+    volume_data: NTFS_VOLUME_DATA_BUFFER,
+) -> Result<Vec<u8>> {
+    let attr = find_index_attribute(record, AttributeType::Bitmap)
+        .with_context(|| "Cannot find $BITMAP attribute of the index")?;
+    if let Some(value) = attr.resident_value() {
+        return Ok(value.to_vec());
+    }
+
+    let (size, runs) = attr
+        .decode_data_runs(volume_data.BytesPerCluster as usize)
+        .with_context(|| "Cannot decode index bitmap data runs")?;
+    let mut bitmap = vec![0u8; size];
+    read_runs(vol, &runs, 0, &mut bitmap)?;
+    Ok(bitmap)
+}
+
+/// Appends the entries of the node whose index header starts at `header` in `data`. Entries of
+/// subnodes are not followed, as every block is parsed on its own.
+fn parse_node(data: &[u8], header: usize, entries: &mut Vec<DirectoryEntry>) -> Result<()> {
+    let first_entry = header + read_u32(data, header)? as usize;
+    let end = (header + read_u32(data, header + 4)? as usize).min(data.len());
+
+    let mut offset = first_entry;
+    while offset + ENTRY_HEADER_SIZE <= end {
+        let reference = read_u64(data, offset)?;
+        let length = read_u16(data, offset + 0x08)? as usize;
+        let key_length = read_u16(data, offset + 0x0A)? as usize;
+        let flags = read_u32(data, offset + 0x0C)?;
+        if flags & LAST_ENTRY != 0 {
+            return Ok(());
+        }
+        if length < ENTRY_HEADER_SIZE {
+            return Err(eyre!("Index entry at {:#x} is invalid", offset));
+        }
+
+        let key_start = offset + ENTRY_HEADER_SIZE;
+        let key = data
+            .get(key_start..key_start + key_length)
+            .with_context(|| format!("Index entry at {:#x} is too long", offset))?;
+        if key_length > KEY_NAME && key[KEY_NAMESPACE] != NAMESPACE_DOS {
+            let name_end = KEY_NAME + key[KEY_NAME_LENGTH] as usize * 2;
+            let name = key
+                .get(KEY_NAME..name_end)
+                .with_context(|| format!("Name of index entry at {:#x} is too long", offset))?
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .collect::<Vec<_>>();
+            entries.push(DirectoryEntry {
+                mft_index: reference & 0x0000_ffff_ffff_ffff,
+                sequence: (reference >> 48) as u16,
+                name: String::from_utf16_lossy(&name),
+            });
+        }
+        offset += length;
+    }
+
+    Err(eyre!("Index node is missing its last entry"))
+}
+
+/// Restores the last two bytes of every sector of an index block, which were replaced by the
+/// update sequence number when it was written, see [FileRecord::fixup].
+fn fixup(block: &mut [u8]) -> Result<()> {
+    let usa_offset = read_u16(block, 0x04)? as usize;
+    let usa_count = read_u16(block, 0x06)? as usize;
+    if usa_count < 2 || !block.len().is_multiple_of(usa_count - 1) {
+        return Err(eyre!("Index block has an invalid update sequence"));
+    }
+
+    let stride = block.len() / (usa_count - 1);
+    let sequence: [u8; 2] = read_bytes(block, usa_offset)?;
+    for i in 1..usa_count {
+        let sector_end = i * stride;
+        if block[sector_end - 2..sector_end] != sequence {
+            return Err(eyre!("Index block is torn"));
+        }
+        let original: [u8; 2] = read_bytes(block, usa_offset + i * 2)?;
+        block[sector_end - 2..sector_end].copy_from_slice(&original);
+    }
+    Ok(())
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    Ok(u16::from_le_bytes(read_bytes(data, offset)?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    Ok(u32::from_le_bytes(read_bytes(data, offset)?))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64> {
+    Ok(u64::from_le_bytes(read_bytes(data, offset)?))
+}
+
+fn read_bytes<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N]> {
+    data.get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .with_context(|| format!("Offset {:#x} is outside of the index", offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ntfs::memory_volume::MemoryVolume;
+
+    const CLUSTER_SIZE: usize = 4096;
+    const SECTOR_SIZE: usize = 512;
+    const BLOCK_SIZE: usize = 4096;
+    /// Offset of the update sequence array in an index block, followed by the first entry
+    const BLOCK_USA_OFFSET: usize = 0x28;
+    const BLOCK_FIRST_ENTRY: usize = 0x40;
+    const SEQUENCE: u16 = 4;
+    const NAMESPACE_WIN32: u8 = 1;
+
+    fn entry(mft_index: u64, name: &str, namespace: u8) -> Vec<u8> {
+        let name = name.encode_utf16().collect::<Vec<_>>();
+        let mut key = vec![0u8; KEY_NAME + name.len() * 2];
+        key[KEY_NAME_LENGTH] = name.len() as u8;
+        key[KEY_NAMESPACE] = namespace;
+        for (i, c) in name.iter().enumerate() {
+            key[KEY_NAME + i * 2..KEY_NAME + i * 2 + 2].copy_from_slice(&c.to_le_bytes());
+        }
+
+        let length = (ENTRY_HEADER_SIZE + key.len()).next_multiple_of(8);
+        let reference = mft_index | (SEQUENCE as u64) << 48;
+        let mut entry = vec![0u8; length];
+        entry[0..8].copy_from_slice(&reference.to_le_bytes());
+        entry[8..10].copy_from_slice(&(length as u16).to_le_bytes());
+        entry[10..12].copy_from_slice(&(key.len() as u16).to_le_bytes());
+        entry[ENTRY_HEADER_SIZE..ENTRY_HEADER_SIZE + key.len()].copy_from_slice(&key);
+        entry
+    }
+
+    fn last_entry() -> Vec<u8> {
+        let mut entry = vec![0u8; ENTRY_HEADER_SIZE];
+        entry[8..10].copy_from_slice(&(ENTRY_HEADER_SIZE as u16).to_le_bytes());
+        entry[12..16].copy_from_slice(&LAST_ENTRY.to_le_bytes());
+        entry
+    }
+
+    /// An index header followed by the entries, which start `first_entry` bytes after the header
+    fn node(entries: &[Vec<u8>], flags: u8, first_entry: usize) -> Vec<u8> {
+        let mut node = vec![0u8; first_entry];
+        for entry in entries {
+            node.extend_from_slice(entry);
+        }
+        node.extend_from_slice(&last_entry());
+
+        let end = node.len() as u32;
+        node[0..4].copy_from_slice(&(first_entry as u32).to_le_bytes());
+        node[4..8].copy_from_slice(&end.to_le_bytes());
+        node[8..12].copy_from_slice(&end.to_le_bytes());
+        node[12] = flags;
+        node
+    }
+
+    /// The value of `$INDEX_ROOT`
+    fn root(entries: &[Vec<u8>], large: bool) -> Vec<u8> {
+        let mut root = vec![0u8; ROOT_HEADER_OFFSET];
+        root[0..4].copy_from_slice(&(AttributeType::FileName as u32).to_le_bytes());
+        root[4..8].copy_from_slice(&1u32.to_le_bytes());
+        root[8..12].copy_from_slice(&(BLOCK_SIZE as u32).to_le_bytes());
+        root[12] = (BLOCK_SIZE / CLUSTER_SIZE) as u8;
+        root.extend(node(entries, large as u8, 0x10));
+        root
+    }
+
+    /// An index block of `$INDEX_ALLOCATION`, protected by an update sequence array like on disk
+    fn block(entries: &[Vec<u8>]) -> Vec<u8> {
+        let node = node(entries, 0, BLOCK_FIRST_ENTRY - BLOCK_HEADER_OFFSET);
+        assert!(BLOCK_HEADER_OFFSET + node.len() <= BLOCK_SIZE);
+
+        let mut block = vec![0u8; BLOCK_SIZE];
+        block[BLOCK_HEADER_OFFSET..BLOCK_HEADER_OFFSET + node.len()].copy_from_slice(&node);
+        block[0..4].copy_from_slice(b"INDX");
+        let usa_count = 1 + BLOCK_SIZE / SECTOR_SIZE;
+        block[4..6].copy_from_slice(&(BLOCK_USA_OFFSET as u16).to_le_bytes());
+        block[6..8].copy_from_slice(&(usa_count as u16).to_le_bytes());
+
+        let update_sequence = [7, 0];
+        block[BLOCK_USA_OFFSET..BLOCK_USA_OFFSET + 2].copy_from_slice(&update_sequence);
+        for i in 1..usa_count {
+            let end = i * SECTOR_SIZE;
+            block.copy_within(end - 2..end, BLOCK_USA_OFFSET + i * 2);
+            block[end - 2..end].copy_from_slice(&update_sequence);
+        }
+        block
+    }
+
+    /// An attribute named `$I30` with the given content after the name
+    fn named_attribute(attribute_type: AttributeType, non_resident: bool, body: &[u8]) -> Vec<u8> {
+        let name_offset = if non_resident { 0x40 } else { 0x18 };
+        let value_offset = name_offset + 8;
+        let mut attribute = vec![0u8; (value_offset + body.len()).next_multiple_of(8)];
+        attribute[0..4].copy_from_slice(&(attribute_type as u32).to_le_bytes());
+        let length = attribute.len() as u32;
+        attribute[4..8].copy_from_slice(&length.to_le_bytes());
+        attribute[8] = non_resident as u8;
+        attribute[9] = 4;
+        attribute[0x0A..0x0C].copy_from_slice(&(name_offset as u16).to_le_bytes());
+        for (i, c) in FILE_NAME_INDEX.encode_utf16().enumerate() {
+            attribute[name_offset + i * 2..name_offset + i * 2 + 2]
+                .copy_from_slice(&c.to_le_bytes());
+        }
+        attribute[value_offset..value_offset + body.len()].copy_from_slice(body);
+
+        if non_resident {
+            attribute[0x20..0x22].copy_from_slice(&(value_offset as u16).to_le_bytes());
+        } else {
+            attribute[0x10..0x14].copy_from_slice(&(body.len() as u32).to_le_bytes());
+            attribute[0x14..0x16].copy_from_slice(&(value_offset as u16).to_le_bytes());
+        }
+        attribute
+    }
+
+    /// `$INDEX_ALLOCATION` stored in one run of `blocks` clusters starting at cluster 1
+    fn allocation(blocks: usize) -> Vec<u8> {
+        let size = (blocks * BLOCK_SIZE) as u64;
+        let mut attribute = named_attribute(
+            AttributeType::IndexAllocation,
+            true,
+            &[0x11, blocks as u8, 1, 0],
+        );
+        attribute[0x18..0x20].copy_from_slice(&(blocks as u64 - 1).to_le_bytes());
+        for offset in [0x28, 0x30, 0x38] {
+            attribute[offset..offset + 8].copy_from_slice(&size.to_le_bytes());
+        }
+        attribute
+    }
+
+    /// A fixed up directory record with the given attributes
+    fn record(attributes: &[Vec<u8>]) -> Vec<u8> {
+        const FIRST_ATTRIBUTE_OFFSET: usize = 0x38;
+
+        let mut record = vec![0u8; 1024];
+        let mut offset = FIRST_ATTRIBUTE_OFFSET;
+        for attribute in attributes {
+            record[offset..offset + attribute.len()].copy_from_slice(attribute);
+            offset += attribute.len();
+        }
+        record[offset..offset + 4].copy_from_slice(&(AttributeType::End as u32).to_le_bytes());
+
+        record[0..4].copy_from_slice(b"FILE");
+        record[16..18].copy_from_slice(&1u16.to_le_bytes());
+        record[20..22].copy_from_slice(&(FIRST_ATTRIBUTE_OFFSET as u16).to_le_bytes());
+        record[22..24].copy_from_slice(&3u16.to_le_bytes());
+        record[24..28].copy_from_slice(&(offset as u32 + 8).to_le_bytes());
+        record[28..32].copy_from_slice(&1024u32.to_le_bytes());
+        record
+    }
+
+    /// A volume with the blocks stored from cluster 1 on
+    fn volume(blocks: &[Vec<u8>]) -> (MemoryVolume, NTFS_VOLUME_DATA_BUFFER) {
+        let volume_data = NTFS_VOLUME_DATA_BUFFER {
+            BytesPerSector: SECTOR_SIZE as u32,
+            BytesPerCluster: CLUSTER_SIZE as u32,
+            BytesPerFileRecordSegment: 1024,
+            ..Default::default()
+        };
+        let mut data = vec![0u8; CLUSTER_SIZE];
+        for block in blocks {
+            data.extend_from_slice(block);
+        }
+        (MemoryVolume::new(data, volume_data), volume_data)
+    }
+
+    fn names(entries: &[DirectoryEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.name.as_str()).collect()
+    }
+
+    /// Long enough that the entries of a block cross the ends of its sectors
+    fn block_name(mft_index: u64) -> String {
+        format!("file {} with a long name to fill the block", mft_index)
+    }
+
+    fn block_entries(first: u64, count: u64) -> Vec<Vec<u8>> {
+        (first..first + count)
+            .map(|i| entry(i, &block_name(i), 3))
+            .collect()
+    }
+
+    #[test]
+    fn reads_small_directory_from_root() {
+        let root = root(
+            &[
+                entry(40, "Résumé.pdf", NAMESPACE_WIN32),
+                entry(40, "RSUM~1.PDF", NAMESPACE_DOS),
+                entry(41, "notes.txt", 3),
+            ],
+            false,
+        );
+        let record = record(&[named_attribute(AttributeType::IndexRoot, false, &root)]);
+        let (volume, volume_data) = volume(&[]);
+
+        let entries = read_entries(&FileRecord::new(&record), &volume, volume_data).unwrap();
+        assert_eq!(names(&entries), ["Résumé.pdf", "notes.txt"]);
+        assert_eq!(entries[0].mft_index, 40);
+        assert_eq!(entries[0].sequence, SEQUENCE);
+        assert_eq!(entries[1].mft_index, 41);
+    }
+
+    #[test]
+    fn reads_every_used_block() {
+        let root = root(&[entry(16, "root entry", NAMESPACE_WIN32)], true);
+        let blocks = [
+            block(&block_entries(100, 20)),
+            block(&block_entries(200, 20)),
+            block(&block_entries(300, 20)),
+        ];
+        // The second block is not in use and its entries are stale
+        let record = record(&[
+            named_attribute(AttributeType::IndexRoot, false, &root),
+            allocation(blocks.len()),
+            named_attribute(AttributeType::Bitmap, false, &[0b101, 0, 0, 0, 0, 0, 0, 0]),
+        ]);
+        let (volume, volume_data) = volume(&blocks);
+
+        let entries = read_entries(&FileRecord::new(&record), &volume, volume_data).unwrap();
+        let indices = entries.iter().map(|e| e.mft_index).collect::<Vec<_>>();
+        let expected = std::iter::once(16)
+            .chain(100..120)
+            .chain(300..320)
+            .collect::<Vec<_>>();
+        assert_eq!(indices, expected);
+        // Names crossing the end of a sector only match if the block was fixed up
+        for entry in &entries[1..] {
+            assert_eq!(entry.name, block_name(entry.mft_index));
+            assert_eq!(entry.sequence, SEQUENCE);
+        }
+    }
+
+    #[test]
+    fn fixup_restores_sector_ends() {
+        let entries = block_entries(100, 20);
+        let mut protected = block(&entries);
+        let mut expected = vec![0u8; BLOCK_SIZE];
+        let node = node(&entries, 0, BLOCK_FIRST_ENTRY - BLOCK_HEADER_OFFSET);
+        expected[BLOCK_HEADER_OFFSET..BLOCK_HEADER_OFFSET + node.len()].copy_from_slice(&node);
+
+        fixup(&mut protected).unwrap();
+        // Everything but the header and update sequence array matches the original again
+        assert_eq!(
+            protected[BLOCK_FIRST_ENTRY..],
+            expected[BLOCK_FIRST_ENTRY..]
+        );
+    }
+
+    #[test]
+    fn torn_block_is_rejected() {
+        let root = root(&[], true);
+        let mut torn = block(&block_entries(100, 20));
+        // The second sector was not written completely
+        torn[2 * SECTOR_SIZE - 1] ^= 0xFF;
+        let record = record(&[
+            named_attribute(AttributeType::IndexRoot, false, &root),
+            allocation(1),
+            named_attribute(AttributeType::Bitmap, false, &[1, 0, 0, 0, 0, 0, 0, 0]),
+        ]);
+        let (volume, volume_data) = volume(&[torn.clone()]);
+
+        let error = read_entries(&FileRecord::new(&record), &volume, volume_data).unwrap_err();
+        assert!(error.to_string().contains("torn"), "{}", error);
+        assert!(fixup(&mut torn).is_err());
+    }
+
+    #[test]
+    fn invalid_update_sequence_is_rejected() {
+        let mut block = block(&block_entries(100, 2));
+        // 3 entries can't cover 8 sectors evenly
+        block[6..8].copy_from_slice(&4u16.to_le_bytes());
+        assert!(fixup(&mut block.clone()).is_err());
+        block[6..8].copy_from_slice(&0u16.to_le_bytes());
+        assert!(fixup(&mut block).is_err());
+    }
+
+    #[test]
+    fn corrupt_nodes_are_rejected() {
+        let mut entries = Vec::new();
+        let mut missing_last = root(&[entry(40, "a.txt", NAMESPACE_WIN32)], false);
+        missing_last.truncate(missing_last.len() - ENTRY_HEADER_SIZE);
+        let end = (missing_last.len() - ROOT_HEADER_OFFSET) as u32;
+        missing_last[ROOT_HEADER_OFFSET + 4..ROOT_HEADER_OFFSET + 8]
+            .copy_from_slice(&end.to_le_bytes());
+        assert!(parse_node(&missing_last, ROOT_HEADER_OFFSET, &mut entries).is_err());
+
+        // An entry claiming to be shorter than its header would never advance
+        let mut short_entry = root(&[entry(40, "a.txt", NAMESPACE_WIN32)], false);
+        let first = ROOT_HEADER_OFFSET + 0x10;
+        short_entry[first + 8..first + 10].copy_from_slice(&4u16.to_le_bytes());
+        assert!(parse_node(&short_entry, ROOT_HEADER_OFFSET, &mut entries).is_err());
+
+        // A name running past the end of the node
+        let mut long_name = root(&[entry(40, "a.txt", NAMESPACE_WIN32)], false);
+        long_name[first + ENTRY_HEADER_SIZE + KEY_NAME_LENGTH] = 200;
+        assert!(parse_node(&long_name, ROOT_HEADER_OFFSET, &mut entries).is_err());
+    }
+}
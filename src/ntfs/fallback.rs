@@ -0,0 +1,336 @@
+use std::ffi::c_void;
+
+use eyre::{Context, Report, Result};
+use tracing::{error, warn};
+use windows::core::HSTRING;
+use windows::Win32::Foundation::{
+    ERROR_FILE_NOT_FOUND, ERROR_IO_INCOMPLETE, ERROR_NO_MORE_FILES, ERROR_PATH_NOT_FOUND, FILETIME,
+    HANDLE,
+};
+use windows::Win32::Storage::FileSystem::{
+    CreateFileW, FindClose, FindExInfoBasic, FindExSearchNameMatch, FindFirstFileExW,
+    FindNextFileW, ReadDirectoryChangesW, FILE_ACTION_ADDED, FILE_ACTION_MODIFIED,
+    FILE_ACTION_REMOVED, FILE_ACTION_RENAMED_NEW_NAME, FILE_ACTION_RENAMED_OLD_NAME,
+    FILE_ATTRIBUTE_DIRECTORY, FILE_ATTRIBUTE_REPARSE_POINT, FILE_FLAG_BACKUP_SEMANTICS,
+    FILE_FLAG_OVERLAPPED, FILE_LIST_DIRECTORY, FILE_NOTIFY_CHANGE_DIR_NAME,
+    FILE_NOTIFY_CHANGE_FILE_NAME, FILE_NOTIFY_CHANGE_LAST_WRITE, FILE_NOTIFY_CHANGE_SIZE,
+    FILE_NOTIFY_INFORMATION, FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE,
+    FIND_FIRST_EX_LARGE_FETCH, OPEN_EXISTING, WIN32_FIND_DATAW,
+};
+use windows::Win32::System::Threading::CreateEventW;
+use windows::Win32::System::IO::{CancelIoEx, GetOverlappedResult, OVERLAPPED};
+
+use crate::ntfs::index::{FileInfo, FileTimes, FIRST_USER_INDEX, ROOT_INDEX};
+use crate::ntfs::try_close_handle;
+use crate::ntfs::volume::Volume;
+
+/// In u64s, as the notifications have to be aligned
+const NOTIFY_BUFFER_SIZE: usize = 8 * 1024;
+
+/// A change reported by [DirectoryWatcher]. Paths are relative to the root of the volume.
+#[derive(Debug, Clone)]
+pub enum PathChange {
+    Added(String),
+    Removed(String),
+    /// The size or the modification time changed
+    Modified(String),
+    Renamed {
+        old: String,
+        new: String,
+    },
+}
+
+/// Lists every directory of a volume which has neither an MFT nor a journal, like FAT32 and exFAT
+/// USB sticks. Files are assigned indices in the order they are found, starting after the indices
+/// which NTFS reserves for metafiles. Directories which can't be listed are skipped.
+pub fn enumerate_files(volume: Volume) -> Result<Vec<Option<FileInfo>>> {
+    let mut infos = vec![None; FIRST_USER_INDEX as usize];
+    let mut pending = vec![(ROOT_INDEX, format!("{}:", volume.id))];
+
+    while let Some((parent, directory)) = pending.pop() {
+        let entries = match list_directory(&directory) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("{:?}", e);
+                continue;
+            }
+        };
+
+        for data in entries {
+            let info = to_file_info(&data, parent);
+            // Junctions could lead into a cycle
+            if info.is_directory() && data.dwFileAttributes & FILE_ATTRIBUTE_REPARSE_POINT.0 == 0 {
+                pending.push((infos.len() as u64, format!("{}\\{}", directory, info.name)));
+            }
+            infos.push(Some(info));
+        }
+    }
+
+    Ok(infos)
+}
+
+/// Returns the entry of a single file or directory, or `None` if it doesn't exist.
+pub fn find_file(path: &str) -> Result<Option<FileInfo>> {
+    let mut data = WIN32_FIND_DATAW::default();
+    let handle = match unsafe { find_first(path, &mut data) } {
+        Ok(handle) => handle,
+        Err(e) if is_not_found(&e) => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to find '{}'", path)),
+    };
+    unsafe { FindClose(handle) }?;
+
+    // The parent is filled in by the caller
+    Ok(Some(to_file_info(&data, ROOT_INDEX)))
+}
+
+pub fn list_directory(directory: &str) -> Result<Vec<WIN32_FIND_DATAW>> {
+    let pattern = format!("{}\\*", directory);
+    let mut entries = Vec::new();
+    let mut data = WIN32_FIND_DATAW::default();
+    let handle = unsafe { find_first(&pattern, &mut data) }
+        .with_context(|| format!("Failed to list '{}'", directory))?;
+
+    let res = (|| loop {
+        let name = file_name(&data);
+        if name != "." && name != ".." {
+            entries.push(data);
+        }
+
+        if let Err(e) = unsafe { FindNextFileW(handle, &mut data) } {
+            if e.code() == ERROR_NO_MORE_FILES.to_hresult() {
+                return Ok(());
+            }
+            return Err(Report::new(e)).with_context(|| format!("Failed to list '{}'", directory));
+        }
+    })();
+    unsafe { FindClose(handle) }?;
+    res?;
+
+    Ok(entries)
+}
+
+unsafe fn find_first(path: &str, data: &mut WIN32_FIND_DATAW) -> windows::core::Result<HANDLE> {
+    FindFirstFileExW(
+        &HSTRING::from(path),
+        // Skips the short 8.3 name, which is not needed
+        FindExInfoBasic,
+        data as *mut WIN32_FIND_DATAW as *mut c_void,
+        FindExSearchNameMatch,
+        None,
+        FIND_FIRST_EX_LARGE_FETCH,
+    )
+}
+
+fn is_not_found(e: &windows::core::Error) -> bool {
+    e.code() == ERROR_FILE_NOT_FOUND.to_hresult() || e.code() == ERROR_PATH_NOT_FOUND.to_hresult()
+}
+
+pub fn to_file_info(data: &WIN32_FIND_DATAW, parent: u64) -> FileInfo {
+    let size = (data.nFileSizeHigh as u64) << 32 | data.nFileSizeLow as u64;
+    FileInfo::new(
+        size,
+        size,
+        data.dwFileAttributes & FILE_ATTRIBUTE_DIRECTORY.0 != 0,
+        parent,
+        file_name(data).into(),
+        FileTimes {
+            created: to_u64(data.ftCreationTime),
+            modified: to_u64(data.ftLastWriteTime),
+            accessed: to_u64(data.ftLastAccessTime),
+        },
+        data.dwFileAttributes,
+    )
+}
+
+fn file_name(data: &WIN32_FIND_DATAW) -> String {
+    let len = data
+        .cFileName
+        .iter()
+        .position(|&c| c == 0)
+        .unwrap_or(data.cFileName.len());
+    String::from_utf16_lossy(&data.cFileName[..len])
+}
+
+fn to_u64(time: FILETIME) -> u64 {
+    (time.dwHighDateTime as u64) << 32 | time.dwLowDateTime as u64
+}
+
+/// Watches a whole volume for changes using `ReadDirectoryChangesW`, which works on every file
+/// system but only reports paths. Changes are buffered by the system between calls to
+/// [Self::read_changes], but are lost if too many happen at once.
+pub struct DirectoryWatcher {
+    handle: HANDLE,
+    event: HANDLE,
+    /// Boxed, as the system writes to it until the pending read completes
+    overlapped: Box<OVERLAPPED>,
+    buffer: Vec<u64>,
+    pending: bool,
+    /// Old path of a rename whose new path was not read yet
+    renamed_from: Option<String>,
+}
+
+// The handles are only used by the thread which owns the watcher
+unsafe impl Send for DirectoryWatcher {}
+
+impl DirectoryWatcher {
+    pub fn new(volume: Volume) -> Result<Self> {
+        let root = format!("{}:\\", volume.id);
+        unsafe {
+            let handle = CreateFileW(
+                &HSTRING::from(&root),
+                FILE_LIST_DIRECTORY.0,
+                FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+                None,
+                OPEN_EXISTING,
+                // Required to open directories
+                FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OVERLAPPED,
+                None,
+            )
+            .with_context(|| format!("CreateFileW failed for '{}'", root))?;
+            let event = match CreateEventW(None, true, false, None) {
+                Ok(event) => event,
+                Err(e) => {
+                    try_close_handle(handle)?;
+                    return Err(e).with_context(|| "Failed to create event");
+                }
+            };
+
+            let mut watcher = Self {
+                handle,
+                event,
+                overlapped: Box::new(OVERLAPPED {
+                    hEvent: event,
+                    ..Default::default()
+                }),
+                buffer: vec![0u64; NOTIFY_BUFFER_SIZE],
+                pending: false,
+                renamed_from: None,
+            };
+            // Changes are only recorded once the first read was started
+            watcher.start_read()?;
+            Ok(watcher)
+        }
+    }
+
+    /// Returns the changes which happened since the last call, without waiting for new ones.
+    /// Returns `None` if the system's buffer overflowed, in which case it doesn't know what changed
+    /// and the volume has to be listed again.
+    pub fn read_changes(&mut self) -> Result<Option<Vec<PathChange>>> {
+        if !self.pending {
+            self.start_read()?;
+        }
+
+        let mut bytes_read = 0u32;
+        let res = unsafe {
+            GetOverlappedResult(
+                self.handle,
+                self.overlapped.as_ref(),
+                &mut bytes_read,
+                false,
+            )
+        };
+        if let Err(e) = res {
+            if e.code() == ERROR_IO_INCOMPLETE.to_hresult() {
+                return Ok(Some(Vec::new()));
+            }
+            self.pending = false;
+            return Err(Report::new(e)).with_context(|| "Failed to read directory changes");
+        }
+        self.pending = false;
+
+        let changes = match bytes_read {
+            0 => {
+                // A rename whose new name was lost can't be completed anymore
+                self.renamed_from = None;
+                None
+            }
+            len => Some(self.parse(len as usize)),
+        };
+        self.start_read()?;
+
+        Ok(changes)
+    }
+
+    fn start_read(&mut self) -> Result<()> {
+        unsafe {
+            ReadDirectoryChangesW(
+                self.handle,
+                self.buffer.as_mut_ptr() as *mut c_void,
+                (self.buffer.len() * size_of::<u64>()) as u32,
+                true,
+                FILE_NOTIFY_CHANGE_FILE_NAME
+                    | FILE_NOTIFY_CHANGE_DIR_NAME
+                    | FILE_NOTIFY_CHANGE_SIZE
+                    | FILE_NOTIFY_CHANGE_LAST_WRITE,
+                None,
+                Some(self.overlapped.as_mut()),
+                None,
+            )
+        }
+        .with_context(|| "ReadDirectoryChangesW failed")?;
+        self.pending = true;
+
+        Ok(())
+    }
+
+    fn parse(&mut self, len: usize) -> Vec<PathChange> {
+        let bytes = unsafe { std::slice::from_raw_parts(self.buffer.as_ptr() as *const u8, len) };
+        let mut changes = Vec::new();
+        let mut offset = 0;
+        while offset + size_of::<FILE_NOTIFY_INFORMATION>() <= bytes.len() {
+            let info = unsafe { &*(bytes[offset..].as_ptr() as *const FILE_NOTIFY_INFORMATION) };
+            let path = unsafe {
+                String::from_utf16_lossy(std::slice::from_raw_parts(
+                    info.FileName.as_ptr(),
+                    info.FileNameLength as usize / 2,
+                ))
+            };
+
+            match info.Action {
+                FILE_ACTION_ADDED => changes.push(PathChange::Added(path)),
+                FILE_ACTION_REMOVED => changes.push(PathChange::Removed(path)),
+                FILE_ACTION_MODIFIED => changes.push(PathChange::Modified(path)),
+                FILE_ACTION_RENAMED_OLD_NAME => self.renamed_from = Some(path),
+                FILE_ACTION_RENAMED_NEW_NAME => match self.renamed_from.take() {
+                    Some(old) => changes.push(PathChange::Renamed { old, new: path }),
+                    None => changes.push(PathChange::Added(path)),
+                },
+                action => warn!("Unknown directory change action {}", action.0),
+            }
+
+            if info.NextEntryOffset == 0 {
+                break;
+            }
+            offset += info.NextEntryOffset as usize;
+        }
+
+        changes
+    }
+}
+
+impl Drop for DirectoryWatcher {
+    fn drop(&mut self) {
+        unsafe {
+            // The buffer must not be freed while the system may still write to it
+            if self.pending && CancelIoEx(self.handle, Some(self.overlapped.as_ref())).is_ok() {
+                let mut bytes_read = 0u32;
+                let _ = GetOverlappedResult(
+                    self.handle,
+                    self.overlapped.as_ref(),
+                    &mut bytes_read,
+                    true,
+                );
+            }
+        }
+        for handle in [self.handle, self.event] {
+            if let Err(e) = try_close_handle(handle) {
+                error!("{:?}", e);
+            }
+        }
+    }
+}
+
+/// Splits a path relative to the root of the volume into the path of the parent and the name.
+pub fn split_path(path: &str) -> (&str, &str) {
+    path.rsplit_once('\\').unwrap_or(("", path))
+}
@@ -36,10 +36,37 @@ impl<'a> Attribute<'a> {
         }
     }
 
+    /// Name of the attribute, empty for the unnamed attribute of its type
+    pub fn name(&self) -> String {
+        let start = self.header.name_offset as usize;
+        let end = start + self.header.name_length as usize * 2;
+        let name = self.data[start..end]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect::<Vec<_>>();
+        String::from_utf16_lossy(&name)
+    }
+
+    /// Returns the content of a resident attribute.
+    pub fn resident_value(&self) -> Option<&'a [u8]> {
+        if self.header.non_resident {
+            return None;
+        }
+
+        let (offset, length) = unsafe {
+            let resident = self.header.last.resident;
+            (
+                resident.value_offset as usize,
+                resident.value_length as usize,
+            )
+        };
+        self.data.get(offset..offset + length)
+    }
+
+    /// Returns the real size and the location of the content of a non-resident attribute.
     pub fn decode_data_runs(&self, bytes_per_cluster: usize) -> Option<(usize, Vec<Range<usize>>)> {
         unsafe {
-            let attribute_type = self.header.attribute_type;
-            if attribute_type != AttributeType::Data || !self.header.non_resident {
+            if !self.header.non_resident {
                 return None;
             }
 
@@ -0,0 +1,75 @@
+use eyre::{Context, Result};
+use rustc_hash::FxHashMap;
+use windows::core::HSTRING;
+use windows::Win32::Storage::FileSystem::{
+    CreateFileW, FileIdInfo, GetFileInformationByHandleEx, FILE_FLAG_BACKUP_SEMANTICS, FILE_ID_128,
+    FILE_ID_INFO, FILE_READ_ATTRIBUTES, FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE,
+    OPEN_EXISTING,
+};
+
+use crate::ntfs::index::{FIRST_USER_INDEX, ROOT_INDEX};
+use crate::ntfs::try_close_handle;
+use crate::ntfs::volume::Volume;
+
+/// Assigns indices to the 128 bit file ids of ReFS. Unlike MFT indices, these ids are not small
+/// consecutive numbers, so they can't be used as positions in the index directly. The root
+/// directory is mapped to the MFT index of the NTFS root, everything else gets the next free index
+/// in the order it is seen. Indices are never reused, ReFS doesn't reuse ids either.
+#[derive(Debug)]
+pub struct FileIdMap {
+    indices: FxHashMap<u128, u64>,
+    next_index: u64,
+}
+
+impl FileIdMap {
+    pub fn new(root_id: u128) -> Self {
+        Self {
+            indices: FxHashMap::from_iter([(root_id, ROOT_INDEX)]),
+            // Keeps the new entries clear of the indices which NTFS reserves for metafiles
+            next_index: FIRST_USER_INDEX,
+        }
+    }
+
+    /// Creates a map for the root directory of `volume`.
+    pub fn for_volume(volume: Volume) -> Result<Self> {
+        let root = format!("{}:\\", volume.id);
+        let mut info = FILE_ID_INFO::default();
+        unsafe {
+            let handle = CreateFileW(
+                &HSTRING::from(&root),
+                FILE_READ_ATTRIBUTES.0,
+                FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+                None,
+                OPEN_EXISTING,
+                // Required to open directories
+                FILE_FLAG_BACKUP_SEMANTICS,
+                None,
+            )
+            .with_context(|| format!("CreateFileW failed for '{}'", root))?;
+            let res = GetFileInformationByHandleEx(
+                handle,
+                FileIdInfo,
+                &mut info as *mut FILE_ID_INFO as *mut _,
+                size_of::<FILE_ID_INFO>() as u32,
+            )
+            .with_context(|| format!("Failed to query the file id of '{}'", root));
+            try_close_handle(handle)?;
+            res?;
+        }
+
+        Ok(Self::new(to_u128(info.FileId)))
+    }
+
+    /// Returns the index of `id`, assigning a new one if it wasn't seen before.
+    pub fn index(&mut self, id: FILE_ID_128) -> u64 {
+        let next_index = &mut self.next_index;
+        *self.indices.entry(to_u128(id)).or_insert_with(|| {
+            *next_index += 1;
+            *next_index - 1
+        })
+    }
+}
+
+fn to_u128(id: FILE_ID_128) -> u128 {
+    u128::from_le_bytes(id.Identifier)
+}
@@ -0,0 +1,300 @@
+use rayon::prelude::*;
+use smartstring::SmartString;
+
+use crate::ntfs::index::{FileInfo, FileTimes};
+
+/// Marks MFT indices without an entry in [FileInfos::slots]
+const NO_ENTRY: u32 = u32::MAX;
+/// Removed entries are only compacted automatically once there are at least this many of them, as
+/// compacting moves every entry
+const MIN_COMPACTION_ENTRIES: usize = 64 * 1024;
+/// Removed entries are compacted automatically once they make up more than 1/this of all entries
+const COMPACTION_RATIO: usize = 4;
+
+/// Entries of the index by MFT index. Only existing entries are stored, while `slots` maps every
+/// MFT index to the position of its entry, so that records which are not in use take 4 bytes
+/// instead of a whole entry.
+///
+/// Removed entries keep their position until [Self::compact] moves the remaining entries together
+/// and updates the slots. MFT indices stay the same, so parents, children and search results which
+/// refer to entries by their MFT index are not affected.
+#[derive(Debug, Default)]
+pub struct FileInfos {
+    slots: Vec<u32>,
+    entries: Vec<FileInfo>,
+    /// Number of entries which are not referenced by any slot anymore
+    removed: usize,
+}
+
+impl From<Vec<Option<FileInfo>>> for FileInfos {
+    fn from(infos: Vec<Option<FileInfo>>) -> Self {
+        let mut slots = Vec::with_capacity(infos.len());
+        let mut entries = Vec::with_capacity(infos.iter().flatten().count());
+        for info in infos {
+            match info {
+                Some(info) => {
+                    slots.push(entries.len() as u32);
+                    entries.push(info);
+                }
+                None => slots.push(NO_ENTRY),
+            }
+        }
+
+        Self {
+            slots,
+            entries,
+            removed: 0,
+        }
+    }
+}
+
+impl FileInfos {
+    /// Number of MFT indices, including the ones without an entry
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Number of entries which exist
+    pub fn count(&self) -> usize {
+        self.entries.len() - self.removed
+    }
+
+    /// Number of removed entries whose memory is freed by [Self::compact]
+    pub fn removed(&self) -> usize {
+        self.removed
+    }
+
+    pub fn get(&self, index: usize) -> Option<&FileInfo> {
+        self.entry(*self.slots.get(index)?)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut FileInfo> {
+        match *self.slots.get(index)? {
+            NO_ENTRY => None,
+            slot => Some(&mut self.entries[slot as usize]),
+        }
+    }
+
+    /// Replaces the entry at `index`, growing the slots if needed, and returns the previous entry.
+    pub fn replace(&mut self, index: usize, info: Option<FileInfo>) -> Option<FileInfo> {
+        if index >= self.slots.len() {
+            // Removing an entry which doesn't exist doesn't need more slots
+            info.as_ref()?;
+            self.slots.resize(index + 1, NO_ENTRY);
+        }
+
+        let slot = self.slots[index];
+        match (slot, info) {
+            (NO_ENTRY, None) => None,
+            (NO_ENTRY, Some(info)) => {
+                self.slots[index] = self.entries.len() as u32;
+                self.entries.push(info);
+                None
+            }
+            (slot, Some(info)) => Some(std::mem::replace(&mut self.entries[slot as usize], info)),
+            (slot, None) => {
+                self.slots[index] = NO_ENTRY;
+                self.removed += 1;
+                Some(std::mem::replace(
+                    &mut self.entries[slot as usize],
+                    removed_entry(),
+                ))
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = Option<&FileInfo>> {
+        self.slots.iter().map(|&slot| self.entry(slot))
+    }
+
+    pub fn par_iter(&self) -> impl IndexedParallelIterator<Item = Option<&FileInfo>> {
+        self.slots.par_iter().map(|&slot| self.entry(slot))
+    }
+
+    /// Splits the entries into chunks of `chunk_size` MFT indices, which are iterated in parallel.
+    pub fn par_chunks(
+        &self,
+        chunk_size: usize,
+    ) -> impl IndexedParallelIterator<Item = impl Iterator<Item = Option<&FileInfo>>> {
+        self.slots
+            .par_chunks(chunk_size)
+            .map(|slots| slots.iter().map(|&slot| self.entry(slot)))
+    }
+
+    /// Whether enough entries were removed that [Self::compact] should be called.
+    pub fn needs_compaction(&self) -> bool {
+        self.removed >= MIN_COMPACTION_ENTRIES
+            && self.removed * COMPACTION_RATIO > self.entries.len()
+    }
+
+    /// Moves the remaining entries together in the order of their MFT indices and frees the memory
+    /// of removed entries and unused capacity, including the capacity of long names. Returns the
+    /// number of bytes which were freed.
+    pub fn compact(&mut self) -> usize {
+        let before = self.heap_size();
+        let mut old = std::mem::take(&mut self.entries);
+        self.entries = Vec::with_capacity(old.len() - self.removed);
+        for slot in &mut self.slots {
+            if *slot == NO_ENTRY {
+                continue;
+            }
+
+            let mut info = std::mem::replace(&mut old[*slot as usize], removed_entry());
+            info.name.shrink_to_fit();
+            *slot = self.entries.len() as u32;
+            self.entries.push(info);
+        }
+        drop(old);
+        self.slots.shrink_to_fit();
+        self.removed = 0;
+
+        before.saturating_sub(self.heap_size())
+    }
+
+    /// Memory used by the slots, the entries and the names which don't fit into an entry
+    fn heap_size(&self) -> usize {
+        let names = self
+            .entries
+            .par_iter()
+            .filter(|info| !info.name.is_inline())
+            .map(|info| info.name.capacity())
+            .sum::<usize>();
+        self.slots.capacity() * size_of::<u32>()
+            + self.entries.capacity() * size_of::<FileInfo>()
+            + names
+    }
+
+    fn entry(&self, slot: u32) -> Option<&FileInfo> {
+        match slot {
+            NO_ENTRY => None,
+            slot => Some(&self.entries[slot as usize]),
+        }
+    }
+}
+
+/// Takes the place of a removed entry until the next [FileInfos::compact]. Doesn't allocate, as
+/// the name is stored inline.
+fn removed_entry() -> FileInfo {
+    FileInfo::new(0, 0, false, 0, SmartString::new(), FileTimes::default(), 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(name: &str) -> FileInfo {
+        FileInfo::new(
+            name.len() as u64,
+            0,
+            false,
+            5,
+            SmartString::from(name),
+            FileTimes::default(),
+            0,
+        )
+    }
+
+    fn names(infos: &FileInfos) -> Vec<Option<String>> {
+        infos
+            .iter()
+            .map(|info| info.map(|info| info.name.to_string()))
+            .collect()
+    }
+
+    /// Names which are too long to be stored inline
+    fn long_name(i: usize) -> String {
+        format!("a file name which is too long to be stored inline {}", i)
+    }
+
+    #[test]
+    fn compact_keeps_entries_by_mft_index() {
+        let mut infos = FileInfos::from(
+            (0..100)
+                .map(|i| Some(info(&long_name(i))))
+                .collect::<Vec<_>>(),
+        );
+        for i in (0..100).step_by(3) {
+            assert!(infos.replace(i, None).is_some());
+        }
+        // Reused MFT indices are appended to the entries, out of order
+        infos.replace(3, Some(info("reused")));
+        infos.replace(120, Some(info("grown")));
+        let before = names(&infos);
+        assert_eq!(infos.removed(), 34);
+        assert_eq!(infos.count(), 68);
+
+        assert!(infos.compact() > 0);
+        assert_eq!(names(&infos), before);
+        assert_eq!(infos.removed(), 0);
+        assert_eq!(infos.count(), 68);
+        assert_eq!(infos.len(), 121);
+        // The entries are in the order of their MFT indices again
+        let order = infos
+            .slots
+            .iter()
+            .filter(|&&slot| slot != NO_ENTRY)
+            .copied()
+            .collect::<Vec<_>>();
+        assert_eq!(order, (0..68).collect::<Vec<_>>());
+
+        // Entries can still be changed afterwards
+        infos.get_mut(1).unwrap().name = "changed".into();
+        assert_eq!(infos.get(1).unwrap().name, "changed");
+        assert!(infos.replace(1, None).is_some());
+        assert_eq!(infos.count(), 67);
+    }
+
+    #[test]
+    fn compact_without_removed_entries() {
+        let mut infos = FileInfos::from(vec![None, Some(info("a")), None, Some(info("b"))]);
+        let before = names(&infos);
+
+        infos.compact();
+        assert_eq!(names(&infos), before);
+        assert_eq!(infos.count(), 2);
+        assert_eq!(FileInfos::default().compact(), 0);
+    }
+
+    #[test]
+    fn removing_missing_entries_changes_nothing() {
+        let mut infos = FileInfos::from(vec![Some(info("a")), None]);
+        assert!(infos.replace(1, None).is_none());
+        assert!(infos.replace(50, None).is_none());
+        assert_eq!(infos.len(), 2);
+        assert_eq!(infos.removed(), 0);
+    }
+
+    #[test]
+    fn needs_compaction_after_many_removals() {
+        // A large share, but too few entries to be worth it
+        let mut small = FileInfos::from(vec![Some(info("a")); 100]);
+        for i in 0..90 {
+            small.replace(i, None);
+        }
+        assert!(!small.needs_compaction());
+
+        let total = MIN_COMPACTION_ENTRIES * 5;
+        let mut infos = FileInfos::from(vec![Some(info("a")); total]);
+        assert!(!infos.needs_compaction());
+
+        // Enough entries, but not a large enough share of all entries
+        for i in 0..MIN_COMPACTION_ENTRIES {
+            infos.replace(i, None);
+        }
+        assert!(!infos.needs_compaction());
+
+        let removed = MIN_COMPACTION_ENTRIES * 3 / 2;
+        for i in MIN_COMPACTION_ENTRIES..removed {
+            infos.replace(i, None);
+        }
+        assert!(infos.needs_compaction());
+
+        infos.compact();
+        assert!(!infos.needs_compaction());
+        assert_eq!(infos.count(), total - removed);
+    }
+}
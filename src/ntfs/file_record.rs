@@ -3,6 +3,7 @@ use std::ops::Range;
 use eyre::{ContextCompat, Result};
 use smartstring::{Compact, SmartString};
 use crate::ntfs::file_attribute::{Attribute, AttributeType};
+use crate::ntfs::index::FileTimes;
 
 pub struct FileRecord<'a> {
     pub header: &'a FileRecordHeader,
@@ -114,6 +115,27 @@ impl<'a> FileRecord<'a> {
             })
     }
     
+    /// Returns the timestamps and the file attribute flags (`FILE_ATTRIBUTE_*`) stored in the
+    /// standard information attribute.
+    pub fn read_standard_information(&self) -> Option<(FileTimes, u32)> {
+        let attr = self.get_attribute(AttributeType::StandardInformation)?;
+        let base = unsafe { attr.header.last.resident.value_offset as usize };
+        // A damaged record can have an attribute which is too short, which is treated as unreadable
+        let read = |offset: usize| {
+            let start = base.checked_add(offset)?;
+            let bytes = attr.data.get(start..start.checked_add(8)?)?;
+            Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+        };
+
+        // 0x10 is the time the MFT record itself was changed, which is not interesting to users
+        let times = FileTimes {
+            created: read(0x00)?,
+            modified: read(0x08)?,
+            accessed: read(0x18)?,
+        };
+        Some((times, read(0x20)? as u32))
+    }
+
     pub fn get_data_attribute_size(&self) -> u64 {
         let Some(attr) = self.get_attribute(AttributeType::Data) else {
             return 0;
@@ -122,13 +144,24 @@ impl<'a> FileRecord<'a> {
         unsafe {
             
             if attr.header.non_resident {
-                return attr.header.last.non_resident.allocated_size;
+                return attr.header.last.non_resident.real_size;
             }
 
             attr.header.last.resident.value_length as u64
         }
     }
 
+    /// Returns the number of bytes allocated on disk for the data attribute. Resident data is
+    /// stored inside the MFT record and does not allocate any clusters.
+    pub fn get_data_attribute_allocated_size(&self) -> u64 {
+        match self.get_attribute(AttributeType::Data) {
+            Some(attr) if attr.header.non_resident => unsafe {
+                attr.header.last.non_resident.allocated_size
+            },
+            _ => 0,
+        }
+    }
+
     pub fn fixup(data: &mut [u8], sector_size: usize) {
         let file = FileRecord::new(data);
         if !file.is_valid() {
@@ -1,46 +1,278 @@
+use std::ffi::c_void;
+use std::fmt;
 use std::ops::Range;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use crate::config::Calibration;
+use crate::ntfs::children::ChildrenMap;
+use crate::ntfs::directory_index::{self, DirectoryEntry};
+use crate::ntfs::fallback::{self, PathChange};
 use crate::ntfs::file_attribute::AttributeType;
+use crate::ntfs::file_ids::FileIdMap;
+use crate::ntfs::file_infos::FileInfos;
 use crate::ntfs::file_record::FileRecord;
 use crate::ntfs::journal::JournalEntry;
-use crate::ntfs::mft::MftFile;
+use crate::ntfs::mft::{read_runs, MftFile};
+use crate::ntfs::trigram::TrigramIndex;
 use crate::ntfs::try_close_handle;
-use crate::ntfs::volume::{create_overlapped, Volume};
-use eyre::{Context, Report, Result};
+use crate::ntfs::usn_enum;
+use crate::ntfs::volume::{create_overlapped, Volume, VolumeRead};
+use eyre::{eyre, Context, ContextCompat, Report, Result};
 use rayon::prelude::*;
+use rustc_hash::{FxHashMap, FxHashSet};
 use smartstring::{Compact, SmartString};
-use windows::Win32::Foundation::WAIT_OBJECT_0;
-use windows::Win32::Storage::FileSystem::ReadFile;
+use tracing::{error, info, warn};
+use windows::Win32::Foundation::{ERROR_IO_PENDING, HANDLE};
+use windows::Win32::Storage::FileSystem::{
+    FileIoPriorityHintInfo, IoPriorityHintLow, ReadFile, SetFileInformationByHandle,
+    FILE_ATTRIBUTE_DIRECTORY, FILE_ATTRIBUTE_SYSTEM, FILE_IO_PRIORITY_HINT_INFO,
+};
 use windows::Win32::System::Ioctl::NTFS_VOLUME_DATA_BUFFER;
-use windows::Win32::System::Threading::{CreateEventW, WaitForMultipleObjects};
-use windows::Win32::System::IO::OVERLAPPED;
+use windows::Win32::System::Threading::INFINITE;
+use windows::Win32::System::IO::{CreateIoCompletionPort, GetQueuedCompletionStatus, OVERLAPPED};
 
-const ROOT_INDEX: u64 = 5;
+pub const ROOT_INDEX: u64 = 5;
+/// Directory holding the metafiles added in later NTFS versions, like `$UsnJrnl` and `$Quota`
+const EXTEND_INDEX: u64 = 11;
+/// The first 16 records of the MFT are reserved for metafiles
+pub const FIRST_USER_INDEX: u64 = 16;
+/// Entries of network locations are stored after this index, so that they never collide with
+/// MFT indices, see [NtfsVolumeIndex::set_network_files]
+pub const NETWORK_INDEX_BASE: u64 = 1 << 48;
+/// Most parents which are followed before the parents of an entry are considered to be a cycle
+const MAX_DEPTH: usize = 4096;
 const PAR_ITER_CHUNK_COUNT: usize = 64;
+/// Size of the chunks the MFT is read and parsed in. Has to be a multiple of every possible file
+/// record size, so that records are never split between chunks.
+const READ_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+/// Number of reads which are queued at the same time on disks without a seek penalty. A chunk
+/// needs one read per run it contains, so a fragmented MFT needs more reads for the same amount of
+/// data. SSDs and especially NVMe drives only reach their full speed with many requests in flight.
+const SSD_PENDING_READS: usize = 32;
+/// Hard disks are slowed down by seeking between many interleaved requests
+const HDD_PENDING_READS: usize = 4;
+/// Used if the disk doesn't tell whether it has a seek penalty
+const DEFAULT_PENDING_READS: usize = 16;
+/// Upper limit for [set_read_queue_depth]
+const MAX_PENDING_READS: usize = 64;
+/// Queue depths which are measured while the MFT of a volume is read for the first time, see
+/// [QueueDepth]
+const CALIBRATION_DEPTHS: [usize; 4] = [4, 16, 32, 64];
+/// Number of chunks which are read with each of [CALIBRATION_DEPTHS], 32 MB each
+const CALIBRATION_CHUNKS: usize = 8;
+/// Number of chunks which are read or parsed at the same time. Together with [READ_CHUNK_SIZE],
+/// this limits the memory needed for reading the MFT to 128 MB.
+const MAX_BUFFERS: usize = 32;
+/// Number of pending reads set by [set_read_queue_depth], 0 to measure it
+static READ_QUEUE_DEPTH: AtomicUsize = AtomicUsize::new(0);
+/// Set by [set_low_io_priority]
+static LOW_IO_PRIORITY: AtomicBool = AtomicBool::new(false);
+/// Reads which fail are retried this many times in total before the records in them are skipped
+const MAX_READ_ATTEMPTS: u32 = 4;
+/// Delay before the first retry, doubled for every further retry
+const READ_RETRY_DELAY: Duration = Duration::from_millis(100);
 
 pub struct NtfsVolumeIndex {
     volume: Volume,
-    infos: Vec<Option<FileInfo>>,
+    infos: FileInfos,
+    /// Number of MFT records which could not be read while building the index
+    unreadable_records: usize,
+    /// `None` if the index was not built from the MFT
+    mft: Option<MftLocation>,
+    /// Records changed by the journal since a verification started, see [Self::begin_verification]
+    touched_records: Option<FxHashSet<u64>>,
+    /// `None` unless [Self::build_trigram_index] was called
+    trigrams: Option<TrigramIndex>,
+    children: ChildrenMap,
+    /// Set for ReFS volumes, whose files are not identified by MFT indices, see [Self::from_refs]
+    file_ids: Option<Arc<Mutex<FileIdMap>>>,
+    /// Set if the index was built by listing directories, see [Self::from_directory_walk]
+    directory_walk: bool,
+    /// Entries of the network locations, starting at [NETWORK_INDEX_BASE]
+    network: FileInfos,
+    network_children: ChildrenMap,
+    /// Incremented whenever an entry changes
+    generation: u64,
 }
 
-#[derive(Debug)]
+/// Where the MFT is stored on the volume, used to read single records after the index was built.
+#[derive(Clone)]
+pub struct MftLocation {
+    reader: Arc<dyn VolumeRead>,
+    runs: Vec<Range<usize>>,
+    volume_data: NTFS_VOLUME_DATA_BUFFER,
+}
+
+/// Identifies a file independently of the index it is looked up in. NTFS reuses the MFT records of
+/// deleted files, so the sequence number of the record is needed to tell a file apart from the
+/// ones which later take its place. An id stays valid as long as the file exists, even if it is
+/// renamed or moved, see [NtfsVolumeIndex::resolve].
+///
+/// Formatted as `<volume>:<MFT index>:<sequence>`, e.g. `C:1234:5`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FileId {
+    pub volume: char,
+    pub mft_index: u64,
+    /// 0 if the index was built from a source without sequence numbers
+    pub sequence: u16,
+}
+
+#[derive(Debug, Clone)]
 pub struct FileInfo {
     pub name: SmartString<Compact>,
     parent: u64,
     size_and_directory: u64,
+    size_on_disk: u64,
+    times: FileTimes,
+    attributes: u32,
+    /// Sequence number of the MFT record, which is incremented whenever the record is reused for
+    /// another file
+    sequence: u16,
+    /// Set for NTFS metafiles, everything inside `$Extend` and empty system files
+    system_noise: bool,
+    /// Set for orphans which could not be repaired, see [NtfsVolumeIndex::repair_orphans]
+    quarantined: bool,
+}
+
+/// Timestamps of a file as FILETIMEs, which count 100ns intervals since 1601-01-01 UTC.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct FileTimes {
+    pub created: u64,
+    pub modified: u64,
+    pub accessed: u64,
+}
+
+/// An entry whose parents don't lead to the root directory, see [NtfsVolumeIndex::repair_orphans].
+#[derive(Debug, Clone)]
+pub struct Orphan {
+    pub mft_index: u64,
+    pub name: String,
+    /// Whether reading the MFT again fixed the entry, otherwise it was quarantined
+    pub resolved: bool,
+}
+
+/// A difference between the parents in the index and the `$I30` index of a directory, see
+/// [NtfsVolumeIndex::compare_directory].
+#[derive(Debug, Clone)]
+pub struct Discrepancy {
+    pub mft_index: u64,
+    pub kind: DiscrepancyKind,
+    /// Full path of the file according to the directory it is listed in, or according to the index
+    /// if the directory doesn't list it
+    pub path: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DiscrepancyKind {
+    /// Listed by the directory, but missing from the index or stored with another sequence number
+    Missing,
+    /// Inside the directory according to the index, but not listed by it
+    Unlisted,
+}
+
+/// A change which was applied to the index while processing journal entries.
+#[derive(Debug)]
+pub struct FileChange {
+    pub kind: ChangeKind,
+    /// Full path of the file after the change, or before it for deletions
+    pub path: String,
+    /// Full path of the file before it was renamed or moved
+    pub old_path: Option<String>,
+    pub id: FileId,
+    pub file: FileInfo,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    Created,
+    Renamed,
+    /// Renamed into another directory
+    Moved,
+    Deleted,
+}
+
+impl ChangeKind {
+    pub const ALL: [ChangeKind; 4] = [
+        ChangeKind::Created,
+        ChangeKind::Renamed,
+        ChangeKind::Moved,
+        ChangeKind::Deleted,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ChangeKind::Created => "Created",
+            ChangeKind::Renamed => "Renamed",
+            ChangeKind::Moved => "Moved",
+            ChangeKind::Deleted => "Deleted",
+        }
+    }
+}
+
+impl fmt::Display for FileId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.volume, self.mft_index, self.sequence)
+    }
+}
+
+impl FromStr for FileId {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parse = || {
+            let mut parts = s.splitn(3, ':');
+            let mut volume = parts.next()?.chars();
+            let id = FileId {
+                volume: volume.next()?.to_ascii_uppercase(),
+                mft_index: parts.next()?.parse().ok()?,
+                sequence: parts.next()?.parse().ok()?,
+            };
+            volume.next().is_none().then_some(id)
+        };
+        parse().with_context(|| format!("Malformed file id '{}'", s))
+    }
 }
 
 impl FileInfo {
-    pub fn new(size: u64, is_directory: bool, parent: u64, name: SmartString<Compact>) -> Self {
+    pub fn new(
+        size: u64,
+        size_on_disk: u64,
+        is_directory: bool,
+        parent: u64,
+        name: SmartString<Compact>,
+        times: FileTimes,
+        attributes: u32,
+    ) -> Self {
         assert!(size <= 0x7FFF_FFFF_FFFF_FFFF);
 
         Self {
             name,
             parent,
             size_and_directory: size | (is_directory as u64) << 63,
+            size_on_disk,
+            times,
+            attributes,
+            sequence: 0,
+            system_noise: false,
+            quarantined: false,
         }
     }
 
+    pub fn with_sequence(mut self, sequence: u16) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    /// See [Self::is_system_noise]
+    pub fn with_system_noise(mut self, system_noise: bool) -> Self {
+        self.system_noise = system_noise;
+        self
+    }
+
     pub fn size(&self) -> u64 {
         self.size_and_directory & !(1 << 63)
     }
@@ -49,101 +281,643 @@ impl FileInfo {
     pub fn is_directory(&self) -> bool {
         self.size_and_directory & (1 << 63) != 0
     }
+
+    /// Returns the extension without the leading dot. Directories never have an extension.
+    pub fn extension(&self) -> Option<&str> {
+        if self.is_directory() {
+            return None;
+        }
+
+        self.name.rsplit_once('.').map(|(_, extension)| extension)
+    }
+
+    pub fn size_on_disk(&self) -> u64 {
+        self.size_on_disk
+    }
+
+    pub fn times(&self) -> FileTimes {
+        self.times
+    }
+
+    /// The `FILE_ATTRIBUTE_*` flags of this file
+    pub fn attributes(&self) -> u32 {
+        self.attributes
+    }
+
+    pub fn sequence(&self) -> u16 {
+        self.sequence
+    }
+
+    /// MFT index of the directory containing this file
+    pub fn parent(&self) -> u64 {
+        self.parent
+    }
+
+    /// Whether this is an NTFS metafile like `$MFT`, inside of `$Extend` or an empty system file.
+    /// Only detected for indices built from the MFT.
+    pub fn is_system_noise(&self) -> bool {
+        self.system_noise
+    }
+
+    /// Whether the parents of this file don't lead to the root directory, so that its path is
+    /// unknown. Such files are left out of search results.
+    pub fn is_quarantined(&self) -> bool {
+        self.quarantined
+    }
+}
+
+impl MftLocation {
+    /// Reads the `$I30` index of the directory at `mft_index` from the volume, see
+    /// [directory_index::read_entries].
+    pub fn read_directory(&self, mft_index: u64) -> Result<Vec<DirectoryEntry>> {
+        let record_size = self.volume_data.BytesPerFileRecordSegment as usize;
+        let mut buffer = vec![0u8; record_size];
+        read_runs(
+            self.reader.as_ref(),
+            &self.runs,
+            mft_index as usize * record_size,
+            &mut buffer,
+        )
+        .with_context(|| format!("Failed to read MFT record {}", mft_index))?;
+
+        let record = FileRecord::new(&buffer);
+        if !record.is_valid() || !record.is_used() || !record.is_directory() {
+            return Err(eyre!("MFT record {} is not a directory", mft_index));
+        }
+        FileRecord::fixup(&mut buffer, self.volume_data.BytesPerSector as usize);
+        directory_index::read_entries(
+            &FileRecord::new(&buffer),
+            self.reader.as_ref(),
+            self.volume_data,
+        )
+        .with_context(|| format!("Failed to read the index of directory {}", mft_index))
+    }
 }
 
 #[allow(unused)]
 impl NtfsVolumeIndex {
     pub fn new(volume: Volume) -> Result<NtfsVolumeIndex> {
-        let volume_data = volume.query_volume_data()?;
-        let mft_file = MftFile::new(volume, volume_data)?;
+        Self::from_reader(volume, Arc::new(volume.open()?))
+    }
+
+    /// Builds the index by reading the MFT through `reader`, which does not have to be backed by
+    /// `volume`. The reader is kept to read single records later on.
+    pub fn from_reader(volume: Volume, reader: Arc<dyn VolumeRead>) -> Result<NtfsVolumeIndex> {
+        let volume_data = reader.query_volume_data()?;
+        let mft_file = MftFile::new(reader.as_ref(), volume_data)?;
 
-        let files = process_mft_data(
+        // Without the bitmap, every record has to be checked
+        let bitmap = mft_file
+            .read_bitmap(reader.as_ref(), volume_data)
+            .map_err(|e| error!("{:?}", e))
+            .ok();
+        let (size, runs) = mft_file
+            .as_record()
+            .read_data_runs(volume_data.BytesPerCluster as usize)?;
+        let (files, unreadable_records) = process_mft_data(
             volume,
-            mft_file
-                .as_record()
-                .read_data_runs(volume_data.BytesPerCluster as usize)?,
+            reader.as_ref(),
+            (size, runs.clone()),
+            bitmap.as_deref(),
         )?;
+        let files = FileInfos::from(files);
+        let children = ChildrenMap::build(&files);
 
         Ok(Self {
             volume,
             infos: files,
+            unreadable_records,
+            mft: Some(MftLocation {
+                reader,
+                runs,
+                volume_data,
+            }),
+            touched_records: None,
+            trigrams: None,
+            children,
+            file_ids: None,
+            directory_walk: false,
+            network: FileInfos::default(),
+            network_children: ChildrenMap::default(),
+            generation: 0,
         })
     }
 
-    pub fn process_journal_entries(&mut self, entries: &[JournalEntry]) {
+    /// Restores an index which was built from the MFT from entries saved earlier, see
+    /// [crate::snapshot]. Only the location of the MFT is read, so that single records can be read
+    /// again later.
+    pub fn from_snapshot(volume: Volume, infos: Vec<Option<FileInfo>>) -> Result<NtfsVolumeIndex> {
+        let reader: Arc<dyn VolumeRead> = Arc::new(volume.open()?);
+        let volume_data = reader.query_volume_data()?;
+        let mft_file = MftFile::new(reader.as_ref(), volume_data)?;
+        let (_, runs) = mft_file
+            .as_record()
+            .read_data_runs(volume_data.BytesPerCluster as usize)?;
+        let infos = FileInfos::from(infos);
+        let children = ChildrenMap::build(&infos);
+
+        Ok(Self {
+            volume,
+            infos,
+            unreadable_records: 0,
+            mft: Some(MftLocation {
+                reader,
+                runs,
+                volume_data,
+            }),
+            touched_records: None,
+            trigrams: None,
+            children,
+            file_ids: None,
+            directory_walk: false,
+            network: FileInfos::default(),
+            network_children: ChildrenMap::default(),
+            generation: 0,
+        })
+    }
+
+    /// Builds the index using `FSCTL_ENUM_USN_DATA` instead of reading the MFT. See
+    /// [usn_enum::enumerate_files] for the limitations.
+    pub fn from_usn_data(volume: Volume) -> Result<NtfsVolumeIndex> {
+        let infos = FileInfos::from(usn_enum::enumerate_files(volume, None)?);
+        let children = ChildrenMap::build(&infos);
+        Ok(Self {
+            volume,
+            infos,
+            unreadable_records: 0,
+            mft: None,
+            touched_records: None,
+            trigrams: None,
+            children,
+            file_ids: None,
+            directory_walk: false,
+            network: FileInfos::default(),
+            network_children: ChildrenMap::default(),
+            generation: 0,
+        })
+    }
+
+    /// Builds the index of a ReFS volume. ReFS has no MFT, so this works like [Self::from_usn_data],
+    /// but maps the 128 bit file ids of ReFS to indices. Passing the `file_ids` of an existing
+    /// index keeps the indices of known files, which is needed to reconcile both indices.
+    pub fn from_refs(
+        volume: Volume,
+        file_ids: Option<Arc<Mutex<FileIdMap>>>,
+    ) -> Result<NtfsVolumeIndex> {
+        let file_ids = match file_ids {
+            Some(file_ids) => file_ids,
+            None => Arc::new(Mutex::new(FileIdMap::for_volume(volume)?)),
+        };
+        let infos = FileInfos::from(usn_enum::enumerate_files(volume, Some(&file_ids))?);
+        let children = ChildrenMap::build(&infos);
+        Ok(Self {
+            volume,
+            infos,
+            unreadable_records: 0,
+            mft: None,
+            touched_records: None,
+            trigrams: None,
+            children,
+            file_ids: Some(file_ids),
+            directory_walk: false,
+            network: FileInfos::default(),
+            network_children: ChildrenMap::default(),
+            generation: 0,
+        })
+    }
+
+    /// Builds the index of a volume which has neither an MFT nor a journal, like FAT32 and exFAT,
+    /// by listing every directory. Such an index is kept up to date with
+    /// [Self::process_path_changes] instead of journal entries.
+    pub fn from_directory_walk(volume: Volume) -> Result<NtfsVolumeIndex> {
+        let infos = FileInfos::from(fallback::enumerate_files(volume)?);
+        let children = ChildrenMap::build(&infos);
+        Ok(Self {
+            volume,
+            infos,
+            unreadable_records: 0,
+            mft: None,
+            touched_records: None,
+            trigrams: None,
+            children,
+            file_ids: None,
+            directory_walk: true,
+            network: FileInfos::default(),
+            network_children: ChildrenMap::default(),
+            generation: 0,
+        })
+    }
+
+    /// Reads a single record from the MFT. Returns `None` if the record is not in use. Fails if the
+    /// index was not built from the MFT.
+    pub fn read_record(&self, mft_index: u64) -> Result<Option<FileInfo>> {
+        let mft = self
+            .mft
+            .as_ref()
+            .with_context(|| "Index was not built from the MFT")?;
+        let record_size = mft.volume_data.BytesPerFileRecordSegment as usize;
+
+        let mut buffer = vec![0u8; record_size];
+        read_runs(
+            mft.reader.as_ref(),
+            &mft.runs,
+            mft_index as usize * record_size,
+            &mut buffer,
+        )
+        .with_context(|| format!("Failed to read MFT record {}", mft_index))?;
+
+        Ok(parse_record(&mut buffer, mft.volume_data))
+    }
+
+    /// Replaces the entry at `mft_index` with the current content of its MFT record. Used when the
+    /// index turns out to be out of date, for example because the record was reused for another
+    /// file. Without access to the MFT, the entry is removed.
+    fn refresh_record(&mut self, mft_index: u64) {
+        let info = self.read_record(mft_index).unwrap_or_else(|e| {
+            error!("{:?}", e);
+            None
+        });
+        self.replace(mft_index, info);
+    }
+
+    /// Replaces the entry at `mft_index`, growing the index if needed, and returns the previous
+    /// entry. Keeps the trigram index up to date.
+    fn replace(&mut self, mft_index: u64, mut info: Option<FileInfo>) -> Option<FileInfo> {
+        if let Some(info) = &mut info {
+            info.system_noise = is_system_noise(mft_index, info)
+                || self
+                    .find_by_index(info.parent)
+                    .is_some_and(|parent| parent.system_noise && parent.is_directory());
+        }
+
+        self.generation += 1;
+        let old = self.infos.replace(mft_index as usize, info);
+        if let Some(trigrams) = &mut self.trigrams {
+            if let Some(old) = &old {
+                trigrams.remove(mft_index, &old.name);
+            }
+            if let Some(new) = self.infos.get(mft_index as usize) {
+                trigrams.insert(mft_index, &new.name);
+            }
+        }
+        if let Some(old) = &old {
+            self.children.remove(old.parent, mft_index);
+        }
+        if let Some(new) = self.infos.get(mft_index as usize) {
+            self.children.insert(new.parent, mft_index);
+        }
+        old
+    }
+
+    /// Removes everything inside the directory at `mft_index` and reports it as deleted, children
+    /// before their parents. Moving a directory doesn't need this, as its contents refer to it by
+    /// index and follow it automatically.
+    fn remove_descendants(&mut self, mft_index: u64, changes: &mut Vec<FileChange>) {
+        // The paths can't be computed anymore once the parents are removed
+        let mut descendants = Vec::new();
+        let mut directories = vec![mft_index];
+        while let Some(directory) = directories.pop() {
+            for child in self.children.get(directory) {
+                // A broken index could contain the directory inside of itself
+                if child == mft_index {
+                    continue;
+                }
+                let Some(info) = self.find_by_index(child) else {
+                    continue;
+                };
+                if info.parent == directory {
+                    directories.push(child);
+                    descendants.push((child, self.compute_full_path(info)));
+                }
+            }
+        }
+
+        for (child, path) in descendants.into_iter().rev() {
+            #[cfg(feature = "journal_dbg")]
+            tracing::debug!("Deleting file inside deleted directory: {}", path);
+
+            if let Some(file) = self.replace(child, None) {
+                changes.push(FileChange {
+                    kind: ChangeKind::Deleted,
+                    path,
+                    old_path: None,
+                    id: self.id_of(child, &file),
+                    file,
+                });
+            }
+        }
+    }
+
+    /// Indexes the trigrams of all names, which makes searching for text much faster on large
+    /// volumes at the cost of memory. The trigram index is kept up to date from then on.
+    pub fn build_trigram_index(&mut self) {
+        let t = Instant::now();
+        self.trigrams = Some(TrigramIndex::build(&self.infos));
+        info!("Building trigram index took: {:?}", t.elapsed());
+    }
+
+    /// Returns the sorted MFT indices of the files whose name might contain `text`, see
+    /// [TrigramIndex::candidates]. Returns `None` if there is no trigram index.
+    pub fn trigram_candidates(&self, text: &str) -> Option<Vec<u64>> {
+        self.trigrams.as_ref()?.candidates(text)
+    }
+
+    /// Applies changes reported by a [DirectoryWatcher](fallback::DirectoryWatcher) to an index
+    /// built with [Self::from_directory_walk]. Entries of added and modified files are read from
+    /// the volume again.
+    pub fn process_path_changes(&mut self, path_changes: &[PathChange]) -> Vec<FileChange> {
+        let mut changes = Vec::new();
+        for change in path_changes {
+            match change {
+                PathChange::Added(path) | PathChange::Modified(path) => {
+                    let (parent_path, name) = fallback::split_path(path);
+                    let Some(parent) = self.find_relative(parent_path) else {
+                        warn!("Parent not found: {}", parent_path);
+                        continue;
+                    };
+                    let full_path = format!("{}:\\{}", self.volume.id, path);
+                    let mut info = match fallback::find_file(&full_path) {
+                        Ok(Some(info)) => info,
+                        // Already deleted again, which is reported separately
+                        Ok(None) => continue,
+                        Err(e) => {
+                            error!("{:?}", e);
+                            continue;
+                        }
+                    };
+                    info.parent = parent;
+
+                    let existing = self.find_child(parent, name);
+                    let mft_index = existing.unwrap_or(self.infos.len() as u64);
+                    self.replace(mft_index, Some(info));
+                    // Files which are added while the volume is listed are reported twice
+                    if existing.is_none() {
+                        let info = self.find_by_index(mft_index).unwrap();
+                        changes.push(FileChange {
+                            kind: ChangeKind::Created,
+                            path: self.compute_full_path(info),
+                            old_path: None,
+                            id: self.id_of(mft_index, info),
+                            file: info.clone(),
+                        });
+                    }
+                }
+                PathChange::Removed(path) => {
+                    let Some(mft_index) = self.find_relative(path) else {
+                        continue;
+                    };
+                    let sequence = self.find_by_index(mft_index).unwrap().sequence;
+                    changes.extend(self.process_journal_entries(&[JournalEntry::FileDelete {
+                        mft_index,
+                        sequence,
+                    }]));
+                }
+                PathChange::Renamed { old, new } => {
+                    let (old_parent_path, old_name) = fallback::split_path(old);
+                    let (new_parent_path, new_name) = fallback::split_path(new);
+                    let (Some(mft_index), Some(old_parent), Some(new_parent)) = (
+                        self.find_relative(old),
+                        self.find_relative(old_parent_path),
+                        self.find_relative(new_parent_path),
+                    ) else {
+                        warn!("Renamed file not found: {} -> {}", old, new);
+                        continue;
+                    };
+                    let sequence = self.find_by_index(mft_index).unwrap().sequence;
+                    changes.extend(self.process_journal_entries(&[JournalEntry::Rename {
+                        mft_index,
+                        sequence,
+                        old_name: old_name.to_string(),
+                        old_parent_mft_index: old_parent,
+                        new_name: new_name.to_string(),
+                        new_parent_mft_index: new_parent,
+                    }]));
+                }
+            }
+        }
+
+        changes
+    }
+
+    /// Resolves a path relative to the root of the volume. Names are compared ignoring case, like
+    /// the file systems of Windows do.
+    fn find_relative(&self, path: &str) -> Option<u64> {
+        path.split('\\')
+            .filter(|name| !name.is_empty())
+            .try_fold(ROOT_INDEX, |parent, name| self.find_child(parent, name))
+    }
+
+    fn find_child(&self, parent: u64, name: &str) -> Option<u64> {
+        let (children, base) = self.children_map(parent);
+        children
+            .get(parent)
+            .map(|child| base + child)
+            .find(|&child| {
+                self.find_by_index(child)
+                    .is_some_and(|info| info.parent == parent && eq_ignore_case(&info.name, name))
+            })
+    }
+
+    /// Returns whether the entry at `mft_index` belongs to the file with the given sequence number.
+    /// Indices built without sequence numbers accept any.
+    fn is_current(&self, mft_index: u64, sequence: u16) -> bool {
+        self.find_by_index(mft_index)
+            .is_some_and(|info| info.sequence == 0 || info.sequence == sequence)
+    }
+
+    /// Applies the journal entries to the index and returns the changes which were made.
+    pub fn process_journal_entries(&mut self, entries: &[JournalEntry]) -> Vec<FileChange> {
+        let mut changes = Vec::new();
         for e in entries {
+            if let Some(touched) = &mut self.touched_records {
+                touched.insert(e.mft_index());
+            }
+
             match e {
                 JournalEntry::FileCreate {
                     mft_index,
+                    sequence,
                     is_directory,
                     parent_mft_index,
                     name,
+                    timestamp,
+                    attributes,
                 } => {
-                    if self.find_by_index(*mft_index).is_some() {
-                        eprintln!("File already exists: {}, {}", mft_index, name);
+                    if self.is_current(*mft_index, *sequence) {
+                        warn!("File already exists: {}, {}", mft_index, name);
                         continue;
                     }
+                    // Otherwise, the entry is a leftover of a file whose deletion was missed and
+                    // gets replaced
 
                     if self.find_by_index(*parent_mft_index).is_none() {
-                        eprintln!("Parent not found: {}", parent_mft_index);
+                        warn!("Parent not found: {}", parent_mft_index);
                         continue;
                     }
 
-                    // Prevent out of bounds access
-                    if *mft_index as usize >= self.infos.len() {
-                        self.infos
-                            .resize_with(*mft_index as usize + 1, Default::default);
-                    }
+                    // The journal entry lacks the size, so prefer the MFT record if it still
+                    // belongs to this file
+                    let record = self
+                        .read_record(*mft_index)
+                        .ok()
+                        .flatten()
+                        .filter(|record| record.sequence == *sequence);
+                    let (size, size_on_disk, times, attributes) = match record {
+                        Some(record) => (
+                            record.size(),
+                            record.size_on_disk,
+                            record.times,
+                            record.attributes,
+                        ),
+                        // The file was just created, so all timestamps match the journal entry
+                        None => (
+                            0,
+                            0,
+                            FileTimes {
+                                created: *timestamp,
+                                modified: *timestamp,
+                                accessed: *timestamp,
+                            },
+                            *attributes,
+                        ),
+                    };
+
+                    self.replace(
+                        *mft_index,
+                        Some(
+                            FileInfo::new(
+                                size,
+                                size_on_disk,
+                                *is_directory,
+                                *parent_mft_index,
+                                SmartString::from(name),
+                                times,
+                                attributes,
+                            )
+                            .with_sequence(*sequence),
+                        ),
+                    );
 
-                    self.infos[*mft_index as usize] = Some(FileInfo::new(
-                        // TODO: Get size from somewhere
-                        0,
-                        *is_directory,
-                        *parent_mft_index,
-                        SmartString::from(name),
-                    ));
+                    let info = self.infos.get(*mft_index as usize).unwrap();
+                    let path = self.compute_full_path(info);
 
                     #[cfg(feature = "journal_dbg")]
-                    println!(
-                        "Creating file: {}",
-                        self.compute_full_path(self.infos[*mft_index as usize].as_ref().unwrap())
-                    );
+                    tracing::debug!("Creating file: {}", path);
+
+                    changes.push(FileChange {
+                        kind: ChangeKind::Created,
+                        path,
+                        old_path: None,
+                        id: self.id_of(*mft_index, info),
+                        file: info.clone(),
+                    });
                 }
                 JournalEntry::Rename {
                     mft_index,
+                    sequence,
+                    old_name,
+                    old_parent_mft_index,
                     new_name,
                     new_parent_mft_index,
                 } => {
+                    let kind = if old_parent_mft_index == new_parent_mft_index {
+                        ChangeKind::Renamed
+                    } else {
+                        ChangeKind::Moved
+                    };
+                    let old_path = self.child_path(*old_parent_mft_index, old_name);
+
                     if self.find_by_index(*new_parent_mft_index).is_none() {
-                        eprintln!("Parent not found: {}", new_parent_mft_index);
+                        warn!("Parent not found: {}", new_parent_mft_index);
                         continue;
                     }
 
-                    #[cfg(feature = "journal_dbg")]
-                    let old_path = self.compute_full_path(self.find_by_index(*mft_index).unwrap());
+                    if self.find_by_index(*mft_index).is_some()
+                        && !self.is_current(*mft_index, *sequence)
+                    {
+                        // The record was reused without us noticing, so the entry describes
+                        // another file
+                        self.refresh_record(*mft_index);
+                        let Some(info) = self.find_by_index(*mft_index) else {
+                            continue;
+                        };
+                        changes.push(FileChange {
+                            kind,
+                            path: self.compute_full_path(info),
+                            old_path: Some(old_path),
+                            id: self.id_of(*mft_index, info),
+                            file: info.clone(),
+                        });
+                        continue;
+                    }
+
+                    let Some(info) = self.infos.get_mut(*mft_index as usize) else {
+                        continue;
+                    };
+                    if let Some(trigrams) = &mut self.trigrams {
+                        trigrams.remove(*mft_index, &info.name);
+                        trigrams.insert(*mft_index, new_name);
+                    }
+                    self.children.remove(info.parent, *mft_index);
+                    self.children.insert(*new_parent_mft_index, *mft_index);
+                    self.generation += 1;
+                    info.name = SmartString::from(new_name);
+                    info.parent = *new_parent_mft_index;
 
-                    if let Some(Some(info)) = self.infos.get_mut(*mft_index as usize) {
-                        info.name = SmartString::from(new_name);
-                        info.parent = *new_parent_mft_index;
+                    let info = self.find_by_index(*mft_index).unwrap();
+                    if info.quarantined && self.reaches_root(info) {
+                        self.infos.get_mut(*mft_index as usize).unwrap().quarantined = false;
                     }
+                    let info = self.find_by_index(*mft_index).unwrap();
+                    let path = self.compute_full_path(info);
 
                     #[cfg(feature = "journal_dbg")]
-                    println!(
-                        "Renaming file: {} -> {}",
-                        old_path,
-                        self.compute_full_path(self.find_by_index(*mft_index).unwrap())
-                    );
+                    tracing::debug!("{} file: {} -> {}", kind.name(), old_path, path);
+
+                    changes.push(FileChange {
+                        kind,
+                        path,
+                        old_path: Some(old_path),
+                        id: self.id_of(*mft_index, info),
+                        file: info.clone(),
+                    });
                 }
-                JournalEntry::FileDelete(index) => {
-                    #[cfg(feature = "journal_dbg")]
-                    if let Some(info) = self.find_by_index(*index) {
-                        println!("Deleting file: {}", self.compute_full_path(info));
+                JournalEntry::FileDelete {
+                    mft_index: index,
+                    sequence,
+                } => {
+                    let Some(info) = self.find_by_index(*index) else {
+                        continue;
+                    };
+                    if !self.is_current(*index, *sequence) {
+                        // The deleted file is not the one in the index, which is refreshed in case
+                        // it is out of date as well
+                        self.refresh_record(*index);
+                        continue;
+                    }
+                    if info.is_directory() {
+                        // NTFS only deletes empty directories, so anything still inside of it was
+                        // missed by the journal
+                        self.remove_descendants(*index, &mut changes);
                     }
+                    let path = self.compute_full_path(self.find_by_index(*index).unwrap());
 
-                    self.infos[*index as usize] = None;
+                    #[cfg(feature = "journal_dbg")]
+                    tracing::debug!("Deleting file: {}", path);
+
+                    let file = self.replace(*index, None).unwrap();
+                    changes.push(FileChange {
+                        kind: ChangeKind::Deleted,
+                        path,
+                        old_path: None,
+                        id: self.id_of(*index, &file),
+                        file,
+                    });
                 }
             }
         }
+
+        changes
     }
 
     pub fn find_by_name(&self, name: &str) -> Option<&FileInfo> {
@@ -152,10 +926,59 @@ impl NtfsVolumeIndex {
             .and_then(|info| info.as_ref().copied())
     }
 
+    /// Returns every file with exactly this name, ordered by MFT index. Files of network locations
+    /// come last.
+    pub fn find_all_by_name(&self, name: &str) -> Vec<&FileInfo> {
+        let mut files = self
+            .par_iter()
+            .flatten()
+            .filter(|info| info.name == name)
+            .collect::<Vec<_>>();
+        files.par_extend(
+            self.par_iter_network()
+                .map(|(_, info)| info)
+                .filter(|info| info.name == name),
+        );
+        files
+    }
+
+    /// Resolves a full path like `C:\foo\bar.txt` or `\\server\share\bar.txt`, see
+    /// [Self::resolve_path].
+    pub fn find_by_path(&self, path: &str) -> Option<&FileInfo> {
+        self.find_by_index(self.resolve_path(path)?)
+    }
+
     pub fn find_by_index(&self, index: u64) -> Option<&FileInfo> {
-        self.infos
-            .get(index as usize)
-            .and_then(|info| info.as_ref())
+        let (infos, index) = match index.checked_sub(NETWORK_INDEX_BASE) {
+            Some(index) => (&self.network, index),
+            None => (&self.infos, index),
+        };
+        infos.get(index as usize)
+    }
+
+    /// Returns the id of the entry at `index`, which can be kept while the index changes
+    pub fn file_id(&self, index: u64) -> Option<FileId> {
+        self.find_by_index(index)
+            .map(|info| self.id_of(index, info))
+    }
+
+    fn id_of(&self, index: u64, info: &FileInfo) -> FileId {
+        FileId {
+            volume: self.volume.id,
+            mft_index: index,
+            sequence: info.sequence,
+        }
+    }
+
+    /// Returns the index of the file with the given id. `None` if it belongs to another volume or
+    /// was deleted, even if its MFT record was reused for another file since.
+    pub fn resolve(&self, id: FileId) -> Option<u64> {
+        if !id.volume.eq_ignore_ascii_case(&self.volume.id) {
+            return None;
+        }
+        self.find_by_index(id.mft_index)
+            .filter(|info| info.sequence == id.sequence)
+            .map(|_| id.mft_index)
     }
 
     pub fn compute_full_path(&self, file_info: &FileInfo) -> String {
@@ -167,9 +990,15 @@ impl NtfsVolumeIndex {
         });
 
         let mut out = String::with_capacity(2 + path_size);
-        out.push(self.volume.id.to_ascii_uppercase());
-        out.push(':');
-        path.iter().rev().for_each(|&s| {
+        let mut path = path.iter().rev().peekable();
+        match path.next_if(|name| is_network_root(name)) {
+            Some(root) => out.push_str(root),
+            None => {
+                out.push(self.volume.id.to_ascii_uppercase());
+                out.push(':');
+            }
+        }
+        path.for_each(|&s| {
             out.push('\\');
             out.push_str(s);
         });
@@ -177,6 +1006,120 @@ impl NtfsVolumeIndex {
         out
     }
 
+    /// Full path of a file named `name` inside the directory at `parent`, which doesn't have to
+    /// exist.
+    fn child_path(&self, parent: u64, name: &str) -> String {
+        let mut path = match self.find_by_index(parent) {
+            Some(info) if parent != ROOT_INDEX => self.compute_full_path(info),
+            _ => format!("{}:", self.volume.id.to_ascii_uppercase()),
+        };
+        path.push('\\');
+        path.push_str(name);
+        path
+    }
+
+    /// Resolves the full path of a directory like `C:\foo` or `\\server\share\foo` to its MFT
+    /// index, see [Self::resolve_path].
+    pub fn find_directory(&self, path: &str) -> Option<u64> {
+        let directory = self.resolve_path(path)?;
+        self.find_by_index(directory)?
+            .is_directory()
+            .then_some(directory)
+    }
+
+    /// Resolves a full path to the MFT index of the file by following the children of every
+    /// component down from the root. Like paths on Windows, the path is compared
+    /// case-insensitively.
+    fn resolve_path(&self, path: &str) -> Option<u64> {
+        let path = path.trim_end_matches('\\');
+        let volume = format!("{}:", self.volume.id);
+        let (root, rest) = match path.get(..2) {
+            Some(prefix) if prefix.eq_ignore_ascii_case(&volume) => (ROOT_INDEX, &path[2..]),
+            _ => self.network.iter().enumerate().find_map(|(i, info)| {
+                let info = info.as_ref()?;
+                let location = info.name.trim_end_matches('\\');
+                let rest = path.get(location.len()..)?;
+                (info.parent == ROOT_INDEX
+                    && eq_ignore_case(&path[..location.len()], location)
+                    && (rest.is_empty() || rest.starts_with('\\')))
+                .then_some((NETWORK_INDEX_BASE + i as u64, rest))
+            })?,
+        };
+
+        rest.split('\\')
+            .filter(|name| !name.is_empty())
+            .try_fold(root, |parent, name| self.find_child(parent, name))
+    }
+
+    /// Whether the entry at `mft_index` is somewhere below the directory at `directory`. Walks up
+    /// the parents instead of comparing paths.
+    pub fn is_inside(&self, mft_index: u64, directory: u64) -> bool {
+        // The roots of network locations are inside the root directory as well
+        mft_index != directory
+            && (mft_index >= NETWORK_INDEX_BASE) == (directory >= NETWORK_INDEX_BASE)
+            && self.find_by_index(mft_index).is_some_and(|info| {
+                self.iter_with_parents(info)
+                    .any(|parent| parent.parent == directory)
+            })
+    }
+
+    /// Returns the MFT indices of everything below the directory at `directory` in ascending
+    /// order. Like [Self::par_iter], this only covers the volume, so it is empty for directories of
+    /// network locations.
+    pub fn descendants(&self, directory: u64) -> Vec<u64> {
+        if directory >= NETWORK_INDEX_BASE {
+            return Vec::new();
+        }
+
+        let mut descendants = Vec::new();
+        let mut directories = vec![directory];
+        while let Some(parent) = directories.pop() {
+            for child in self.children.get(parent) {
+                // A broken index could contain a directory inside of itself
+                if child == directory || child == ROOT_INDEX {
+                    continue;
+                }
+                let Some(info) = self.find_by_index(child) else {
+                    continue;
+                };
+                if info.parent == parent {
+                    descendants.push(child);
+                    if info.is_directory() {
+                        directories.push(child);
+                    }
+                }
+            }
+        }
+        descendants.sort_unstable();
+        descendants
+    }
+
+    /// Returns the entries directly inside the directory at `mft_index`, in no particular order.
+    pub fn children(&self, mft_index: u64) -> impl Iterator<Item = &FileInfo> {
+        let (children, base) = self.children_map(mft_index);
+        children
+            .get(mft_index)
+            .filter_map(move |child| self.find_by_index(base + child))
+            .filter(move |info| info.parent == mft_index)
+    }
+
+    /// Whether the entry at `mft_index` is a directory without anything inside of it.
+    pub fn is_empty_directory(&self, mft_index: u64) -> bool {
+        self.find_by_index(mft_index)
+            .is_some_and(|info| info.is_directory())
+            && self.children(mft_index).next().is_none()
+    }
+
+    /// The children map which contains the children of `mft_index`, and the index of its first
+    /// entry
+    fn children_map(&self, mft_index: u64) -> (&ChildrenMap, u64) {
+        if mft_index >= NETWORK_INDEX_BASE {
+            (&self.network_children, NETWORK_INDEX_BASE)
+        } else {
+            (&self.children, 0)
+        }
+    }
+
     pub fn iter_with_parents<'a>(
         &'a self,
         file_info: &'a FileInfo,
@@ -184,20 +1127,93 @@ impl NtfsVolumeIndex {
         HierarchyIter::<'a> {
             index: self,
             current: Some(file_info),
+            steps: 0,
         }
     }
 
     pub fn iter(&self) -> impl ExactSizeIterator<Item = Option<&FileInfo>> {
-        self.infos.iter().map(|info| info.as_ref())
+        self.infos.iter()
     }
 
     pub fn par_iter(&self) -> impl IndexedParallelIterator<Item = Option<&FileInfo>> {
         self.infos
             .par_iter()
-            .map(|info| info.as_ref())
             .with_min_len(self.infos.len() / PAR_ITER_CHUNK_COUNT)
     }
 
+    /// Returns the entries of the network locations with their indices. They are not part of
+    /// [Self::par_iter], which only covers the volume.
+    pub fn par_iter_network(&self) -> impl ParallelIterator<Item = (u64, &FileInfo)> {
+        self.network
+            .par_iter()
+            .enumerate()
+            .filter_map(|(i, info)| Some((NETWORK_INDEX_BASE + i as u64, info?)))
+    }
+
+    /// Replaces the entries of all network locations. Every location lists its root directory
+    /// first, whose name is the path of the location and whose parent is [ROOT_INDEX]. The parents
+    /// of the other entries are positions in the same list.
+    pub fn set_network_files(&mut self, locations: &[&[FileInfo]]) {
+        self.generation += 1;
+        let mut network = Vec::new();
+        for files in locations {
+            let base = NETWORK_INDEX_BASE + network.len() as u64;
+            network.extend(files.iter().enumerate().map(|(i, info)| {
+                let mut info = info.clone();
+                if i != 0 {
+                    info.parent += base;
+                }
+                Some(info)
+            }));
+        }
+        self.network = FileInfos::from(network);
+        self.network_children = ChildrenMap::build(&self.network);
+    }
+
+    /// Returns the entries of an index built with [Self::from_directory_walk] in the layout of
+    /// [Self::set_network_files], so that the volume can be searched together with another one.
+    /// The root directory is named after the volume, like `E:`. Entries which are cut off from the
+    /// root are left out.
+    pub fn to_location(&self) -> Vec<FileInfo> {
+        let mut positions = FxHashMap::default();
+        positions.insert(ROOT_INDEX, 0);
+        for (i, info) in self.infos.iter().enumerate() {
+            if info.is_some_and(|info| self.reaches_root(info)) {
+                positions.insert(i as u64, positions.len() as u64);
+            }
+        }
+
+        let mut files = Vec::with_capacity(positions.len());
+        files.push(FileInfo::new(
+            0,
+            0,
+            true,
+            ROOT_INDEX,
+            format!("{}:", self.volume.id.to_ascii_uppercase()).into(),
+            FileTimes::default(),
+            FILE_ATTRIBUTE_DIRECTORY.0,
+        ));
+        files.extend(self.infos.iter().enumerate().filter_map(|(i, info)| {
+            positions.get(&(i as u64))?;
+            let mut info = info?.clone();
+            info.parent = positions[&info.parent];
+            Some(info)
+        }));
+        files
+    }
+
+    /// Replaces all entries with those of `fresh`, which listed the same volume again. Used for
+    /// indices built with [Self::from_directory_walk], whose entries have no stable indices that
+    /// [Self::reconcile] could compare. Network locations are kept.
+    pub fn replace_entries(&mut self, fresh: NtfsVolumeIndex) {
+        self.generation += 1;
+        self.infos = fresh.infos;
+        self.children = fresh.children;
+        if self.trigrams.is_some() {
+            self.trigrams = Some(TrigramIndex::build(&self.infos));
+        }
+    }
+
     pub fn volume(&self) -> Volume {
         self.volume
     }
@@ -206,15 +1222,295 @@ impl NtfsVolumeIndex {
         self.infos.len()
     }
 
-    // TODO: Cache this
+    /// Number of MFT records which could not be read. The files stored in them are missing from
+    /// the index.
+    pub fn unreadable_records(&self) -> usize {
+        self.unreadable_records
+    }
+
+    /// Changes whenever a file in the index is added, removed or changed, so that results computed
+    /// from the index can be reused as long as it stays the same.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Whether the index was built by reading the MFT, as opposed to USN enumeration
+    pub fn is_from_mft(&self) -> bool {
+        self.mft.is_some()
+    }
+
+    pub fn is_from_directory_walk(&self) -> bool {
+        self.directory_walk
+    }
+
+    /// The mapping of ReFS file ids to indices, which has to be shared with the journal. `None`
+    /// for NTFS volumes.
+    pub fn file_ids(&self) -> Option<Arc<Mutex<FileIdMap>>> {
+        self.file_ids.clone()
+    }
+
+    /// Location of the MFT, which can be read without holding the index. `None` if the index was
+    /// not built from the MFT.
+    pub fn mft_location(&self) -> Option<MftLocation> {
+        self.mft.clone()
+    }
+
+    /// MFT indices of all directories on the volume
+    pub fn directories(&self) -> Vec<u64> {
+        self.par_iter()
+            .enumerate()
+            .filter(|(_, info)| info.is_some_and(|info| info.is_directory()))
+            .map(|(i, _)| i as u64)
+            .collect()
+    }
+
+    /// Compares the children of the directory at `mft_index` with the `entries` of its `$I30`
+    /// index, read with [MftLocation::read_directory]. Files which the directory lists but which
+    /// have another parent in the index are hard links and not reported. A file which was moved
+    /// without the index noticing is still reported as unlisted by its old parent.
+    pub fn compare_directory(
+        &self,
+        mft_index: u64,
+        entries: &[DirectoryEntry],
+    ) -> Vec<Discrepancy> {
+        let mut discrepancies = entries
+            .iter()
+            .filter(|entry| match self.find_by_index(entry.mft_index) {
+                Some(info) => info.sequence != entry.sequence,
+                None => true,
+            })
+            .map(|entry| Discrepancy {
+                mft_index: entry.mft_index,
+                kind: DiscrepancyKind::Missing,
+                path: self.child_path(mft_index, &entry.name),
+            })
+            .collect::<Vec<_>>();
+
+        let listed = entries
+            .iter()
+            .map(|entry| entry.mft_index)
+            .collect::<FxHashSet<_>>();
+        discrepancies.extend(
+            self.children
+                .get(mft_index)
+                .filter(|child| !listed.contains(child))
+                .filter_map(|child| Some((child, self.find_by_index(child)?)))
+                .filter(|(_, info)| info.parent == mft_index)
+                .map(|(child, info)| Discrepancy {
+                    mft_index: child,
+                    kind: DiscrepancyKind::Unlisted,
+                    path: self.compute_full_path(info),
+                }),
+        );
+        discrepancies
+    }
+
+    /// Starts remembering which records the journal changes, so that [Self::reconcile] does not
+    /// revert changes which happened after the volume was read again.
+    pub fn begin_verification(&mut self) {
+        self.touched_records = Some(FxHashSet::default());
+    }
+
+    /// Stops remembering the records changed by the journal, for verifications which end without
+    /// [Self::reconcile].
+    pub fn end_verification(&mut self) {
+        self.touched_records = None;
+    }
+
+    /// Replaces every entry which differs from `fresh`, except for records which were changed
+    /// by the journal since [Self::begin_verification]. Returns the number of files which were
+    /// missing, left over or had a wrong name or parent. Sizes and dates are not tracked by the
+    /// journal, so they are updated without being counted.
+    pub fn reconcile(&mut self, fresh: NtfsVolumeIndex) -> usize {
+        let touched = self.touched_records.take().unwrap_or_default();
+        self.generation += 1;
+        let len = self.infos.len().max(fresh.infos.len());
+        let mut fresh_infos = fresh.infos;
+
+        let mut corrections = 0;
+        for i in 0..len {
+            if touched.contains(&(i as u64)) {
+                continue;
+            }
+
+            let info = self.infos.get(i);
+            let fresh = fresh_infos.replace(i, None);
+            let is_same_file = match (info, &fresh) {
+                (Some(a), Some(b)) => {
+                    a.name == b.name && a.parent == b.parent && a.is_directory() == b.is_directory()
+                }
+                (None, None) => continue,
+                _ => false,
+            };
+            if !is_same_file {
+                corrections += 1;
+            }
+            if let Some(trigrams) = &mut self.trigrams {
+                if info.map(|info| &info.name) != fresh.as_ref().map(|info| &info.name) {
+                    if let Some(old) = info {
+                        trigrams.remove(i as u64, &old.name);
+                    }
+                    if let Some(new) = &fresh {
+                        trigrams.insert(i as u64, &new.name);
+                    }
+                }
+            }
+            self.infos.replace(i, fresh);
+        }
+
+        if let Some(mft) = fresh.mft {
+            self.mft = Some(mft);
+        }
+        self.children = ChildrenMap::build(&self.infos);
+        corrections
+    }
+
+    /// Closes the handle used to read single MFT records. Afterwards, entries which turn out to be
+    /// out of date are removed instead of being read again.
+    pub fn close(&mut self) {
+        self.mft = None;
+    }
+
+    /// Returns the MFT indices of all entries whose parents don't lead to the root directory,
+    /// because a parent is missing or the parents form a cycle.
+    pub fn find_orphans(&self) -> Vec<u64> {
+        self.par_iter()
+            .enumerate()
+            .filter_map(|(i, info)| (!self.reaches_root(info?)).then_some(i as u64))
+            .collect()
+    }
+
+    /// Reads the orphans and their missing parents from the MFT again. Orphans which still don't
+    /// reach the root directory afterwards are quarantined, which hides them from search results
+    /// until the journal or a verification replaces them.
+    pub fn repair_orphans(&mut self) -> Vec<Orphan> {
+        let orphans = self
+            .find_orphans()
+            .into_iter()
+            .map(|mft_index| {
+                (
+                    mft_index,
+                    self.infos.get(mft_index as usize).unwrap().clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        if orphans.is_empty() {
+            return Vec::new();
+        }
+
+        if self.mft.is_some() {
+            for &(mft_index, _) in &orphans {
+                self.refresh_record(mft_index);
+
+                let mut current = mft_index;
+                for _ in 0..MAX_DEPTH {
+                    let Some(info) = self.find_by_index(current) else {
+                        break;
+                    };
+                    let parent = info.parent;
+                    if parent == ROOT_INDEX {
+                        break;
+                    }
+                    if self.find_by_index(parent).is_none() {
+                        self.refresh_record(parent);
+                    }
+                    current = parent;
+                }
+            }
+        }
+
+        self.generation += 1;
+        orphans
+            .into_iter()
+            .map(|(mft_index, old)| {
+                // Records which are not in use anymore were removed, which resolves them as well
+                let resolved = self
+                    .find_by_index(mft_index)
+                    .is_none_or(|info| self.reaches_root(info));
+                if let Some(info) = self.infos.get_mut(mft_index as usize) {
+                    info.quarantined = !resolved;
+                }
+
+                Orphan {
+                    mft_index,
+                    name: old.name.to_string(),
+                    resolved,
+                }
+            })
+            .collect()
+    }
+
+    fn reaches_root(&self, info: &FileInfo) -> bool {
+        let mut current = info;
+        for _ in 0..MAX_DEPTH {
+            if current.parent == ROOT_INDEX {
+                return true;
+            }
+            match self.find_by_index(current.parent) {
+                Some(parent) => current = parent,
+                None => return false,
+            }
+        }
+        false
+    }
+
+    /// Returns the number of files and the number of directories in the index
+    pub fn count_files_and_directories(&self) -> (usize, usize) {
+        self.par_iter()
+            .flatten()
+            .fold(
+                || (0, 0),
+                |(files, directories), info| {
+                    if info.is_directory() {
+                        (files, directories + 1)
+                    } else {
+                        (files + 1, directories)
+                    }
+                },
+            )
+            .reduce(|| (0, 0), |a, b| (a.0 + b.0, a.1 + b.1))
+    }
+
     pub fn real_file_count(&self) -> usize {
-        self.infos.iter().filter(|i| i.is_some()).count()
+        self.infos.count()
+    }
+
+    /// Number of removed entries whose memory is freed by [Self::compact]
+    pub fn removed_entries(&self) -> usize {
+        self.infos.removed()
+    }
+
+    /// Whether enough entries were removed since the last compaction that the memory should be
+    /// freed, see [FileInfos::needs_compaction].
+    pub fn needs_compaction(&self) -> bool {
+        self.infos.needs_compaction()
+    }
+
+    /// Frees the memory of removed entries and unused capacity. Returns the number of bytes which
+    /// were freed.
+    pub fn compact(&mut self) -> usize {
+        self.infos.compact() + self.children.shrink_to_fit()
     }
 }
 
+/// Roots of network locations are named after their whole path, like `\\server\share` or `Z:`.
+/// Names of real files can't contain either character.
+fn is_network_root(name: &str) -> bool {
+    name.contains(['\\', ':'])
+}
+
+fn eq_ignore_case(a: &str, b: &str) -> bool {
+    a.chars()
+        .flat_map(char::to_lowercase)
+        .eq(b.chars().flat_map(char::to_lowercase))
+}
+
+/// Iterates over an entry and its parents. Stops after [MAX_DEPTH] entries, so that a cycle in the
+/// parents of a damaged index doesn't loop forever.
 struct HierarchyIter<'a> {
     index: &'a NtfsVolumeIndex,
     current: Option<&'a FileInfo>,
+    steps: usize,
 }
 
 impl<'a> Iterator for HierarchyIter<'a> {
@@ -225,7 +1521,8 @@ impl<'a> Iterator for HierarchyIter<'a> {
             None => None,
             Some(current) => {
                 let next = current;
-                self.current = if current.parent == ROOT_INDEX {
+                self.steps += 1;
+                self.current = if current.parent == ROOT_INDEX || self.steps >= MAX_DEPTH {
                     None
                 } else {
                     Some(self.index.find_by_index(current.parent)?)
@@ -237,169 +1534,793 @@ impl<'a> Iterator for HierarchyIter<'a> {
     }
 }
 
+/// Reads the MFT using an I/O completion port if the reader supports it, otherwise one chunk after
+/// another. Completed chunks are parsed on the rayon thread pool while the following chunks are
+/// still being read. Also returns the number of records which could not be read.
+///
+/// If the bitmap of the $MFT is given, records which are not in use are skipped and the unused
+/// tail of the MFT is not read at all.
 fn process_mft_data(
     volume: Volume,
-    (total_size, runs): (usize, Vec<Range<usize>>),
-) -> Result<Vec<Option<FileInfo>>> {
-    let volume_data = volume.query_volume_data()?;
+    reader: &dyn VolumeRead,
+    (_, runs): (usize, Vec<Range<usize>>),
+    bitmap: Option<&[u8]>,
+) -> Result<(Vec<Option<FileInfo>>, usize)> {
+    let volume_data = reader.query_volume_data()?;
+    let record_size = volume_data.BytesPerFileRecordSegment as usize;
+    let used_records = bitmap.map(|bitmap| {
+        bitmap
+            .iter()
+            .rposition(|&byte| byte != 0)
+            .map_or(0, |i| i * 8 + 8 - bitmap[i].leading_zeros() as usize)
+    });
+    let chunks = split_runs_into_chunks(runs, used_records.map(|count| count * record_size));
 
-    let run_groups = distribute_runs_to_cpus(volume_data, (total_size, runs));
+    let mut file_infos = vec![None; chunks.iter().map(|c| c.len / record_size).sum()];
+    // Every chunk is parsed into its own part of the result, so the workers don't need to
+    // synchronize
+    let mut parts = Vec::with_capacity(chunks.len());
+    let mut rest = file_infos.as_mut_slice();
+    let mut first_record = 0usize;
+    for chunk in &chunks {
+        let (part, remaining) = rest.split_at_mut(chunk.len / record_size);
+        let len = part.len();
+        parts.push(Some((first_record, part)));
+        first_record += len;
+        rest = remaining;
+    }
 
-    let file_infos = std::thread::scope(|s| {
-        // Spawn all threads
-        let threads = run_groups
-            .into_iter()
-            .map(|runs| {
-                s.spawn(move || {
-                    if runs.is_empty() {
-                        return Ok(Vec::default());
-                    }
+    let mut buffers = BufferPool::new();
+    let returned = buffers.sender();
+    let handle = reader.open_overlapped()?;
+    if let Some(handle) = handle.filter(|_| is_low_io_priority()) {
+        let hint = FILE_IO_PRIORITY_HINT_INFO {
+            PriorityHint: IoPriorityHintLow,
+        };
+        let res = unsafe {
+            SetFileInformationByHandle(
+                handle,
+                FileIoPriorityHintInfo,
+                &hint as *const _ as *const c_void,
+                size_of_val(&hint) as u32,
+            )
+        };
+        if let Err(e) = res {
+            warn!("Failed to lower the I/O priority of the MFT reads: {:?}", e);
+        }
+    }
+    let res = rayon::in_place_scope(|s| {
+        let on_read = |i: usize, mut buffer: Vec<u8>| {
+            let (first_record, part) = parts[i].take().unwrap();
+            let returned = returned.clone();
+            s.spawn(move |_| {
+                let in_use = |record: usize| {
+                    let record = first_record + record;
+                    bitmap.is_none_or(|bitmap| bitmap[record / 8] & (1 << (record % 8)) != 0)
+                };
+                parse_records(&mut buffer, part, in_use, volume_data);
+                let _ = returned.send(buffer);
+            });
+        };
+        match handle {
+            Some(handle) => {
+                let mut queue_depth = QueueDepth::choose(volume, reader, chunks.len());
+                let res = read_chunks(handle, &chunks, &mut queue_depth, &mut buffers, on_read);
+                queue_depth.save(volume);
+                res
+            }
+            None => read_chunks_sequentially(reader, &chunks, &mut buffers, on_read),
+        }
+    });
+    if let Some(handle) = handle {
+        try_close_handle(handle)?;
+    }
+    let unreadable_bytes = res?;
+    mark_system_noise(&mut file_infos);
+
+    Ok((file_infos, unreadable_bytes.div_ceil(record_size)))
+}
 
-                    let mut buffer = read_runs_from_disk(volume, runs)?;
-
-                    Ok(buffer
-                        .chunks_mut(volume_data.BytesPerFileRecordSegment as usize)
-                        .map(|chunk| {
-                            let record = FileRecord::new(chunk);
-                            // Should be fine to determine without fixup
-                            if !record.is_valid() || !record.is_used() {
-                                return None;
-                            }
-
-                            FileRecord::fixup(chunk, volume_data.BytesPerSector as usize);
-                            let record = FileRecord::new(chunk);
-                            let (real_size, parent, name) =
-                                record.destructure_file_name_attribute()?;
-
-                            // We get the size from the data attribute or the file name attribute.
-                            // Some files don't have a data attribute, others don't have the size
-                            // stored in the file name attribute.
-                            Some(FileInfo::new(
-                                record.get_data_attribute_size().max(real_size),
-                                record.is_directory(),
-                                parent,
-                                name,
-                            ))
-                        })
-                        .collect())
-                })
+/// Sets [FileInfo::is_system_noise] for all files. The directories inside of `$Extend` are
+/// collected level by level, as a parent can come after its children in the MFT.
+fn mark_system_noise(file_infos: &mut [Option<FileInfo>]) {
+    let mut directories = FxHashSet::from_iter([EXTEND_INDEX]);
+    loop {
+        let found = file_infos
+            .par_iter()
+            .enumerate()
+            .filter_map(|(i, info)| {
+                let info = info.as_ref()?;
+                (info.is_directory()
+                    && directories.contains(&info.parent)
+                    && !directories.contains(&(i as u64)))
+                .then_some(i as u64)
             })
             .collect::<Vec<_>>();
+        if found.is_empty() {
+            break;
+        }
+        directories.extend(found);
+    }
 
-        // Join all threads
-        threads
-            .into_iter()
-            .map(|t| t.join().unwrap())
-            // Vec<Result<...>> -> Result<Vec<...>>
-            .collect::<Result<Vec<_>>>()
-    })?
-    .into_iter()
-    .fold(
-        Vec::with_capacity(total_size / volume_data.BytesPerFileRecordSegment as usize),
-        |mut acc, vec| {
-            acc.extend(vec);
-            acc
-        },
-    );
-
-    Ok(file_infos)
-}
-
-fn read_runs_from_disk(volume: Volume, runs: RunGroup) -> Result<Vec<u8>> {
-    let handle = volume.create_read_handle()?;
-    let mut events = Vec::with_capacity(runs.len());
-    let mut buffer: Vec<u8> = Vec::with_capacity(runs.iter().map(|r| r.len()).sum::<usize>());
-    let mut write_offset = 0usize;
-    for run in runs {
-        unsafe {
-            let mut ov = create_overlapped(run.start);
-            ov.hEvent = CreateEventW(None, true, false, None)?;
+    file_infos.par_iter_mut().enumerate().for_each(|(i, info)| {
+        if let Some(info) = info {
+            info.system_noise =
+                is_system_noise(i as u64, info) || directories.contains(&info.parent);
+        }
+    });
+}
 
-            let res = ReadFile(
-                handle,
-                Some(std::slice::from_raw_parts_mut(
-                    buffer.as_mut_ptr().add(write_offset),
-                    run.len(),
-                )),
-                None,
-                Some(&mut ov as *mut OVERLAPPED),
+/// Whether the file is a metafile or an empty system file. Files inside of `$Extend` are not
+/// detected, as that depends on their parents.
+fn is_system_noise(mft_index: u64, info: &FileInfo) -> bool {
+    let is_metafile = mft_index < FIRST_USER_INDEX && mft_index != ROOT_INDEX;
+    let is_empty_system_file =
+        !info.is_directory() && info.size() == 0 && info.attributes & FILE_ATTRIBUTE_SYSTEM.0 != 0;
+    is_metafile || is_empty_system_file
+}
+
+/// Parses the records in `buffer`. `in_use` is called with the index of a record in the buffer and
+/// returns whether the record needs to be parsed.
+fn parse_records(
+    buffer: &mut [u8],
+    file_infos: &mut [Option<FileInfo>],
+    in_use: impl Fn(usize) -> bool,
+    volume_data: NTFS_VOLUME_DATA_BUFFER,
+) {
+    let chunks = buffer.chunks_mut(volume_data.BytesPerFileRecordSegment as usize);
+    for (i, (chunk, info)) in chunks.zip(file_infos).enumerate() {
+        if !in_use(i) {
+            continue;
+        }
+
+        *info = parse_record(chunk, volume_data);
+    }
+}
+
+/// Parses a single file record. Returns `None` if the record is not in use or does not describe a
+/// file.
+fn parse_record(chunk: &mut [u8], volume_data: NTFS_VOLUME_DATA_BUFFER) -> Option<FileInfo> {
+    let record = FileRecord::new(chunk);
+    // Should be fine to determine without fixup
+    if !record.is_valid() || !record.is_used() {
+        return None;
+    }
+
+    FileRecord::fixup(chunk, volume_data.BytesPerSector as usize);
+    let record = FileRecord::new(chunk);
+    let (real_size, parent, name) = record.destructure_file_name_attribute()?;
+    let (times, attributes) = record.read_standard_information().unwrap_or_default();
+
+    // We get the size from the data attribute or the file name attribute. Some files don't have a
+    // data attribute, others don't have the size stored in the file name attribute.
+    Some(
+        FileInfo::new(
+            record.get_data_attribute_size().max(real_size),
+            record.get_data_attribute_allocated_size(),
+            record.is_directory(),
+            parent,
+            name,
+            times,
+            attributes,
+        )
+        .with_sequence(record.header.sequence_number),
+    )
+}
+
+/// Sets the number of reads which are queued at the same time while reading the MFT, at most 64.
+/// 0 measures it while the MFT of a volume is read for the first time, which is the default.
+pub fn set_read_queue_depth(depth: usize) {
+    READ_QUEUE_DEPTH.store(depth.min(MAX_PENDING_READS), Ordering::Relaxed);
+}
+
+/// Reads the MFT with a low I/O priority if enabled, so that other applications stay responsive
+/// while the index is built. Disabled by default.
+pub fn set_low_io_priority(enabled: bool) {
+    LOW_IO_PRIORITY.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_low_io_priority() -> bool {
+    LOW_IO_PRIORITY.load(Ordering::Relaxed)
+}
+
+/// Number of reads which [read_chunks] keeps in flight. Unless the depth was set or measured
+/// before, the first chunks of the MFT are read with each of [CALIBRATION_DEPTHS] in turn, waiting
+/// for all reads before switching to the next one. The remaining chunks are read with the depth
+/// which was the fastest, which is also saved in [Calibration] for the next time.
+///
+/// A chunk is only read into a buffer which was parsed already, so the measurements include how
+/// fast the rayon pool parses the records, and a fragmented MFT needs more reads per chunk.
+struct QueueDepth {
+    depth: usize,
+    calibration: Option<CalibrationStep>,
+    /// Set once the calibration finished
+    measured: Option<usize>,
+}
+
+struct CalibrationStep {
+    /// Index into [CALIBRATION_DEPTHS]
+    step: usize,
+    started: Instant,
+    fastest: Option<(usize, Duration)>,
+}
+
+impl QueueDepth {
+    fn choose(volume: Volume, reader: &dyn VolumeRead, chunk_count: usize) -> Self {
+        let fixed = |depth| Self {
+            depth,
+            calibration: None,
+            measured: None,
+        };
+
+        let depth = READ_QUEUE_DEPTH.load(Ordering::Relaxed);
+        if depth > 0 {
+            return fixed(depth);
+        }
+
+        let calibration = Calibration::load();
+        if let Some(&depth) = calibration.mft_read_queue_depth.get(&volume.id.to_string()) {
+            info!("Reading the MFT with {} reads in flight", depth);
+            return fixed(depth.clamp(1, MAX_PENDING_READS));
+        }
+
+        let depth = match reader.has_seek_penalty() {
+            Some(true) => HDD_PENDING_READS,
+            Some(false) => SSD_PENDING_READS,
+            None => DEFAULT_PENDING_READS,
+        };
+        // Small MFTs are read quickly with any depth, so they aren't worth measuring
+        if chunk_count < 2 * CALIBRATION_DEPTHS.len() * CALIBRATION_CHUNKS {
+            info!("Reading the MFT with {} reads in flight", depth);
+            return fixed(depth);
+        }
+
+        info!("Measuring how many reads in flight read the MFT the fastest");
+        Self {
+            depth,
+            calibration: Some(CalibrationStep {
+                step: 0,
+                started: Instant::now(),
+                fastest: None,
+            }),
+            measured: None,
+        }
+    }
+
+    /// Returns the depth to read with, and the chunk before which reading has to pause until all
+    /// pending reads completed.
+    fn current(&self, chunk_count: usize) -> (usize, usize) {
+        match &self.calibration {
+            Some(calibration) => (
+                CALIBRATION_DEPTHS[calibration.step],
+                (calibration.step + 1) * CALIBRATION_CHUNKS,
+            ),
+            None => (self.depth, chunk_count),
+        }
+    }
+
+    /// Called once all reads before `next` completed. Moves on to the next step of the
+    /// calibration and returns whether one was running.
+    fn finish_step(&mut self, next: usize) -> bool {
+        let Some(calibration) = &mut self.calibration else {
+            return false;
+        };
+        if next != (calibration.step + 1) * CALIBRATION_CHUNKS {
+            return false;
+        }
+
+        let depth = CALIBRATION_DEPTHS[calibration.step];
+        let elapsed = calibration.started.elapsed();
+        info!(
+            "Read {} chunks of the MFT with {} reads in flight in {:?}",
+            CALIBRATION_CHUNKS, depth, elapsed
+        );
+        if calibration
+            .fastest
+            .is_none_or(|(_, fastest)| elapsed < fastest)
+        {
+            calibration.fastest = Some((depth, elapsed));
+        }
+
+        calibration.step += 1;
+        calibration.started = Instant::now();
+        if calibration.step == CALIBRATION_DEPTHS.len() {
+            let (fastest, _) = calibration.fastest.unwrap();
+            info!(
+                "Reading the rest of the MFT with {} reads in flight",
+                fastest
+            );
+            self.depth = fastest;
+            self.measured = Some(fastest);
+            self.calibration = None;
+        }
+        true
+    }
+
+    /// Saves the measured depth, if the calibration finished
+    fn save(&self, volume: Volume) {
+        let Some(depth) = self.measured else {
+            return;
+        };
+
+        let mut calibration = Calibration::load();
+        calibration
+            .mft_read_queue_depth
+            .insert(volume.id.to_string(), depth);
+        if let Err(e) = calibration.save() {
+            error!(
+                "{:?}",
+                e.wrap_err("Failed to save the MFT read queue depth")
             );
+        }
+    }
+}
+
+/// A part of the MFT which is read into a single buffer. Small runs are combined and large runs are
+/// split, so that every chunk except the last one is exactly [READ_CHUNK_SIZE] bytes long. This
+/// keeps file records which cross the boundary of two runs in one piece.
+#[derive(Debug, Default)]
+struct Chunk {
+    /// Byte ranges on the volume, in the order they appear in the MFT
+    ranges: Vec<Range<usize>>,
+    len: usize,
+}
+
+/// A read of one range of a chunk which was queued on the completion port. Owned by the kernel
+/// until it completes.
+#[repr(C)]
+struct PendingRead {
+    /// Has to be the first field, completions only return a pointer to it
+    overlapped: OVERLAPPED,
+    chunk: usize,
+    range: Range<usize>,
+    /// Part of the chunk's buffer which the range is read into
+    destination: *mut u8,
+    /// Number of previous attempts which failed
+    attempt: u32,
+}
+
+/// A chunk which is being read, along with the number of its ranges which are still pending.
+type ChunkRead = (Vec<u8>, usize);
+
+/// Buffers for reading chunks. Buffers are handed back once their chunk was parsed, so that at
+/// most [MAX_BUFFERS] chunks are held in memory, no matter how large the MFT is.
+struct BufferPool {
+    allocated: usize,
+    sender: Sender<Vec<u8>>,
+    returned: Receiver<Vec<u8>>,
+}
+
+impl BufferPool {
+    fn new() -> Self {
+        let (sender, returned) = channel();
+        Self {
+            allocated: 0,
+            sender,
+            returned,
+        }
+    }
+
+    /// Returns a free buffer. If all buffers are in use, this blocks until one is handed back if
+    /// `wait` is set and returns `None` otherwise.
+    fn take(&mut self, wait: bool) -> Option<Vec<u8>> {
+        if let Ok(buffer) = self.returned.try_recv() {
+            return Some(buffer);
+        }
+
+        if self.allocated < MAX_BUFFERS {
+            self.allocated += 1;
+            return Some(Vec::with_capacity(READ_CHUNK_SIZE));
+        }
+
+        if wait {
+            self.returned.recv().ok()
+        } else {
+            None
+        }
+    }
+
+    /// Used to hand back buffers
+    fn sender(&self) -> Sender<Vec<u8>> {
+        self.sender.clone()
+    }
+}
+
+/// Reads all chunks with as many reads in flight as `queue_depth` allows. `on_read` is called on
+/// this thread with the index and the content of every chunk as soon as all of its ranges were
+/// read, in no particular order. The content has to be handed back to `buffers` once it is not
+/// needed anymore.
+///
+/// Ranges which can't be read even after retrying are zeroed, so that the records in them are
+/// skipped. Returns the number of bytes which could not be read.
+fn read_chunks(
+    handle: HANDLE,
+    chunks: &[Chunk],
+    queue_depth: &mut QueueDepth,
+    buffers: &mut BufferPool,
+    mut on_read: impl FnMut(usize, Vec<u8>),
+) -> Result<usize> {
+    let port = unsafe { CreateIoCompletionPort(handle, HANDLE::default(), 0, 1) }
+        .with_context(|| "CreateIoCompletionPort failed")?;
+
+    let mut reading = FxHashMap::<usize, ChunkRead>::default();
+    let mut next = 0usize;
+    let mut pending = 0usize;
+    let mut unreadable = 0usize;
+    loop {
+        let (depth, end) = queue_depth.current(chunks.len());
+        while pending < depth && next < end {
+            // Only wait for a buffer if there are no reads left to wait for instead
+            let Some(mut buffer) = buffers.take(pending == 0) else {
+                break;
+            };
+
+            let chunk = &chunks[next];
+            buffer.resize(chunk.len, 0);
+            let mut offset = 0usize;
+            let mut started = 0usize;
+            for range in &chunk.ranges {
+                let read = Box::new(PendingRead {
+                    overlapped: OVERLAPPED::default(),
+                    chunk: next,
+                    range: range.clone(),
+                    destination: unsafe { buffer.as_mut_ptr().add(offset) },
+                    attempt: 0,
+                });
+                if submit_read(handle, read, None) {
+                    started += 1;
+                } else {
+                    unreadable += range.len();
+                }
+                offset += range.len();
+            }
 
-            // Might return true if the read is completed immediately
-            if res.is_err() {
-                events.push(ov.hEvent);
+            pending += started;
+            if started == 0 {
+                on_read(next, buffer);
             } else {
-                try_close_handle(ov.hEvent)?;
+                reading.insert(next, (buffer, started));
+            }
+            next += 1;
+        }
+
+        if pending == 0 {
+            if next < chunks.len() && queue_depth.finish_step(next) {
+                continue;
             }
+            break;
+        }
 
-            write_offset += run.len();
+        let mut bytes_read = 0u32;
+        let mut key = 0usize;
+        let mut overlapped = std::ptr::null_mut();
+        let res = unsafe {
+            GetQueuedCompletionStatus(port, &mut bytes_read, &mut key, &mut overlapped, INFINITE)
+        };
+        if overlapped.is_null() {
+            // The port itself failed, so the pending reads can't be waited for anymore. Leaking
+            // their buffers is the only safe option.
+            std::mem::forget(reading);
+            try_close_handle(port)?;
+            return Err(Report::new(res.unwrap_err()))
+                .with_context(|| "GetQueuedCompletionStatus failed");
+        }
+
+        pending -= 1;
+        let read = unsafe { Box::from_raw(overlapped as *mut PendingRead) };
+        let chunk = read.chunk;
+        let len = read.range.len();
+        let error = match res {
+            Err(e) => Some(Report::new(e).wrap_err("Reading from the MFT failed")),
+            Ok(()) if bytes_read as usize != len => Some(eyre!(
+                "Short read from the MFT, expected {} bytes but got {}",
+                len,
+                bytes_read
+            )),
+            Ok(()) => None,
+        };
+        if let Some(error) = error {
+            if submit_read(handle, read, Some(error)) {
+                pending += 1;
+                continue;
+            }
+
+            unreadable += len;
+        }
+
+        let (_, remaining) = reading.get_mut(&chunk).unwrap();
+        *remaining -= 1;
+        if *remaining == 0 {
+            let (buffer, _) = reading.remove(&chunk).unwrap();
+            on_read(chunk, buffer);
         }
     }
 
-    unsafe {
-        let res = WaitForMultipleObjects(&events, true, 50000);
-        events
-            .iter()
-            .chain(std::iter::once(&handle))
-            .try_for_each(|&e| try_close_handle(e))?;
-
-        if res != WAIT_OBJECT_0 {
-            return Err(Report::new(std::io::Error::last_os_error())).with_context(|| {
-                format!(
-                    "WaitForMultipleObjects failed {:?} {:?}",
-                    res.0 as i32,
-                    std::thread::current().id()
-                )
-            });
+    try_close_handle(port)?;
+    Ok(unreadable)
+}
+
+/// Reads all chunks one after another using [VolumeRead::read_at], for readers which don't support
+/// overlapped reads. Behaves like [read_chunks] otherwise.
+fn read_chunks_sequentially(
+    reader: &dyn VolumeRead,
+    chunks: &[Chunk],
+    buffers: &mut BufferPool,
+    mut on_read: impl FnMut(usize, Vec<u8>),
+) -> Result<usize> {
+    let mut unreadable = 0usize;
+    for (i, chunk) in chunks.iter().enumerate() {
+        let mut buffer = buffers
+            .take(true)
+            .with_context(|| "All buffers were dropped")?;
+        buffer.resize(chunk.len, 0);
+
+        let mut offset = 0usize;
+        for range in &chunk.ranges {
+            let destination = &mut buffer[offset..offset + range.len()];
+            if let Err(e) = reader.read_at(range.start, destination) {
+                error!(
+                    "{:?}",
+                    e.wrap_err(format!("Skipping unreadable part of the MFT {:?}", range))
+                );
+                destination.fill(0);
+                unreadable += range.len();
+            }
+            offset += range.len();
         }
 
-        buffer.set_len(buffer.capacity());
+        on_read(i, buffer);
     }
 
-    Ok(buffer)
+    Ok(unreadable)
 }
 
-type RunGroup = Vec<Range<usize>>;
+/// Queues a read, or retries it with an increasing delay if `error` is set. Returns `false` if the
+/// range could not be read after [MAX_READ_ATTEMPTS], in which case its destination is zeroed.
+fn submit_read(handle: HANDLE, mut read: Box<PendingRead>, mut error: Option<Report>) -> bool {
+    loop {
+        if let Some(e) = error.take() {
+            read.attempt += 1;
+            if read.attempt >= MAX_READ_ATTEMPTS {
+                error!(
+                    "{:?}",
+                    e.wrap_err(format!(
+                        "Skipping unreadable part of the MFT {:?}",
+                        read.range
+                    ))
+                );
+                unsafe { std::ptr::write_bytes(read.destination, 0, read.range.len()) };
+                return false;
+            }
 
-fn distribute_runs_to_cpus(
-    volume_data: NTFS_VOLUME_DATA_BUFFER,
-    (total_size, mut runs): (usize, RunGroup),
-) -> Vec<RunGroup> {
-    let cpus = num_cpus::get_physical() - 1 /* Save one thread for UI */;
-    let run_size = total_size / cpus;
-    let run_size = run_size - (run_size % volume_data.BytesPerCluster as usize);
-
-    // Distribute runs evenly
-    let mut run_groups = Vec::with_capacity(cpus);
-    // Last thread gets the rest after the loop
-    for _ in 0..(cpus - 1) {
-        let mut run_group = Vec::with_capacity(2);
-        let mut run_group_size = 0usize;
-        while run_group_size < run_size && !runs.is_empty() {
-            let run = runs.remove(0);
-
-            let run_len = run.len();
-            assert_eq!(run_len % volume_data.BytesPerCluster as usize, 0);
-            // Get as close as possible to the run size, but don't split runs into non-cluster
-            // aligned chunks
-            if run_group_size + run_len > run_size {
-                let split = run_size - run_group_size;
-                // Push back the remaining part of the run
-                runs.insert(0, run.start + split..run.end);
-                // Give the second part to the current group
-                run_group.push(run.start..run.start + split);
-                run_group_size += split;
-            } else {
-                run_group_size += run_len;
-                run_group.push(run);
+            std::thread::sleep(READ_RETRY_DELAY * 2u32.pow(read.attempt - 1));
+        }
+
+        read.overlapped = create_overlapped(read.range.start);
+        let read_ptr = Box::into_raw(read);
+        unsafe {
+            let destination =
+                std::slice::from_raw_parts_mut((*read_ptr).destination, (*read_ptr).range.len());
+            // Even reads which complete immediately post a completion
+            match ReadFile(
+                handle,
+                Some(destination),
+                None,
+                Some(&mut (*read_ptr).overlapped),
+            ) {
+                Err(e) if e.code() != ERROR_IO_PENDING.to_hresult() => {
+                    read = Box::from_raw(read_ptr);
+                    error = Some(Report::new(e).wrap_err("ReadFile failed"));
+                }
+                _ => return true,
+            }
+        }
+    }
+}
+
+/// Distributes the runs over chunks, keeping them in order. On a fragmented MFT, a chunk can
+/// consist of many small runs. Only the first `limit` bytes of the runs are included, if given.
+fn split_runs_into_chunks(runs: Vec<Range<usize>>, limit: Option<usize>) -> Vec<Chunk> {
+    let mut remaining = limit.unwrap_or(usize::MAX);
+    let mut chunks = Vec::new();
+    let mut current = Chunk::default();
+    for run in runs {
+        let mut start = run.start;
+        while start < run.end && remaining > 0 {
+            let len = (READ_CHUNK_SIZE - current.len)
+                .min(run.end - start)
+                .min(remaining);
+            remaining -= len;
+            current.ranges.push(start..start + len);
+            current.len += len;
+            start += len;
+
+            if current.len == READ_CHUNK_SIZE {
+                chunks.push(std::mem::take(&mut current));
             }
         }
+    }
 
-        run_groups.push(run_group);
+    if current.len > 0 {
+        chunks.push(current);
     }
 
-    // Everything that remains is given to the last thread
-    run_groups.push(runs);
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUN_SIZE: usize = 4096;
+
+    /// Runs of one cluster, each followed by a gap of one cluster
+    fn fragmented_runs(count: usize) -> Vec<Range<usize>> {
+        (0..count)
+            .map(|i| i * 2 * RUN_SIZE..(i * 2 + 1) * RUN_SIZE)
+            .collect()
+    }
+
+    /// Checks the invariants of the chunks and returns their ranges in order
+    fn flatten(chunks: &[Chunk]) -> Vec<Range<usize>> {
+        for (i, chunk) in chunks.iter().enumerate() {
+            assert!(chunk.len > 0);
+            assert_eq!(
+                chunk.len,
+                chunk.ranges.iter().map(|r| r.len()).sum::<usize>()
+            );
+            assert!(chunk.ranges.iter().all(|r| !r.is_empty()));
+            if i + 1 < chunks.len() {
+                assert_eq!(chunk.len, READ_CHUNK_SIZE);
+            }
+        }
 
-    run_groups
+        chunks
+            .iter()
+            .flat_map(|chunk| chunk.ranges.iter().cloned())
+            .collect()
+    }
+
+    #[test]
+    fn chunk_holds_more_than_64_runs() {
+        let runs = fragmented_runs(1000);
+        let chunks = split_runs_into_chunks(runs.clone(), None);
+
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].ranges.len(), 1000);
+        assert_eq!(flatten(&chunks), runs);
+    }
+
+    #[test]
+    fn many_runs_fill_several_chunks() {
+        let runs_per_chunk = READ_CHUNK_SIZE / RUN_SIZE;
+        let runs = fragmented_runs(runs_per_chunk * 2 + 10);
+        let chunks = split_runs_into_chunks(runs.clone(), None);
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].ranges.len(), runs_per_chunk);
+        assert_eq!(chunks[1].ranges.len(), runs_per_chunk);
+        assert_eq!(chunks[2].ranges.len(), 10);
+        assert_eq!(flatten(&chunks), runs);
+    }
+
+    #[test]
+    fn long_run_is_split_between_chunks() {
+        let end = READ_CHUNK_SIZE * 2 + RUN_SIZE;
+        let last = end + RUN_SIZE..end + 2 * RUN_SIZE;
+        let chunks = split_runs_into_chunks(vec![0..end, last.clone()], None);
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(
+            flatten(&chunks),
+            vec![
+                0..READ_CHUNK_SIZE,
+                READ_CHUNK_SIZE..READ_CHUNK_SIZE * 2,
+                READ_CHUNK_SIZE * 2..end,
+                last,
+            ]
+        );
+    }
+
+    #[test]
+    fn zero_length_runs_are_skipped() {
+        let runs = vec![
+            0..0,
+            0..RUN_SIZE,
+            5 * RUN_SIZE..5 * RUN_SIZE,
+            8 * RUN_SIZE..9 * RUN_SIZE,
+        ];
+        let chunks = split_runs_into_chunks(runs, None);
+
+        assert_eq!(
+            flatten(&chunks),
+            vec![0..RUN_SIZE, 8 * RUN_SIZE..9 * RUN_SIZE]
+        );
+        assert!(split_runs_into_chunks(vec![0..0, 10..10], None).is_empty());
+        assert!(split_runs_into_chunks(Vec::new(), None).is_empty());
+    }
+
+    #[test]
+    fn sparse_runs_keep_their_order() {
+        // Far apart and partly before each other on the volume, like runs with negative offsets
+        let runs = vec![
+            1 << 40..(1 << 40) + 3 * RUN_SIZE,
+            RUN_SIZE..2 * RUN_SIZE,
+            1 << 30..(1 << 30) + READ_CHUNK_SIZE,
+            1 << 20..(1 << 20) + RUN_SIZE,
+        ];
+        let chunks = split_runs_into_chunks(runs, None);
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(
+            flatten(&chunks),
+            vec![
+                1 << 40..(1 << 40) + 3 * RUN_SIZE,
+                RUN_SIZE..2 * RUN_SIZE,
+                1 << 30..(1 << 30) + READ_CHUNK_SIZE - 4 * RUN_SIZE,
+                (1 << 30) + READ_CHUNK_SIZE - 4 * RUN_SIZE..(1 << 30) + READ_CHUNK_SIZE,
+                1 << 20..(1 << 20) + RUN_SIZE,
+            ]
+        );
+    }
+
+    #[test]
+    fn limit_ends_inside_of_a_run() {
+        let runs = fragmented_runs(100);
+        let chunks = split_runs_into_chunks(runs.clone(), Some(10 * RUN_SIZE + 100));
+
+        let mut expected = runs[..10].to_vec();
+        expected.push(runs[10].start..runs[10].start + 100);
+        assert_eq!(flatten(&chunks), expected);
+        assert!(split_runs_into_chunks(runs, Some(0)).is_empty());
+    }
+
+    /// An index without a volume to read from, containing `infos`
+    fn index_with(infos: Vec<Option<FileInfo>>) -> NtfsVolumeIndex {
+        let infos = FileInfos::from(infos);
+        let children = ChildrenMap::build(&infos);
+        NtfsVolumeIndex {
+            volume: Volume { id: 'T' },
+            infos,
+            unreadable_records: 0,
+            mft: None,
+            touched_records: None,
+            trigrams: None,
+            children,
+            file_ids: None,
+            directory_walk: false,
+            network: FileInfos::default(),
+            network_children: ChildrenMap::default(),
+            generation: 0,
+        }
+    }
+
+    fn directory(parent: u64, name: &str) -> Option<FileInfo> {
+        Some(FileInfo::new(
+            0,
+            0,
+            true,
+            parent,
+            name.into(),
+            FileTimes::default(),
+            0,
+        ))
+    }
+
+    #[test]
+    fn cycle_in_parents_ends_iteration() {
+        let mut infos = vec![None; FIRST_USER_INDEX as usize + 2];
+        let a = FIRST_USER_INDEX;
+        let b = FIRST_USER_INDEX + 1;
+        infos[ROOT_INDEX as usize] = directory(ROOT_INDEX, ".");
+        infos[a as usize] = directory(b, "a");
+        infos[b as usize] = directory(a, "b");
+        let index = index_with(infos);
+        let info = index.find_by_index(a).unwrap();
+
+        assert_eq!(index.iter_with_parents(info).count(), MAX_DEPTH);
+        assert!(index.compute_full_path(info).ends_with("\\b\\a"));
+        assert!(!index.is_inside(a, ROOT_INDEX));
+        assert!(!index.reaches_root(info));
+    }
 }
@@ -1,69 +1,89 @@
 use std::collections::VecDeque;
-use std::ffi::c_void;
+use std::sync::{Arc, Mutex};
 
-use eyre::{eyre, ContextCompat, Report, Result, WrapErr};
-use windows::Win32::Foundation::HANDLE;
+use eyre::{eyre, ContextCompat, Result};
 use windows::Win32::Storage::FileSystem::{
     FILE_ATTRIBUTE_DIRECTORY
     , FILE_ID_128
     ,
 };
 use windows::Win32::System::Ioctl::{
-    FSCTL_QUERY_USN_JOURNAL, FSCTL_READ_USN_JOURNAL, READ_USN_JOURNAL_DATA_V1, USN_JOURNAL_DATA_V2,
-    USN_REASON_FILE_CREATE, USN_REASON_FILE_DELETE, USN_REASON_RENAME_NEW_NAME,
-    USN_REASON_RENAME_OLD_NAME, USN_RECORD_UNION, USN_RECORD_V3,
+    READ_USN_JOURNAL_DATA_V1, USN_JOURNAL_DATA_V2, USN_REASON_FILE_CREATE, USN_REASON_FILE_DELETE,
+    USN_REASON_RENAME_NEW_NAME, USN_REASON_RENAME_OLD_NAME, USN_RECORD_UNION,
 };
-use windows::Win32::System::IO::DeviceIoControl;
 
-use crate::ntfs::try_close_handle;
-use crate::ntfs::volume::Volume;
+use crate::ntfs::file_ids::FileIdMap;
+use crate::ntfs::volume::{Volume, VolumeRead};
 
 const MAX_UNMATCHED_RENAMES: usize = 2000;
 
 pub struct Journal {
-    handle: HANDLE,
+    reader: Box<dyn VolumeRead>,
     next_usn: i64,
     journal_id: u64,
-    unmatched_renames: VecDeque<u64>,
+    /// Highest record version supported by the journal. Records are read in version 3 if possible,
+    /// older journals only support version 2.
+    max_major_version: u16,
+    /// MFT index, old name and old parent of renamed files whose new name was not read yet
+    unmatched_renames: VecDeque<(u64, String, u64)>,
+    /// Maps the file ids of ReFS to the indices used by the index, see [Self::set_file_ids]
+    file_ids: Option<Arc<Mutex<FileIdMap>>>,
 }
 
-unsafe impl Send for Journal {}
-unsafe impl Sync for Journal {}
-
 impl Journal {
     pub fn new(vol: Volume) -> Result<Self> {
-        let handle = vol.create_read_handle()?;
+        Self::from_reader(Box::new(vol.open()?))
+    }
 
-        let mut data = USN_JOURNAL_DATA_V2::default();
-        unsafe {
-            let query = DeviceIoControl(
-                handle,
-                FSCTL_QUERY_USN_JOURNAL,
-                None,
-                0,
-                Some(&mut data as *mut _ as *mut c_void),
-                size_of_val(&data) as u32,
-                None,
-                None,
-            );
-
-            if query.is_err() {
-                return Err(Report::new(std::io::Error::last_os_error()))
-                    .with_context(|| "DeviceIoControl failed trying to query journal data");
-            }
-        }
+    /// Starts following the journal of `reader` at its next USN.
+    pub fn from_reader(reader: Box<dyn VolumeRead>) -> Result<Self> {
+        let data = reader.query_journal()?;
 
         Ok(Self {
-            handle,
+            reader,
             next_usn: data.NextUsn,
             journal_id: data.UsnJournalID,
+            // Older systems only fill in the fields of USN_JOURNAL_DATA_V0, which leaves this at 0
+            max_major_version: data.MaxSupportedMajorVersion.clamp(2, 3),
             unmatched_renames: VecDeque::new(),
+            file_ids: None,
         })
     }
 
+    /// Continues following the journal of `vol` at `next_usn`, for example where a saved index left
+    /// off. Returns `None` if the journal was recreated or the records since then were overwritten.
+    pub fn resume(vol: Volume, journal_id: u64, next_usn: i64) -> Result<Option<Self>> {
+        let mut journal = Self::new(vol)?;
+        let data = journal.reader.query_journal()?;
+        if data.UsnJournalID != journal_id
+            || next_usn < data.LowestValidUsn
+            || next_usn > data.NextUsn
+        {
+            return Ok(None);
+        }
+
+        journal.next_usn = next_usn;
+        Ok(Some(journal))
+    }
+
+    /// Makes the journal map file ids to the same indices as the index, which is required on ReFS.
+    /// See [NtfsVolumeIndex::file_ids](crate::ntfs::index::NtfsVolumeIndex::file_ids).
+    pub fn set_file_ids(&mut self, file_ids: Option<Arc<Mutex<FileIdMap>>>) {
+        self.file_ids = file_ids;
+    }
+
+    /// The USN of the next record which will be read
+    pub fn next_usn(&self) -> i64 {
+        self.next_usn
+    }
+
+    pub fn journal_id(&self) -> u64 {
+        self.journal_id
+    }
+
     pub fn read_entries(&mut self) -> Result<Vec<JournalEntry>> {
         unsafe {
-            let mut read_input = READ_USN_JOURNAL_DATA_V1 {
+            let read_input = READ_USN_JOURNAL_DATA_V1 {
                 StartUsn: self.next_usn,
                 ReasonMask: USN_REASON_FILE_CREATE
                     | USN_REASON_FILE_DELETE
@@ -73,27 +93,12 @@ impl Journal {
                 Timeout: 0,
                 BytesToWaitFor: 1,
                 UsnJournalID: self.journal_id,
-                MinMajorVersion: 3,
-                MaxMajorVersion: 3,
+                MinMajorVersion: 2,
+                MaxMajorVersion: self.max_major_version,
             };
             // TODO: Use cluster size from volume data?
             let mut buffer = [0u8; 4096];
-            let mut bytes_read = 0u32;
-            let query = DeviceIoControl(
-                self.handle,
-                FSCTL_READ_USN_JOURNAL,
-                Some(&mut read_input as *mut _ as *mut c_void),
-                size_of_val(&read_input) as u32,
-                Some(&mut buffer as *mut _ as *mut c_void),
-                size_of_val(&buffer) as u32,
-                Some(&mut bytes_read as *mut u32),
-                None,
-            );
-
-            if query.is_err() {
-                return Err(Report::new(std::io::Error::last_os_error()))
-                    .with_context(|| "DeviceIoControl failed trying to read journal entries");
-            }
+            let bytes_read = self.reader.read_journal(&read_input, &mut buffer)?;
 
             let next_usn = i64::from_le_bytes(buffer[0..size_of::<i64>()].try_into()?);
             if next_usn == 0 || next_usn < self.next_usn {
@@ -104,43 +109,51 @@ impl Journal {
 
             let mut entries = Vec::new();
             let mut offset = size_of::<i64>();
+            let file_ids = self.file_ids.clone();
+            let mut file_ids = file_ids.as_ref().map(|file_ids| file_ids.lock().unwrap());
 
-            while offset < bytes_read as usize {
+            while offset < bytes_read {
                 let union = buffer[offset..].as_ptr() as *const USN_RECORD_UNION;
                 let header = (*union).Header;
                 let record_length = header.RecordLength as usize;
 
-                if record_length == 0 || header.MajorVersion != 3 {
-                    return Err(eyre!("Invalid record length or major version {:?}", header));
+                if record_length == 0 {
+                    return Err(eyre!("Invalid record length {:?}", header));
                 }
 
-                let record = &(*union).V3;
+                let record = Record::read(union, file_ids.as_deref_mut())?;
 
-                if record.Reason & USN_REASON_RENAME_OLD_NAME != 0 {
+                if record.reason & USN_REASON_RENAME_OLD_NAME != 0 {
                     if self.unmatched_renames.len() >= MAX_UNMATCHED_RENAMES {
                         self.unmatched_renames.pop_front();
                     }
 
-                    self.unmatched_renames
-                        .push_back(get_mft_index_from_file_id(record.FileReferenceNumber));
+                    self.unmatched_renames.push_back((
+                        record.mft_index,
+                        record.name,
+                        record.parent_mft_index,
+                    ));
                 } else {
-                    let is_directory = record.FileAttributes & FILE_ATTRIBUTE_DIRECTORY.0 != 0;
-                    let reason = match record.Reason {
+                    let is_directory = record.attributes & FILE_ATTRIBUTE_DIRECTORY.0 != 0;
+                    let reason = match record.reason {
                         x if x & USN_REASON_FILE_CREATE == x => Ok(JournalEntry::FileCreate {
-                            mft_index: get_mft_index_from_file_id(record.FileReferenceNumber),
-                            parent_mft_index: get_mft_index_from_file_id(
-                                record.ParentFileReferenceNumber,
-                            ),
-                            name: get_record_file_name(record),
+                            mft_index: record.mft_index,
+                            sequence: record.sequence,
+                            parent_mft_index: record.parent_mft_index,
+                            name: record.name,
                             is_directory,
+                            timestamp: record.timestamp,
+                            attributes: record.attributes,
+                        }),
+                        x if x & USN_REASON_FILE_DELETE != 0 => Ok(JournalEntry::FileDelete {
+                            mft_index: record.mft_index,
+                            sequence: record.sequence,
                         }),
-                        x if x & USN_REASON_FILE_DELETE != 0 => Ok(JournalEntry::FileDelete(
-                            get_mft_index_from_file_id(record.FileReferenceNumber),
-                        )),
                         x if x & USN_REASON_RENAME_NEW_NAME != 0 => self.match_rename(
-                            get_mft_index_from_file_id(record.FileReferenceNumber),
-                            get_record_file_name(record),
-                            get_mft_index_from_file_id(record.ParentFileReferenceNumber),
+                            record.mft_index,
+                            record.sequence,
+                            record.name,
+                            record.parent_mft_index,
                         ),
                         _ => Err(eyre!("")),
                     };
@@ -155,13 +168,14 @@ impl Journal {
 
             // Match file creates to deletes
             let mut i = 0usize;
-            while let Some((pos1, JournalEntry::FileCreate { mft_index, .. })) = entries
+            while let Some((pos1, JournalEntry::FileCreate { mft_index, sequence, .. })) = entries
                 .iter()
                 .enumerate()
                 .find(|(j, e)| *j >= i && matches!(e, JournalEntry::FileCreate { .. }))
             {
                 if let Some(pos2) = entries.iter().skip(pos1).rposition(
-                    |e| matches!(e, JournalEntry::FileDelete(mft_index2) if mft_index == mft_index2),
+                    |e| matches!(e, JournalEntry::FileDelete { mft_index: mft_index2, sequence: sequence2 }
+                        if mft_index == mft_index2 && sequence == sequence2),
                 ) {
                     entries.remove(pos2);
                     entries.remove(pos1);
@@ -177,12 +191,13 @@ impl Journal {
     fn match_rename(
         &mut self,
         mft_index: u64,
+        sequence: u16,
         new_name: String,
         new_parent_mft_index: u64,
     ) -> Result<JournalEntry> {
         let idx = self.unmatched_renames
             .iter()
-            .position(|x| *x == mft_index)
+            .position(|(x, ..)| *x == mft_index)
             .with_context(|| {
                 format!(
                     "Failed to find old name for rename {:?} {:?} {:?}",
@@ -190,11 +205,14 @@ impl Journal {
                 )
             })?;
         
-        self.unmatched_renames.remove(idx);
+        let (_, old_name, old_parent_mft_index) = self.unmatched_renames.remove(idx).unwrap();
 
         // We can't immediately remove the rename from the queue because it can be used multiple times
         Ok(JournalEntry::Rename {
             mft_index,
+            sequence,
+            old_name,
+            old_parent_mft_index,
             new_name,
             new_parent_mft_index,
         })
@@ -208,37 +226,131 @@ fn get_mft_index_from_file_id(id: FILE_ID_128) -> u64 {
     u64::from_le_bytes(id.Identifier[..8].try_into().unwrap()) & 0xffff_ffff_ffffu64
 }
 
-impl Drop for Journal {
-    fn drop(&mut self) {
-        try_close_handle(self.handle).expect("Failed to close journal handle");
+fn get_sequence_from_file_id(id: FILE_ID_128) -> u16 {
+    u16::from_le_bytes(id.Identifier[6..8].try_into().unwrap())
+}
+
+/// The fields of a USN record which are used, independent of the record version.
+struct Record {
+    mft_index: u64,
+    /// Sequence number of the MFT record, which is incremented whenever the record is reused
+    sequence: u16,
+    parent_mft_index: u64,
+    reason: u32,
+    attributes: u32,
+    /// FILETIME of the change
+    timestamp: u64,
+    name: String,
+}
+
+impl Record {
+    /// Reads a version 2 or 3 record. Version 2 records contain 64 bit file reference numbers,
+    /// version 3 records 128 bit file ids, which are mapped using `file_ids` on ReFS.
+    unsafe fn read(
+        union: *const USN_RECORD_UNION,
+        file_ids: Option<&mut FileIdMap>,
+    ) -> Result<Self> {
+        match ((*union).Header.MajorVersion, file_ids) {
+            (2, _) => {
+                let record = &(*union).V2;
+                Ok(Self {
+                    mft_index: get_mft_index_from_file_reference(record.FileReferenceNumber),
+                    sequence: get_sequence_from_file_reference(record.FileReferenceNumber),
+                    parent_mft_index: get_mft_index_from_file_reference(
+                        record.ParentFileReferenceNumber,
+                    ),
+                    reason: record.Reason,
+                    attributes: record.FileAttributes,
+                    timestamp: record.TimeStamp as u64,
+                    name: get_record_file_name(record.FileName.as_ptr(), record.FileNameLength),
+                })
+            }
+            (3, Some(file_ids)) => {
+                let record = &(*union).V3;
+                Ok(Self {
+                    mft_index: file_ids.index(record.FileReferenceNumber),
+                    // ReFS doesn't reuse ids, so there is no sequence number
+                    sequence: 0,
+                    parent_mft_index: file_ids.index(record.ParentFileReferenceNumber),
+                    reason: record.Reason,
+                    attributes: record.FileAttributes,
+                    timestamp: record.TimeStamp as u64,
+                    name: get_record_file_name(record.FileName.as_ptr(), record.FileNameLength),
+                })
+            }
+            (3, None) => {
+                let record = &(*union).V3;
+                Ok(Self {
+                    mft_index: get_mft_index_from_file_id(record.FileReferenceNumber),
+                    sequence: get_sequence_from_file_id(record.FileReferenceNumber),
+                    parent_mft_index: get_mft_index_from_file_id(record.ParentFileReferenceNumber),
+                    reason: record.Reason,
+                    attributes: record.FileAttributes,
+                    timestamp: record.TimeStamp as u64,
+                    name: get_record_file_name(record.FileName.as_ptr(), record.FileNameLength),
+                })
+            }
+            (version, _) => Err(eyre!("Unsupported record major version {}", version)),
+        }
     }
 }
 
-fn get_record_file_name(record: &USN_RECORD_V3) -> String {
-    let name_length = record.FileNameLength / 2;
+/// Queries the current state of the journal of a volume, like its id and the USN of the next
+/// record.
+pub fn query_journal(vol: Volume) -> Result<USN_JOURNAL_DATA_V2> {
+    vol.open()?.query_journal()
+}
 
-    unsafe {
-        let file_name = String::from_utf16_lossy(std::slice::from_raw_parts(
-            record.FileName.as_ptr(),
-            name_length as usize,
-        ));
+/// A file reference number consists of the MFT index in the lower 6 bytes and the sequence number
+/// in the upper 2 bytes.
+pub fn get_mft_index_from_file_reference(reference: u64) -> u64 {
+    reference & 0xffff_ffff_ffffu64
+}
 
-        file_name
-    }
+pub fn get_sequence_from_file_reference(reference: u64) -> u16 {
+    (reference >> 48) as u16
+}
+
+/// `length` is in bytes
+unsafe fn get_record_file_name(name: *const u16, length: u16) -> String {
+    String::from_utf16_lossy(std::slice::from_raw_parts(name, length as usize / 2))
 }
 
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq)]
 pub enum JournalEntry {
     FileCreate {
         mft_index: u64,
+        /// Sequence number of the MFT record, used to detect whether the index refers to the same
+        /// file
+        sequence: u16,
         parent_mft_index: u64,
         name: String,
         is_directory: bool,
+        /// FILETIME of the creation
+        timestamp: u64,
+        attributes: u32,
+    },
+    FileDelete {
+        mft_index: u64,
+        sequence: u16,
     },
-    FileDelete(u64),
+    /// Also describes moves to another directory, in which case the parents differ
     Rename {
         mft_index: u64,
+        sequence: u16,
+        old_name: String,
+        old_parent_mft_index: u64,
         new_name: String,
         new_parent_mft_index: u64,
     },
 }
+
+impl JournalEntry {
+    pub fn mft_index(&self) -> u64 {
+        match self {
+            JournalEntry::FileCreate { mft_index, .. }
+            | JournalEntry::FileDelete { mft_index, .. }
+            | JournalEntry::Rename { mft_index, .. } => *mft_index,
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::sync::RwLock;
+
+use eyre::{eyre, Result};
+use windows::Win32::System::Ioctl::{
+    NTFS_VOLUME_DATA_BUFFER, READ_USN_JOURNAL_DATA_V1, USN_JOURNAL_DATA_V2,
+};
+
+use crate::ntfs::volume::VolumeRead;
+
+/// Size of a version 2 USN record without its file name
+const RECORD_HEADER_SIZE: usize = 60;
+
+/// A volume image held in memory, which can be indexed and followed like a real volume. Used for
+/// development and benchmarks, as it neither needs administrator rights nor an NTFS volume.
+///
+/// The journal only contains version 2 records, which are added with
+/// [MemoryVolume::push_journal_record]. The USN of a record is its offset in the journal.
+pub struct MemoryVolume {
+    data: RwLock<Vec<u8>>,
+    volume_data: NTFS_VOLUME_DATA_BUFFER,
+    journal_id: u64,
+    journal: RwLock<Vec<u8>>,
+}
+
+impl MemoryVolume {
+    /// `data` is the content of the volume starting at byte 0, `volume_data` has to describe where
+    /// the MFT is stored in it.
+    pub fn new(data: Vec<u8>, volume_data: NTFS_VOLUME_DATA_BUFFER) -> Self {
+        Self {
+            data: RwLock::new(data),
+            volume_data,
+            journal_id: 1,
+            journal: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Overwrites part of the volume, for example to change an MFT record before the matching
+    /// journal record is added.
+    pub fn write_at(&self, offset: usize, bytes: &[u8]) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        let destination = data
+            .get_mut(offset..offset + bytes.len())
+            .ok_or_else(|| eyre!("Write at {} is outside of the volume", offset))?;
+        destination.copy_from_slice(bytes);
+
+        Ok(())
+    }
+
+    /// Appends a record to the journal and returns its USN. File references contain the MFT index
+    /// in the lower 6 bytes and the sequence number in the upper 2 bytes.
+    pub fn push_journal_record(
+        &self,
+        file_reference: u64,
+        parent_file_reference: u64,
+        reason: u32,
+        attributes: u32,
+        timestamp: u64,
+        name: &str,
+    ) -> i64 {
+        let name = name
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+        let length = (RECORD_HEADER_SIZE + name.len()).next_multiple_of(8);
+
+        let mut journal = self.journal.write().unwrap();
+        let usn = journal.len() as i64;
+        let mut record = Vec::with_capacity(length);
+        record.extend_from_slice(&(length as u32).to_le_bytes());
+        record.extend_from_slice(&2u16.to_le_bytes());
+        record.extend_from_slice(&0u16.to_le_bytes());
+        record.extend_from_slice(&file_reference.to_le_bytes());
+        record.extend_from_slice(&parent_file_reference.to_le_bytes());
+        record.extend_from_slice(&usn.to_le_bytes());
+        record.extend_from_slice(&timestamp.to_le_bytes());
+        record.extend_from_slice(&reason.to_le_bytes());
+        // Source info and security id
+        record.extend_from_slice(&[0u8; 8]);
+        record.extend_from_slice(&attributes.to_le_bytes());
+        record.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        record.extend_from_slice(&(RECORD_HEADER_SIZE as u16).to_le_bytes());
+        record.extend_from_slice(&name);
+        record.resize(length, 0);
+        journal.extend_from_slice(&record);
+
+        usn
+    }
+}
+
+impl VolumeRead for MemoryVolume {
+    fn query_volume_data(&self) -> Result<NTFS_VOLUME_DATA_BUFFER> {
+        Ok(self.volume_data)
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<()> {
+        let data = self.data.read().unwrap();
+        let source = data
+            .get(offset..offset + buffer.len())
+            .ok_or_else(|| eyre!("Read at {} is outside of the volume", offset))?;
+        buffer.copy_from_slice(source);
+
+        Ok(())
+    }
+
+    fn query_journal(&self) -> Result<USN_JOURNAL_DATA_V2> {
+        Ok(USN_JOURNAL_DATA_V2 {
+            UsnJournalID: self.journal_id,
+            NextUsn: self.journal.read().unwrap().len() as i64,
+            MaxUsn: i64::MAX,
+            MinSupportedMajorVersion: 2,
+            MaxSupportedMajorVersion: 2,
+            ..Default::default()
+        })
+    }
+
+    fn read_journal(&self, input: &READ_USN_JOURNAL_DATA_V1, buffer: &mut [u8]) -> Result<usize> {
+        if input.UsnJournalID != self.journal_id {
+            return Err(eyre!("Journal id {} does not exist", input.UsnJournalID));
+        }
+
+        let journal = self.journal.read().unwrap();
+        let mut usn = (input.StartUsn.max(0) as usize).min(journal.len());
+        let mut written = size_of::<i64>();
+        while usn < journal.len() {
+            let record = &journal[usn..];
+            let length = u32::from_le_bytes(record[0..4].try_into()?) as usize;
+            let reason = u32::from_le_bytes(record[40..44].try_into()?);
+            if reason & input.ReasonMask != 0 {
+                if written + length > buffer.len() {
+                    break;
+                }
+
+                buffer[written..written + length].copy_from_slice(&record[..length]);
+                written += length;
+            }
+            usn += length;
+        }
+
+        // Like the real journal, the output starts with the USN to continue reading at
+        buffer[..size_of::<i64>()].copy_from_slice(&(usn as i64).to_le_bytes());
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volume() -> MemoryVolume {
+        MemoryVolume::new((0..64).collect(), NTFS_VOLUME_DATA_BUFFER::default())
+    }
+
+    fn input(volume: &MemoryVolume, start: i64, reason_mask: u32) -> READ_USN_JOURNAL_DATA_V1 {
+        READ_USN_JOURNAL_DATA_V1 {
+            StartUsn: start,
+            ReasonMask: reason_mask,
+            UsnJournalID: volume.query_journal().unwrap().UsnJournalID,
+            ..Default::default()
+        }
+    }
+
+    /// File reference of the first record in the output of [MemoryVolume::read_journal]
+    fn first_file_reference(buffer: &[u8]) -> u64 {
+        u64::from_le_bytes(buffer[16..24].try_into().unwrap())
+    }
+
+    #[test]
+    fn reads_and_writes_stay_inside_of_the_volume() {
+        let volume = volume();
+        let mut buffer = [0u8; 4];
+        volume.read_at(60, &mut buffer).unwrap();
+        assert_eq!(buffer, [60, 61, 62, 63]);
+        assert!(volume.read_at(61, &mut buffer).is_err());
+
+        volume.write_at(0, &[9, 9]).unwrap();
+        volume.read_at(0, &mut buffer).unwrap();
+        assert_eq!(buffer, [9, 9, 2, 3]);
+        assert!(volume.write_at(63, &[1, 2]).is_err());
+    }
+
+    #[test]
+    fn journal_records_are_read_from_their_usn() {
+        let volume = volume();
+        let first = volume.push_journal_record(16, 5, 0x100, 0, 0, "a.txt");
+        let second = volume.push_journal_record(17, 5, 0x200, 0, 0, "bb.txt");
+        let next = volume.query_journal().unwrap().NextUsn;
+        assert_eq!(first, 0);
+        // Records are padded to 8 bytes
+        assert_eq!(second, 72);
+        assert_eq!(next, 144);
+
+        let mut buffer = vec![0u8; 4096];
+        let written = volume
+            .read_journal(&input(&volume, first, u32::MAX), &mut buffer)
+            .unwrap();
+        assert_eq!(written, 8 + next as usize);
+        assert_eq!(i64::from_le_bytes(buffer[..8].try_into().unwrap()), next);
+        assert_eq!(first_file_reference(&buffer), 16);
+
+        // Records with other reasons are skipped
+        let written = volume
+            .read_journal(&input(&volume, first, 0x200), &mut buffer)
+            .unwrap();
+        assert_eq!(written, 8 + (next - second) as usize);
+        assert_eq!(first_file_reference(&buffer), 17);
+
+        let written = volume
+            .read_journal(&input(&volume, next, u32::MAX), &mut buffer)
+            .unwrap();
+        assert_eq!(written, 8);
+        assert_eq!(i64::from_le_bytes(buffer[..8].try_into().unwrap()), next);
+    }
+
+    #[test]
+    fn records_which_do_not_fit_are_read_next_time() {
+        let volume = volume();
+        volume.push_journal_record(16, 5, 0x100, 0, 0, "a.txt");
+        let second = volume.push_journal_record(17, 5, 0x100, 0, 0, "b.txt");
+
+        let mut buffer = vec![0u8; 8 + second as usize];
+        let written = volume
+            .read_journal(&input(&volume, 0, u32::MAX), &mut buffer)
+            .unwrap();
+        assert_eq!(written, buffer.len());
+        assert_eq!(i64::from_le_bytes(buffer[..8].try_into().unwrap()), second);
+    }
+
+    #[test]
+    fn other_journal_ids_fail() {
+        let volume = volume();
+        let mut input = input(&volume, 0, u32::MAX);
+        input.UsnJournalID += 1;
+        assert!(volume.read_journal(&input, &mut [0u8; 64]).is_err());
+    }
+}
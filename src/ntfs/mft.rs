@@ -1,12 +1,11 @@
-use eyre::{Report, Result};
-use windows::Win32::Foundation::WAIT_OBJECT_0;
-use windows::Win32::Storage::FileSystem::ReadFile;
+use std::ops::Range;
+
+use eyre::{eyre, Context, ContextCompat, Result};
 use windows::Win32::System::Ioctl::NTFS_VOLUME_DATA_BUFFER;
-use windows::Win32::System::Threading::WaitForSingleObject;
 
+use crate::ntfs::file_attribute::AttributeType;
 use crate::ntfs::file_record::FileRecord;
-use crate::ntfs::try_close_handle;
-use crate::ntfs::volume::{create_overlapped, Volume};
+use crate::ntfs::volume::VolumeRead;
 
 #[derive(Debug)]
 pub struct MftFile {
@@ -14,35 +13,83 @@ pub struct MftFile {
 }
 
 impl MftFile {
-    pub fn new(vol: Volume, data: NTFS_VOLUME_DATA_BUFFER) -> Result<Self> {
-        let handle = vol.create_read_handle()?;
-
+    pub fn new(vol: &dyn VolumeRead, data: NTFS_VOLUME_DATA_BUFFER) -> Result<Self> {
         // Move to $MFT start
-        let mut ov = create_overlapped(data.BytesPerCluster as usize * data.MftStartLcn as usize);
-        let mut mft_file_buf = Vec::<u8>::with_capacity(data.BytesPerFileRecordSegment as usize);
+        let mut mft_file_buf = vec![0u8; data.BytesPerFileRecordSegment as usize];
+        vol.read_at(
+            data.BytesPerCluster as usize * data.MftStartLcn as usize,
+            &mut mft_file_buf,
+        )
+        .with_context(|| "Failed to read $MFT record")?;
 
-        unsafe {
-            let _ = ReadFile(
-                handle,
-                Some(std::slice::from_raw_parts_mut(mft_file_buf.as_mut_ptr() as *mut _,mft_file_buf.capacity())),
-                None,
-                Some(&mut ov as *mut _),
-            );
+        FileRecord::fixup(&mut mft_file_buf, data.BytesPerSector as usize);
+        Ok(MftFile { data: mft_file_buf })
+    }
 
-            if WaitForSingleObject(handle, 1000) != WAIT_OBJECT_0 {
-                return Err(Report::new(std::io::Error::last_os_error()));
-            }
+    pub fn as_record(&self) -> FileRecord {
+        FileRecord::new(&self.data)
+    }
 
-            try_close_handle(handle)?;
+    /// Reads the bitmap attribute of the $MFT, in which bit n is set if record n is in use.
+    pub fn read_bitmap(
+        &self,
+        vol: &dyn VolumeRead,
+        data: NTFS_VOLUME_DATA_BUFFER,
+    ) -> Result<Vec<u8>> {
+        let record = self.as_record();
+        let attr = record
+            .get_attribute(AttributeType::Bitmap)
+            .with_context(|| "Cannot find $MFT bitmap attribute")?;
 
-            mft_file_buf.set_len(mft_file_buf.capacity());
+        if !attr.header.non_resident {
+            let (offset, length) = unsafe {
+                let resident = attr.header.last.resident;
+                (
+                    resident.value_offset as usize,
+                    resident.value_length as usize,
+                )
+            };
+            return Ok(attr.data[offset..offset + length].to_vec());
         }
 
-        FileRecord::fixup(&mut mft_file_buf, data.BytesPerSector as usize);
-        Ok(MftFile { data: mft_file_buf })
+        let (size, runs) = attr
+            .decode_data_runs(data.BytesPerCluster as usize)
+            .with_context(|| "Cannot decode $MFT bitmap data runs")?;
+        let mut bitmap = vec![0u8; runs.iter().map(|r| r.len()).sum()];
+        read_runs(vol, &runs, 0, &mut bitmap).with_context(|| "Failed to read $MFT bitmap")?;
+
+        bitmap.truncate(size);
+        Ok(bitmap)
     }
+}
 
-    pub fn as_record(&self) -> FileRecord {
-        FileRecord::new(&self.data)
+/// Fills `buffer` with the content described by `runs`, starting at `offset` bytes into the content.
+/// The reads can span multiple runs.
+pub fn read_runs(
+    vol: &dyn VolumeRead,
+    runs: &[Range<usize>],
+    mut offset: usize,
+    buffer: &mut [u8],
+) -> Result<()> {
+    let mut filled = 0usize;
+    for run in runs {
+        if filled == buffer.len() {
+            break;
+        }
+        if offset >= run.len() {
+            offset -= run.len();
+            continue;
+        }
+
+        let len = (run.len() - offset).min(buffer.len() - filled);
+        vol.read_at(run.start + offset, &mut buffer[filled..filled + len])?;
+        filled += len;
+        offset = 0;
     }
+
+    if filled != buffer.len() {
+        return Err(eyre!("Range is outside of the data runs"));
+    }
+
+    Ok(())
 }
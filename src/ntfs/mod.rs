@@ -2,8 +2,17 @@ pub mod file_attribute;
 pub mod file_record;
 pub mod volume;
 pub mod mft;
+pub mod usn_enum;
 pub mod index;
 pub mod journal;
+pub mod memory_volume;
+pub mod synthetic;
+pub mod trigram;
+pub mod children;
+pub mod directory_index;
+pub mod file_ids;
+pub mod file_infos;
+pub mod fallback;
 
 pub fn try_close_handle(handle: windows::Win32::Foundation::HANDLE) -> eyre::Result<()> {
     use eyre::WrapErr;
@@ -0,0 +1,741 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use eyre::Result;
+use windows::Win32::System::Ioctl::{
+    NTFS_VOLUME_DATA_BUFFER, USN_REASON_FILE_CREATE, USN_REASON_FILE_DELETE,
+    USN_REASON_RENAME_NEW_NAME, USN_REASON_RENAME_OLD_NAME,
+};
+
+use crate::ntfs::file_attribute::AttributeType;
+use crate::ntfs::index::NtfsVolumeIndex;
+use crate::ntfs::journal::Journal;
+use crate::ntfs::memory_volume::MemoryVolume;
+use crate::ntfs::volume::{Volume, VolumeRead};
+
+const SECTOR_SIZE: usize = 512;
+const CLUSTER_SIZE: usize = 4096;
+const RECORD_SIZE: usize = 1024;
+const ROOT_INDEX: u64 = 5;
+/// Records below this index are reserved for the NTFS metafiles
+const FIRST_USER_RECORD: u64 = 16;
+/// The data runs of the $MFT have to fit into its record
+const MAX_MFT_FRAGMENTS: usize = 32;
+/// Clusters at the start of the volume which are left empty, like the boot sector
+const RESERVED_CLUSTERS: usize = 16;
+/// 2020-01-01 as a FILETIME
+const FIRST_TIMESTAMP: u64 = 132_223_104_000_000_000;
+/// About six years in FILETIME units
+const TIMESTAMP_SPAN: u64 = 6 * 365 * 24 * 60 * 60 * 10_000_000;
+
+const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x10;
+const FILE_ATTRIBUTE_ARCHIVE: u32 = 0x20;
+/// Flag in the file name attribute marking directories
+const FILE_NAME_DIRECTORY: u32 = 0x1000_0000;
+
+const FILE_WORDS: &[&str] = &[
+    "report", "invoice", "IMG", "document", "backup", "project", "notes", "setup", "data",
+    "config", "readme", "draft", "final", "scan", "video", "song", "archive", "build", "test",
+    "Résumé",
+];
+const EXTENSIONS: &[&str] = &[
+    "txt", "pdf", "jpg", "png", "docx", "xlsx", "mp3", "mp4", "zip", "rs", "dll", "exe", "log",
+    "json", "xml", "html", "cs", "cpp", "h", "",
+];
+const DIRECTORY_WORDS: &[&str] = &[
+    "src",
+    "docs",
+    "Downloads",
+    "Pictures",
+    "Music",
+    "bin",
+    "obj",
+    "node_modules",
+    "assets",
+    "cache",
+    "Program Files",
+    "Windows",
+    "Users",
+    "temp",
+    "projects",
+    "AppData",
+    "Local",
+    "Roaming",
+    "lib",
+    "include",
+];
+
+/// Describes the volume generated by [SyntheticVolume::generate].
+#[derive(Debug, Clone)]
+pub struct SyntheticOptions {
+    pub files: usize,
+    pub directories: usize,
+    /// Number of runs the MFT is split into, with gaps between them. At most 32.
+    pub mft_fragments: usize,
+    /// Unused records at the end of the MFT, which are used by files created by
+    /// [SyntheticVolume::generate_changes]
+    pub free_records: usize,
+    /// The same seed always generates the same volume and changes
+    pub seed: u64,
+}
+
+impl Default for SyntheticOptions {
+    fn default() -> Self {
+        Self {
+            files: 100_000,
+            directories: 10_000,
+            mft_fragments: 8,
+            free_records: 10_000,
+            seed: 1,
+        }
+    }
+}
+
+/// An NTFS volume with a random directory tree, generated in memory. Its MFT records and journal
+/// records are laid out like on a real volume, so the index can be built and updated through the
+/// same code paths, which makes it useful for benchmarks and for development without a real
+/// volume.
+pub struct SyntheticVolume {
+    volume: Arc<MemoryVolume>,
+    rng: Rng,
+    /// Byte ranges of the MFT on the volume
+    mft_runs: Vec<Range<usize>>,
+    bitmap_offset: usize,
+    records: Vec<Option<RecordSpec>>,
+    /// Sequence number of every record, incremented whenever a record is freed
+    sequences: Vec<u16>,
+    directories: Vec<u64>,
+    /// Indices of the files which were generated and not deleted yet
+    files: Vec<u64>,
+    free_records: Vec<u64>,
+    /// FILETIME of the last generated change
+    now: u64,
+}
+
+#[derive(Debug, Clone)]
+struct RecordSpec {
+    parent: u64,
+    name: String,
+    is_directory: bool,
+    size: u64,
+    created: u64,
+    modified: u64,
+}
+
+impl SyntheticVolume {
+    /// The drive letter of the generated volume, which only shows up in paths
+    pub const VOLUME: Volume = Volume { id: 'S' };
+
+    pub fn generate(options: &SyntheticOptions) -> Self {
+        let mut rng = Rng(options.seed);
+        let record_count = (FIRST_USER_RECORD as usize
+            + options.directories
+            + options.files
+            + options.free_records)
+            .next_multiple_of(CLUSTER_SIZE / RECORD_SIZE);
+        let mft_clusters = record_count * RECORD_SIZE / CLUSTER_SIZE;
+
+        // Uneven runs with gaps in between, like on a volume on which the MFT grew over time
+        let fragments = options
+            .mft_fragments
+            .clamp(1, MAX_MFT_FRAGMENTS)
+            .min(mft_clusters);
+        let mut splits = (1..fragments)
+            .map(|_| 1 + rng.below(mft_clusters as u64 - 1) as usize)
+            .collect::<Vec<_>>();
+        splits.extend([0, mft_clusters]);
+        splits.sort_unstable();
+        splits.dedup();
+        let mut lcn = RESERVED_CLUSTERS;
+        let mut cluster_runs = Vec::new();
+        for window in splits.windows(2) {
+            let length = window[1] - window[0];
+            cluster_runs.push((lcn, length));
+            lcn += length + 1 + rng.below(64) as usize;
+        }
+
+        let bitmap_size = record_count.div_ceil(8).next_multiple_of(8);
+        let bitmap_lcn = lcn;
+        let bitmap_clusters = bitmap_size.div_ceil(CLUSTER_SIZE);
+        let total_clusters = bitmap_lcn + bitmap_clusters;
+
+        let volume_data = NTFS_VOLUME_DATA_BUFFER {
+            NumberSectors: (total_clusters * CLUSTER_SIZE / SECTOR_SIZE) as i64,
+            TotalClusters: total_clusters as i64,
+            BytesPerSector: SECTOR_SIZE as u32,
+            BytesPerCluster: CLUSTER_SIZE as u32,
+            BytesPerFileRecordSegment: RECORD_SIZE as u32,
+            MftValidDataLength: (record_count * RECORD_SIZE) as i64,
+            MftStartLcn: cluster_runs[0].0 as i64,
+            ..Default::default()
+        };
+        let volume = Arc::new(MemoryVolume::new(
+            vec![0u8; total_clusters * CLUSTER_SIZE],
+            volume_data,
+        ));
+
+        let mut synthetic = Self {
+            volume,
+            rng,
+            mft_runs: cluster_runs
+                .iter()
+                .map(|&(lcn, length)| lcn * CLUSTER_SIZE..(lcn + length) * CLUSTER_SIZE)
+                .collect(),
+            bitmap_offset: bitmap_lcn * CLUSTER_SIZE,
+            records: vec![None; record_count],
+            sequences: vec![1; record_count],
+            directories: Vec::with_capacity(options.directories + 1),
+            files: Vec::with_capacity(options.files),
+            free_records: Vec::new(),
+            now: FIRST_TIMESTAMP + TIMESTAMP_SPAN,
+        };
+
+        let mft_record = synthetic.mft_record(
+            &encode_runs(&cluster_runs),
+            (record_count * RECORD_SIZE) as u64,
+            &encode_runs(&[(bitmap_lcn, bitmap_clusters)]),
+            bitmap_size as u64,
+        );
+        synthetic.write(0, &mft_record);
+        synthetic
+            .volume
+            .write_at(synthetic.bitmap_offset, &[1])
+            .unwrap();
+        let root = synthetic.spec(ROOT_INDEX, ".".into(), true);
+        synthetic.set_record(ROOT_INDEX, Some(root));
+        synthetic.directories.push(ROOT_INDEX);
+
+        let mut index = FIRST_USER_RECORD;
+        for _ in 0..options.directories {
+            // Prefer recent directories as parents to get a deeper tree
+            let count = synthetic.directories.len() as u64;
+            let parent =
+                synthetic.directories[(count - 1 - synthetic.rng.below(count.min(50))) as usize];
+            let name = synthetic.directory_name();
+            let spec = synthetic.spec(parent, name, true);
+            synthetic.set_record(index, Some(spec));
+            synthetic.directories.push(index);
+            index += 1;
+        }
+        for _ in 0..options.files {
+            let parent = synthetic.random_directory();
+            let name = synthetic.file_name();
+            let spec = synthetic.spec(parent, name, false);
+            synthetic.set_record(index, Some(spec));
+            synthetic.files.push(index);
+            index += 1;
+        }
+        synthetic.free_records = (index..record_count as u64).rev().collect();
+
+        synthetic
+    }
+
+    /// The in-memory volume, for example to read it with [NtfsVolumeIndex::from_reader].
+    pub fn reader(&self) -> Arc<MemoryVolume> {
+        self.volume.clone()
+    }
+
+    pub fn build_index(&self) -> Result<NtfsVolumeIndex> {
+        NtfsVolumeIndex::from_reader(Self::VOLUME, self.volume.clone())
+    }
+
+    /// Follows the journal, starting after the changes which were generated so far.
+    pub fn open_journal(&self) -> Result<Journal> {
+        Journal::from_reader(Box::new(self.volume.clone()))
+    }
+
+    /// Number of files and directories which currently exist, not counting the root
+    pub fn file_count(&self) -> usize {
+        self.files.len() + self.directories.len() - 1
+    }
+
+    /// Creates, renames and deletes `count` random files. Every change is written to the MFT and
+    /// to the journal.
+    pub fn generate_changes(&mut self, count: usize) {
+        for _ in 0..count {
+            self.now += 1 + self.rng.below(10_000_000);
+            match self.rng.below(10) {
+                0..=3 if !self.free_records.is_empty() => self.create_file(),
+                _ if self.files.is_empty() => {}
+                0..=6 => self.rename_file(),
+                _ => self.delete_file(),
+            }
+        }
+    }
+
+    fn create_file(&mut self) {
+        let index = self.free_records.pop().unwrap();
+        let parent = self.random_directory();
+        let name = self.file_name();
+        let mut spec = self.spec(parent, name, false);
+        spec.created = self.now;
+        spec.modified = self.now;
+
+        self.push_journal_record(index, &spec, USN_REASON_FILE_CREATE);
+        self.set_record(index, Some(spec));
+        self.files.push(index);
+    }
+
+    fn rename_file(&mut self) {
+        let index = self.files[self.rng.below(self.files.len() as u64) as usize];
+        let mut spec = self.records[index as usize].clone().unwrap();
+        self.push_journal_record(index, &spec, USN_REASON_RENAME_OLD_NAME);
+
+        spec.name = self.file_name();
+        if self.rng.below(4) == 0 {
+            spec.parent = self.random_directory();
+        }
+        self.push_journal_record(index, &spec, USN_REASON_RENAME_NEW_NAME);
+        self.set_record(index, Some(spec));
+    }
+
+    fn delete_file(&mut self) {
+        let i = self.rng.below(self.files.len() as u64) as usize;
+        let index = self.files.swap_remove(i);
+        let spec = self.records[index as usize].clone().unwrap();
+
+        self.push_journal_record(index, &spec, USN_REASON_FILE_DELETE);
+        // NTFS increments the sequence number when a record is freed
+        self.sequences[index as usize] = self.sequences[index as usize].wrapping_add(1).max(1);
+        self.set_record(index, None);
+        self.free_records.push(index);
+    }
+
+    fn push_journal_record(&self, index: u64, spec: &RecordSpec, reason: u32) {
+        let attributes = if spec.is_directory {
+            FILE_ATTRIBUTE_DIRECTORY
+        } else {
+            FILE_ATTRIBUTE_ARCHIVE
+        };
+        self.volume.push_journal_record(
+            self.file_reference(index),
+            self.file_reference(spec.parent),
+            reason,
+            attributes,
+            self.now,
+            &spec.name,
+        );
+    }
+
+    fn random_directory(&mut self) -> u64 {
+        self.directories[self.rng.below(self.directories.len() as u64) as usize]
+    }
+
+    fn file_name(&mut self) -> String {
+        let word = FILE_WORDS[self.rng.below(FILE_WORDS.len() as u64) as usize];
+        let extension = EXTENSIONS[self.rng.below(EXTENSIONS.len() as u64) as usize];
+        let number = self.rng.below(10_000);
+        if extension.is_empty() {
+            format!("{} {}", word, number)
+        } else {
+            format!("{}_{}.{}", word, number, extension)
+        }
+    }
+
+    fn directory_name(&mut self) -> String {
+        let word = DIRECTORY_WORDS[self.rng.below(DIRECTORY_WORDS.len() as u64) as usize];
+        match self.rng.below(3) {
+            0 => word.to_string(),
+            _ => format!("{}_{}", word, self.rng.below(1000)),
+        }
+    }
+
+    fn spec(&mut self, parent: u64, name: String, is_directory: bool) -> RecordSpec {
+        // Roughly log-uniform, so that most files are small
+        let size = if is_directory {
+            0
+        } else {
+            let bits = self.rng.below(34);
+            self.rng.below(1 << bits)
+        };
+        let created = FIRST_TIMESTAMP + self.rng.below(TIMESTAMP_SPAN);
+
+        RecordSpec {
+            parent,
+            name,
+            is_directory,
+            size,
+            created,
+            modified: created + self.rng.below(TIMESTAMP_SPAN / 10),
+        }
+    }
+
+    fn file_reference(&self, index: u64) -> u64 {
+        index | ((self.sequences[index as usize] as u64) << 48)
+    }
+
+    /// Writes the MFT record and the bitmap bit of `index`. `None` marks the record as not in use.
+    fn set_record(&mut self, index: u64, spec: Option<RecordSpec>) {
+        let record = self.file_record(index, spec.as_ref());
+        self.write(index, &record);
+
+        let offset = self.bitmap_offset + index as usize / 8;
+        let mut byte = [0u8];
+        self.volume.read_at(offset, &mut byte).unwrap();
+        match spec {
+            Some(_) => byte[0] |= 1 << (index % 8),
+            None => byte[0] &= !(1 << (index % 8)),
+        }
+        self.volume.write_at(offset, &byte).unwrap();
+
+        self.records[index as usize] = spec;
+    }
+
+    /// Writes a record to its place in the MFT, which can be split between two runs.
+    fn write(&self, index: u64, record: &[u8]) {
+        let mut offset = index as usize * RECORD_SIZE;
+        let mut written = 0usize;
+        for run in &self.mft_runs {
+            if offset >= run.len() {
+                offset -= run.len();
+                continue;
+            }
+
+            let len = (run.len() - offset).min(record.len() - written);
+            self.volume
+                .write_at(run.start + offset, &record[written..written + len])
+                .unwrap();
+            written += len;
+            offset = 0;
+            if written == record.len() {
+                break;
+            }
+        }
+    }
+
+    fn file_record(&self, index: u64, spec: Option<&RecordSpec>) -> Vec<u8> {
+        let Some(spec) = spec else {
+            return encode_record(self.sequences[index as usize], 0, &[]);
+        };
+
+        let attributes = if spec.is_directory {
+            FILE_ATTRIBUTE_DIRECTORY
+        } else {
+            FILE_ATTRIBUTE_ARCHIVE
+        };
+        let mut standard_information = vec![0u8; 0x48];
+        for (i, time) in [spec.created, spec.modified, spec.modified, spec.modified]
+            .into_iter()
+            .enumerate()
+        {
+            standard_information[i * 8..i * 8 + 8].copy_from_slice(&time.to_le_bytes());
+        }
+        standard_information[0x20..0x24].copy_from_slice(&attributes.to_le_bytes());
+
+        let mut encoded = vec![
+            resident_attribute(AttributeType::StandardInformation, 0, &standard_information),
+            resident_attribute(AttributeType::FileName, 1, &self.file_name_value(spec)),
+        ];
+        if !spec.is_directory {
+            let allocated = spec.size.next_multiple_of(CLUSTER_SIZE as u64);
+            encoded.push(non_resident_attribute(
+                AttributeType::Data,
+                2,
+                &[0],
+                allocated,
+                spec.size,
+            ));
+        }
+
+        let flags = if spec.is_directory { 0x3 } else { 0x1 };
+        encode_record(self.sequences[index as usize], flags, &encoded)
+    }
+
+    fn file_name_value(&self, spec: &RecordSpec) -> Vec<u8> {
+        let name = spec.name.encode_utf16().collect::<Vec<_>>();
+        let mut value = vec![0u8; 0x42 + name.len() * 2];
+        value[0..8].copy_from_slice(&self.file_reference(spec.parent).to_le_bytes());
+        for (i, time) in [spec.created, spec.modified, spec.modified, spec.modified]
+            .into_iter()
+            .enumerate()
+        {
+            value[8 + i * 8..16 + i * 8].copy_from_slice(&time.to_le_bytes());
+        }
+        let allocated = spec.size.next_multiple_of(CLUSTER_SIZE as u64);
+        value[0x28..0x30].copy_from_slice(&allocated.to_le_bytes());
+        value[0x30..0x38].copy_from_slice(&spec.size.to_le_bytes());
+        let flags = if spec.is_directory {
+            FILE_NAME_DIRECTORY
+        } else {
+            FILE_ATTRIBUTE_ARCHIVE
+        };
+        value[0x38..0x3C].copy_from_slice(&flags.to_le_bytes());
+        value[0x40] = name.len() as u8;
+        // Win32 and DOS namespace
+        value[0x41] = 3;
+        for (i, c) in name.iter().enumerate() {
+            value[0x42 + i * 2..0x44 + i * 2].copy_from_slice(&c.to_le_bytes());
+        }
+
+        value
+    }
+
+    /// The record of the $MFT itself, which contains the location of the MFT and its bitmap.
+    fn mft_record(
+        &mut self,
+        runs: &[u8],
+        size: u64,
+        bitmap_runs: &[u8],
+        bitmap_size: u64,
+    ) -> Vec<u8> {
+        let spec = RecordSpec {
+            parent: ROOT_INDEX,
+            name: "$MFT".into(),
+            is_directory: false,
+            size,
+            created: FIRST_TIMESTAMP,
+            modified: FIRST_TIMESTAMP,
+        };
+        let encoded = [
+            resident_attribute(AttributeType::StandardInformation, 0, &[0u8; 0x48]),
+            resident_attribute(AttributeType::FileName, 1, &self.file_name_value(&spec)),
+            non_resident_attribute(
+                AttributeType::Bitmap,
+                2,
+                bitmap_runs,
+                bitmap_size,
+                bitmap_size,
+            ),
+            non_resident_attribute(AttributeType::Data, 3, runs, size, size),
+        ];
+        self.records[0] = Some(spec);
+
+        encode_record(self.sequences[0], 0x1, &encoded)
+    }
+}
+
+/// Builds a file record with the given attributes, protected by an update sequence array like
+/// records on disk.
+fn encode_record(sequence: u16, flags: u16, attributes: &[Vec<u8>]) -> Vec<u8> {
+    const USA_OFFSET: usize = 0x30;
+    const FIRST_ATTRIBUTE_OFFSET: usize = 0x38;
+    const UPDATE_SEQUENCE: [u8; 2] = [1, 0];
+
+    let mut record = vec![0u8; RECORD_SIZE];
+    let mut offset = FIRST_ATTRIBUTE_OFFSET;
+    for attribute in attributes {
+        record[offset..offset + attribute.len()].copy_from_slice(attribute);
+        offset += attribute.len();
+    }
+    record[offset..offset + 4].copy_from_slice(&(AttributeType::End as u32).to_le_bytes());
+    let bytes_used = offset + 8;
+    assert!(
+        bytes_used <= RECORD_SIZE,
+        "Attributes don't fit into a record"
+    );
+
+    record[0..4].copy_from_slice(b"FILE");
+    record[4..6].copy_from_slice(&(USA_OFFSET as u16).to_le_bytes());
+    record[6..8].copy_from_slice(&((1 + RECORD_SIZE / SECTOR_SIZE) as u16).to_le_bytes());
+    record[16..18].copy_from_slice(&sequence.to_le_bytes());
+    // Hard link count
+    record[18..20].copy_from_slice(&1u16.to_le_bytes());
+    record[20..22].copy_from_slice(&(FIRST_ATTRIBUTE_OFFSET as u16).to_le_bytes());
+    record[22..24].copy_from_slice(&flags.to_le_bytes());
+    record[24..28].copy_from_slice(&(bytes_used as u32).to_le_bytes());
+    record[28..32].copy_from_slice(&(RECORD_SIZE as u32).to_le_bytes());
+    record[40..42].copy_from_slice(&(attributes.len() as u16).to_le_bytes());
+
+    // The last two bytes of every sector are moved into the array and replaced by the update
+    // sequence number, which FileRecord::fixup reverts
+    record[USA_OFFSET..USA_OFFSET + 2].copy_from_slice(&UPDATE_SEQUENCE);
+    for sector in 0..RECORD_SIZE / SECTOR_SIZE {
+        let end = (sector + 1) * SECTOR_SIZE - 2;
+        let entry = USA_OFFSET + 2 + sector * 2;
+        record.copy_within(end..end + 2, entry);
+        record[end..end + 2].copy_from_slice(&UPDATE_SEQUENCE);
+    }
+
+    record
+}
+
+fn resident_attribute(attribute_type: AttributeType, id: u16, value: &[u8]) -> Vec<u8> {
+    const VALUE_OFFSET: usize = 0x18;
+
+    let mut attribute = vec![0u8; (VALUE_OFFSET + value.len()).next_multiple_of(8)];
+    write_attribute_header(&mut attribute, attribute_type, id, false);
+    attribute[0x10..0x14].copy_from_slice(&(value.len() as u32).to_le_bytes());
+    attribute[0x14..0x16].copy_from_slice(&(VALUE_OFFSET as u16).to_le_bytes());
+    attribute[VALUE_OFFSET..VALUE_OFFSET + value.len()].copy_from_slice(value);
+
+    attribute
+}
+
+fn non_resident_attribute(
+    attribute_type: AttributeType,
+    id: u16,
+    runs: &[u8],
+    allocated_size: u64,
+    real_size: u64,
+) -> Vec<u8> {
+    const RUNS_OFFSET: usize = 0x40;
+
+    let mut attribute = vec![0u8; (RUNS_OFFSET + runs.len()).next_multiple_of(8)];
+    write_attribute_header(&mut attribute, attribute_type, id, true);
+    let last_vcn = allocated_size
+        .div_ceil(CLUSTER_SIZE as u64)
+        .saturating_sub(1);
+    attribute[0x18..0x20].copy_from_slice(&last_vcn.to_le_bytes());
+    attribute[0x20..0x22].copy_from_slice(&(RUNS_OFFSET as u16).to_le_bytes());
+    attribute[0x28..0x30].copy_from_slice(&allocated_size.to_le_bytes());
+    attribute[0x30..0x38].copy_from_slice(&real_size.to_le_bytes());
+    attribute[0x38..0x40].copy_from_slice(&real_size.to_le_bytes());
+    attribute[RUNS_OFFSET..RUNS_OFFSET + runs.len()].copy_from_slice(runs);
+
+    attribute
+}
+
+fn write_attribute_header(
+    attribute: &mut [u8],
+    attribute_type: AttributeType,
+    id: u16,
+    non_resident: bool,
+) {
+    let length = attribute.len() as u32;
+    attribute[0..4].copy_from_slice(&(attribute_type as u32).to_le_bytes());
+    attribute[4..8].copy_from_slice(&length.to_le_bytes());
+    attribute[8] = non_resident as u8;
+    attribute[0x0E..0x10].copy_from_slice(&id.to_le_bytes());
+}
+
+/// Encodes `(lcn, cluster count)` pairs as data runs. Every run stores the signed distance to the
+/// start of the previous run, using as few bytes as possible.
+fn encode_runs(runs: &[(usize, usize)]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let mut previous = 0i64;
+    for &(lcn, length) in runs {
+        let length = (length as u64).to_le_bytes();
+        let length_size = 8 - (u64::from_le_bytes(length).leading_zeros() as usize / 8);
+        let delta = lcn as i64 - previous;
+        previous = lcn as i64;
+        // One more bit than the value needs, for the sign
+        let offset_size = (1..=8)
+            .find(|&size| {
+                let shift = 64 - size * 8;
+                (delta << shift) >> shift == delta
+            })
+            .unwrap();
+
+        encoded.push(((offset_size << 4) | length_size) as u8);
+        encoded.extend_from_slice(&length[..length_size]);
+        encoded.extend_from_slice(&delta.to_le_bytes()[..offset_size]);
+    }
+    encoded.push(0);
+
+    encoded
+}
+
+/// SplitMix64, which is good enough to generate test data and keeps the output stable across
+/// versions.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `0..bound`, or 0 if `bound` is 0
+    fn below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            return 0;
+        }
+
+        self.next() % bound
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(mft_fragments: usize) -> SyntheticOptions {
+        SyntheticOptions {
+            files: 2_000,
+            directories: 200,
+            mft_fragments,
+            free_records: 500,
+            seed: 7,
+        }
+    }
+
+    /// Compares every record of the generated volume with the index
+    fn assert_matches(volume: &SyntheticVolume, index: &NtfsVolumeIndex) {
+        let root = index.find_by_index(ROOT_INDEX).unwrap();
+        assert!(root.is_directory());
+
+        for (i, spec) in volume
+            .records
+            .iter()
+            .enumerate()
+            .skip(FIRST_USER_RECORD as usize)
+        {
+            let info = index.find_by_index(i as u64);
+            let Some(spec) = spec else {
+                assert!(info.is_none(), "record {} should be free", i);
+                continue;
+            };
+
+            let info = info.unwrap_or_else(|| panic!("record {} is missing", i));
+            assert_eq!(info.name, spec.name, "record {}", i);
+            assert_eq!(info.parent(), spec.parent, "record {}", i);
+            assert_eq!(info.is_directory(), spec.is_directory, "record {}", i);
+            assert_eq!(info.size(), spec.size, "record {}", i);
+            assert_eq!(info.sequence(), volume.sequences[i], "record {}", i);
+        }
+    }
+
+    #[test]
+    fn index_matches_generated_records() {
+        let volume = SyntheticVolume::generate(&options(1));
+        let index = volume.build_index().unwrap();
+
+        assert_matches(&volume, &index);
+    }
+
+    #[test]
+    fn index_reads_fragmented_mft() {
+        let volume = SyntheticVolume::generate(&options(MAX_MFT_FRAGMENTS));
+        assert!(volume.mft_runs.len() > 1);
+        let index = volume.build_index().unwrap();
+
+        assert_matches(&volume, &index);
+    }
+
+    #[test]
+    fn journal_replay_matches_changed_records() {
+        let mut volume = SyntheticVolume::generate(&options(8));
+        let mut index = volume.build_index().unwrap();
+        let mut journal = volume.open_journal().unwrap();
+
+        volume.generate_changes(1_000);
+        let end = volume.reader().query_journal().unwrap().NextUsn;
+        assert!(end > 0);
+
+        let mut changes = 0;
+        while journal.next_usn() < end {
+            let entries = journal.read_entries().unwrap();
+            changes += index.process_journal_entries(&entries).len();
+        }
+
+        assert!(changes > 0);
+        assert_matches(&volume, &index);
+    }
+
+    #[test]
+    fn journal_starts_after_existing_records() {
+        let mut volume = SyntheticVolume::generate(&options(8));
+        volume.generate_changes(100);
+        let mut index = volume.build_index().unwrap();
+        let mut journal = volume.open_journal().unwrap();
+
+        // Changes made before the journal was opened are already part of the index
+        let entries = journal.read_entries().unwrap();
+        assert!(entries.is_empty());
+        assert!(index.process_journal_entries(&entries).is_empty());
+        assert_matches(&volume, &index);
+    }
+}
@@ -0,0 +1,113 @@
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+
+use crate::ntfs::file_infos::FileInfos;
+use crate::query::fold;
+
+/// Number of records whose trigrams are collected into one map before the maps are merged
+const BUILD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Maps every sequence of three characters in the folded file names to the MFT indices of the
+/// files whose name contains it. A name can only contain a text if it contains all trigrams of
+/// the text, so intersecting their lists narrows the files which have to be checked from the whole
+/// volume down to a few candidates.
+#[derive(Debug, Default)]
+pub struct TrigramIndex {
+    /// Sorted MFT indices. u32 is enough for every real MFT and halves the memory needed.
+    postings: FxHashMap<u64, Vec<u32>>,
+}
+
+impl TrigramIndex {
+    pub fn build(infos: &FileInfos) -> Self {
+        let postings = infos
+            .par_chunks(BUILD_CHUNK_SIZE)
+            .enumerate()
+            .map(|(chunk, infos)| {
+                let mut postings = FxHashMap::<u64, Vec<u32>>::default();
+                for (i, info) in infos.enumerate() {
+                    let Some(info) = info else {
+                        continue;
+                    };
+
+                    let index = (chunk * BUILD_CHUNK_SIZE + i) as u32;
+                    for trigram in trigrams(&info.name) {
+                        postings.entry(trigram).or_default().push(index);
+                    }
+                }
+                postings
+            })
+            // Chunks are combined in order, so appending keeps the lists sorted
+            .reduce(FxHashMap::default, |mut left, right| {
+                for (trigram, indices) in right {
+                    left.entry(trigram).or_default().extend(indices);
+                }
+                left
+            });
+
+        Self { postings }
+    }
+
+    pub fn insert(&mut self, index: u64, name: &str) {
+        for trigram in trigrams(name) {
+            let indices = self.postings.entry(trigram).or_default();
+            if let Err(pos) = indices.binary_search(&(index as u32)) {
+                indices.insert(pos, index as u32);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, index: u64, name: &str) {
+        for trigram in trigrams(name) {
+            let Some(indices) = self.postings.get_mut(&trigram) else {
+                continue;
+            };
+            if let Ok(pos) = indices.binary_search(&(index as u32)) {
+                indices.remove(pos);
+            }
+            if indices.is_empty() {
+                self.postings.remove(&trigram);
+            }
+        }
+    }
+
+    /// Returns the sorted MFT indices of all files whose name might contain `text` after folding.
+    /// The names still have to be checked. Returns `None` if `text` is too short to narrow the
+    /// search down.
+    pub fn candidates(&self, text: &str) -> Option<Vec<u64>> {
+        let mut lists = Vec::new();
+        for trigram in trigrams(text) {
+            match self.postings.get(&trigram) {
+                Some(indices) => lists.push(indices),
+                None => return Some(Vec::new()),
+            }
+        }
+        if lists.is_empty() {
+            return None;
+        }
+
+        lists.sort_unstable_by_key(|indices| indices.len());
+        let (shortest, rest) = lists.split_first().unwrap();
+        Some(
+            shortest
+                .iter()
+                .filter(|index| {
+                    rest.iter()
+                        .all(|indices| indices.binary_search(index).is_ok())
+                })
+                .map(|&index| index as u64)
+                .collect(),
+        )
+    }
+}
+
+/// Returns the distinct trigrams of the folded text, each packed into a u64.
+fn trigrams(text: &str) -> Vec<u64> {
+    let chars = fold(text).chars().collect::<Vec<_>>();
+    let mut trigrams = chars
+        .windows(3)
+        .map(|w| (w[0] as u64) << 42 | (w[1] as u64) << 21 | w[2] as u64)
+        .collect::<Vec<_>>();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
@@ -0,0 +1,149 @@
+use std::ffi::c_void;
+use std::sync::Mutex;
+
+use eyre::{eyre, Report, Result, WrapErr};
+use windows::Win32::Foundation::{ERROR_HANDLE_EOF, HANDLE};
+use windows::Win32::Storage::FileSystem::FILE_ATTRIBUTE_DIRECTORY;
+use windows::Win32::System::Ioctl::{FSCTL_ENUM_USN_DATA, MFT_ENUM_DATA_V1, USN_RECORD_UNION};
+use windows::Win32::System::IO::DeviceIoControl;
+
+use crate::ntfs::file_ids::FileIdMap;
+use crate::ntfs::index::{FileInfo, FileTimes};
+use crate::ntfs::journal::{get_mft_index_from_file_reference, get_sequence_from_file_reference};
+use crate::ntfs::try_close_handle;
+use crate::ntfs::volume::Volume;
+
+/// In u64s, to keep the records 8 byte aligned
+const BUFFER_SIZE: usize = 128 * 1024;
+
+/// Enumerates all files of the volume through the USN subsystem using `FSCTL_ENUM_USN_DATA`. Unlike
+/// reading the MFT, this does not need raw read access to the volume, but the records contain
+/// neither sizes nor timestamps, so those are left empty.
+///
+/// On ReFS, files are identified by 128 bit ids, which are mapped to indices using `file_ids`.
+/// Without `file_ids`, the 64 bit file reference numbers of NTFS are used. The map is only locked
+/// while the records of a single read are mapped, as the journal needs it as well.
+pub fn enumerate_files(
+    volume: Volume,
+    file_ids: Option<&Mutex<FileIdMap>>,
+) -> Result<Vec<Option<FileInfo>>> {
+    let handle = volume.create_read_handle()?;
+    let res = read_records(handle, file_ids);
+    try_close_handle(handle)?;
+
+    res
+}
+
+fn read_records(
+    handle: HANDLE,
+    file_ids: Option<&Mutex<FileIdMap>>,
+) -> Result<Vec<Option<FileInfo>>> {
+    let mut infos = Vec::new();
+    let mut input = MFT_ENUM_DATA_V1 {
+        StartFileReferenceNumber: 0,
+        LowUsn: 0,
+        HighUsn: i64::MAX,
+        // V2 records use 64 bit file references, which is all NTFS needs. ReFS only returns V3
+        // records with 128 bit file ids.
+        MinMajorVersion: 2,
+        MaxMajorVersion: if file_ids.is_some() { 3 } else { 2 },
+    };
+    let mut buffer = vec![0u64; BUFFER_SIZE];
+
+    loop {
+        let mut bytes_read = 0u32;
+        let res = unsafe {
+            DeviceIoControl(
+                handle,
+                FSCTL_ENUM_USN_DATA,
+                Some(&input as *const _ as *const c_void),
+                size_of_val(&input) as u32,
+                Some(buffer.as_mut_ptr() as *mut c_void),
+                (buffer.len() * size_of::<u64>()) as u32,
+                Some(&mut bytes_read as *mut u32),
+                None,
+            )
+        };
+
+        if res.is_err() {
+            let error = std::io::Error::last_os_error();
+            // Returned once all records have been enumerated
+            if error.raw_os_error() == Some(ERROR_HANDLE_EOF.0 as i32) {
+                return Ok(infos);
+            }
+
+            return Err(Report::new(error))
+                .with_context(|| "DeviceIoControl failed trying to enumerate USN data");
+        }
+
+        let bytes = unsafe {
+            std::slice::from_raw_parts(buffer.as_ptr() as *const u8, bytes_read as usize)
+        };
+        // The output starts with the file reference number to continue with
+        input.StartFileReferenceNumber = u64::from_le_bytes(bytes[..8].try_into()?);
+
+        let mut file_ids = file_ids.map(|file_ids| file_ids.lock().unwrap());
+
+        let mut offset = size_of::<u64>();
+        while offset < bytes.len() {
+            let union = unsafe { &*(bytes[offset..].as_ptr() as *const USN_RECORD_UNION) };
+            let header = unsafe { union.Header };
+            if header.RecordLength == 0 {
+                return Err(eyre!("Invalid record length {}", header.RecordLength));
+            }
+
+            // (index, sequence, parent index, attributes, name)
+            let (index, sequence, parent, attributes, name) =
+                match (header.MajorVersion, file_ids.as_deref_mut()) {
+                    (2, _) => {
+                        let record = unsafe { &union.V2 };
+                        (
+                            get_mft_index_from_file_reference(record.FileReferenceNumber),
+                            get_sequence_from_file_reference(record.FileReferenceNumber),
+                            get_mft_index_from_file_reference(record.ParentFileReferenceNumber),
+                            record.FileAttributes,
+                            unsafe { read_name(record.FileName.as_ptr(), record.FileNameLength) },
+                        )
+                    }
+                    (3, Some(file_ids)) => {
+                        let record = unsafe { &union.V3 };
+                        (
+                            file_ids.index(record.FileReferenceNumber),
+                            // ReFS doesn't reuse ids, so there is no sequence number
+                            0,
+                            file_ids.index(record.ParentFileReferenceNumber),
+                            record.FileAttributes,
+                            unsafe { read_name(record.FileName.as_ptr(), record.FileNameLength) },
+                        )
+                    }
+                    (version, _) => {
+                        return Err(eyre!("Unexpected record major version {}", version));
+                    }
+                };
+
+            let index = index as usize;
+            if infos.len() <= index {
+                infos.resize(index + 1, None);
+            }
+            infos[index] = Some(
+                FileInfo::new(
+                    0,
+                    0,
+                    attributes & FILE_ATTRIBUTE_DIRECTORY.0 != 0,
+                    parent,
+                    name.into(),
+                    FileTimes::default(),
+                    attributes,
+                )
+                .with_sequence(sequence),
+            );
+
+            offset += header.RecordLength as usize;
+        }
+    }
+}
+
+/// `length` is in bytes
+unsafe fn read_name(name: *const u16, length: u16) -> String {
+    String::from_utf16_lossy(std::slice::from_raw_parts(name, length as usize / 2))
+}
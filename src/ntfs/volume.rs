@@ -1,12 +1,22 @@
-use eyre::{Context, Report, Result};
+use std::ffi::c_void;
+use std::sync::Arc;
+
+use eyre::{eyre, Context, Result};
 use windows::core::HSTRING;
-use windows::Win32::Foundation::HANDLE;
+use windows::Win32::Foundation::{ERROR_IO_PENDING, HANDLE};
 use windows::Win32::Storage::FileSystem::{
-    CreateFileW, GetVolumeNameForVolumeMountPointW, FILE_FLAG_OVERLAPPED, FILE_GENERIC_READ,
+    CreateFileW, GetDiskFreeSpaceExW, GetDiskFreeSpaceW, GetVolumeInformationW,
+    GetVolumeNameForVolumeMountPointW, ReadFile, FILE_FLAG_OVERLAPPED, FILE_GENERIC_READ,
     FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
 };
-use windows::Win32::System::Ioctl::{FSCTL_GET_NTFS_VOLUME_DATA, NTFS_VOLUME_DATA_BUFFER};
-use windows::Win32::System::IO::{DeviceIoControl, OVERLAPPED};
+use windows::Win32::System::Ioctl::{
+    PropertyStandardQuery, StorageDeviceSeekPenaltyProperty, DEVICE_SEEK_PENALTY_DESCRIPTOR,
+    FSCTL_GET_NTFS_VOLUME_DATA, FSCTL_GET_REFS_VOLUME_DATA, FSCTL_QUERY_USN_JOURNAL,
+    FSCTL_READ_USN_JOURNAL, IOCTL_STORAGE_QUERY_PROPERTY, NTFS_VOLUME_DATA_BUFFER,
+    READ_USN_JOURNAL_DATA_V1, REFS_VOLUME_DATA_BUFFER, STORAGE_PROPERTY_QUERY, USN_JOURNAL_DATA_V2,
+};
+use windows::Win32::System::Threading::CreateEventW;
+use windows::Win32::System::IO::{DeviceIoControl, GetOverlappedResult, OVERLAPPED};
 
 use crate::ntfs::try_close_handle;
 
@@ -32,30 +42,284 @@ impl Volume {
     }
 
     pub fn query_volume_data(&self) -> Result<NTFS_VOLUME_DATA_BUFFER> {
-        let handle = self.create_read_handle()?;
-        let data = NTFS_VOLUME_DATA_BUFFER::default();
-        let res = unsafe {
-            let res = DeviceIoControl(
-                handle,
+        self.open()?.query_volume_data()
+    }
+
+    /// The ReFS counterpart of [Self::query_volume_data], which fails on ReFS volumes.
+    pub fn query_refs_volume_data(&self) -> Result<REFS_VOLUME_DATA_BUFFER> {
+        let handle = self.open()?;
+        let mut data = REFS_VOLUME_DATA_BUFFER {
+            ByteCount: size_of::<REFS_VOLUME_DATA_BUFFER>() as u32,
+            ..Default::default()
+        };
+        unsafe {
+            DeviceIoControl(
+                handle.handle,
+                FSCTL_GET_REFS_VOLUME_DATA,
+                None,
+                0,
+                Some(&mut data as *mut REFS_VOLUME_DATA_BUFFER as *mut c_void),
+                size_of_val(&data) as u32,
+                None,
+                None,
+            )
+        }
+        .with_context(|| "DeviceIoControl failed")?;
+
+        Ok(data)
+    }
+
+    /// Name of the file system of the volume, like `NTFS`, `ReFS` or `FAT32`.
+    pub fn file_system(&self) -> Result<String> {
+        let mut name = [0u16; 32];
+        unsafe {
+            GetVolumeInformationW(
+                &HSTRING::from(&format!("{}:\\", self.id)),
+                None,
+                None,
+                None,
+                None,
+                Some(&mut name),
+            )
+        }
+        .with_context(|| format!("GetVolumeInformationW failed for '{}'", self.id))?;
+
+        let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        Ok(String::from_utf16_lossy(&name[..len]))
+    }
+
+    /// Returns the total and free bytes and the cluster size of the volume. Unlike
+    /// [VolumeRead::query_volume_data], this works for every file system.
+    pub fn query_space(&self) -> Result<(u64, u64, u32)> {
+        let root = HSTRING::from(&format!("{}:\\", self.id));
+        let (mut total_bytes, mut free_bytes) = (0, 0);
+        unsafe { GetDiskFreeSpaceExW(&root, None, Some(&mut total_bytes), Some(&mut free_bytes)) }
+            .with_context(|| format!("GetDiskFreeSpaceExW failed for '{}'", self.id))?;
+        let (mut sectors_per_cluster, mut bytes_per_sector) = (0, 0);
+        unsafe {
+            GetDiskFreeSpaceW(
+                &root,
+                Some(&mut sectors_per_cluster),
+                Some(&mut bytes_per_sector),
+                None,
+                None,
+            )
+        }
+        .with_context(|| format!("GetDiskFreeSpaceW failed for '{}'", self.id))?;
+
+        Ok((
+            total_bytes,
+            free_bytes,
+            sectors_per_cluster * bytes_per_sector,
+        ))
+    }
+
+    /// Opens the volume for reading. The handle is closed once the returned value is dropped.
+    pub fn open(&self) -> Result<VolumeHandle> {
+        Ok(VolumeHandle {
+            volume: *self,
+            handle: self.create_read_handle()?,
+        })
+    }
+}
+
+/// Raw access to an NTFS volume. Implemented by [VolumeHandle] for real volumes and by
+/// [MemoryVolume](crate::ntfs::memory_volume::MemoryVolume) for images held in memory, so that the
+/// MFT and the journal can be read without administrator rights or a real volume.
+pub trait VolumeRead: Send + Sync {
+    fn query_volume_data(&self) -> Result<NTFS_VOLUME_DATA_BUFFER>;
+
+    /// Fills `buffer` with the bytes at `offset` of the volume.
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<()>;
+
+    fn query_journal(&self) -> Result<USN_JOURNAL_DATA_V2>;
+
+    /// Works like `FSCTL_READ_USN_JOURNAL`: The output starts with the USN to continue reading at,
+    /// followed by the records. Returns the number of bytes written to `buffer`.
+    fn read_journal(&self, input: &READ_USN_JOURNAL_DATA_V1, buffer: &mut [u8]) -> Result<usize>;
+
+    /// Opens a new handle for overlapped reads through an I/O completion port, which the caller
+    /// has to close. Volumes which don't support this return `None` and are read using
+    /// [Self::read_at] instead.
+    fn open_overlapped(&self) -> Result<Option<HANDLE>> {
+        Ok(None)
+    }
+
+    /// Whether the disk behind the volume has to seek between reads, i.e. is a hard disk. `None`
+    /// if this is unknown, for example for volumes spanning several disks.
+    fn has_seek_penalty(&self) -> Option<bool> {
+        None
+    }
+}
+
+/// Allows keeping access to a reader which was handed to an index or a journal, for example to
+/// change a [MemoryVolume](crate::ntfs::memory_volume::MemoryVolume) while it is being followed.
+impl<T: VolumeRead + ?Sized> VolumeRead for Arc<T> {
+    fn query_volume_data(&self) -> Result<NTFS_VOLUME_DATA_BUFFER> {
+        (**self).query_volume_data()
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<()> {
+        (**self).read_at(offset, buffer)
+    }
+
+    fn query_journal(&self) -> Result<USN_JOURNAL_DATA_V2> {
+        (**self).query_journal()
+    }
+
+    fn read_journal(&self, input: &READ_USN_JOURNAL_DATA_V1, buffer: &mut [u8]) -> Result<usize> {
+        (**self).read_journal(input, buffer)
+    }
+
+    fn open_overlapped(&self) -> Result<Option<HANDLE>> {
+        (**self).open_overlapped()
+    }
+
+    fn has_seek_penalty(&self) -> Option<bool> {
+        (**self).has_seek_penalty()
+    }
+}
+
+/// An open handle to a real volume, see [Volume::open].
+pub struct VolumeHandle {
+    volume: Volume,
+    handle: HANDLE,
+}
+
+// Every request uses its own OVERLAPPED structure and waits for its own completion, so the handle
+// can be shared between threads
+unsafe impl Send for VolumeHandle {}
+unsafe impl Sync for VolumeHandle {}
+
+impl Drop for VolumeHandle {
+    fn drop(&mut self) {
+        try_close_handle(self.handle).expect("Failed to close volume handle");
+    }
+}
+
+impl VolumeRead for VolumeHandle {
+    fn query_volume_data(&self) -> Result<NTFS_VOLUME_DATA_BUFFER> {
+        let mut data = NTFS_VOLUME_DATA_BUFFER::default();
+        unsafe {
+            DeviceIoControl(
+                self.handle,
                 FSCTL_GET_NTFS_VOLUME_DATA,
                 None,
                 0,
-                Some(&data as *const NTFS_VOLUME_DATA_BUFFER as *mut _),
-                std::mem::size_of_val(&data) as u32,
+                Some(&mut data as *mut NTFS_VOLUME_DATA_BUFFER as *mut c_void),
+                size_of_val(&data) as u32,
                 None,
                 None,
+            )
+        }
+        .with_context(|| "DeviceIoControl failed")?;
+
+        Ok(data)
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<()> {
+        unsafe {
+            // The handle is signaled by any completed request, so wait for an event instead
+            let event = CreateEventW(None, true, false, None)?;
+            let mut ov = create_overlapped(offset);
+            ov.hEvent = event;
+            // Waiting for a request that was never queued would block forever
+            let res = ReadFile(
+                self.handle,
+                Some(&mut *buffer),
+                None,
+                Some(&mut ov as *mut _),
             );
-            try_close_handle(handle)?;
-            res
-        };
+            if let Err(e) = res {
+                if e.code() != ERROR_IO_PENDING.to_hresult() {
+                    try_close_handle(event)?;
+                    return Err(e).with_context(|| format!("ReadFile failed at offset {}", offset));
+                }
+            }
+
+            let mut bytes_read = 0u32;
+            let res = GetOverlappedResult(self.handle, &ov, &mut bytes_read, true);
+            try_close_handle(event)?;
+            res.with_context(|| format!("ReadFile failed at offset {}", offset))?;
 
-        if res.is_err() {
-            return Err(Report::new(std::io::Error::last_os_error()))
-                .with_context(|| "DeviceIoControl failed");
+            if bytes_read as usize != buffer.len() {
+                return Err(eyre!(
+                    "Read {} of {} bytes at offset {}",
+                    bytes_read,
+                    buffer.len(),
+                    offset
+                ));
+            }
         }
 
+        Ok(())
+    }
+
+    fn query_journal(&self) -> Result<USN_JOURNAL_DATA_V2> {
+        let mut data = USN_JOURNAL_DATA_V2::default();
+        unsafe {
+            DeviceIoControl(
+                self.handle,
+                FSCTL_QUERY_USN_JOURNAL,
+                None,
+                0,
+                Some(&mut data as *mut _ as *mut c_void),
+                size_of_val(&data) as u32,
+                None,
+                None,
+            )
+        }
+        .with_context(|| "DeviceIoControl failed trying to query journal data")?;
+
         Ok(data)
     }
+
+    fn read_journal(&self, input: &READ_USN_JOURNAL_DATA_V1, buffer: &mut [u8]) -> Result<usize> {
+        let mut bytes_read = 0u32;
+        unsafe {
+            DeviceIoControl(
+                self.handle,
+                FSCTL_READ_USN_JOURNAL,
+                Some(input as *const _ as *const c_void),
+                size_of_val(input) as u32,
+                Some(buffer.as_mut_ptr() as *mut c_void),
+                buffer.len() as u32,
+                Some(&mut bytes_read as *mut u32),
+                None,
+            )
+        }
+        .with_context(|| "DeviceIoControl failed trying to read journal entries")?;
+
+        Ok(bytes_read as usize)
+    }
+
+    fn open_overlapped(&self) -> Result<Option<HANDLE>> {
+        Ok(Some(self.volume.create_read_handle()?))
+    }
+
+    fn has_seek_penalty(&self) -> Option<bool> {
+        let query = STORAGE_PROPERTY_QUERY {
+            PropertyId: StorageDeviceSeekPenaltyProperty,
+            QueryType: PropertyStandardQuery,
+            ..Default::default()
+        };
+        let mut descriptor = DEVICE_SEEK_PENALTY_DESCRIPTOR::default();
+        unsafe {
+            DeviceIoControl(
+                self.handle,
+                IOCTL_STORAGE_QUERY_PROPERTY,
+                Some(&query as *const _ as *const c_void),
+                size_of_val(&query) as u32,
+                Some(&mut descriptor as *mut _ as *mut c_void),
+                size_of_val(&descriptor) as u32,
+                None,
+                None,
+            )
+        }
+        .ok()?;
+
+        Some(descriptor.IncursSeekPenalty.as_bool())
+    }
 }
 
 pub fn create_overlapped(offset: usize) -> OVERLAPPED {
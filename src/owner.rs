@@ -0,0 +1,165 @@
+//! Owners of files. They are not part of the index, as the security descriptors of the MFT records
+//! only refer to `$Secure`, so they are queried from Windows when needed and cached.
+
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use eyre::{Context, Result};
+use lru::LruCache;
+use rustc_hash::FxHashMap;
+use tracing::debug;
+use windows::core::{HSTRING, PCWSTR, PWSTR};
+use windows::Win32::Foundation::{LocalFree, HLOCAL};
+use windows::Win32::Security::Authorization::{
+    ConvertSidToStringSidW, GetNamedSecurityInfoW, SE_FILE_OBJECT,
+};
+use windows::Win32::Security::{
+    GetLengthSid, LookupAccountSidW, OWNER_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR, PSID,
+    SID_NAME_USE,
+};
+
+use crate::ntfs::index::FileChange;
+
+/// Owners cached by path. Enough for the visible results and sorting or filtering typical result
+/// sets, owners of larger ones are queried again.
+const CACHE_SIZE: usize = 64 * 1024;
+/// Longest account and domain name, in UTF-16 units
+const MAX_NAME_LENGTH: usize = 256;
+
+type OwnerCache = LruCache<String, Option<Arc<str>>>;
+
+/// Returns the owner of the file at `path` as `DOMAIN\name`, or as SID if the account doesn't
+/// exist anymore. `None` if the owner can't be read, for example because access is denied.
+pub fn owner(path: &str) -> Option<Arc<str>> {
+    if let Some(owner) = owners().lock().unwrap().get(path) {
+        return owner.clone();
+    }
+
+    let owner = query_owner(path).map_err(|e| debug!("{:?}", e)).ok();
+    owners()
+        .lock()
+        .unwrap()
+        .put(path.to_string(), owner.clone());
+    owner
+}
+
+/// Drops the cached owners of the paths which were created, renamed, moved or deleted, as a
+/// different file may be found at them now. Changing only the owner of a file is not part of the
+/// journal entries read for the index, so those are picked up once the cache evicts the file.
+pub fn forget(changes: &[FileChange]) {
+    let mut owners = owners().lock().unwrap();
+    for change in changes {
+        owners.pop(&change.path);
+        if let Some(old_path) = &change.old_path {
+            owners.pop(old_path);
+        }
+    }
+}
+
+/// Whether `owner`, as returned by [owner], is the account `name`, ignoring case. The domain can be
+/// left out of `name`.
+pub fn is_owned_by(owner: &str, name: &str) -> bool {
+    let owner = owner.to_lowercase();
+    let name = name.to_lowercase();
+    owner == name
+        || owner
+            .rsplit_once('\\')
+            .is_some_and(|(_, account)| account == name)
+}
+
+fn owners() -> &'static Mutex<OwnerCache> {
+    static OWNERS: OnceLock<Mutex<OwnerCache>> = OnceLock::new();
+    OWNERS.get_or_init(|| Mutex::new(LruCache::new(NonZeroUsize::new(CACHE_SIZE).unwrap())))
+}
+
+/// Account names by SID, as most files belong to a handful of accounts
+fn accounts() -> &'static Mutex<FxHashMap<Vec<u8>, Arc<str>>> {
+    static ACCOUNTS: OnceLock<Mutex<FxHashMap<Vec<u8>, Arc<str>>>> = OnceLock::new();
+    ACCOUNTS.get_or_init(Default::default)
+}
+
+fn query_owner(path: &str) -> Result<Arc<str>> {
+    let mut sid = PSID::default();
+    let mut descriptor = PSECURITY_DESCRIPTOR::default();
+    unsafe {
+        GetNamedSecurityInfoW(
+            &HSTRING::from(path),
+            SE_FILE_OBJECT,
+            OWNER_SECURITY_INFORMATION,
+            Some(&mut sid),
+            None,
+            None,
+            None,
+            &mut descriptor,
+        )
+        .ok()
+        .with_context(|| format!("Failed to read the owner of {}", path))?;
+    }
+
+    // The SID points into the descriptor, so it has to be resolved before freeing it
+    let name = account_name(sid);
+    unsafe {
+        let _ = LocalFree(HLOCAL(descriptor.0));
+    }
+    name
+}
+
+fn account_name(sid: PSID) -> Result<Arc<str>> {
+    let key = unsafe {
+        std::slice::from_raw_parts(sid.0 as *const u8, GetLengthSid(sid) as usize).to_vec()
+    };
+    if let Some(name) = accounts().lock().unwrap().get(&key) {
+        return Ok(name.clone());
+    }
+
+    let name: Arc<str> = match lookup_account(sid) {
+        Ok(name) => name.into(),
+        Err(e) => {
+            debug!("{:?}", e);
+            sid_string(sid)?.into()
+        }
+    };
+    accounts().lock().unwrap().insert(key, name.clone());
+    Ok(name)
+}
+
+fn lookup_account(sid: PSID) -> Result<String> {
+    let mut name = [0u16; MAX_NAME_LENGTH];
+    let mut domain = [0u16; MAX_NAME_LENGTH];
+    let mut name_length = name.len() as u32;
+    let mut domain_length = domain.len() as u32;
+    let mut name_use = SID_NAME_USE::default();
+    unsafe {
+        LookupAccountSidW(
+            PCWSTR::null(),
+            sid,
+            PWSTR(name.as_mut_ptr()),
+            &mut name_length,
+            PWSTR(domain.as_mut_ptr()),
+            &mut domain_length,
+            &mut name_use,
+        )
+        .with_context(|| "Failed to look up the account of a SID")?;
+    }
+
+    let name = String::from_utf16_lossy(&name[..name_length as usize]);
+    Ok(match domain_length {
+        0 => name,
+        _ => format!(
+            "{}\\{}",
+            String::from_utf16_lossy(&domain[..domain_length as usize]),
+            name
+        ),
+    })
+}
+
+/// Formats the SID like `S-1-5-21-...`
+fn sid_string(sid: PSID) -> Result<String> {
+    unsafe {
+        let mut string = PWSTR::null();
+        ConvertSidToStringSidW(sid, &mut string).with_context(|| "Failed to format a SID")?;
+        let res = string.to_string();
+        let _ = LocalFree(HLOCAL(string.0 as _));
+        Ok(res?)
+    }
+}
@@ -0,0 +1,108 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use eyre::{Context, Result};
+use slint::{Rgba8Pixel, SharedPixelBuffer};
+use tracing::error;
+
+const IMAGE_EXTENSIONS: [&str; 8] = ["png", "jpg", "jpeg", "gif", "bmp", "webp", "ico", "tiff"];
+/// Decoding huge images takes too long to be useful for a preview
+const MAX_IMAGE_FILE_SIZE: u64 = 50 * 1024 * 1024;
+/// Images are scaled down to fit into a square of this size
+const MAX_IMAGE_DIMENSION: u32 = 512;
+const MAX_TEXT_FILE_SIZE: u64 = 1024 * 1024;
+const MAX_TEXT_PREVIEW_BYTES: u64 = 16 * 1024;
+
+/// Content preview of a file.
+pub enum Preview {
+    Image(SharedPixelBuffer<Rgba8Pixel>),
+    Text(String),
+    /// The file is not an image or a small text file
+    Unsupported,
+}
+
+/// Loads previews on a background thread. Only the most recently requested preview is loaded, older
+/// requests are skipped.
+pub struct PreviewLoader {
+    requests: Sender<String>,
+}
+
+impl PreviewLoader {
+    /// `on_loaded` is called on the UI thread with the path and preview of each loaded file.
+    pub fn new(on_loaded: impl Fn(String, Preview) + Send + Clone + 'static) -> Self {
+        let (requests, receiver) = channel();
+        std::thread::spawn(move || load_previews(receiver, on_loaded));
+
+        Self { requests }
+    }
+
+    pub fn request(&self, path: String) {
+        let _ = self.requests.send(path);
+    }
+}
+
+fn load_previews(
+    receiver: Receiver<String>,
+    on_loaded: impl Fn(String, Preview) + Send + Clone + 'static,
+) {
+    while let Ok(path) = receiver.recv() {
+        // Skip everything that was selected while the last preview was loading
+        let path = receiver.try_iter().last().unwrap_or(path);
+        let preview = load_preview(&path).unwrap_or_else(|e| {
+            error!("{:?}", e);
+            Preview::Unsupported
+        });
+
+        let on_loaded = on_loaded.clone();
+        if slint::invoke_from_event_loop(move || on_loaded(path, preview)).is_err() {
+            return;
+        }
+    }
+}
+
+fn load_preview(path: &str) -> Result<Preview> {
+    let size = std::fs::metadata(path)
+        .with_context(|| format!("Failed to read metadata of '{}'", path))?
+        .len();
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    if IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+        if size > MAX_IMAGE_FILE_SIZE {
+            return Ok(Preview::Unsupported);
+        }
+
+        let mut image =
+            image::open(path).with_context(|| format!("Failed to decode image '{}'", path))?;
+        if image.width() > MAX_IMAGE_DIMENSION || image.height() > MAX_IMAGE_DIMENSION {
+            image = image.thumbnail(MAX_IMAGE_DIMENSION, MAX_IMAGE_DIMENSION);
+        }
+
+        let image = image.into_rgba8();
+        return Ok(Preview::Image(SharedPixelBuffer::clone_from_slice(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+        )));
+    }
+
+    if size > MAX_TEXT_FILE_SIZE {
+        return Ok(Preview::Unsupported);
+    }
+
+    let mut buffer = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(MAX_TEXT_PREVIEW_BYTES).read_to_end(&mut buffer))
+        .with_context(|| format!("Failed to read '{}'", path))?;
+    // Binary files almost always contain null bytes, text files practically never
+    if buffer.contains(&0) {
+        return Ok(Preview::Unsupported);
+    }
+
+    Ok(Preview::Text(String::from_utf8_lossy(&buffer).into_owned()))
+}
@@ -0,0 +1,722 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+use eyre::{eyre, Context, ContextCompat, Result};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::filetime;
+use crate::glob::{Glob, PathGlob};
+use crate::ntfs::index::{FileInfo, FileTimes, NtfsVolumeIndex};
+use crate::{owner, recycle_bin};
+
+/// A parsed search query. Whitespace separated tokens in the form `<filter>:<value>` are parsed
+/// into filters, the remaining text is matched against the file name, or the full path if
+/// [SearchOptions::match_path] is set. Text with `*` or `?` is a wildcard pattern instead, which
+/// has to match the whole name, or the last components of the path if it contains backslashes,
+/// see [PathGlob]. Double quotes keep text with spaces together, e.g. `path:"C:\My files"`.
+///
+/// Supported filters:
+/// - `ext:mp4;mkv` matches files with one of the given extensions
+/// - `size:>1gb`, `size:<=10kb`, `size:512` matches files by size. Directories never match, so
+///   `size:0` finds empty files
+/// - `empty:` matches directories without anything inside of them
+/// - `dm:`, `dc:` and `da:` match files by their modification, creation or access date. Accepts
+///   dates like `2024-01-31` with an optional comparison, `today`, `yesterday` and relative ranges
+///   like `last7days`, `last2weeks` or `last12hours`
+/// - `in:recyclebin` only matches deleted files in the recycle bin, even if
+///   [SearchOptions::hide_recycle_bin] is set
+/// - `owner:alice` or `owner:DOMAIN\alice` matches files owned by the account, see [owner::owner]
+/// - `path:C:\Users` only matches files inside the folder, see [Query::resolve]
+#[derive(Debug, Default)]
+pub struct Query {
+    text: String,
+    /// Set if the text is a wildcard pattern
+    glob: Option<PathGlob>,
+    filters: Vec<Filter>,
+    options: SearchOptions,
+}
+
+/// Options which change how a query is matched, toggled in the UI.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SearchOptions {
+    /// Only match text which is not surrounded by other letters or digits
+    pub whole_word: bool,
+    /// Match against the full path instead of only the file name
+    pub match_path: bool,
+    /// Ignore case and accents, so that `resume` finds `Résumé.docx`
+    pub fold: bool,
+    /// Skip deleted files in the recycle bin, unless the query asks for them with `in:recyclebin`
+    pub hide_recycle_bin: bool,
+    /// Skip NTFS metafiles and empty system files, see [FileInfo::is_system_noise]
+    pub hide_metafiles: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum Filter {
+    /// Lowercase extensions without the leading dot
+    Extension(Vec<String>),
+    /// Only matches files, as directories have no size of their own
+    Size(Comparison, u64),
+    /// Directories without children
+    Empty,
+    /// Half-open range of FILETIMEs
+    Date(DateField, Range<u64>),
+    /// Files in the recycle bin, see [recycle_bin::is_recycled]
+    InRecycleBin,
+    /// Account name, with or without the domain
+    Owner(String),
+    Path(Scope),
+}
+
+/// A folder whose contents a query is limited to with `path:`.
+#[derive(Debug, PartialEq)]
+struct Scope {
+    path: String,
+    /// MFT index of the folder, `None` until the query was resolved and `Some(None)` if there is
+    /// no such folder
+    folder: Option<Option<u64>>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum DateField {
+    Created,
+    Modified,
+    Accessed,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Query {
+    pub fn parse(input: &str, options: SearchOptions) -> Result<Self> {
+        let mut filters = Vec::new();
+        let mut text = Vec::new();
+
+        for token in tokens(input) {
+            match token.split_once(':') {
+                Some(("ext", value)) => filters.push(Filter::Extension(
+                    value
+                        .split(';')
+                        .filter(|s| !s.is_empty())
+                        .map(|s| s.trim_start_matches('.').to_lowercase())
+                        .collect(),
+                )),
+                Some(("size", value)) => {
+                    let (comparison, value) = Comparison::parse(value);
+                    filters.push(Filter::Size(comparison, parse_size(value)?));
+                }
+                Some(("dc", value)) => {
+                    filters.push(Filter::Date(DateField::Created, parse_date_range(value)?))
+                }
+                Some(("dm", value)) => {
+                    filters.push(Filter::Date(DateField::Modified, parse_date_range(value)?))
+                }
+                Some(("da", value)) => {
+                    filters.push(Filter::Date(DateField::Accessed, parse_date_range(value)?))
+                }
+                Some(("empty", "")) => filters.push(Filter::Empty),
+                Some(("in", value)) if value.eq_ignore_ascii_case("recyclebin") => {
+                    filters.push(Filter::InRecycleBin)
+                }
+                Some(("owner", value)) if !value.is_empty() => {
+                    filters.push(Filter::Owner(value.to_string()))
+                }
+                Some(("path", value)) if !value.is_empty() => filters.push(Filter::Path(Scope {
+                    path: value.replace('/', "\\"),
+                    folder: None,
+                })),
+                _ => text.push(token),
+            }
+        }
+
+        // Paths computed by the index always use backslashes
+        let text = text.join(" ").replace('/', "\\");
+        let text = if options.fold {
+            fold(&text).into_owned()
+        } else {
+            text
+        };
+        Ok(Self {
+            glob: Glob::is_pattern(&text).then(|| PathGlob::new(&text)),
+            text,
+            filters,
+            options,
+        })
+    }
+
+    /// Whether the entry `info` at `mft_index` matches the query. `owner:` filters are left out,
+    /// see [Query::matches_owner].
+    pub fn matches(&self, ntfs_index: &NtfsVolumeIndex, mft_index: u64, info: &FileInfo) -> bool {
+        // The path of quarantined files is unknown
+        if info.is_quarantined() || (self.options.hide_metafiles && info.is_system_noise()) {
+            return false;
+        }
+        if !self
+            .filters
+            .iter()
+            .filter(|filter| !filter.is_expensive())
+            .all(|filter| filter.matches(ntfs_index, mft_index, info))
+        {
+            return false;
+        }
+        let text_matches = if let Some(glob) = &self.glob {
+            self.matches_glob(glob, ntfs_index, info)
+        } else if self.text.is_empty() {
+            true
+        } else if self.options.match_path {
+            self.matches_text(&ntfs_index.compute_full_path(info))
+        } else {
+            self.matches_text(&info.name)
+        };
+
+        // Checked last, as they have to walk up to the root
+        text_matches
+            && self
+                .filters
+                .iter()
+                .filter(|filter| filter.is_expensive())
+                .all(|filter| filter.matches(ntfs_index, mft_index, info))
+            && !(self.options.hide_recycle_bin
+                && !self.filters.contains(&Filter::InRecycleBin)
+                && recycle_bin::is_recycled(ntfs_index, info))
+    }
+
+    /// Whether every file matching this query also matches `previous`, so that only the results of
+    /// `previous` have to be checked. Holds if this query contains the text of `previous` and a
+    /// filter at least as narrow as each of its filters, see [Filter::narrows]. Whole words can't be narrowed down like this, as `foo` doesn't match `foob`.
+    /// Adding `in:recyclebin` can't either if the recycle bin is hidden, as it shows more files.
+    /// Neither can wildcard patterns, as `*.log` doesn't match `a.logs`.
+    pub fn narrows(&self, previous: &Query) -> bool {
+        self.options == previous.options
+            && !self.options.whole_word
+            && (self.glob.is_none() && previous.glob.is_none() || self.text == previous.text)
+            && (!self.options.hide_recycle_bin
+                || self.filters.contains(&Filter::InRecycleBin)
+                    == previous.filters.contains(&Filter::InRecycleBin))
+            && self.text.contains(previous.text.as_str())
+            && previous
+                .filters
+                .iter()
+                .all(|previous| self.filters.iter().any(|filter| filter.narrows(previous)))
+    }
+
+    /// Whether the query filters by date. Dates like `today` or `last2hours` are relative to the
+    /// time the query was parsed, so its results go stale even if no file changes.
+    pub fn has_date_filter(&self) -> bool {
+        self.filters
+            .iter()
+            .any(|filter| matches!(filter, Filter::Date(..)))
+    }
+
+    /// Whether the query contains an `owner:` filter, whose files have to be checked with
+    /// [Query::matches_owner] as well
+    pub fn has_owner_filter(&self) -> bool {
+        self.filters
+            .iter()
+            .any(|filter| matches!(filter, Filter::Owner(_)))
+    }
+
+    /// Whether the file at `path` belongs to the accounts of all `owner:` filters. Owners are read
+    /// from disk, so this is meant to be called without holding the lock of the index.
+    pub fn matches_owner(&self, path: &str) -> bool {
+        self.filters.iter().all(|filter| match filter {
+            Filter::Owner(name) => {
+                owner::owner(path).is_some_and(|owner| owner::is_owned_by(&owner, name))
+            }
+            _ => true,
+        })
+    }
+
+    /// Whether the query only finds empty folders, because it contains `empty:`
+    pub fn finds_empty_folders(&self) -> bool {
+        self.filters.contains(&Filter::Empty)
+    }
+
+    /// Looks up the folders of `path:` filters in `ntfs_index`. Files are then matched by walking up
+    /// their parents until reaching the folder, instead of looking it up for every file. The
+    /// results are only valid as long as the index doesn't change.
+    pub fn resolve(&mut self, ntfs_index: &NtfsVolumeIndex) {
+        for filter in &mut self.filters {
+            if let Filter::Path(scope) = filter {
+                scope.folder = Some(ntfs_index.find_directory(&scope.path));
+            }
+        }
+    }
+
+    /// Narrows the files which can match down using the trigram index, if the index has one, or to
+    /// the contents of the folder the query is limited to. Returns `None` if every file has to be
+    /// checked.
+    pub fn candidates(&self, ntfs_index: &NtfsVolumeIndex) -> Option<Vec<u64>> {
+        // Paths can also match through the names of the parent directories
+        let trigrams = if self.options.match_path || self.text.is_empty() {
+            None
+        } else {
+            match &self.glob {
+                Some(glob) => ntfs_index.trigram_candidates(&glob.name().longest_literal()),
+                None => ntfs_index.trigram_candidates(&self.text),
+            }
+        };
+
+        trigrams.or_else(|| {
+            self.filters.iter().find_map(|filter| match filter {
+                Filter::Path(scope) => Some(match scope.folder? {
+                    Some(folder) => ntfs_index.descendants(folder),
+                    None => Vec::new(),
+                }),
+                _ => None,
+            })
+        })
+    }
+
+    fn matches_glob(&self, glob: &PathGlob, ntfs_index: &NtfsVolumeIndex, info: &FileInfo) -> bool {
+        let fold = |haystack| match self.options.fold {
+            true => fold(haystack),
+            false => Cow::Borrowed(haystack),
+        };
+        if glob.is_name_only() {
+            return glob.matches(&fold(&info.name));
+        }
+
+        glob.matches(&fold(&ntfs_index.compute_full_path(info)))
+    }
+
+    fn matches_text(&self, haystack: &str) -> bool {
+        let haystack = if self.options.fold {
+            fold(haystack)
+        } else {
+            Cow::Borrowed(haystack)
+        };
+
+        if !self.options.whole_word {
+            return haystack.contains(self.text.as_str());
+        }
+
+        let is_word_char = |c: Option<char>| c.is_some_and(char::is_alphanumeric);
+        haystack
+            .match_indices(self.text.as_str())
+            .any(|(start, m)| {
+                !is_word_char(haystack[..start].chars().next_back())
+                    && !is_word_char(haystack[start + m.len()..].chars().next())
+            })
+    }
+}
+
+impl Filter {
+    fn matches(&self, ntfs_index: &NtfsVolumeIndex, mft_index: u64, info: &FileInfo) -> bool {
+        match self {
+            Filter::Extension(extensions) => info.extension().is_some_and(|extension| {
+                extensions.iter().any(|e| e.eq_ignore_ascii_case(extension))
+            }),
+            Filter::Size(comparison, size) => {
+                !info.is_directory() && comparison.compare(info.size(), *size)
+            }
+            Filter::Empty => ntfs_index.is_empty_directory(mft_index),
+            Filter::Date(field, range) => range.contains(&field.get(info.times())),
+            Filter::InRecycleBin => recycle_bin::is_recycled(ntfs_index, info),
+            // Checked separately, see [Query::matches_owner]
+            Filter::Owner(_) => true,
+            Filter::Path(scope) => scope
+                .folder
+                .unwrap_or_else(|| ntfs_index.find_directory(&scope.path))
+                .is_some_and(|folder| ntfs_index.is_inside(mft_index, folder)),
+        }
+    }
+
+    /// Whether every file matching this filter also matches `previous`. Relative dates like
+    /// `last2hours` end up with a different range every time they are parsed, so dates only have
+    /// to lie within the range of `previous`.
+    fn narrows(&self, previous: &Filter) -> bool {
+        match (self, previous) {
+            (Filter::Date(field, range), Filter::Date(previous_field, previous_range)) => {
+                field == previous_field
+                    && previous_range.start <= range.start
+                    && range.end <= previous_range.end
+            }
+            _ => self == previous,
+        }
+    }
+
+    /// Whether the filter is too slow to be checked before the text
+    fn is_expensive(&self) -> bool {
+        matches!(self, Filter::Path(_))
+    }
+}
+
+impl DateField {
+    fn get(self, times: FileTimes) -> u64 {
+        match self {
+            DateField::Created => times.created,
+            DateField::Modified => times.modified,
+            DateField::Accessed => times.accessed,
+        }
+    }
+}
+
+impl Comparison {
+    /// Splits the comparison operator off the front of `value`. Defaults to `Equal`.
+    fn parse(value: &str) -> (Self, &str) {
+        [
+            ("<=", Comparison::LessOrEqual),
+            (">=", Comparison::GreaterOrEqual),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater),
+            ("=", Comparison::Equal),
+        ]
+        .into_iter()
+        .find_map(|(prefix, comparison)| value.strip_prefix(prefix).map(|v| (comparison, v)))
+        .unwrap_or((Comparison::Equal, value))
+    }
+
+    fn compare(self, left: u64, right: u64) -> bool {
+        match self {
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Equal => left == right,
+            Comparison::GreaterOrEqual => left >= right,
+            Comparison::Greater => left > right,
+        }
+    }
+}
+
+/// Returns the folder the search `input` is limited to with `path:`, if any.
+pub fn scope(input: &str) -> Option<String> {
+    tokens(input).into_iter().find_map(|token| {
+        token
+            .strip_prefix("path:")
+            .filter(|path| !path.is_empty())
+            .map(|path| path.replace('/', "\\"))
+    })
+}
+
+/// Replaces the `path:` filters of the search `input` with one for `folder`, or removes them if
+/// `folder` is `None`. The rest of the input is kept.
+pub fn with_scope(input: &str, folder: Option<&str>) -> String {
+    let rest = tokens(input)
+        .into_iter()
+        .filter(|token| !token.starts_with("path:"))
+        .map(|token| quote(&token))
+        .collect::<Vec<_>>()
+        .join(" ");
+    match folder {
+        // Ends with a space, so that the search text can be typed right after it
+        Some(folder) => format!("{} {}", quote(&format!("path:{}", folder)), rest),
+        None => rest,
+    }
+}
+
+/// Quotes the value of a filter, or the whole token if it is text, if it contains whitespace.
+/// Reverses [tokens].
+fn quote(token: &str) -> String {
+    if !token.contains(char::is_whitespace) {
+        return token.to_string();
+    }
+    match token.split_once(':') {
+        Some((filter, value)) if !filter.contains(char::is_whitespace) => {
+            format!("{}:\"{}\"", filter, value)
+        }
+        _ => format!("\"{}\"", token),
+    }
+}
+
+/// Splits the input at whitespace outside of double quotes and removes the quotes. Names can't
+/// contain quotes, so they are never meant literally.
+fn tokens(input: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in input.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// Decomposes the text (NFKD), removes all combining marks and lowercases the rest. Most names are
+/// plain lowercase ASCII already, these are returned as is to avoid allocating for every file.
+pub fn fold(text: &str) -> Cow<'_, str> {
+    if text
+        .bytes()
+        .all(|b| b.is_ascii() && !b.is_ascii_uppercase())
+    {
+        return Cow::Borrowed(text);
+    }
+
+    Cow::Owned(
+        text.nfkd()
+            .filter(|&c| !is_combining_mark(c))
+            .flat_map(char::to_lowercase)
+            .collect(),
+    )
+}
+
+/// Parses the value of a date filter into a range of FILETIMEs.
+fn parse_date_range(value: &str) -> Result<Range<u64>> {
+    let value = value.to_ascii_lowercase();
+    let (year, month, day) = filetime::today();
+    let today = filetime::local_midnight(year, month, day)?;
+
+    match value.as_str() {
+        "today" => return Ok(today..u64::MAX),
+        "yesterday" => return Ok(today - filetime::DAY..today),
+        _ => {}
+    }
+
+    if let Some(relative) = value.strip_prefix("last") {
+        let split = relative
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(relative.len());
+        let (count, unit) = relative.split_at(split);
+
+        let count = count
+            .parse::<u64>()
+            .with_context(|| format!("Invalid relative date '{}'", value))?;
+        let unit = match unit {
+            "hour" | "hours" => filetime::HOUR,
+            "day" | "days" => filetime::DAY,
+            "week" | "weeks" => 7 * filetime::DAY,
+            _ => return Err(eyre!("Unknown time unit '{}'", unit)),
+        };
+
+        let duration = count
+            .checked_mul(unit)
+            .with_context(|| format!("Invalid relative date '{}'", value))?;
+        return Ok(filetime::now().saturating_sub(duration)..u64::MAX);
+    }
+
+    let (comparison, date) = Comparison::parse(&value);
+    let mut parts = date.splitn(3, '-').map(|part| {
+        part.parse::<u16>()
+            .with_context(|| format!("Invalid date '{}', expected YYYY-MM-DD", date))
+    });
+    let (Some(year), Some(month), Some(day)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(eyre!("Invalid date '{}', expected YYYY-MM-DD", date));
+    };
+
+    let start = filetime::local_midnight(year?, month?, day?)?;
+    let end = start + filetime::DAY;
+    Ok(match comparison {
+        Comparison::Less => 0..start,
+        Comparison::LessOrEqual => 0..end,
+        Comparison::Equal => start..end,
+        Comparison::GreaterOrEqual => start..u64::MAX,
+        Comparison::Greater => end..u64::MAX,
+    })
+}
+
+/// Parses sizes like `512`, `10kb` or `1.5gb`. Units are powers of 1024.
+fn parse_size(value: &str) -> Result<u64> {
+    let value = value.to_ascii_lowercase();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let multiplier: u64 = match unit {
+        "" | "b" => 1,
+        "kb" | "k" => 1 << 10,
+        "mb" | "m" => 1 << 20,
+        "gb" | "g" => 1 << 30,
+        "tb" | "t" => 1 << 40,
+        _ => return Err(eyre!("Unknown size unit '{}'", unit)),
+    };
+    let number = number
+        .parse::<f64>()
+        .with_context(|| format!("Invalid size '{}'", value))?;
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Query {
+        Query::parse(input, SearchOptions::default()).unwrap()
+    }
+
+    fn midnight(year: u16, month: u16, day: u16) -> u64 {
+        filetime::local_midnight(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn sizes_use_powers_of_1024() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("512b").unwrap(), 512);
+        assert_eq!(parse_size("10kb").unwrap(), 10 * 1024);
+        assert_eq!(parse_size("10K").unwrap(), 10 * 1024);
+        assert_eq!(parse_size("1.5gb").unwrap(), 3 << 29);
+        assert_eq!(parse_size("2m").unwrap(), 2 << 20);
+        assert_eq!(parse_size("1TB").unwrap(), 1 << 40);
+        assert!(parse_size("10xb").is_err());
+        assert!(parse_size("kb").is_err());
+        assert!(parse_size("1.2.3mb").is_err());
+    }
+
+    #[test]
+    fn size_filters_take_a_comparison() {
+        assert_eq!(
+            parse("size:>=1kb").filters,
+            vec![Filter::Size(Comparison::GreaterOrEqual, 1024)]
+        );
+        assert_eq!(
+            parse("size:<10").filters,
+            vec![Filter::Size(Comparison::Less, 10)]
+        );
+        assert_eq!(
+            parse("size:0").filters,
+            vec![Filter::Size(Comparison::Equal, 0)]
+        );
+        assert!(Query::parse("size:>lots", SearchOptions::default()).is_err());
+    }
+
+    #[test]
+    fn dates_cover_whole_days() {
+        let start = midnight(2024, 1, 31);
+        let end = start + filetime::DAY;
+        assert_eq!(parse_date_range("2024-01-31").unwrap(), start..end);
+        assert_eq!(parse_date_range("<2024-01-31").unwrap(), 0..start);
+        assert_eq!(parse_date_range("<=2024-01-31").unwrap(), 0..end);
+        assert_eq!(parse_date_range(">=2024-01-31").unwrap(), start..u64::MAX);
+        assert_eq!(parse_date_range(">2024-01-31").unwrap(), end..u64::MAX);
+        assert!(parse_date_range("2024-01").is_err());
+        assert!(parse_date_range("2024-01-xx").is_err());
+        assert!(parse_date_range("31.01.2024").is_err());
+    }
+
+    #[test]
+    fn relative_dates_end_now() {
+        let (year, month, day) = filetime::today();
+        let today = midnight(year, month, day);
+        assert_eq!(parse_date_range("today").unwrap(), today..u64::MAX);
+        assert_eq!(
+            parse_date_range("Yesterday").unwrap(),
+            today - filetime::DAY..today
+        );
+
+        let before = filetime::now();
+        let range = parse_date_range("last2days").unwrap();
+        assert_eq!(range.end, u64::MAX);
+        assert!(range.start >= before - 2 * filetime::DAY);
+        assert!(range.start <= filetime::now() - 2 * filetime::DAY);
+
+        let hours = parse_date_range("last12hours").unwrap();
+        let weeks = parse_date_range("last1week").unwrap();
+        assert!(weeks.start < hours.start);
+        assert!(parse_date_range("last2fortnights").is_err());
+        assert!(parse_date_range("lastdays").is_err());
+    }
+
+    #[test]
+    fn quotes_keep_whitespace_together() {
+        assert_eq!(
+            tokens(r#"path:"C:\My files" report  2024"#),
+            vec![r"path:C:\My files", "report", "2024"]
+        );
+        assert_eq!(tokens(r#""annual report".pdf"#), vec!["annual report.pdf"]);
+        assert!(tokens("   ").is_empty());
+        assert_eq!(parse(r#""annual report""#).text, "annual report");
+    }
+
+    #[test]
+    fn quoting_reverses_tokens() {
+        assert_eq!(quote("report"), "report");
+        assert_eq!(quote(r"path:C:\My files"), r#"path:"C:\My files""#);
+        assert_eq!(quote("annual report"), r#""annual report""#);
+        for input in [
+            r#"path:"C:\My files" ext:pdf"#,
+            r#""annual report" size:>1mb"#,
+        ] {
+            let quoted = tokens(input)
+                .iter()
+                .map(|token| quote(token))
+                .collect::<Vec<_>>()
+                .join(" ");
+            assert_eq!(tokens(&quoted), tokens(input));
+        }
+    }
+
+    #[test]
+    fn scope_is_replaced_and_keeps_the_rest() {
+        assert_eq!(
+            scope(r#"report path:"C:/My files""#).unwrap(),
+            r"C:\My files"
+        );
+        assert_eq!(scope("report path:"), None);
+        assert_eq!(
+            with_scope("report path:C:\\Users ext:pdf", Some(r"D:\My files")),
+            r#"path:"D:\My files" report ext:pdf"#
+        );
+        assert_eq!(
+            with_scope(r#"path:"C:\My files" "annual report""#, None),
+            r#""annual report""#
+        );
+    }
+
+    #[test]
+    fn longer_text_and_more_filters_narrow() {
+        assert!(parse("report").narrows(&parse("rep")));
+        assert!(!parse("rep").narrows(&parse("report")));
+        assert!(parse("report ext:pdf").narrows(&parse("report")));
+        assert!(!parse("report").narrows(&parse("report ext:pdf")));
+        assert!(!parse("report ext:pdf").narrows(&parse("report ext:docx")));
+        assert!(parse("size:>1mb ext:pdf rep").narrows(&parse("ext:pdf size:>1mb")));
+        // Wildcard patterns have to match the whole name
+        assert!(!parse("*.log").narrows(&parse("*.lo")));
+        assert!(parse("*.log ext:log").narrows(&parse("*.log")));
+
+        let whole_word = SearchOptions {
+            whole_word: true,
+            ..SearchOptions::default()
+        };
+        let query = |input| Query::parse(input, whole_word).unwrap();
+        assert!(!query("report").narrows(&query("rep")));
+        assert!(!parse("report").narrows(&query("rep")));
+    }
+
+    #[test]
+    fn dates_narrow_if_they_lie_within_the_previous_range() {
+        // Relative dates are parsed again, so their ranges start a bit later
+        assert!(parse("dm:last2days report").narrows(&parse("dm:last2days")));
+        assert!(parse("dm:last2days").narrows(&parse("dm:last2days")));
+        assert!(parse("dm:today").narrows(&parse("dm:last2days")));
+        assert!(!parse("dm:last2days").narrows(&parse("dm:today")));
+        assert!(parse("dm:2024-01-31").narrows(&parse("dm:>=2024-01-01")));
+        assert!(!parse("dm:2023-12-31").narrows(&parse("dm:>=2024-01-01")));
+        assert!(!parse("dc:today").narrows(&parse("dm:last2days")));
+        assert!(!parse("report").narrows(&parse("dm:today")));
+        assert!(parse("dm:today dc:today").narrows(&parse("dm:last2days")));
+    }
+
+    #[test]
+    fn folding_ignores_case_and_accents() {
+        assert_eq!(fold("Résumé.DOCX"), "resume.docx");
+        assert!(matches!(fold("plain.txt"), Cow::Borrowed(_)));
+        assert_eq!(parse("Résumé").text, "Résumé");
+        let folded = Query::parse(
+            "Résumé",
+            SearchOptions {
+                fold: true,
+                ..SearchOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(folded.text, "resume");
+    }
+}
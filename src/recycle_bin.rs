@@ -0,0 +1,103 @@
+use eyre::{eyre, Context, Result};
+
+use crate::ntfs::index::{FileInfo, NtfsVolumeIndex};
+
+/// Hidden directory in the root of every volume which holds the deleted files of all users
+pub const RECYCLE_BIN_NAME: &str = "$Recycle.Bin";
+
+/// Size of the header of a `$I` file before the original path
+const HEADER_SIZE: usize = 24;
+/// Version 1 files, written before Windows 10, store the path in a fixed size buffer
+const V1_PATH_CHARS: usize = 260;
+
+/// A deleted file or directory, as described by the `$I` file next to it.
+///
+/// Deleting `C:\foo\bar.txt` moves it to `C:\$Recycle.Bin\<SID>\$R<random>.txt` and writes the
+/// original path, size and deletion time to `$I<random>.txt` in the same directory.
+#[derive(Debug, Clone)]
+pub struct RecycledItem {
+    pub original_path: String,
+    pub size: u64,
+    /// FILETIME at which the item was deleted
+    pub deleted: u64,
+}
+
+impl RecycledItem {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let read_u64 = |offset: usize| -> Result<u64> {
+            Ok(u64::from_le_bytes(
+                data.get(offset..offset + 8)
+                    .ok_or_else(|| eyre!("$I file is too short"))?
+                    .try_into()?,
+            ))
+        };
+
+        let path = match read_u64(0)? {
+            1 => data.get(HEADER_SIZE..HEADER_SIZE + V1_PATH_CHARS * 2),
+            2 => {
+                let length = u32::from_le_bytes(
+                    data.get(HEADER_SIZE..HEADER_SIZE + 4)
+                        .ok_or_else(|| eyre!("$I file is too short"))?
+                        .try_into()?,
+                ) as usize;
+                data.get(HEADER_SIZE + 4..HEADER_SIZE + 4 + length * 2)
+            }
+            version => return Err(eyre!("Unknown $I file version {}", version)),
+        }
+        .ok_or_else(|| eyre!("$I file is too short"))?;
+
+        let path = path
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0)
+            .collect::<Vec<_>>();
+
+        Ok(Self {
+            original_path: String::from_utf16_lossy(&path),
+            size: read_u64(8)?,
+            deleted: read_u64(16)?,
+        })
+    }
+}
+
+/// Returns the path which `path` had before it was deleted, read from the matching `$I` file.
+/// Returns `None` if `path` is not inside the recycle bin. For files inside a deleted directory,
+/// this is the path they had before the directory was deleted.
+pub fn original_path(path: &str) -> Result<Option<String>> {
+    // C:\$Recycle.Bin\<SID>\$R<random>[\<rest>]
+    let mut components = path.splitn(5, '\\');
+    let (Some(volume), Some(recycle_bin), Some(sid), Some(name)) = (
+        components.next(),
+        components.next(),
+        components.next(),
+        components.next(),
+    ) else {
+        return Ok(None);
+    };
+    if !recycle_bin.eq_ignore_ascii_case(RECYCLE_BIN_NAME) {
+        return Ok(None);
+    }
+    let Some(suffix) = name.strip_prefix("$R") else {
+        return Ok(None);
+    };
+
+    let info_path = format!("{}\\{}\\{}\\$I{}", volume, recycle_bin, sid, suffix);
+    let data =
+        std::fs::read(&info_path).with_context(|| format!("Failed to read '{}'", info_path))?;
+    let item =
+        RecycledItem::parse(&data).with_context(|| format!("Failed to parse '{}'", info_path))?;
+
+    Ok(Some(match components.next() {
+        Some(rest) => format!("{}\\{}", item.original_path, rest),
+        None => item.original_path,
+    }))
+}
+
+/// Whether the file is the recycle bin itself or anywhere inside of it.
+pub fn is_recycled(ntfs_index: &NtfsVolumeIndex, info: &FileInfo) -> bool {
+    // The last entry is the one in the root of the volume
+    ntfs_index
+        .iter_with_parents(info)
+        .last()
+        .is_some_and(|top| top.name.eq_ignore_ascii_case(RECYCLE_BIN_NAME))
+}
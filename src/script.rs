@@ -0,0 +1,87 @@
+//! Runs [Rhai](https://rhai.rs) scripts against the index of the running service, for reports
+//! which the other commands don't cover. Scripts can only query the service and print, writing
+//! files has to be allowed with `--allow-write`.
+//!
+//! Available to scripts:
+//! - `search(query)` returns the results as maps with `id`, `path`, `name`, `extension` and
+//!   `size`
+//! - `info()` returns the volume information printed by `reverything info` as a map
+//! - `format_size(bytes)` formats a size like the UI, e.g. `1.2 MB`
+//! - `write_file(path, text)` replaces the content of a file, only with `--allow-write`
+//! - `ARGS` holds the arguments given after the script
+
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use eyre::{eyre, Context, Result};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope};
+
+use crate::config::SizeFormat;
+use crate::format;
+use crate::ipc::{Client, SearchResult};
+
+type ScriptResult<T> = std::result::Result<T, Box<EvalAltResult>>;
+
+pub fn run(path: &Path, args: &[String], allow_write: bool) -> Result<()> {
+    let script = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read script {}", path.display()))?;
+    let client = Rc::new(RefCell::new(Client::connect()?));
+
+    let mut engine = Engine::new();
+    engine.register_fn("search", {
+        let client = client.clone();
+        move |query: &str| -> ScriptResult<Array> {
+            let results = client
+                .borrow_mut()
+                .search(query)
+                .map_err(|e| format!("{:#}", e))?;
+            Ok(results.into_iter().map(result_to_map).collect())
+        }
+    });
+    engine.register_fn("info", move || -> ScriptResult<Map> {
+        let info = client.borrow_mut().info().map_err(|e| format!("{:#}", e))?;
+        Ok(info
+            .into_iter()
+            .map(|(label, value)| (label.into(), value.into()))
+            .collect())
+    });
+    engine.register_fn("format_size", |size: i64| {
+        format::format_size(size.max(0) as u64, SizeFormat::HumanReadable)
+    });
+    if allow_write {
+        engine.register_fn("write_file", |path: &str, text: &str| -> ScriptResult<()> {
+            std::fs::write(path, text)
+                .map_err(|e| format!("Failed to write {}: {}", path, e).into())
+        });
+    }
+
+    let mut scope = Scope::new();
+    let args = args.iter().cloned().map(Dynamic::from).collect::<Array>();
+    scope.push_constant("ARGS", args);
+
+    // Errors of Rhai can't be sent between threads, which eyre requires
+    engine
+        .run_with_scope(&mut scope, &script)
+        .map_err(|e| eyre!("Script {} failed: {}", path.display(), e))
+}
+
+fn result_to_map(result: SearchResult) -> Dynamic {
+    let name = result
+        .path
+        .rsplit_once('\\')
+        .map_or(result.path.as_str(), |(_, name)| name)
+        .to_string();
+    let extension = name
+        .rsplit_once('.')
+        .map_or("", |(_, extension)| extension)
+        .to_lowercase();
+
+    let mut map = Map::new();
+    map.insert("id".into(), result.id.to_string().into());
+    map.insert("path".into(), result.path.into());
+    map.insert("name".into(), name.into());
+    map.insert("extension".into(), extension.into());
+    map.insert("size".into(), (result.size as i64).into());
+    map.into()
+}
@@ -0,0 +1,319 @@
+use crate::config::Column;
+use crate::metrics::{self, FilterMetrics};
+use crate::ntfs::index::{FileId, FileInfo, NtfsVolumeIndex};
+use crate::owner;
+use crate::query::{Query, SearchOptions};
+use eyre::{eyre, Result};
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+use std::cmp::Ordering;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+use tracing::debug;
+
+/// Number of previous results kept by [RefinementCache]
+const MAX_CACHED_RESULTS: usize = 8;
+/// Most files whose owner is read for a single search or sort. Every owner is read from disk, so
+/// `owner:` has to be combined with text or other filters which narrow the results down first.
+const MAX_OWNER_LOOKUPS: usize = 50_000;
+
+/// A result with everything that is shown about it, which stays usable once the index is unlocked
+/// or when it was received from the service.
+#[derive(Debug, Clone)]
+pub struct ResultEntry {
+    pub id: FileId,
+    pub path: String,
+    pub info: FileInfo,
+}
+
+impl ResultEntry {
+    pub fn new(ntfs_index: &NtfsVolumeIndex, mft_index: u64) -> Option<Self> {
+        let info = ntfs_index.find_by_index(mft_index)?;
+        Some(Self {
+            id: ntfs_index.file_id(mft_index)?,
+            path: ntfs_index.compute_full_path(info),
+            info: info.clone(),
+        })
+    }
+}
+
+/// Returns the MFT indices of all files matching the given search query. The index is only locked
+/// while it is read, and unlocked while the owners of `owner:` filters are looked up.
+pub fn filter(
+    index: &Mutex<NtfsVolumeIndex>,
+    search: &str,
+    options: SearchOptions,
+) -> Result<Vec<u64>> {
+    let start = Instant::now();
+    let mut query = Query::parse(search, options)?;
+    let ntfs_index = index.lock().unwrap();
+    query.resolve(&ntfs_index);
+    let (files, scanned) = filter_query(&ntfs_index, &query);
+    let files = filter_owners(ntfs_index, &query, files)?;
+    record(start, scanned, &files);
+    Ok(files)
+}
+
+/// Keeps the files among `files` which match the `owner:` filters of the query, in the same order.
+/// The lock of the index is released before the owners are read from disk.
+fn filter_owners(
+    ntfs_index: MutexGuard<NtfsVolumeIndex>,
+    query: &Query,
+    files: Vec<u64>,
+) -> Result<Vec<u64>> {
+    if !query.has_owner_filter() {
+        return Ok(files);
+    }
+    if files.len() > MAX_OWNER_LOOKUPS {
+        return Err(eyre!(
+            "owner: would have to check {} files, narrow the search down first",
+            files.len()
+        ));
+    }
+
+    let paths = full_paths(&ntfs_index, &files);
+    drop(ntfs_index);
+    Ok(paths
+        .into_par_iter()
+        .filter(|(_, path)| query.matches_owner(path))
+        .map(|(i, _)| i)
+        .collect())
+}
+
+fn full_paths(ntfs_index: &NtfsVolumeIndex, files: &[u64]) -> Vec<(u64, String)> {
+    files
+        .iter()
+        .filter_map(|&i| {
+            Some((
+                i,
+                ntfs_index.compute_full_path(ntfs_index.find_by_index(i)?),
+            ))
+        })
+        .collect()
+}
+
+/// Returns the matching MFT indices and the number of entries which were checked.
+fn filter_query(ntfs_index: &NtfsVolumeIndex, query: &Query) -> (Vec<u64>, usize) {
+    let (mut files, scanned) = match query.candidates(ntfs_index) {
+        Some(candidates) => (
+            filter_candidates(ntfs_index, query, &candidates),
+            candidates.len(),
+        ),
+        None => (
+            ntfs_index
+                .par_iter()
+                .enumerate()
+                .map(|(i, info)| (i as u64, info))
+                .filter(
+                    |&(i, info)| matches!(info, Some(info) if query.matches(ntfs_index, i, info)),
+                )
+                .map(|(i, _)| i)
+                .collect(),
+            ntfs_index.real_file_count(),
+        ),
+    };
+
+    // Network locations are not part of the trigram index, but small enough to always be checked
+    files.par_extend(
+        ntfs_index
+            .par_iter_network()
+            .filter(|&(i, info)| query.matches(ntfs_index, i, info))
+            .map(|(i, _)| i),
+    );
+    (files, scanned)
+}
+
+fn record(start: Instant, scanned: usize, files: &[u64]) -> FilterMetrics {
+    let filter = FilterMetrics {
+        duration: start.elapsed(),
+        scanned,
+        matched: files.len(),
+    };
+    metrics::record_filter(filter);
+    filter
+}
+
+/// Returns the MFT indices among `candidates` which match the query, in the same order.
+fn filter_candidates(ntfs_index: &NtfsVolumeIndex, query: &Query, candidates: &[u64]) -> Vec<u64> {
+    candidates
+        .par_iter()
+        .copied()
+        .filter(|&i| {
+            matches!(ntfs_index.find_by_index(i), Some(info) if query.matches(ntfs_index, i, info))
+        })
+        .collect()
+}
+
+/// Remembers the results of recent queries. While typing, every query usually narrows the previous
+/// one down, so only its results have to be checked instead of the whole index. Deleting
+/// characters returns to a query which is still cached. Results are dropped as soon as the index
+/// changes.
+#[derive(Default)]
+pub struct RefinementCache {
+    /// Least recently used first
+    entries: Vec<CachedResults>,
+    last_metrics: Option<FilterMetrics>,
+}
+
+struct CachedResults {
+    search: String,
+    options: SearchOptions,
+    query: Query,
+    generation: u64,
+    files: Vec<u64>,
+}
+
+impl RefinementCache {
+    /// Same as [filter], but reuses the cached results where possible.
+    pub fn filter(
+        &mut self,
+        index: &Mutex<NtfsVolumeIndex>,
+        search: &str,
+        options: SearchOptions,
+    ) -> Result<Vec<u64>> {
+        let start = Instant::now();
+        let ntfs_index = index.lock().unwrap();
+        let generation = ntfs_index.generation();
+        self.entries.retain(|entry| entry.generation == generation);
+
+        if let Some(pos) = self.entries.iter().position(|entry| {
+            entry.search == search && entry.options == options && !entry.query.has_date_filter()
+        }) {
+            let entry = self.entries.remove(pos);
+            let files = entry.files.clone();
+            self.entries.push(entry);
+            return Ok(files);
+        }
+
+        let mut query = Query::parse(search, options)?;
+        query.resolve(&ntfs_index);
+        let narrowest = self
+            .entries
+            .iter()
+            .filter(|entry| query.narrows(&entry.query))
+            .min_by_key(|entry| entry.files.len());
+        let (files, scanned) = match narrowest {
+            Some(entry) => (
+                filter_candidates(&ntfs_index, &query, &entry.files),
+                entry.files.len(),
+            ),
+            None => filter_query(&ntfs_index, &query),
+        };
+        let files = filter_owners(ntfs_index, &query, files)?;
+        self.last_metrics = Some(record(start, scanned, &files));
+
+        if self.entries.len() >= MAX_CACHED_RESULTS {
+            self.entries.remove(0);
+        }
+        self.entries.push(CachedResults {
+            search: search.to_string(),
+            options,
+            query,
+            generation,
+            files: files.clone(),
+        });
+        Ok(files)
+    }
+
+    /// Timing of the last query which was not answered from the cache. Cached results are not
+    /// recorded at all, as the UI refreshes them every second.
+    pub fn last_metrics(&self) -> Option<FilterMetrics> {
+        self.last_metrics
+    }
+}
+
+/// Sorts the given MFT indices in place by the values displayed in the given column. Text is
+/// compared case-insensitively, sizes and dates numerically. Owners are read with the index
+/// unlocked, and only for up to [MAX_OWNER_LOOKUPS] files. More files are sorted by name instead.
+pub fn sort(index: &Mutex<NtfsVolumeIndex>, files: &mut [u64], column: Column, ascending: bool) {
+    let ntfs_index = index.lock().unwrap();
+    if column == Column::Owner && files.len() <= MAX_OWNER_LOOKUPS {
+        let paths = full_paths(&ntfs_index, files);
+        drop(ntfs_index);
+        sort_by_owner(files, paths, ascending);
+        return;
+    }
+    if column == Column::Owner {
+        debug!("Sorting {} files by name instead of by owner", files.len());
+    }
+
+    sort_index(&ntfs_index, files, column, ascending);
+}
+
+/// Files whose owner can't be read come first
+fn sort_by_owner(files: &mut [u64], paths: Vec<(u64, String)>, ascending: bool) {
+    let owners = paths
+        .into_par_iter()
+        .filter_map(|(i, path)| Some((i, owner::owner(&path)?.to_lowercase())))
+        .collect::<FxHashMap<_, _>>();
+    files.par_sort_by_cached_key(|i| owners.get(i).cloned());
+    if !ascending {
+        files.reverse();
+    }
+}
+
+fn sort_index(ntfs_index: &NtfsVolumeIndex, files: &mut [u64], column: Column, ascending: bool) {
+    let order = |ordering: Ordering| {
+        if ascending {
+            ordering
+        } else {
+            ordering.reverse()
+        }
+    };
+
+    if column == Column::Path {
+        // Computing paths is expensive, so do it only once per file
+        files.par_sort_by_cached_key(|&i| {
+            ntfs_index
+                .find_by_index(i)
+                .map(|info| ntfs_index.compute_full_path(info).to_lowercase())
+        });
+        if !ascending {
+            files.reverse();
+        }
+        return;
+    }
+    files.par_sort_by(|&a, &b| {
+        let (Some(a), Some(b)) = (ntfs_index.find_by_index(a), ntfs_index.find_by_index(b)) else {
+            return Ordering::Equal;
+        };
+
+        order(match column {
+            Column::Name | Column::Path | Column::Owner => compare_ignore_case(&a.name, &b.name),
+            Column::Size => a.size().cmp(&b.size()),
+            Column::SizeOnDisk => a.size_on_disk().cmp(&b.size_on_disk()),
+            Column::DateModified => a.times().modified.cmp(&b.times().modified),
+            Column::DateCreated => a.times().created.cmp(&b.times().created),
+            Column::DateAccessed => a.times().accessed.cmp(&b.times().accessed),
+            Column::Attributes => a.attributes().cmp(&b.attributes()),
+            Column::Extension => compare_ignore_case(
+                a.extension().unwrap_or_default(),
+                b.extension().unwrap_or_default(),
+            ),
+        })
+    });
+}
+
+/// Groups the files by the directory containing them. Directories are ordered by their first file,
+/// so that the order of sorted results is kept.
+pub fn group_by_folder(ntfs_index: &NtfsVolumeIndex, files: &[u64]) -> Vec<(u64, Vec<u64>)> {
+    let mut positions = FxHashMap::default();
+    let mut groups = Vec::<(u64, Vec<u64>)>::new();
+    for &file in files {
+        let Some(info) = ntfs_index.find_by_index(file) else {
+            continue;
+        };
+        let position = *positions.entry(info.parent()).or_insert_with(|| {
+            groups.push((info.parent(), Vec::new()));
+            groups.len() - 1
+        });
+        groups[position].1.push(file);
+    }
+    groups
+}
+
+fn compare_ignore_case(a: &str, b: &str) -> Ordering {
+    a.chars()
+        .flat_map(char::to_lowercase)
+        .cmp(b.chars().flat_map(char::to_lowercase))
+}
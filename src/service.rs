@@ -0,0 +1,209 @@
+use std::ffi::c_void;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use eyre::{Context, Result};
+use tracing::error;
+use windows::core::{HSTRING, PCWSTR, PWSTR};
+use windows::Win32::Foundation::ERROR_CALL_NOT_IMPLEMENTED;
+use windows::Win32::Storage::FileSystem::DELETE;
+use windows::Win32::System::Services::{
+    CloseServiceHandle, ControlService, CreateServiceW, DeleteService, OpenSCManagerW,
+    OpenServiceW, RegisterServiceCtrlHandlerExW, SetServiceStatus, StartServiceCtrlDispatcherW,
+    StartServiceW, SC_MANAGER_CONNECT, SC_MANAGER_CREATE_SERVICE, SERVICE_ACCEPT_SHUTDOWN,
+    SERVICE_ACCEPT_STOP, SERVICE_ALL_ACCESS, SERVICE_AUTO_START, SERVICE_CONTROL_INTERROGATE,
+    SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP, SERVICE_ERROR_NORMAL, SERVICE_RUNNING,
+    SERVICE_START_PENDING, SERVICE_STATUS, SERVICE_STATUS_CURRENT_STATE, SERVICE_STATUS_HANDLE,
+    SERVICE_STOP, SERVICE_STOPPED, SERVICE_STOP_PENDING, SERVICE_TABLE_ENTRYW,
+    SERVICE_WIN32_OWN_PROCESS,
+};
+
+use crate::changelog::ChangeLog;
+use crate::config::Config;
+use crate::ipc;
+use crate::query::SearchOptions;
+
+const SERVICE_NAME: &str = "reverything";
+const SERVICE_DISPLAY_NAME: &str = "Reverything";
+
+/// Hands the current thread over to the service control manager. Only returns once the service
+/// has been stopped. Must be called from a process started by the SCM.
+pub fn run() -> Result<()> {
+    let mut name = SERVICE_NAME.encode_utf16().chain([0]).collect::<Vec<_>>();
+    let table = [
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: PWSTR(name.as_mut_ptr()),
+            lpServiceProc: Some(service_main),
+        },
+        SERVICE_TABLE_ENTRYW::default(),
+    ];
+
+    unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) }.with_context(|| {
+        "StartServiceCtrlDispatcherW failed, the service must be started by the SCM"
+    })
+}
+
+/// Registers the service to start automatically and starts it right away.
+pub fn install() -> Result<()> {
+    let binary_path = format!("\"{}\" service", std::env::current_exe()?.display());
+
+    unsafe {
+        let manager = OpenSCManagerW(PCWSTR::null(), PCWSTR::null(), SC_MANAGER_CREATE_SERVICE)
+            .with_context(|| "OpenSCManagerW failed, installing requires an elevated shell")?;
+        let service = CreateServiceW(
+            manager,
+            &HSTRING::from(SERVICE_NAME),
+            &HSTRING::from(SERVICE_DISPLAY_NAME),
+            SERVICE_ALL_ACCESS,
+            SERVICE_WIN32_OWN_PROCESS,
+            SERVICE_AUTO_START,
+            SERVICE_ERROR_NORMAL,
+            &HSTRING::from(binary_path),
+            PCWSTR::null(),
+            None,
+            PCWSTR::null(),
+            PCWSTR::null(),
+            PCWSTR::null(),
+        )
+        .with_context(|| "CreateServiceW failed");
+        let _ = CloseServiceHandle(manager);
+        let service = service?;
+
+        let res = StartServiceW(service, None).with_context(|| "StartServiceW failed");
+        let _ = CloseServiceHandle(service);
+        res
+    }
+}
+
+/// Stops the service if it is running and removes it from the SCM.
+pub fn uninstall() -> Result<()> {
+    unsafe {
+        let manager = OpenSCManagerW(PCWSTR::null(), PCWSTR::null(), SC_MANAGER_CONNECT)
+            .with_context(|| "OpenSCManagerW failed, uninstalling requires an elevated shell")?;
+        let service = OpenServiceW(
+            manager,
+            &HSTRING::from(SERVICE_NAME),
+            SERVICE_STOP | DELETE.0,
+        )
+        .with_context(|| "OpenServiceW failed, is the service installed?");
+        let _ = CloseServiceHandle(manager);
+        let service = service?;
+
+        // Fails if the service is not running, which is fine
+        let mut status = SERVICE_STATUS::default();
+        let _ = ControlService(service, SERVICE_CONTROL_STOP, &mut status);
+
+        let res = DeleteService(service).with_context(|| "DeleteService failed");
+        let _ = CloseServiceHandle(service);
+        res
+    }
+}
+
+unsafe extern "system" fn service_main(_argc: u32, _argv: *mut PWSTR) {
+    if let Err(e) = run_service() {
+        error!("Service failed: {:?}", e);
+    }
+}
+
+fn run_service() -> Result<()> {
+    let (stop_sender, stop_receiver) = channel::<()>();
+    // Leaked on purpose, the handler may be called until the process exits
+    let context = Box::into_raw(Box::new(stop_sender));
+
+    let status_handle = unsafe {
+        RegisterServiceCtrlHandlerExW(
+            &HSTRING::from(SERVICE_NAME),
+            Some(service_control_handler),
+            Some(context as *const c_void),
+        )
+    }
+    .with_context(|| "RegisterServiceCtrlHandlerExW failed")?;
+
+    set_status(status_handle, SERVICE_START_PENDING)?;
+    let config = Config::load();
+    let change_log = Arc::new(Mutex::new(ChangeLog::default()));
+    let index = match reverything::build_index(config.index_mode, config.journal_options(), {
+        let change_log = change_log.clone();
+        move |_, changes| change_log.lock().unwrap().record(changes)
+    }) {
+        Ok(index) => index,
+        Err(e) => {
+            set_status(status_handle, SERVICE_STOPPED)?;
+            return Err(e);
+        }
+    };
+
+    if config.trigram_index {
+        index.lock().unwrap().build_trigram_index();
+    }
+    if config.verify_interval_hours > 0 {
+        reverything::start_verification_thread(
+            index.clone(),
+            Duration::from_secs(config.verify_interval_hours * 60 * 60),
+        );
+    }
+    if !config.network_locations.is_empty() {
+        reverything::start_network_thread(
+            index.clone(),
+            config.network_locations.clone(),
+            Duration::from_secs(config.network_rescan_minutes * 60),
+        );
+    }
+
+    // The IPC thread is never joined, it dies with the process once the dispatcher returns
+    let options = SearchOptions {
+        hide_metafiles: config.hide_metafiles,
+        ..Default::default()
+    };
+    let ipc_index = index.clone();
+    std::thread::spawn(move || {
+        if let Err(e) = ipc::serve(ipc_index, change_log, options, config.presets) {
+            error!("IPC server failed: {:?}", e);
+        }
+    });
+    set_status(status_handle, SERVICE_RUNNING)?;
+
+    let _ = stop_receiver.recv();
+    set_status(status_handle, SERVICE_STOP_PENDING)?;
+    reverything::shutdown(&index);
+    set_status(status_handle, SERVICE_STOPPED)
+}
+
+unsafe extern "system" fn service_control_handler(
+    control: u32,
+    _event_type: u32,
+    _event_data: *mut c_void,
+    context: *mut c_void,
+) -> u32 {
+    match control {
+        SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+            let stop_sender = &*(context as *const Sender<()>);
+            let _ = stop_sender.send(());
+            0
+        }
+        SERVICE_CONTROL_INTERROGATE => 0,
+        _ => ERROR_CALL_NOT_IMPLEMENTED.0,
+    }
+}
+
+fn set_status(handle: SERVICE_STATUS_HANDLE, state: SERVICE_STATUS_CURRENT_STATE) -> Result<()> {
+    let status = SERVICE_STATUS {
+        dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+        dwCurrentState: state,
+        dwControlsAccepted: if state == SERVICE_RUNNING {
+            SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN
+        } else {
+            0
+        },
+        // Building the index can take a while
+        dwWaitHint: if state == SERVICE_START_PENDING {
+            60_000
+        } else {
+            0
+        },
+        ..Default::default()
+    };
+
+    unsafe { SetServiceStatus(handle, &status) }.with_context(|| "SetServiceStatus failed")
+}
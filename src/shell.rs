@@ -0,0 +1,321 @@
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::path::{Path, PathBuf};
+
+use eyre::{eyre, Context, Result};
+use windows::core::{w, Interface, HSTRING, PCSTR, PCWSTR};
+use windows::Win32::Foundation::{
+    GlobalFree, ERROR_CANCELLED, HANDLE, HWND, LPARAM, LRESULT, POINT, RECT, WPARAM,
+};
+use windows::Win32::Graphics::Gdi::{MonitorFromRect, MONITOR_DEFAULTTONULL};
+use windows::Win32::System::Com::{
+    CoCreateInstance, CoInitializeEx, CoTaskMemFree, CoUninitialize, IBindCtx, CLSCTX_ALL,
+    COINIT_APARTMENTTHREADED,
+};
+use windows::Win32::System::DataExchange::{
+    CloseClipboard, EmptyClipboard, OpenClipboard, SetClipboardData,
+};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::System::Memory::{GlobalAlloc, GlobalLock, GlobalUnlock, GMEM_MOVEABLE};
+use windows::Win32::System::Ole::CF_UNICODETEXT;
+use windows::Win32::UI::Shell::Common::{COMDLG_FILTERSPEC, ITEMIDLIST};
+use windows::Win32::UI::Shell::{
+    FileSaveDialog, IContextMenu, IContextMenu3, IFileSaveDialog, ILFindLastID, IShellFolder,
+    SHBindToParent, SHObjectProperties, SHParseDisplayName, ShellExecuteW, CMF_NORMAL,
+    CMINVOKECOMMANDINFO, SHOP_FILEPATH, SIGDN_FILESYSPATH,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreatePopupMenu, CreateWindowExW, DefWindowProcW, DestroyMenu, DestroyWindow, GetCursorPos,
+    MessageBoxW, RegisterClassW, SetForegroundWindow, TrackPopupMenuEx, HMENU, IDRETRY,
+    MB_ICONERROR, MB_RETRYCANCEL, SW_SHOWNORMAL, TPM_RETURNCMD, TPM_RIGHTBUTTON, WINDOW_EX_STYLE,
+    WM_DRAWITEM, WM_INITMENUPOPUP, WM_MEASUREITEM, WM_MENUCHAR, WNDCLASSW, WS_POPUP,
+};
+
+const CONTEXT_MENU_CLASS: PCWSTR = w!("reverything_context_menu");
+const FIRST_COMMAND_ID: u32 = 1;
+const LAST_COMMAND_ID: u32 = 0x7FFF;
+
+thread_local! {
+    /// The context menu which is currently shown. Submenus like "Send to" are populated lazily
+    /// through window messages, which have to be forwarded to the menu.
+    static ACTIVE_CONTEXT_MENU: RefCell<Option<IContextMenu3>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with COM initialized for the current thread.
+pub fn with_com<R>(f: impl FnOnce() -> Result<R>) -> Result<R> {
+    unsafe {
+        CoInitializeEx(None, COINIT_APARTMENTTHREADED)
+            .ok()
+            .with_context(|| "CoInitializeEx failed")?;
+        let res = f();
+        CoUninitialize();
+
+        res
+    }
+}
+
+pub fn copy_to_clipboard(text: &str) -> Result<()> {
+    let text = text.encode_utf16().chain([0]).collect::<Vec<_>>();
+
+    unsafe {
+        OpenClipboard(HWND::default()).with_context(|| "OpenClipboard failed")?;
+        let res = (|| {
+            EmptyClipboard()?;
+
+            let memory = GlobalAlloc(GMEM_MOVEABLE, text.len() * size_of::<u16>())?;
+            let ptr = GlobalLock(memory) as *mut u16;
+            ptr.copy_from_nonoverlapping(text.as_ptr(), text.len());
+            let _ = GlobalUnlock(memory);
+
+            // The clipboard owns the memory once this succeeds
+            if let Err(e) = SetClipboardData(CF_UNICODETEXT.0 as u32, HANDLE(memory.0)) {
+                let _ = GlobalFree(memory);
+                return Err(e);
+            }
+
+            Ok(())
+        })();
+        let _ = CloseClipboard();
+
+        res.with_context(|| "Failed to copy text to the clipboard")
+    }
+}
+
+/// Shows a save dialog for a CSV file. Returns `None` if the user cancelled the dialog.
+pub fn pick_csv_save_path(default_name: &str) -> Result<Option<PathBuf>> {
+    with_com(|| unsafe {
+        let dialog: IFileSaveDialog = CoCreateInstance(&FileSaveDialog, None, CLSCTX_ALL)?;
+        let csv_name = HSTRING::from("CSV files");
+        let csv_spec = HSTRING::from("*.csv");
+        dialog.SetFileTypes(&[COMDLG_FILTERSPEC {
+            pszName: PCWSTR(csv_name.as_ptr()),
+            pszSpec: PCWSTR(csv_spec.as_ptr()),
+        }])?;
+        dialog.SetDefaultExtension(&HSTRING::from("csv"))?;
+        dialog.SetFileName(&HSTRING::from(default_name))?;
+
+        if let Err(e) = dialog.Show(HWND::default()) {
+            if e.code() == ERROR_CANCELLED.to_hresult() {
+                return Ok(None);
+            }
+
+            return Err(e.into());
+        }
+
+        let path = dialog.GetResult()?.GetDisplayName(SIGDN_FILESYSPATH)?;
+        let res = path.to_string();
+        CoTaskMemFree(Some(path.0 as *const _));
+
+        Ok(Some(PathBuf::from(res?)))
+    })
+}
+
+/// Shows an error dialog with "Retry" and "Cancel" buttons. Returns true if "Retry" was clicked.
+pub fn ask_retry(message: &str) -> bool {
+    unsafe {
+        MessageBoxW(
+            HWND::default(),
+            &HSTRING::from(message),
+            w!("Reverything"),
+            MB_RETRYCANCEL | MB_ICONERROR,
+        ) == IDRETRY
+    }
+}
+
+/// Whether any part of the rectangle is on one of the connected monitors, given in physical pixels.
+pub fn is_on_screen(x: i32, y: i32, width: u32, height: u32) -> bool {
+    let rect = RECT {
+        left: x,
+        top: y,
+        right: x + width as i32,
+        bottom: y + height as i32,
+    };
+    !unsafe { MonitorFromRect(&rect, MONITOR_DEFAULTTONULL) }.is_invalid()
+}
+
+/// Opens the file at `path` with its default application, like double-clicking it in the explorer.
+pub fn open(path: &str) -> Result<()> {
+    let instance = unsafe {
+        ShellExecuteW(
+            HWND::default(),
+            w!("open"),
+            &HSTRING::from(path),
+            PCWSTR::null(),
+            PCWSTR::null(),
+            SW_SHOWNORMAL,
+        )
+    };
+
+    // Values up to 32 are error codes
+    if instance.0 as usize <= 32 {
+        return Err(eyre!(
+            "Failed to open '{}', ShellExecuteW returned {}",
+            path,
+            instance.0 as usize
+        ));
+    }
+
+    Ok(())
+}
+
+/// Opens the explorer properties dialog of the file at `path`. The dialog runs on its own thread,
+/// so this returns immediately.
+pub fn show_properties(path: &str) -> Result<()> {
+    with_com(|| unsafe {
+        SHObjectProperties(
+            HWND::default(),
+            SHOP_FILEPATH,
+            &HSTRING::from(path),
+            PCWSTR::null(),
+        )
+        .ok()
+        .with_context(|| format!("Failed to show properties of '{}'", path))
+    })
+}
+
+/// Shows the explorer context menu for the given files at the cursor position and runs the selected
+/// command. The shell can only build a menu for files in the same folder, so files outside the
+/// folder of the first one are ignored. Blocks until the menu is closed.
+pub fn show_context_menu(paths: &[String]) -> Result<()> {
+    let Some(first) = paths.first() else {
+        return Ok(());
+    };
+    let folder_path = Path::new(first).parent();
+
+    with_com(|| unsafe {
+        let pidls = paths
+            .iter()
+            .filter(|path| Path::new(path).parent() == folder_path)
+            .map(|path| Pidl::parse(path))
+            .collect::<Result<Vec<_>>>()?;
+        let children = pidls
+            .iter()
+            .map(|pidl| ILFindLastID(pidl.0) as *const ITEMIDLIST)
+            .collect::<Vec<_>>();
+        let folder: IShellFolder = SHBindToParent(pidls[0].0, None)?;
+
+        let hwnd = create_context_menu_owner()?;
+        let res = (|| {
+            let menu: IContextMenu = folder.GetUIObjectOf(hwnd, &children, None)?;
+            let popup = CreatePopupMenu()?;
+            let res = track_context_menu(hwnd, &menu, popup);
+            let _ = DestroyMenu(popup);
+
+            res
+        })();
+        let _ = DestroyWindow(hwnd);
+
+        res.with_context(|| "Failed to show the context menu")
+    })
+}
+
+unsafe fn track_context_menu(hwnd: HWND, menu: &IContextMenu, popup: HMENU) -> Result<()> {
+    menu.QueryContextMenu(popup, 0, FIRST_COMMAND_ID, LAST_COMMAND_ID, CMF_NORMAL)?;
+
+    let mut cursor = POINT::default();
+    GetCursorPos(&mut cursor)?;
+    // Otherwise the menu does not close when clicking somewhere else
+    let _ = SetForegroundWindow(hwnd);
+
+    ACTIVE_CONTEXT_MENU.set(menu.cast::<IContextMenu3>().ok());
+    let command = TrackPopupMenuEx(
+        popup,
+        (TPM_RETURNCMD | TPM_RIGHTBUTTON).0,
+        cursor.x,
+        cursor.y,
+        hwnd,
+        None,
+    );
+    ACTIVE_CONTEXT_MENU.set(None);
+
+    // 0 means the menu was closed without selecting anything
+    if command.0 < FIRST_COMMAND_ID as i32 {
+        return Ok(());
+    }
+
+    let info = CMINVOKECOMMANDINFO {
+        cbSize: size_of::<CMINVOKECOMMANDINFO>() as u32,
+        hwnd,
+        // Commands can be passed as offsets instead of verb strings
+        lpVerb: PCSTR((command.0 as u32 - FIRST_COMMAND_ID) as usize as *const u8),
+        nShow: SW_SHOWNORMAL.0,
+        ..Default::default()
+    };
+    menu.InvokeCommand(&info)?;
+
+    Ok(())
+}
+
+/// Creates an invisible window which owns the context menu and receives its messages.
+unsafe fn create_context_menu_owner() -> Result<HWND> {
+    let instance = GetModuleHandleW(None)?;
+    let class = WNDCLASSW {
+        lpfnWndProc: Some(context_menu_window_proc),
+        hInstance: instance.into(),
+        lpszClassName: CONTEXT_MENU_CLASS,
+        ..Default::default()
+    };
+    // Fails if the class was registered by a previous call, which is fine
+    RegisterClassW(&class);
+
+    Ok(CreateWindowExW(
+        WINDOW_EX_STYLE::default(),
+        CONTEXT_MENU_CLASS,
+        PCWSTR::null(),
+        WS_POPUP,
+        0,
+        0,
+        0,
+        0,
+        HWND::default(),
+        HMENU::default(),
+        instance,
+        None,
+    )?)
+}
+
+unsafe extern "system" fn context_menu_window_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    if matches!(
+        msg,
+        WM_INITMENUPOPUP | WM_DRAWITEM | WM_MEASUREITEM | WM_MENUCHAR
+    ) {
+        let handled = ACTIVE_CONTEXT_MENU.with_borrow(|menu| {
+            let mut result = LRESULT(0);
+            menu.as_ref()?
+                .HandleMenuMsg2(msg, wparam, lparam, Some(&mut result))
+                .ok()
+                .map(|_| result)
+        });
+        if let Some(result) = handled {
+            return result;
+        }
+    }
+
+    DefWindowProcW(hwnd, msg, wparam, lparam)
+}
+
+/// An absolute item identifier list, which is how the shell identifies files.
+struct Pidl(*mut ITEMIDLIST);
+
+impl Pidl {
+    fn parse(path: &str) -> Result<Self> {
+        let mut pidl = std::ptr::null_mut();
+        unsafe {
+            SHParseDisplayName(&HSTRING::from(path), None::<&IBindCtx>, &mut pidl, 0, None)
+                .with_context(|| format!("Failed to find '{}'", path))?;
+        }
+
+        Ok(Self(pidl))
+    }
+}
+
+impl Drop for Pidl {
+    fn drop(&mut self) {
+        unsafe { CoTaskMemFree(Some(self.0 as *const c_void)) };
+    }
+}
@@ -0,0 +1,666 @@
+//! Saves the index to disk, so that the next start can load it and catch up through the journal
+//! instead of reading the whole MFT again. Journal entries applied after the snapshot was written
+//! are appended to a write-ahead log, so that a crash loses at most the entries of the last
+//! [SYNC_INTERVAL]. The log stores the id of its snapshot and is ignored if that doesn't match.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use eyre::{eyre, Context, Result};
+use smartstring::{Compact, SmartString};
+
+use crate::config::app_dir;
+use crate::ntfs::index::{FileInfo, FileTimes, NtfsVolumeIndex};
+use crate::ntfs::journal::JournalEntry;
+
+const SNAPSHOT_FILE_NAME: &str = "index.bin";
+const LOG_FILE_NAME: &str = "index.wal";
+const SNAPSHOT_MAGIC: &[u8; 4] = b"RVIX";
+const LOG_MAGIC: &[u8; 4] = b"RVWL";
+/// Files written in another format are ignored
+const FORMAT_VERSION: u32 = 1;
+/// Most time between writing journal entries to the log and forcing them to disk
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+/// Larger records are considered corrupt instead of allocating a huge buffer
+const MAX_LOG_RECORD_SIZE: usize = 256 * 1024 * 1024;
+
+const CREATE_ENTRY: u8 = 0;
+const DELETE_ENTRY: u8 = 1;
+const RENAME_ENTRY: u8 = 2;
+
+/// An index loaded by [load], including the journal entries from the log.
+pub struct Snapshot {
+    pub id: u64,
+    pub journal_id: u64,
+    /// USN of the next journal record, after the entries of [Self::log]
+    pub next_usn: i64,
+    pub infos: Vec<Option<FileInfo>>,
+    /// Entries which were applied after the snapshot was written, in order
+    pub log: Vec<JournalEntry>,
+    /// Size of the intact part of the log, 0 if there is no log belonging to the snapshot
+    pub log_len: u64,
+}
+
+/// Journal entries applied since the snapshot with the same id was written.
+pub struct WriteAheadLog {
+    file: File,
+    last_sync: Instant,
+}
+
+impl WriteAheadLog {
+    /// Replaces the log with an empty one belonging to the snapshot `id`.
+    pub fn create(id: u64) -> Result<Self> {
+        Self::create_in(&app_dir()?, id)
+    }
+
+    fn create_in(dir: &Path, id: u64) -> Result<Self> {
+        let path = log_path(dir);
+        let mut file = File::create(&path)
+            .with_context(|| format!("Failed to create '{}'", path.display()))?;
+        file.write_all(LOG_MAGIC)?;
+        file.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        file.write_all(&id.to_le_bytes())?;
+        file.sync_data()?;
+
+        Ok(Self {
+            file,
+            last_sync: Instant::now(),
+        })
+    }
+
+    /// Continues the log of a loaded snapshot, see [Snapshot::log_len]. Anything after its first
+    /// `len` bytes, like a record which was only partly written before a crash, is cut off. If the
+    /// snapshot has no log, a new one is created.
+    pub fn open(id: u64, len: u64) -> Result<Self> {
+        Self::open_in(&app_dir()?, id, len)
+    }
+
+    fn open_in(dir: &Path, id: u64, len: u64) -> Result<Self> {
+        if len == 0 {
+            return Self::create_in(dir, id);
+        }
+
+        let path = log_path(dir);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open '{}'", path.display()))?;
+        file.set_len(len)?;
+        file.seek(SeekFrom::End(0))?;
+
+        Ok(Self {
+            file,
+            last_sync: Instant::now(),
+        })
+    }
+
+    /// Appends the entries of one journal batch along with the position after them. Every record
+    /// is written right away, but only forced to disk every [SYNC_INTERVAL].
+    pub fn append(
+        &mut self,
+        journal_id: u64,
+        next_usn: i64,
+        entries: &[JournalEntry],
+    ) -> Result<()> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&journal_id.to_le_bytes());
+        payload.extend_from_slice(&next_usn.to_le_bytes());
+        payload.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        for entry in entries {
+            write_entry(&mut payload, entry)?;
+        }
+
+        let mut record = Vec::with_capacity(payload.len() + 8);
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+        record.extend_from_slice(&payload);
+        self.file
+            .write_all(&record)
+            .with_context(|| "Failed to append to the write-ahead log")?;
+
+        if self.last_sync.elapsed() >= SYNC_INTERVAL {
+            self.file.sync_data()?;
+            self.last_sync = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for WriteAheadLog {
+    fn drop(&mut self) {
+        let _ = self.file.sync_data();
+    }
+}
+
+/// Writes all entries of `index`, which has to be built from the MFT, along with the position in
+/// the journal it is up to date with. The previous snapshot is only replaced once the new one was
+/// written completely. Returns the id of the new snapshot, which a new log has to be created for.
+pub fn save(index: &NtfsVolumeIndex, journal_id: u64, next_usn: i64) -> Result<u64> {
+    save_in(&app_dir()?, index, journal_id, next_usn)
+}
+
+fn save_in(dir: &Path, index: &NtfsVolumeIndex, journal_id: u64, next_usn: i64) -> Result<u64> {
+    let id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    let path = snapshot_path(dir);
+    let temp_path = path.with_extension("tmp");
+    let file = File::create(&temp_path)
+        .with_context(|| format!("Failed to create '{}'", temp_path.display()))?;
+
+    let mut writer = BufWriter::new(file);
+    writer.write_all(SNAPSHOT_MAGIC)?;
+    writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    writer.write_all(&id.to_le_bytes())?;
+    writer.write_all(&(index.volume().id as u32).to_le_bytes())?;
+    writer.write_all(&journal_id.to_le_bytes())?;
+    writer.write_all(&next_usn.to_le_bytes())?;
+    writer.write_all(&(index.file_info_count() as u64).to_le_bytes())?;
+    for info in index.iter() {
+        let Some(info) = info else {
+            writer.write_all(&[0])?;
+            continue;
+        };
+
+        let flags = 1 | (info.is_directory() as u8) << 1 | (info.is_system_noise() as u8) << 2;
+        writer.write_all(&[flags])?;
+        write_string(&mut writer, &info.name)?;
+        writer.write_all(&info.parent().to_le_bytes())?;
+        writer.write_all(&info.size().to_le_bytes())?;
+        writer.write_all(&info.size_on_disk().to_le_bytes())?;
+        let times = info.times();
+        for time in [times.created, times.modified, times.accessed] {
+            writer.write_all(&time.to_le_bytes())?;
+        }
+        writer.write_all(&info.attributes().to_le_bytes())?;
+        writer.write_all(&info.sequence().to_le_bytes())?;
+    }
+
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&temp_path, &path)
+        .with_context(|| format!("Failed to replace '{}'", path.display()))?;
+
+    Ok(id)
+}
+
+/// Loads the snapshot of `volume` and the journal entries of its log. Returns `None` if there is
+/// no snapshot of this volume in the current format. Corrupt log records and everything after them
+/// are ignored.
+pub fn load(volume: char) -> Result<Option<Snapshot>> {
+    load_from(&app_dir()?, volume)
+}
+
+fn load_from(dir: &Path, volume: char) -> Result<Option<Snapshot>> {
+    let path = snapshot_path(dir);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to open '{}'", path.display()));
+        }
+    };
+
+    let mut reader = BufReader::new(file);
+    if &read_array(&mut reader)? != SNAPSHOT_MAGIC
+        || u32::from_le_bytes(read_array(&mut reader)?) != FORMAT_VERSION
+    {
+        return Ok(None);
+    }
+    let id = u64::from_le_bytes(read_array(&mut reader)?);
+    if char::from_u32(u32::from_le_bytes(read_array(&mut reader)?)) != Some(volume) {
+        return Ok(None);
+    }
+    let journal_id = u64::from_le_bytes(read_array(&mut reader)?);
+    let mut next_usn = i64::from_le_bytes(read_array(&mut reader)?);
+    let count = u64::from_le_bytes(read_array(&mut reader)?) as usize;
+
+    let mut infos = Vec::with_capacity(count);
+    for _ in 0..count {
+        let [flags] = read_array(&mut reader)?;
+        if flags & 1 == 0 {
+            infos.push(None);
+            continue;
+        }
+
+        let name = read_string(&mut reader)?;
+        let parent = u64::from_le_bytes(read_array(&mut reader)?);
+        let size = u64::from_le_bytes(read_array(&mut reader)?);
+        let size_on_disk = u64::from_le_bytes(read_array(&mut reader)?);
+        let times = FileTimes {
+            created: u64::from_le_bytes(read_array(&mut reader)?),
+            modified: u64::from_le_bytes(read_array(&mut reader)?),
+            accessed: u64::from_le_bytes(read_array(&mut reader)?),
+        };
+        let attributes = u32::from_le_bytes(read_array(&mut reader)?);
+        let sequence = u16::from_le_bytes(read_array(&mut reader)?);
+        infos.push(Some(
+            FileInfo::new(
+                size,
+                size_on_disk,
+                flags & 2 != 0,
+                parent,
+                SmartString::<Compact>::from(name),
+                times,
+                attributes,
+            )
+            .with_sequence(sequence)
+            .with_system_noise(flags & 4 != 0),
+        ));
+    }
+
+    let mut log = Vec::new();
+    let mut log_len = 0;
+    if let Some(mut reader) = open_log(dir, id)? {
+        log_len = (LOG_MAGIC.len() + 12) as u64;
+        while let Some((record_journal_id, record_next_usn, entries, len)) =
+            read_record(&mut reader)
+        {
+            // The journal was recreated, so the index can't catch up from the snapshot
+            if record_journal_id != journal_id {
+                return Ok(None);
+            }
+            next_usn = record_next_usn;
+            log.extend(entries);
+            log_len += len;
+        }
+    }
+
+    Ok(Some(Snapshot {
+        id,
+        journal_id,
+        next_usn,
+        infos,
+        log,
+        log_len,
+    }))
+}
+
+/// Deletes the snapshot and its log, for example because they can't be kept in sync with the
+/// index anymore.
+pub fn discard() -> Result<()> {
+    let dir = app_dir()?;
+    for path in [snapshot_path(&dir), log_path(&dir)] {
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("Failed to delete '{}'", path.display()));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn snapshot_path(dir: &Path) -> PathBuf {
+    dir.join(SNAPSHOT_FILE_NAME)
+}
+
+fn log_path(dir: &Path) -> PathBuf {
+    dir.join(LOG_FILE_NAME)
+}
+
+/// Opens the log and skips its header. Returns `None` if it doesn't belong to the snapshot `id`.
+fn open_log(dir: &Path, id: u64) -> Result<Option<BufReader<File>>> {
+    let file = match File::open(log_path(dir)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| "Failed to open the write-ahead log"),
+    };
+
+    let mut reader = BufReader::new(file);
+    let header = (|| -> Result<_> {
+        Ok((
+            read_array::<4>(&mut reader)?,
+            u32::from_le_bytes(read_array(&mut reader)?),
+            u64::from_le_bytes(read_array(&mut reader)?),
+        ))
+    })();
+    match header {
+        Ok((magic, version, log_id))
+            if &magic == LOG_MAGIC && version == FORMAT_VERSION && log_id == id =>
+        {
+            Ok(Some(reader))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Reads the next record of the log. Returns `None` at the end of the log or if the record is
+/// incomplete or corrupt.
+fn read_record(reader: &mut impl Read) -> Option<(u64, i64, Vec<JournalEntry>, u64)> {
+    let len = u32::from_le_bytes(read_array(reader).ok()?) as usize;
+    let checksum = u32::from_le_bytes(read_array(reader).ok()?);
+    if len > MAX_LOG_RECORD_SIZE {
+        return None;
+    }
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload).ok()?;
+    if crc32fast::hash(&payload) != checksum {
+        return None;
+    }
+
+    let mut payload = payload.as_slice();
+    let journal_id = u64::from_le_bytes(read_array(&mut payload).ok()?);
+    let next_usn = i64::from_le_bytes(read_array(&mut payload).ok()?);
+    let count = u32::from_le_bytes(read_array(&mut payload).ok()?);
+    let entries = (0..count)
+        .map(|_| read_entry(&mut payload))
+        .collect::<Result<Vec<_>>>()
+        .ok()?;
+
+    Some((journal_id, next_usn, entries, len as u64 + 8))
+}
+
+fn write_entry(writer: &mut impl Write, entry: &JournalEntry) -> Result<()> {
+    match entry {
+        JournalEntry::FileCreate {
+            mft_index,
+            sequence,
+            parent_mft_index,
+            name,
+            is_directory,
+            timestamp,
+            attributes,
+        } => {
+            writer.write_all(&[CREATE_ENTRY])?;
+            writer.write_all(&mft_index.to_le_bytes())?;
+            writer.write_all(&sequence.to_le_bytes())?;
+            writer.write_all(&parent_mft_index.to_le_bytes())?;
+            write_string(writer, name)?;
+            writer.write_all(&[*is_directory as u8])?;
+            writer.write_all(&timestamp.to_le_bytes())?;
+            writer.write_all(&attributes.to_le_bytes())?;
+        }
+        JournalEntry::FileDelete {
+            mft_index,
+            sequence,
+        } => {
+            writer.write_all(&[DELETE_ENTRY])?;
+            writer.write_all(&mft_index.to_le_bytes())?;
+            writer.write_all(&sequence.to_le_bytes())?;
+        }
+        JournalEntry::Rename {
+            mft_index,
+            sequence,
+            old_name,
+            old_parent_mft_index,
+            new_name,
+            new_parent_mft_index,
+        } => {
+            writer.write_all(&[RENAME_ENTRY])?;
+            writer.write_all(&mft_index.to_le_bytes())?;
+            writer.write_all(&sequence.to_le_bytes())?;
+            write_string(writer, old_name)?;
+            writer.write_all(&old_parent_mft_index.to_le_bytes())?;
+            write_string(writer, new_name)?;
+            writer.write_all(&new_parent_mft_index.to_le_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn read_entry(reader: &mut impl Read) -> Result<JournalEntry> {
+    let [kind] = read_array(reader)?;
+    let mft_index = u64::from_le_bytes(read_array(reader)?);
+    let sequence = u16::from_le_bytes(read_array(reader)?);
+    match kind {
+        CREATE_ENTRY => Ok(JournalEntry::FileCreate {
+            mft_index,
+            sequence,
+            parent_mft_index: u64::from_le_bytes(read_array(reader)?),
+            name: read_string(reader)?,
+            is_directory: read_array::<1>(reader)?[0] != 0,
+            timestamp: u64::from_le_bytes(read_array(reader)?),
+            attributes: u32::from_le_bytes(read_array(reader)?),
+        }),
+        DELETE_ENTRY => Ok(JournalEntry::FileDelete {
+            mft_index,
+            sequence,
+        }),
+        RENAME_ENTRY => Ok(JournalEntry::Rename {
+            mft_index,
+            sequence,
+            old_name: read_string(reader)?,
+            old_parent_mft_index: u64::from_le_bytes(read_array(reader)?),
+            new_name: read_string(reader)?,
+            new_parent_mft_index: u64::from_le_bytes(read_array(reader)?),
+        }),
+        _ => Err(eyre!("Unknown journal entry kind {}", kind)),
+    }
+}
+
+/// File names are at most 255 UTF-16 characters, so their UTF-8 length always fits into a `u16`
+fn write_string(writer: &mut impl Write, string: &str) -> Result<()> {
+    writer.write_all(&(string.len() as u16).to_le_bytes())?;
+    writer.write_all(string.as_bytes())?;
+    Ok(())
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String> {
+    let len = u16::from_le_bytes(read_array(reader)?) as usize;
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).with_context(|| "Invalid file name")
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ntfs::synthetic::{SyntheticOptions, SyntheticVolume};
+
+    const JOURNAL_ID: u64 = 7;
+    /// Size of the header of the log
+    const LOG_HEADER_SIZE: u64 = 16;
+
+    /// A directory in the temp directory which is deleted again after the test
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "reverything-snapshot-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn index() -> NtfsVolumeIndex {
+        SyntheticVolume::generate(&SyntheticOptions {
+            files: 500,
+            directories: 50,
+            free_records: 50,
+            ..Default::default()
+        })
+        .build_index()
+        .unwrap()
+    }
+
+    fn batch(mft_index: u64) -> Vec<JournalEntry> {
+        vec![
+            JournalEntry::FileCreate {
+                mft_index,
+                sequence: 3,
+                parent_mft_index: 5,
+                name: format!("created {}.txt", mft_index),
+                is_directory: false,
+                timestamp: 132_223_104_000_000_000,
+                attributes: 0x20,
+            },
+            JournalEntry::Rename {
+                mft_index,
+                sequence: 3,
+                old_name: format!("created {}.txt", mft_index),
+                old_parent_mft_index: 5,
+                new_name: "Résumé.pdf".into(),
+                new_parent_mft_index: 16,
+            },
+            JournalEntry::FileDelete {
+                mft_index,
+                sequence: 3,
+            },
+        ]
+    }
+
+    /// Saves the index and appends one log record per batch. Returns the id of the snapshot and the
+    /// size of the log after every record.
+    fn save_with_log(dir: &Path, batches: &[Vec<JournalEntry>]) -> (u64, Vec<u64>) {
+        let id = save_in(dir, &index(), JOURNAL_ID, 100).unwrap();
+        let mut log = WriteAheadLog::create_in(dir, id).unwrap();
+        let mut lens = Vec::new();
+        for (i, entries) in batches.iter().enumerate() {
+            log.append(JOURNAL_ID, 200 + i as i64, entries).unwrap();
+            lens.push(std::fs::metadata(log_path(dir)).unwrap().len());
+        }
+
+        (id, lens)
+    }
+
+    /// Changes one byte of a file
+    fn corrupt(path: &Path, offset: u64) {
+        let mut bytes = std::fs::read(path).unwrap();
+        bytes[offset as usize] ^= 0xFF;
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = TestDir::new("round-trip");
+        let index = index();
+        let batches = [batch(600), batch(601)];
+        let (id, lens) = save_with_log(&dir.0, &batches);
+
+        let snapshot = load_from(&dir.0, SyntheticVolume::VOLUME.id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(snapshot.id, id);
+        assert_eq!(snapshot.journal_id, JOURNAL_ID);
+        assert_eq!(snapshot.next_usn, 201);
+        assert_eq!(
+            snapshot.log,
+            batches.into_iter().flatten().collect::<Vec<_>>()
+        );
+        assert_eq!(snapshot.log_len, lens[1]);
+
+        assert_eq!(snapshot.infos.len(), index.file_info_count());
+        for (loaded, info) in snapshot.infos.iter().zip(index.iter()) {
+            let (Some(loaded), Some(info)) = (loaded, info) else {
+                assert!(loaded.is_none() && info.is_none());
+                continue;
+            };
+            assert_eq!(loaded.name, info.name);
+            assert_eq!(loaded.parent(), info.parent());
+            assert_eq!(loaded.size(), info.size());
+            assert_eq!(loaded.size_on_disk(), info.size_on_disk());
+            assert_eq!(loaded.is_directory(), info.is_directory());
+            assert_eq!(loaded.is_system_noise(), info.is_system_noise());
+            assert_eq!(loaded.times().created, info.times().created);
+            assert_eq!(loaded.times().modified, info.times().modified);
+            assert_eq!(loaded.times().accessed, info.times().accessed);
+            assert_eq!(loaded.attributes(), info.attributes());
+            assert_eq!(loaded.sequence(), info.sequence());
+        }
+
+        // Another volume has no snapshot
+        assert!(load_from(&dir.0, 'Q').unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_last_record_is_cut_off() {
+        let dir = TestDir::new("truncated");
+        let batches = [batch(600), batch(601)];
+        let (id, lens) = save_with_log(&dir.0, &batches);
+
+        // A crash while the second record was written
+        let file = OpenOptions::new()
+            .write(true)
+            .open(log_path(&dir.0))
+            .unwrap();
+        file.set_len(lens[1] - 5).unwrap();
+        drop(file);
+
+        let snapshot = load_from(&dir.0, SyntheticVolume::VOLUME.id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(snapshot.log, batches[0]);
+        assert_eq!(snapshot.next_usn, 200);
+        assert_eq!(snapshot.log_len, lens[0]);
+
+        // Continuing the log replaces the partly written record
+        let mut log = WriteAheadLog::open_in(&dir.0, id, snapshot.log_len).unwrap();
+        log.append(JOURNAL_ID, 300, &batch(602)).unwrap();
+        drop(log);
+
+        let snapshot = load_from(&dir.0, SyntheticVolume::VOLUME.id)
+            .unwrap()
+            .unwrap();
+        let expected = [batch(600), batch(602)].into_iter().flatten();
+        assert_eq!(snapshot.log, expected.collect::<Vec<_>>());
+        assert_eq!(snapshot.next_usn, 300);
+    }
+
+    #[test]
+    fn bad_checksum_ends_the_log() {
+        let dir = TestDir::new("checksum");
+        let batches = [batch(600), batch(601), batch(602)];
+        let (_, lens) = save_with_log(&dir.0, &batches);
+
+        // Corrupt the payload of the second record, which hides the third one as well
+        corrupt(&log_path(&dir.0), lens[0] + 20);
+        let snapshot = load_from(&dir.0, SyntheticVolume::VOLUME.id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(snapshot.log, batches[0]);
+        assert_eq!(snapshot.next_usn, 200);
+        assert_eq!(snapshot.log_len, lens[0]);
+
+        // Without an intact record, the index continues where the snapshot left off
+        corrupt(&log_path(&dir.0), LOG_HEADER_SIZE + 20);
+        let snapshot = load_from(&dir.0, SyntheticVolume::VOLUME.id)
+            .unwrap()
+            .unwrap();
+        assert!(snapshot.log.is_empty());
+        assert_eq!(snapshot.next_usn, 100);
+        assert_eq!(snapshot.log_len, LOG_HEADER_SIZE);
+    }
+
+    #[test]
+    fn other_versions_are_ignored() {
+        let dir = TestDir::new("version");
+        save_with_log(&dir.0, &[batch(600)]);
+
+        // A log in another format is ignored, but the snapshot is still used
+        corrupt(&log_path(&dir.0), LOG_MAGIC.len() as u64);
+        let snapshot = load_from(&dir.0, SyntheticVolume::VOLUME.id)
+            .unwrap()
+            .unwrap();
+        assert!(snapshot.log.is_empty());
+        assert_eq!(snapshot.next_usn, 100);
+        assert_eq!(snapshot.log_len, 0);
+
+        corrupt(&snapshot_path(&dir.0), SNAPSHOT_MAGIC.len() as u64);
+        assert!(load_from(&dir.0, SyntheticVolume::VOLUME.id)
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,105 @@
+use std::borrow::Cow;
+
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+
+use crate::ntfs::index::NtfsVolumeIndex;
+
+/// Number of files and their total size, aggregated by extension and by top-level directory.
+/// Directories themselves are not counted.
+#[derive(Debug, Default, Clone)]
+pub struct Statistics {
+    /// Lowercase extensions without the dot, empty for files without one
+    pub by_extension: Vec<GroupStats>,
+    /// Names of the directories in the root of the volume, empty for files directly in the root
+    pub by_directory: Vec<GroupStats>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GroupStats {
+    pub name: String,
+    pub files: usize,
+    pub size: u64,
+}
+
+type Groups<'a> = FxHashMap<Cow<'a, str>, (usize, u64)>;
+
+impl Statistics {
+    /// Aggregates every file in the index. Groups are sorted by size, largest first.
+    pub fn compute(ntfs_index: &NtfsVolumeIndex) -> Self {
+        let (extensions, directories) = ntfs_index
+            .par_iter()
+            .flatten()
+            .filter(|info| !info.is_directory())
+            .fold(
+                || (Groups::default(), Groups::default()),
+                |(mut extensions, mut directories), info| {
+                    let extension = match info.extension() {
+                        Some(e) if e.bytes().any(|b| b.is_ascii_uppercase()) => {
+                            Cow::Owned(e.to_ascii_lowercase())
+                        }
+                        Some(e) => Cow::Borrowed(e),
+                        None => Cow::Borrowed(""),
+                    };
+                    add(&mut extensions, extension, info.size());
+
+                    // The last entry is the one in the root of the volume
+                    let directory = match ntfs_index.iter_with_parents(info).last() {
+                        Some(top) if top.is_directory() => top.name.as_str(),
+                        _ => "",
+                    };
+                    add(&mut directories, Cow::Borrowed(directory), info.size());
+
+                    (extensions, directories)
+                },
+            )
+            .reduce(
+                || (Groups::default(), Groups::default()),
+                |(mut extensions, mut directories), (other_extensions, other_directories)| {
+                    for (name, (files, size)) in other_extensions {
+                        let group = extensions.entry(name).or_default();
+                        group.0 += files;
+                        group.1 += size;
+                    }
+                    for (name, (files, size)) in other_directories {
+                        let group = directories.entry(name).or_default();
+                        group.0 += files;
+                        group.1 += size;
+                    }
+                    (extensions, directories)
+                },
+            );
+
+        Self {
+            by_extension: into_sorted(extensions),
+            by_directory: into_sorted(directories),
+        }
+    }
+
+    pub fn total_files(&self) -> usize {
+        self.by_extension.iter().map(|group| group.files).sum()
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.by_extension.iter().map(|group| group.size).sum()
+    }
+}
+
+fn add<'a>(groups: &mut Groups<'a>, name: Cow<'a, str>, size: u64) {
+    let group = groups.entry(name).or_default();
+    group.0 += 1;
+    group.1 += size;
+}
+
+fn into_sorted(groups: Groups) -> Vec<GroupStats> {
+    let mut groups = groups
+        .into_iter()
+        .map(|(name, (files, size))| GroupStats {
+            name: name.into_owned(),
+            files,
+            size,
+        })
+        .collect::<Vec<_>>();
+    groups.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+    groups
+}
@@ -1,26 +1,158 @@
-use crate::ntfs::index::{FileInfo, NtfsVolumeIndex};
+use crate::autostart;
+use crate::backend::{Backend, ResultSource};
+use crate::changelog::ChangeLog;
+use crate::checksum;
+use crate::config::{
+    self, Column, ColumnLayout, Config, SizeFormat, SortOrder, State, WindowGeometry,
+};
+use crate::content_search;
+use crate::duplicates::{self, DuplicateSet};
+use crate::explorer_menu;
+use crate::history::SearchHistory;
+use crate::icons::{IconCache, IconKey, IconPixels};
+use crate::instance;
+use crate::jump_list;
+use crate::logging::RecentLog;
+use crate::ntfs::index::FileId;
+use crate::preview::{Preview, PreviewLoader};
+use crate::query::{self, Query, SearchOptions};
+use crate::recycle_bin;
+use crate::search::ResultEntry;
+use crate::stats::Statistics;
+use crate::{external_tools, fileops, format, metrics, owner, shell};
 use rayon::prelude::*;
+use rustc_hash::{FxHashMap, FxHashSet};
 use slint::{
-    Model, ModelNotify, ModelRc, ModelTracker, SharedString, StandardListViewItem, VecModel,
+    Model, ModelNotify, ModelRc, ModelTracker, SharedString, StandardListViewItem, TableColumn,
+    Timer, TimerMode, VecModel,
 };
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::default::Default;
+use std::fmt::Write;
+use std::fs;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, error};
+use windows::Win32::UI::HiDpi::GetDpiForSystem;
 
 slint::include_modules!();
 
-pub fn run_ui(index: Arc<Mutex<NtfsVolumeIndex>>) -> Result<(), slint::PlatformError> {
+/// Rows shown in the recent changes tab. The change log itself may hold more entries.
+const MAX_RECENT_CHANGES: usize = 1000;
+/// Rows shown in the log tab
+const MAX_LOG_ROWS: usize = 1000;
+const CHECKSUM_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+/// DPI at which Windows doesn't scale, 100% in the display settings
+const DEFAULT_DPI: f32 = 96.0;
+const MIN_ZOOM: f32 = 0.5;
+const MAX_ZOOM: f32 = 4.0;
+/// How often the performance overlay is updated, short enough to follow typing
+const PERFORMANCE_UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+
+pub fn run_ui(
+    backend: Backend,
+    change_log: Arc<Mutex<ChangeLog>>,
+    recent_log: Arc<Mutex<RecentLog>>,
+    config: Config,
+    query: String,
+    minimized: bool,
+) -> Result<(), slint::PlatformError> {
+    configure_scaling(&config);
     let app = App::new()?;
+    app.set_results_font_size(config.results_font_size.max(0.0));
+    let state = Rc::new(RefCell::new(State::load()));
+
+    let app_weak = app.as_weak();
+    let icons = IconCache::new(move |icons| {
+        if let Some(app) = app_weak.upgrade() {
+            app.get_data()
+                .as_any()
+                .downcast_ref::<NtfsIndexTableModel>()
+                .unwrap()
+                .insert_icons(icons);
+        }
+    });
 
     let model = Rc::new(NtfsIndexTableModel {
-        ntfs_index: index,
+        source: RefCell::new(backend.results()),
         filter: RefCell::new("".to_string()),
-        filtered_files: RefCell::new(Vec::new()),
+        preset: RefCell::new("".to_string()),
+        options: Cell::new(SearchOptions {
+            hide_metafiles: config.hide_metafiles,
+            ..Default::default()
+        }),
+        sort: Cell::new(
+            state
+                .borrow()
+                .sort
+                .map(|sort| (sort.column, sort.ascending)),
+        ),
+        columns: RefCell::new(Vec::new()),
+        icons,
+        size_format: config.size_format,
+        resolve_recycle_bin: config.resolve_recycle_bin,
+        original_paths: RefCell::new(FxHashMap::default()),
+        group_by_folder: Cell::new(state.borrow().group_by_folder && backend.can_group()),
+        expanded_groups: RefCell::new(FxHashSet::default()),
+        selection: RefCell::new(FxHashSet::default()),
+        selection_anchor: Cell::new(None),
         notify: Default::default(),
     });
     model.set_filter("".to_string());
 
+    match backend.status() {
+        Ok(status) => {
+            if status.unreadable_records > 0 {
+                app.set_index_warning(
+                    format!(
+                        "{} file records could not be read, the files stored in them are missing",
+                        status.unreadable_records
+                    )
+                    .into(),
+                );
+            }
+            app.set_indexing_paused(status.paused);
+        }
+        Err(e) => error!("{:?}", e),
+    }
+    let backend_clone = backend.clone();
+    app.on_paused_changed(move |paused| {
+        if let Err(e) = backend_clone.set_paused(paused) {
+            error!("{:?}", e);
+        }
+    });
+
+    // The task may have been removed by hand, or the config copied from another machine
+    let autostart = config.autostart;
+    app.set_autostart(autostart);
+    run_file_operation(move || autostart::set_enabled(autostart));
+    app.on_autostart_changed(|enabled| {
+        run_file_operation(move || {
+            autostart::set_enabled(enabled)?;
+            // Loaded again, so that edits made while the app is running are kept
+            let mut config = Config::load();
+            config.autostart = enabled;
+            config.save()
+        })
+    });
+
+    // The entries may point to an executable which was moved since
+    let explorer_menu = config.explorer_menu;
+    app.set_explorer_menu(explorer_menu);
+    if explorer_menu || explorer_menu::is_enabled() {
+        run_file_operation(move || explorer_menu::set_enabled(explorer_menu));
+    }
+    app.on_explorer_menu_changed(|enabled| {
+        run_file_operation(move || {
+            explorer_menu::set_enabled(enabled)?;
+            let mut config = Config::load();
+            config.explorer_menu = enabled;
+            config.save()
+        })
+    });
+
     let app_weak = app.as_weak();
     std::thread::spawn(move || loop {
         // While this is a bit lazy (we simply match the journal update loop found in the main file),
@@ -41,18 +173,1142 @@ pub fn run_ui(index: Arc<Mutex<NtfsVolumeIndex>>) -> Result<(), slint::PlatformE
     });
 
     app.set_data(model.clone().into());
+    apply_columns(&app, &model, &state.borrow().columns);
+    if let Some((_, ascending)) = model.sort.get() {
+        app.set_sort_ascending(ascending);
+    }
+
+    let recent_changes = Rc::new(VecModel::<ModelRc<StandardListViewItem>>::default());
+    app.set_recent_changes(recent_changes.clone().into());
+    let next_sequence = Cell::new(0);
+    let recent_changes_timer = Timer::default();
+    recent_changes_timer.start(
+        TimerMode::Repeated,
+        std::time::Duration::from_secs(1),
+        move || update_recent_changes(&recent_changes, &change_log, &next_sequence),
+    );
+
+    let log_rows = Rc::new(VecModel::<ModelRc<StandardListViewItem>>::default());
+    app.set_log_entries(log_rows.clone().into());
+    let next_log_sequence = Cell::new(0);
+    let log_timer = Timer::default();
+    log_timer.start(
+        TimerMode::Repeated,
+        std::time::Duration::from_secs(1),
+        move || update_log(&log_rows, &recent_log, &next_log_sequence),
+    );
+    let dismissed_error = Rc::new(RefCell::new(None::<String>));
+    let app_weak = app.as_weak();
+    let dismissed_error_clone = dismissed_error.clone();
+    let backend_clone = backend.clone();
+    let error_timer = Timer::default();
+    error_timer.start(
+        TimerMode::Repeated,
+        std::time::Duration::from_secs(1),
+        move || {
+            update_error_banner(&app_weak.unwrap(), &dismissed_error_clone, &backend_clone);
+        },
+    );
+    let performance_timer = Timer::default();
+    if config.performance_overlay {
+        let app_weak = app.as_weak();
+        let model = model.clone();
+        performance_timer.start(
+            TimerMode::Repeated,
+            PERFORMANCE_UPDATE_INTERVAL,
+            move || {
+                let status = format::format_performance(
+                    model.source.borrow().last_metrics(),
+                    metrics::get().last_batch,
+                );
+                app_weak.unwrap().set_performance_status(status.into());
+            },
+        );
+    }
+    let backend_clone = backend.clone();
+    app.on_retry_failed(move || {
+        if let Err(e) = backend_clone.retry_journal() {
+            error!("{:?}", e);
+        }
+    });
+    let app_weak = app.as_weak();
+    app.on_dismiss_error(move || {
+        let app = app_weak.unwrap();
+        *dismissed_error.borrow_mut() = Some(app.get_error_message().to_string());
+        app.set_error_message("".into());
+    });
+
+    let app_weak = app.as_weak();
+    let backend_clone = backend.clone();
+    let size_format = config.size_format;
+    app.on_refresh_volume_info(move || {
+        update_volume_info(&app_weak.unwrap(), &backend_clone, size_format)
+    });
+    app.invoke_refresh_volume_info();
+
+    let app_weak = app.as_weak();
+    let backend_clone = backend.clone();
+    app.on_compact_index(move || {
+        let app = app_weak.unwrap();
+        app.set_compacting(true);
+        app.set_compaction_status("Compacting the index...".into());
+
+        let backend = backend_clone.clone();
+        let app_weak = app_weak.clone();
+        std::thread::spawn(move || {
+            let status = match backend.compact() {
+                Ok(freed) => format!("Freed {}", format::format_size(freed as u64, size_format)),
+                Err(e) => format!("Compacting failed: {:#}", e),
+            };
+            let _ = slint::invoke_from_event_loop(move || {
+                let Some(app) = app_weak.upgrade() else {
+                    return;
+                };
+                app.set_compaction_status(status.into());
+                app.set_compacting(false);
+                app.invoke_refresh_volume_info();
+            });
+        });
+    });
+
+    let app_weak = app.as_weak();
+    let backend_clone = backend.clone();
+    app.on_refresh_statistics(move || {
+        let app = app_weak.unwrap();
+        app.set_computing_statistics(true);
+        app.set_statistics_status("Aggregating the index...".into());
+
+        let backend = backend_clone.clone();
+        let app_weak = app_weak.clone();
+        std::thread::spawn(move || {
+            let stats = backend.statistics();
+            let _ = slint::invoke_from_event_loop(move || {
+                let Some(app) = app_weak.upgrade() else {
+                    return;
+                };
+                match stats {
+                    Ok(stats) => update_statistics(&app, &stats, size_format),
+                    Err(e) => {
+                        app.set_statistics_status(format!("{:#}", e).into());
+                        app.set_computing_statistics(false);
+                    }
+                }
+            });
+        });
+    });
+    app.invoke_refresh_statistics();
+
+    app.on_open_log_folder(|| {
+        run_file_operation(|| {
+            let dir = config::app_dir()?.join("logs");
+            shell::open(&dir.to_string_lossy())
+        })
+    });
+
+    let app_weak = app.as_weak();
+    let model_clone = model.clone();
+    let state_clone = state.clone();
+    app.on_toggle_column(move |index| {
+        let column = Column::ALL[index as usize];
+        update_columns(&app_weak, &model_clone, &state_clone, |columns| {
+            if let Some(position) = columns.iter().position(|l| l.column == column) {
+                // At least one column has to stay visible
+                if columns.len() > 1 {
+                    columns.remove(position);
+                }
+            } else {
+                columns.push(ColumnLayout {
+                    column,
+                    width: 160.0,
+                });
+            }
+        });
+    });
+
+    let app_weak = app.as_weak();
+    let model_clone = model.clone();
+    let state_clone = state.clone();
+    app.on_move_column(move |index, offset| {
+        update_columns(&app_weak, &model_clone, &state_clone, |columns| {
+            let target = index + offset;
+            if target >= 0 && (target as usize) < columns.len() {
+                columns.swap(index as usize, target as usize);
+            }
+        });
+    });
+
+    let presets = config.presets;
+    let preset_names = std::iter::once(SharedString::from("All files"))
+        .chain(presets.iter().map(|p| SharedString::from(&p.name)))
+        .collect::<Vec<_>>();
+    app.set_presets(ModelRc::from(Rc::new(VecModel::from(preset_names))));
+
+    let app_weak = app.as_weak();
+    let model_clone = model.clone();
+    app.on_preset_selected(move |index| {
+        // The first entry is "All files", which has no query
+        let query = presets
+            .get((index as usize).wrapping_sub(1))
+            .map(|p| p.query.clone())
+            .unwrap_or_default();
+        model_clone.set_preset(query);
+        app_weak
+            .unwrap()
+            .set_finds_empty_folders(model_clone.finds_empty_folders());
+    });
+
+    let model_clone = model.clone();
+    let hide_metafiles = config.hide_metafiles;
+    app.on_search_options_change(move |whole_word, match_path, fold, hide_recycle_bin| {
+        model_clone.set_options(SearchOptions {
+            whole_word,
+            match_path,
+            fold,
+            hide_recycle_bin,
+            hide_metafiles,
+        });
+    });
+
+    let model_clone = model.clone();
+    app.on_sort_changed(move |column, ascending| {
+        let column = model_clone.columns.borrow()[column as usize];
+        model_clone.set_sort(column, ascending);
+    });
+
+    app.set_history(history_model(&state.borrow().history));
+    update_jump_list(&state.borrow().history);
+
+    let app_weak = app.as_weak();
+    let state_clone = state.clone();
+    app.on_remember_query(move |query| {
+        update_history(&app_weak, &state_clone, |history| history.remember(&query));
+    });
+
+    let app_weak = app.as_weak();
+    let state_clone = state.clone();
+    app.on_toggle_query_pinned(move |query| {
+        update_history(&app_weak, &state_clone, |history| {
+            history.toggle_pinned(&query)
+        });
+    });
+
+    let app_weak = app.as_weak();
+    let model_clone = model.clone();
+    let state_clone = state.clone();
+    app.on_open_file(move |id| {
+        let Some(path) = model_clone.path_of(&id) else {
+            return;
+        };
+        update_history(&app_weak, &state_clone, |history| {
+            history.remember_opened(&path)
+        });
+        run_file_operation(move || shell::open(&path));
+    });
+
+    let model_clone = model.clone();
+    app.on_file_id(move |row| {
+        model_clone
+            .file_id_at(row as usize)
+            .map(|id| SharedString::from(id.to_string()))
+            .unwrap_or_default()
+    });
+
+    let model_clone = model.clone();
+    app.on_file_name(move |id| {
+        model_clone
+            .entry_of(&id)
+            .map(|entry| SharedString::from(entry.info.name.as_str()))
+            .unwrap_or_default()
+    });
+
+    let model_clone = model.clone();
+    app.on_rename_file(move |id, new_name| {
+        if let Some(path) = model_clone.path_of(&id) {
+            run_file_operation(move || fileops::rename(&path, &new_name));
+        }
+    });
+
+    let model_clone = model.clone();
+    app.on_move_file(move |id, destination| {
+        if let Some(path) = model_clone.path_of(&id) {
+            run_file_operation(move || fileops::move_to(&path, &destination));
+        }
+    });
+
+    let app_weak = app.as_weak();
+    let preview_loader = Rc::new(PreviewLoader::new(move |path, preview| {
+        if let Some(app) = app_weak.upgrade() {
+            show_preview_content(&app, &path, preview);
+        }
+    }));
+    app.set_preview_visible(state.borrow().show_preview);
+    app.set_can_group(backend.can_group());
+    app.set_group_by_folder(model.group_by_folder.get());
+
+    let model_clone = model.clone();
+    let state_clone = state.clone();
+    app.on_group_by_folder_changed(move |group_by_folder| {
+        let mut state = state_clone.borrow_mut();
+        state.group_by_folder = group_by_folder;
+        if let Err(e) = state.save() {
+            error!("{:?}", e);
+        }
+
+        model_clone.set_group_by_folder(group_by_folder);
+    });
+
+    let model_clone = model.clone();
+    app.on_toggle_group(move |row| model_clone.toggle_group(row as usize));
+
+    let app_weak = app.as_weak();
+    let model_clone = model.clone();
+    let state_clone = state.clone();
+    let preview_loader_clone = preview_loader.clone();
+    app.on_preview_visibility_changed(move |visible| {
+        let mut state = state_clone.borrow_mut();
+        state.show_preview = visible;
+        if let Err(e) = state.save() {
+            error!("{:?}", e);
+        }
+
+        update_preview(&app_weak.unwrap(), &model_clone, &preview_loader_clone);
+    });
+
+    let app_weak = app.as_weak();
+    let model_clone = model.clone();
+    app.on_select_row(move |row, toggle, extend| {
+        model_clone.select_row(row as usize, toggle, extend);
+        update_preview(&app_weak.unwrap(), &model_clone, &preview_loader);
+    });
+
+    let model_clone = model.clone();
+    app.on_select_all(move || model_clone.select_all());
+
+    let model_clone = model.clone();
+    app.on_is_row_selected(move |row| model_clone.is_row_selected(row as usize));
+
+    let model_clone = model.clone();
+    app.on_copy_selected_paths(move || {
+        let paths = model_clone
+            .selected_files()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect::<Vec<_>>();
+        let text = paths.join("\r\n");
+        if let Err(e) = shell::copy_to_clipboard(&text) {
+            error!("{:?}", e);
+        }
+    });
+
+    let model_clone = model.clone();
+    app.on_export_selected(move || {
+        let files = model_clone.selected_files();
+        run_file_operation(move || export_csv(&files));
+    });
+
+    let tools = config.external_tools;
+    let tool_names = tools
+        .iter()
+        .map(|tool| SharedString::from(&tool.name))
+        .collect::<Vec<_>>();
+    app.set_external_tools(ModelRc::from(Rc::new(VecModel::from(tool_names))));
+
+    let model_clone = model.clone();
+    app.on_run_external_tool(move |tool, id| {
+        let Some(tool) = tools.get(tool as usize).cloned() else {
+            return;
+        };
+        if let Some(path) = model_clone.path_of(&id) {
+            run_file_operation(move || external_tools::run(&tool, &path));
+        }
+    });
+
+    let model_clone = model.clone();
+    app.on_show_properties(move |id| {
+        if let Some(path) = model_clone.path_of(&id) {
+            run_file_operation(move || shell::show_properties(&path));
+        }
+    });
+
+    app.on_copy_text(|text| {
+        if let Err(e) = shell::copy_to_clipboard(&text) {
+            error!("{:?}", e);
+        }
+    });
+
+    let model_clone = model.clone();
+    app.on_file_details(move |id| model_clone.file_details(&id).unwrap_or_default().into());
+
+    let model_clone = model.clone();
+    app.on_show_shell_menu(move || {
+        let paths = model_clone
+            .selected_files()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect::<Vec<_>>();
+        // The menu runs its own message loop until it is closed
+        run_file_operation(move || shell::show_context_menu(&paths));
+    });
+
+    let model_clone = model.clone();
+    app.on_delete_selected(move || {
+        let paths = model_clone
+            .selected_files()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect::<Vec<_>>();
+        if !paths.is_empty() {
+            run_file_operation(move || fileops::recycle(&paths));
+        }
+    });
+
+    let model_clone = model.clone();
+    app.on_delete_empty_folders(move || {
+        let paths = model_clone.empty_result_directories();
+        if !paths.is_empty() {
+            run_file_operation(move || fileops::recycle(&paths));
+        }
+    });
+
+    let duplicates = Rc::new(DuplicatesModel {
+        sets: RefCell::new(Vec::new()),
+        rows: RefCell::new(Vec::new()),
+        size_format: config.size_format,
+        notify: Default::default(),
+    });
+    app.set_duplicates(duplicates.clone().into());
+    let duplicates_cancel = Rc::new(RefCell::new(Arc::new(AtomicBool::new(false))));
+
+    let app_weak = app.as_weak();
+    let model_clone = model.clone();
+    let duplicates_cancel_clone = duplicates_cancel.clone();
+    let size_format = config.size_format;
+    app.on_find_duplicates(move || {
+        let app = app_weak.unwrap();
+        let files = model_clone.result_files();
+        app.set_finding_duplicates(true);
+        app.set_duplicates_status(format!("Comparing {} files...", files.len()).into());
 
+        let cancel = Arc::new(AtomicBool::new(false));
+        *duplicates_cancel_clone.borrow_mut() = cancel.clone();
+        let app_weak = app_weak.clone();
+        std::thread::spawn(move || {
+            let sets = duplicates::find_duplicates(files, &cancel);
+            let _ = slint::invoke_from_event_loop(move || {
+                let Some(app) = app_weak.upgrade() else {
+                    return;
+                };
+                app.set_finding_duplicates(false);
+
+                let Some(sets) = sets else {
+                    app.set_duplicates_status("Cancelled".into());
+                    return;
+                };
+                let wasted = sets.iter().map(DuplicateSet::wasted_size).sum();
+                app.set_duplicates_status(
+                    format!(
+                        "{} sets of duplicates, {} could be freed",
+                        sets.len(),
+                        format::format_size(wasted, size_format)
+                    )
+                    .into(),
+                );
+                app.get_duplicates()
+                    .as_any()
+                    .downcast_ref::<DuplicatesModel>()
+                    .unwrap()
+                    .set_sets(sets);
+            });
+        });
+    });
+
+    app.on_cancel_duplicates(move || {
+        duplicates_cancel.borrow().store(true, Ordering::Relaxed);
+    });
+
+    let duplicates_clone = duplicates.clone();
+    app.on_open_duplicate(move |row| {
+        if let Some(path) = duplicates_clone.path_at(row as usize) {
+            run_file_operation(move || shell::open(&path));
+        }
+    });
+
+    app.on_keep_duplicate(move |row| {
+        let paths = duplicates.take_others(row as usize);
+        if !paths.is_empty() {
+            run_file_operation(move || fileops::recycle(&paths));
+        }
+    });
+
+    let content_matches = Rc::new(VecModel::<ModelRc<StandardListViewItem>>::default());
+    app.set_content_matches(content_matches.clone().into());
+    let content_search_cancel = Rc::new(RefCell::new(Arc::new(AtomicBool::new(false))));
+
+    let app_weak = app.as_weak();
+    let model_clone = model.clone();
+    let content_search_cancel_clone = content_search_cancel.clone();
+    app.on_search_contents(move |pattern, regex, ignore_case| {
+        let app = app_weak.unwrap();
+        let pattern = match content_search::build_pattern(&pattern, regex, ignore_case) {
+            Ok(pattern) => pattern,
+            Err(e) => {
+                app.set_content_status(format!("{:#}", e).into());
+                return;
+            }
+        };
+        let files = model_clone.result_files();
+        app.set_searching_contents(true);
+        app.set_content_status(format!("Searching {} files...", files.len()).into());
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        *content_search_cancel_clone.borrow_mut() = cancel.clone();
+        let app_weak = app_weak.clone();
+        std::thread::spawn(move || {
+            let matches = content_search::search_contents(&files, &pattern, &cancel);
+            let _ = slint::invoke_from_event_loop(move || {
+                let Some(app) = app_weak.upgrade() else {
+                    return;
+                };
+                app.set_searching_contents(false);
+
+                let Some(matches) = matches else {
+                    app.set_content_status("Cancelled".into());
+                    return;
+                };
+                app.set_content_status(
+                    format!(
+                        "{} matching lines. Files larger than {} are not searched.",
+                        matches.len(),
+                        format::format_size(
+                            content_search::MAX_FILE_SIZE,
+                            SizeFormat::HumanReadable
+                        )
+                    )
+                    .into(),
+                );
+                let rows = matches
+                    .into_iter()
+                    .map(|m| {
+                        ModelRc::new(VecModel::from(vec![
+                            StandardListViewItem::from(m.path.as_str()),
+                            StandardListViewItem::from(m.line_number.to_string().as_str()),
+                            StandardListViewItem::from(m.line.as_str()),
+                        ]))
+                    })
+                    .collect::<Vec<_>>();
+                app.get_content_matches()
+                    .as_any()
+                    .downcast_ref::<VecModel<ModelRc<StandardListViewItem>>>()
+                    .unwrap()
+                    .set_vec(rows);
+            });
+        });
+    });
+
+    app.on_cancel_content_search(move || {
+        content_search_cancel
+            .borrow()
+            .store(true, Ordering::Relaxed);
+    });
+
+    app.on_open_content_match(move |row| {
+        // The path is the first cell of the row
+        let path = content_matches
+            .row_data(row as usize)
+            .and_then(|cells| cells.row_data(0));
+        if let Some(path) = path {
+            run_file_operation(move || shell::open(&path.text));
+        }
+    });
+
+    let checksum_cancel = Rc::new(RefCell::new(Arc::new(AtomicBool::new(false))));
+
+    let app_weak = app.as_weak();
+    let model_clone = model.clone();
+    let checksum_cancel_clone = checksum_cancel.clone();
+    app.on_compute_checksums(move || {
+        let app = app_weak.unwrap();
+        let files = model_clone.selected_files();
+        app.set_checksum_progress(0.0);
+        app.set_checksum_text(format!("Computing checksums of {} file(s)...", files.len()).into());
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        // Results of a previous run must not overwrite the new ones
+        checksum_cancel_clone
+            .replace(cancel.clone())
+            .store(true, Ordering::Relaxed);
+        let app_weak = app_weak.clone();
+        std::thread::spawn(move || compute_checksums(files, cancel, app_weak));
+    });
+
+    app.on_cancel_checksums(move || {
+        checksum_cancel.borrow().store(true, Ordering::Relaxed);
+    });
+
+    let app_weak = app.as_weak();
+    let model_clone = model.clone();
     app.on_search_input_change(move |search: SharedString| {
-        model.set_filter(search.to_string());
+        let crumbs = query::scope(&search)
+            .map(|folder| {
+                folder
+                    .split('\\')
+                    .filter(|component| !component.is_empty())
+                    .map(SharedString::from)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        app_weak
+            .unwrap()
+            .set_scope_crumbs(ModelRc::from(Rc::new(VecModel::from(crumbs))));
+        model_clone.set_filter(search.to_string());
+        app_weak
+            .unwrap()
+            .set_finds_empty_folders(model_clone.finds_empty_folders());
+    });
+
+    let app_weak = app.as_weak();
+    let model_clone = model.clone();
+    app.on_search_in_folder(move |id| {
+        if let Some(folder) = model_clone.folder_of(&id) {
+            let search = query::with_scope(&model_clone.filter.borrow(), Some(&folder));
+            app_weak.unwrap().invoke_set_query(search.into());
+        }
+    });
+
+    let app_weak = app.as_weak();
+    let model_clone = model.clone();
+    app.on_scope_selected(move |crumb| {
+        let search = model_clone.filter.borrow().clone();
+        let Some(folder) = query::scope(&search) else {
+            return;
+        };
+        // Network locations start with `\\`, which the components leave out
+        let (prefix, components) = match folder.strip_prefix("\\\\") {
+            Some(rest) => ("\\\\", rest),
+            None => ("", folder.as_str()),
+        };
+        let folder = components
+            .split('\\')
+            .filter(|component| !component.is_empty())
+            .take(crumb as usize + 1)
+            .collect::<Vec<_>>()
+            .join("\\");
+        let folder = format!("{}{}", prefix, folder);
+        app_weak
+            .unwrap()
+            .invoke_set_query(query::with_scope(&search, Some(&folder)).into());
+    });
+
+    let app_weak = app.as_weak();
+    let model_clone = model.clone();
+    app.on_clear_scope(move || {
+        let search = query::with_scope(&model_clone.filter.borrow(), None);
+        app_weak.unwrap().invoke_set_query(search.into());
+    });
+
+    // Continue where the last session ended, unless started with a query
+    let query = if query.is_empty() {
+        state.borrow().last_query.clone()
+    } else {
+        query
+    };
+    if !query.is_empty() {
+        app.invoke_set_query(query.into());
+    }
+    // Other instances forward their query instead of building their own index
+    let app_weak = app.as_weak();
+    instance::listen(move |query| {
+        let app_weak = app_weak.clone();
+        let _ = slint::invoke_from_event_loop(move || {
+            let Some(app) = app_weak.upgrade() else {
+                return;
+            };
+            if !query.is_empty() {
+                app.invoke_set_query(query.into());
+            }
+            instance::bring_to_foreground();
+        });
+    });
+
+    if let Some(window) = state.borrow().window {
+        restore_window(&app, window);
+    }
+    app.show()?;
+    if minimized {
+        app.window().set_minimized(true);
+    }
+    slint::run_event_loop()?;
+
+    // These change too often to save them every time, so they are only saved on exit
+    let mut state = state.borrow_mut();
+    sync_column_widths(&app, &mut state.columns);
+    state.sort = model
+        .sort
+        .get()
+        .map(|(column, ascending)| SortOrder { column, ascending });
+    state.last_query = model.filter.borrow().clone();
+    // The bounds of a minimized window are meaningless, so keep the previous ones
+    if !app.window().is_minimized() {
+        let position = app.window().position();
+        let size = app.window().size();
+        state.window = Some(WindowGeometry {
+            x: position.x,
+            y: position.y,
+            width: size.width,
+            height: size.height,
+            maximized: app.window().is_maximized(),
+        });
+    }
+    app.hide()?;
+    if let Err(e) = state.save() {
+        error!("{:?}", e);
+    }
+
+    Ok(())
+}
+
+/// Computes the checksums of all files in parallel and shows them in the checksum dialog. Progress
+/// is reported based on the number of bytes read.
+fn compute_checksums(
+    files: Vec<(String, u64)>,
+    cancel: Arc<AtomicBool>,
+    app_weak: slint::Weak<App>,
+) {
+    let total = files.iter().map(|(_, size)| size).sum::<u64>().max(1);
+    let done = AtomicU64::new(0);
+    // The weak handle is not Sync, so it can only be shared between threads behind a lock
+    let progress_reporter = Mutex::new((Instant::now(), app_weak.clone()));
+
+    let results = files
+        .par_iter()
+        .map(|(path, _)| {
+            let checksums = checksum::checksums(path, &cancel, |read| {
+                let done = done.fetch_add(read, Ordering::Relaxed) + read;
+
+                // Updating the UI after every chunk would flood the event loop
+                let mut progress_reporter = progress_reporter.lock().unwrap();
+                let (last_report, app_weak) = &mut *progress_reporter;
+                if last_report.elapsed() < CHECKSUM_PROGRESS_INTERVAL {
+                    return;
+                }
+                *last_report = Instant::now();
+
+                let progress = done as f32 / total as f32;
+                let app_weak = app_weak.clone();
+                let cancel = cancel.clone();
+                let _ = slint::invoke_from_event_loop(move || {
+                    if let Some(app) = app_weak
+                        .upgrade()
+                        .filter(|_| !cancel.load(Ordering::Relaxed))
+                    {
+                        app.set_checksum_progress(progress);
+                    }
+                });
+            });
+            (path, checksums)
+        })
+        .collect::<Vec<_>>();
+
+    let mut text = String::new();
+    for (path, checksums) in results {
+        writeln!(text, "{}", path).unwrap();
+        match checksums {
+            Ok(checksums) => {
+                for (algorithm, digest) in checksums {
+                    writeln!(text, "{:<9}{}", algorithm.name(), digest).unwrap();
+                }
+            }
+            Err(e) => writeln!(text, "{:#}", e).unwrap(),
+        }
+        text.push('\n');
+    }
+
+    let _ = slint::invoke_from_event_loop(move || {
+        if let Some(app) = app_weak
+            .upgrade()
+            .filter(|_| !cancel.load(Ordering::Relaxed))
+        {
+            app.set_checksum_text(text.trim_end().into());
+            app.set_checksum_progress(-1.0);
+        }
+    });
+}
+
+/// Adds the changes which were recorded since the last update to the top of the recent changes.
+fn update_recent_changes(
+    recent_changes: &VecModel<ModelRc<StandardListViewItem>>,
+    change_log: &Mutex<ChangeLog>,
+    next_sequence: &Cell<u64>,
+) {
+    let change_log = change_log.lock().unwrap();
+    let mut rows = change_log
+        .since(next_sequence.get())
+        .map(|entry| {
+            ModelRc::new(VecModel::from(vec![
+                StandardListViewItem::from(format::format_time_precise(entry.time).as_str()),
+                StandardListViewItem::from(entry.kind.name()),
+                StandardListViewItem::from(
+                    format::format_change_path(&entry.path, entry.old_path.as_deref()).as_str(),
+                ),
+            ]))
+        })
+        .collect::<Vec<_>>();
+    next_sequence.set(change_log.next_sequence());
+    drop(change_log);
+
+    if rows.is_empty() {
+        return;
+    }
+
+    rows.reverse();
+    rows.extend(
+        recent_changes
+            .iter()
+            .take(MAX_RECENT_CHANGES.saturating_sub(rows.len())),
+    );
+    rows.truncate(MAX_RECENT_CHANGES);
+    recent_changes.set_vec(rows);
+}
+
+fn update_volume_info(app: &App, backend: &Backend, size_format: SizeFormat) {
+    let info = match backend.volume_info(size_format) {
+        Ok(info) => info,
+        Err(e) => {
+            error!("{:?}", e);
+            return;
+        }
+    };
+
+    let rows = info
+        .into_iter()
+        .map(|(label, value)| {
+            ModelRc::new(VecModel::from(vec![
+                StandardListViewItem::from(label.as_str()),
+                StandardListViewItem::from(value.as_str()),
+            ]))
+        })
+        .collect::<Vec<_>>();
+    app.set_volume_info(ModelRc::new(VecModel::from(rows)));
+}
+
+fn update_statistics(app: &App, stats: &Statistics, size_format: SizeFormat) {
+    let to_rows = |groups, unnamed| {
+        let rows = format::format_group_stats(groups, stats.total_size(), unnamed, size_format)
+            .into_iter()
+            .map(|columns| {
+                ModelRc::new(VecModel::from(
+                    columns
+                        .iter()
+                        .map(|column| StandardListViewItem::from(column.as_str()))
+                        .collect::<Vec<_>>(),
+                ))
+            })
+            .collect::<Vec<_>>();
+        ModelRc::new(VecModel::from(rows))
+    };
+
+    app.set_extension_stats(to_rows(&stats.by_extension, "(none)"));
+    app.set_directory_stats(to_rows(&stats.by_directory, "(root)"));
+    app.set_statistics_status(
+        format!(
+            "{} files, {}",
+            format::group_thousands(stats.total_files() as u64),
+            format::format_size(stats.total_size(), size_format)
+        )
+        .into(),
+    );
+    app.set_computing_statistics(false);
+}
+
+/// Shows a banner while the journal cannot be read or the service cannot be reached, unless the
+/// user dismissed the same error before.
+fn update_error_banner(app: &App, dismissed_error: &RefCell<Option<String>>, backend: &Backend) {
+    let (error, retryable) = match backend.status() {
+        Ok(status) => (
+            status.journal_error.map(|error| {
+                format!(
+                    "Changes to files are not picked up, reading the journal failed: {}",
+                    error
+                )
+            }),
+            true,
+        ),
+        Err(e) => (Some(format!("{:#}", e)), false),
+    };
+    if error.is_none() {
+        *dismissed_error.borrow_mut() = None;
+    }
+
+    match error {
+        Some(error) if dismissed_error.borrow().as_ref() != Some(&error) => {
+            app.set_error_message(error.into());
+            app.set_error_retryable(retryable);
+        }
+        Some(_) => {}
+        None => {
+            app.set_error_message("".into());
+            app.set_error_retryable(false);
+        }
+    }
+}
+
+/// Adds the messages which were logged since the last update to the top of the log tab.
+fn update_log(
+    log_rows: &VecModel<ModelRc<StandardListViewItem>>,
+    recent_log: &Mutex<RecentLog>,
+    next_sequence: &Cell<u64>,
+) {
+    let recent_log = recent_log.lock().unwrap();
+    let mut rows = recent_log
+        .since(next_sequence.get())
+        .map(|entry| {
+            ModelRc::new(VecModel::from(vec![
+                StandardListViewItem::from(format::format_time_precise(entry.time).as_str()),
+                StandardListViewItem::from(entry.level.as_str()),
+                StandardListViewItem::from(entry.message.as_str()),
+            ]))
+        })
+        .collect::<Vec<_>>();
+    next_sequence.set(recent_log.next_sequence());
+    drop(recent_log);
+
+    if rows.is_empty() {
+        return;
+    }
+
+    rows.reverse();
+    rows.extend(
+        log_rows
+            .iter()
+            .take(MAX_LOG_ROWS.saturating_sub(rows.len())),
+    );
+    rows.truncate(MAX_LOG_ROWS);
+    log_rows.set_vec(rows);
+}
+
+/// Shows the metadata of the last clicked file in the preview pane and starts loading its content.
+fn update_preview(app: &App, model: &NtfsIndexTableModel, loader: &PreviewLoader) {
+    if !app.get_preview_visible() {
+        return;
+    }
+
+    let preview = model.anchor_preview().unwrap_or_default();
+    if preview.loading {
+        loader.request(preview.path.to_string());
+    }
+    app.set_preview(preview);
+}
+
+fn show_preview_content(app: &App, path: &str, content: Preview) {
+    let mut preview = app.get_preview();
+    // The selection changed while loading
+    if preview.path != path {
+        return;
+    }
+
+    preview.loading = false;
+    match content {
+        Preview::Image(pixels) => preview.image = slint::Image::from_rgba8(pixels),
+        Preview::Text(text) => preview.text = text.into(),
+        Preview::Unsupported => {}
+    }
+    app.set_preview(preview);
+}
+
+/// Shows the given columns in the results table.
+fn apply_columns(app: &App, model: &NtfsIndexTableModel, layouts: &[ColumnLayout]) {
+    let columns = layouts
+        .iter()
+        .map(|layout| {
+            let mut column = TableColumn::default();
+            column.title = layout.column.title().into();
+            column.width = layout.width;
+            column
+        })
+        .collect::<Vec<_>>();
+    app.set_columns(ModelRc::from(Rc::new(VecModel::from(columns))));
+
+    let choices = Column::ALL
+        .iter()
+        .map(|&column| ColumnChoice {
+            title: column.title().into(),
+            visible: layouts.iter().any(|l| l.column == column),
+        })
+        .collect::<Vec<_>>();
+    app.set_column_choices(ModelRc::from(Rc::new(VecModel::from(choices))));
+
+    // The table only knows the position of the sorted column, which might have changed
+    let sort_column = model
+        .sort
+        .get()
+        .and_then(|(column, _)| layouts.iter().position(|l| l.column == column));
+    app.set_sort_column(sort_column.map_or(-1, |i| i as i32));
+
+    let icon_column = layouts.iter().position(|l| l.column == Column::Name);
+    app.set_icon_column(icon_column.map_or(-1, |i| i as i32));
+
+    model.set_columns(layouts.iter().map(|l| l.column).collect());
+}
+
+fn update_columns(
+    app_weak: &slint::Weak<App>,
+    model: &NtfsIndexTableModel,
+    state: &RefCell<State>,
+    f: impl FnOnce(&mut Vec<ColumnLayout>),
+) {
+    let Some(app) = app_weak.upgrade() else {
+        return;
+    };
+
+    let mut state = state.borrow_mut();
+    sync_column_widths(&app, &mut state.columns);
+    f(&mut state.columns);
+    if let Err(e) = state.save() {
+        error!("{:?}", e);
+    }
+
+    apply_columns(&app, model, &state.columns);
+}
+
+/// Has to be called before the window is created. Slint only follows the display scaling while no
+/// scale factor is forced, so zooming fixes the scaling to the one of the primary display at
+/// startup.
+fn configure_scaling(config: &Config) {
+    if config.follow_system_dpi && config.ui_zoom == 1.0 {
+        return;
+    }
+
+    let system_scale = if config.follow_system_dpi {
+        (unsafe { GetDpiForSystem() }) as f32 / DEFAULT_DPI
+    } else {
+        1.0
+    };
+    let zoom = config.ui_zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+    std::env::set_var("SLINT_SCALE_FACTOR", (system_scale * zoom).to_string());
+}
+
+fn restore_window(app: &App, window: WindowGeometry) {
+    let app_window = app.window();
+    app_window.set_size(slint::PhysicalSize::new(window.width, window.height));
+    // The monitor the window was on might have been disconnected since
+    if shell::is_on_screen(window.x, window.y, window.width, window.height) {
+        app_window.set_position(slint::PhysicalPosition::new(window.x, window.y));
+    }
+    app_window.set_maximized(window.maximized);
+}
+
+fn sync_column_widths(app: &App, layouts: &mut [ColumnLayout]) {
+    for (layout, column) in layouts.iter_mut().zip(app.get_columns().iter()) {
+        layout.width = column.width;
+    }
+}
+
+fn history_model(history: &SearchHistory) -> ModelRc<HistoryEntry> {
+    let entries = history
+        .entries()
+        .map(|(query, pinned)| HistoryEntry {
+            query: query.into(),
+            pinned,
+        })
+        .collect::<Vec<_>>();
+
+    ModelRc::from(Rc::new(VecModel::from(entries)))
+}
+
+fn update_history(
+    app_weak: &slint::Weak<App>,
+    state: &RefCell<State>,
+    f: impl FnOnce(&mut SearchHistory),
+) {
+    let mut state = state.borrow_mut();
+    let jump_list = (
+        state.history.opened().to_vec(),
+        state.history.pinned().to_vec(),
+    );
+    f(&mut state.history);
+    if let Err(e) = state.save() {
+        error!("{:?}", e);
+    }
+
+    if let Some(app) = app_weak.upgrade() {
+        app.set_history(history_model(&state.history));
+    }
+    if jump_list
+        != (
+            state.history.opened().to_vec(),
+            state.history.pinned().to_vec(),
+        )
+    {
+        update_jump_list(&state.history);
+    }
+}
+
+/// Shows the recently opened results and the pinned queries in the jump list of the taskbar
+/// button. Runs in the background, as the shell may take a moment.
+fn update_jump_list(history: &SearchHistory) {
+    let opened = history.opened().to_vec();
+    let pinned = history.pinned().to_vec();
+    std::thread::spawn(move || {
+        if let Err(e) = jump_list::update(&opened, &pinned) {
+            error!("{:?}", e);
+        }
+    });
+}
+
+/// File operations can show shell dialogs, so they are moved off the UI thread. Their results flow
+/// back into the index through the journal.
+fn run_file_operation(op: impl FnOnce() -> eyre::Result<()> + Send + 'static) {
+    std::thread::spawn(move || {
+        if let Err(e) = op() {
+            error!("File operation failed: {:?}", e);
+        }
     });
+}
+
+/// Writes the given files as `path,size` lines into a CSV file picked by the user.
+fn export_csv(files: &[(String, u64)]) -> eyre::Result<()> {
+    let Some(path) = shell::pick_csv_save_path("results.csv")? else {
+        return Ok(());
+    };
+
+    let mut csv = String::from("Path,Size\r\n");
+    for (file, size) in files {
+        // Paths can contain commas and quotes
+        writeln!(csv, "\"{}\",{}\r", file.replace('"', "\"\""), size)?;
+    }
 
-    app.run()
+    fs::write(path, csv)?;
+    Ok(())
 }
 
 pub struct NtfsIndexTableModel {
-    ntfs_index: Arc<Mutex<NtfsVolumeIndex>>,
+    /// The rows of the table, searched locally or by the service
+    source: RefCell<Box<dyn ResultSource>>,
     filter: RefCell<String>,
-    filtered_files: RefCell<Vec<u64>>,
+    /// Query of the selected preset, empty if none is selected
+    preset: RefCell<String>,
+    options: Cell<SearchOptions>,
+    /// Column to sort the results by and whether to sort ascending, unsorted if `None`
+    sort: Cell<Option<(Column, bool)>>,
+    /// Visible columns in display order, only these are computed for each row
+    columns: RefCell<Vec<Column>>,
+    icons: IconCache,
+    size_format: SizeFormat,
+    /// Show the path files in the recycle bin had before they were deleted
+    resolve_recycle_bin: bool,
+    /// Original paths of the files in the recycle bin by their current path, `None` if the `$I`
+    /// file could not be read
+    original_paths: RefCell<FxHashMap<String, Option<String>>>,
+    group_by_folder: Cell<bool>,
+    /// Folders whose results are shown below them
+    expanded_groups: RefCell<FxHashSet<FileId>>,
+    /// The selected files. Stored by id instead of by row, because the rows shift around whenever
+    /// the journal updates the index, and by id instead of by MFT index, so that a selected file
+    /// which is deleted doesn't pass its selection on to the next file in its MFT record.
+    selection: RefCell<FxHashSet<FileId>>,
+    /// The last file that was clicked without holding shift and the row it was in, used for range
+    /// selection
+    selection_anchor: Cell<Option<(FileId, usize)>>,
     notify: ModelNotify,
 }
 
@@ -61,71 +1317,440 @@ unsafe impl Sync for NtfsIndexTableModel {}
 
 impl NtfsIndexTableModel {
     fn refresh(&self) {
-        self.set_filter(self.filter.take());
+        self.set_filter(self.filter.borrow().clone());
     }
 
     fn set_filter(&self, search: String) {
+        if *self.filter.borrow() != search {
+            self.clear_selection();
+            self.expanded_groups.borrow_mut().clear();
+        }
         self.filter.replace(search.to_string());
 
-        let mut vec = self.filtered_files.take();
-        vec.clear();
+        // Presets are simply prepended to the search
+        let query = format!("{} {}", self.preset.borrow(), search);
+        let sort = self
+            .sort
+            .get()
+            .map(|(column, ascending)| SortOrder { column, ascending });
+        let expanded_groups = self.expanded_groups.borrow();
+        let res = self.source.borrow_mut().search(
+            &query,
+            self.options.get(),
+            sort,
+            self.group_by_folder.get().then_some(&*expanded_groups),
+        );
+        // Keep the previous results, the user is most likely still typing
+        if let Err(e) = res {
+            debug!("Invalid query: {:?}", e);
+        }
 
-        let search = search
-            .split(|c| c == '\\' || c == '/')
-            .filter(|s| !s.is_empty())
-            .rev()
-            .collect::<Vec<_>>();
-        let ntfs_index = self.ntfs_index.lock().unwrap();
+        self.notify.reset();
+    }
 
-        let match_fn: Box<dyn Fn(&(usize, Option<&FileInfo>)) -> bool + Send + Sync> =
-            if search.is_empty() {
-                Box::new(|(_, info)| info.is_some())
-            } else {
-                Box::new(|(_, info)| {
-                    let Some(info) = info else {
-                        return false;
-                    };
-
-                    ntfs_index
-                        .iter_with_parents(info)
-                        .zip(search.iter())
-                        .all(|(info, search)| info.name.contains(search))
+    fn set_group_by_folder(&self, group_by_folder: bool) {
+        self.group_by_folder.set(group_by_folder);
+        self.clear_selection();
+        self.refresh();
+    }
+
+    /// Expands or collapses the group in the given row. Does nothing for other rows.
+    fn toggle_group(&self, row: usize) {
+        let mut source = self.source.borrow_mut();
+        if source.group_count(row).is_none() {
+            return;
+        }
+        let Some(&(_, folder)) = source.ids(row..row + 1).first() else {
+            return;
+        };
+        drop(source);
+
+        let mut expanded_groups = self.expanded_groups.borrow_mut();
+        if !expanded_groups.remove(&folder) {
+            expanded_groups.insert(folder);
+        }
+        drop(expanded_groups);
+        self.refresh();
+    }
+
+    fn set_preset(&self, query: String) {
+        self.preset.replace(query);
+        self.clear_selection();
+        self.refresh();
+    }
+
+    fn set_options(&self, options: SearchOptions) {
+        self.options.set(options);
+        self.clear_selection();
+        self.refresh();
+    }
+
+    fn set_sort(&self, column: Column, ascending: bool) {
+        self.sort.set(Some((column, ascending)));
+        self.refresh();
+    }
+
+    fn insert_icons(&self, icons: Vec<(IconKey, Option<IconPixels>)>) {
+        for (key, pixels) in icons {
+            self.icons.insert(key, pixels);
+        }
+        self.notify.reset();
+    }
+
+    fn set_columns(&self, columns: Vec<Column>) {
+        self.columns.replace(columns);
+        self.notify.reset();
+    }
+
+    fn clear_selection(&self) {
+        self.selection.borrow_mut().clear();
+        self.selection_anchor.set(None);
+    }
+
+    fn file_id_at(&self, row: usize) -> Option<FileId> {
+        self.source
+            .borrow_mut()
+            .ids(row..row + 1)
+            .first()
+            .map(|&(_, id)| id)
+    }
+
+    /// Looks up a file by the id which the UI got from [Self::file_id_at]. Dialogs and menus keep
+    /// the id instead of the row, as the rows shift around whenever the index changes.
+    fn entry_of(&self, id: &str) -> Option<ResultEntry> {
+        let id = id.parse::<FileId>().ok()?;
+        self.source.borrow_mut().find(id)
+    }
+
+    fn path_of(&self, id: &str) -> Option<String> {
+        self.entry_of(id).map(|entry| entry.path)
+    }
+
+    /// Returns the path of the given directory, or of the one containing the given file.
+    fn folder_of(&self, id: &str) -> Option<String> {
+        let entry = self.entry_of(id)?;
+        if entry.info.is_directory() {
+            return Some(entry.path);
+        }
+        entry
+            .path
+            .rsplit_once('\\')
+            .map(|(folder, _)| folder.to_string())
+    }
+
+    /// Updates the selection like a file explorer would. `toggle` adds or removes a single row,
+    /// `extend` selects everything between the anchor and the given row.
+    fn select_row(&self, row: usize, toggle: bool, extend: bool) {
+        let mut source = self.source.borrow_mut();
+        // The folders grouping the results only expand and collapse
+        if source.group_count(row).is_some() {
+            return;
+        }
+        let Some(&(_, id)) = source.ids(row..row + 1).first() else {
+            return;
+        };
+
+        let mut selection = self.selection.borrow_mut();
+        if extend {
+            let anchor_row = self
+                .selection_anchor
+                .get()
+                .and_then(|(anchor, anchor_row)| {
+                    // The rows shift around when the index changes, so the anchor may have moved
+                    if source
+                        .ids(anchor_row..anchor_row + 1)
+                        .first()
+                        .is_some_and(|&(_, id)| id == anchor)
+                    {
+                        return Some(anchor_row);
+                    }
+                    let row_count = source.row_count();
+                    source
+                        .ids(0..row_count)
+                        .into_iter()
+                        .find(|&(_, id)| id == anchor)
+                        .map(|(row, _)| row)
                 })
-            };
+                .unwrap_or(row);
 
-        let vec = ntfs_index
-            .par_iter()
-            .enumerate()
-            .filter(match_fn)
-            .map(|(i, _)| i as u64)
-            .collect();
-        self.filtered_files.replace(vec);
+            if !toggle {
+                selection.clear();
+            }
+            let ids = source.ids(anchor_row.min(row)..anchor_row.max(row) + 1);
+            selection.extend(
+                ids.into_iter()
+                    .filter(|&(row, _)| source.group_count(row).is_none())
+                    .map(|(_, id)| id),
+            );
+        } else {
+            if toggle {
+                if !selection.remove(&id) {
+                    selection.insert(id);
+                }
+            } else {
+                selection.clear();
+                selection.insert(id);
+            }
+
+            self.selection_anchor.set(Some((id, row)));
+        }
+        drop(selection);
+        drop(source);
+
+        self.notify.reset();
+    }
 
+    fn select_all(&self) {
+        let mut source = self.source.borrow_mut();
+        let row_count = source.row_count();
+        let ids = source.ids(0..row_count);
+        self.selection.borrow_mut().extend(
+            ids.into_iter()
+                .filter(|&(row, _)| source.group_count(row).is_none())
+                .map(|(_, id)| id),
+        );
+        drop(source);
         self.notify.reset();
     }
+
+    fn is_row_selected(&self, row: usize) -> bool {
+        let mut source = self.source.borrow_mut();
+        source.group_count(row).is_none()
+            && source
+                .ids(row..row + 1)
+                .first()
+                .is_some_and(|(_, id)| self.selection.borrow().contains(id))
+    }
+
+    /// Describes everything the index knows about the given file.
+    fn file_details(&self, id: &str) -> Option<String> {
+        let ResultEntry { id, path, info } = self.entry_of(id)?;
+        let times = info.times();
+        let size = |size| {
+            format!(
+                "{} bytes ({})",
+                format::format_size(size, SizeFormat::Bytes),
+                format::format_size(size, SizeFormat::HumanReadable)
+            )
+        };
+
+        let mut details = format!(
+            "Path: {}\nSize: {}\nSize on disk: {}\nCreated: {}\nModified: {}\n\
+             Accessed: {}\nAttributes: {}\nFile ID: {}",
+            path,
+            size(info.size()),
+            size(info.size_on_disk()),
+            format::format_time(times.created),
+            format::format_time(times.modified),
+            format::format_time(times.accessed),
+            format::format_attributes(info.attributes(), info.is_directory()),
+            id,
+        );
+
+        // Opening the file should be quick, it is not worth moving this off the UI thread
+        match fileops::hard_link_count(&path) {
+            Ok(count) => write!(details, "\nHard links: {}", count).unwrap(),
+            Err(e) => error!("{:?}", e),
+        }
+
+        Some(details)
+    }
+
+    /// Returns the metadata of the last clicked file, without its content.
+    fn anchor_preview(&self) -> Option<FilePreview> {
+        let (anchor, _) = self.selection_anchor.get()?;
+        let ResultEntry { path, info, .. } = self.source.borrow_mut().find(anchor)?;
+        let times = info.times();
+
+        let mut details = format!(
+            "Modified: {}\nCreated: {}\nAccessed: {}",
+            format::format_time(times.modified),
+            format::format_time(times.created),
+            format::format_time(times.accessed),
+        );
+        if !info.is_directory() {
+            details.insert_str(
+                0,
+                &format!(
+                    "Size: {}\n",
+                    format::format_size(info.size(), self.size_format)
+                ),
+            );
+        }
+
+        Some(FilePreview {
+            name: info.name.as_str().into(),
+            path: path.into(),
+            details: details.into(),
+            loading: !info.is_directory(),
+            ..Default::default()
+        })
+    }
+
+    /// Returns all results in the order they are displayed in, without the folders grouping them.
+    fn all_entries(&self) -> Vec<ResultEntry> {
+        let mut source = self.source.borrow_mut();
+        let row_count = source.row_count();
+        let entries = source.entries(0..row_count);
+        entries
+            .into_iter()
+            .filter(|&(row, _)| source.group_count(row).is_none())
+            .map(|(_, entry)| entry)
+            .collect()
+    }
+
+    /// Returns the paths and sizes of all selected files in the order they are displayed in.
+    fn selected_files(&self) -> Vec<(String, u64)> {
+        if self.selection.borrow().is_empty() {
+            return Vec::new();
+        }
+
+        let selection = self.selection.borrow();
+        self.all_entries()
+            .into_iter()
+            .filter(|entry| selection.contains(&entry.id))
+            .map(|entry| (entry.path, entry.info.size()))
+            .collect()
+    }
+
+    /// Returns the path and size of every file in the results, skipping directories.
+    fn result_files(&self) -> Vec<(String, u64)> {
+        self.all_entries()
+            .into_iter()
+            .filter(|entry| !entry.info.is_directory())
+            .map(|entry| (entry.path, entry.info.size()))
+            .collect()
+    }
+
+    /// Whether the current search only finds empty folders, which is the only time deleting them
+    /// is offered
+    fn finds_empty_folders(&self) -> bool {
+        let query = format!("{} {}", self.preset.borrow(), self.filter.borrow());
+        Query::parse(&query, self.options.get()).is_ok_and(|query| query.finds_empty_folders())
+    }
+
+    /// Returns the paths of the directories found by an `empty:` search which are still empty. The
+    /// index may lag behind the disk, so every folder is checked again right before it is deleted.
+    fn empty_result_directories(&self) -> Vec<String> {
+        if !self.finds_empty_folders() {
+            return Vec::new();
+        }
+
+        self.all_entries()
+            .into_iter()
+            .filter(|entry| entry.info.is_directory())
+            .map(|entry| entry.path)
+            .filter(|path| fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_none()))
+            .collect()
+    }
+
+    /// Returns the path a file in the recycle bin had before it was deleted. Returns `None` for
+    /// all other files, or if resolving them is disabled.
+    fn original_path(&self, path: &str) -> Option<String> {
+        if !self.resolve_recycle_bin {
+            return None;
+        }
+        if let Some(original) = self.original_paths.borrow().get(path) {
+            return original.clone();
+        }
+
+        let original = match recycle_bin::original_path(path) {
+            Ok(None) => return None,
+            Ok(Some(original)) => Some(original),
+            Err(e) => {
+                debug!("Failed to resolve recycled file: {:?}", e);
+                None
+            }
+        };
+        self.original_paths
+            .borrow_mut()
+            .insert(path.to_string(), original.clone());
+        original
+    }
+
+    fn cell(&self, entry: &ResultEntry, column: Column) -> SharedString {
+        let info = &entry.info;
+        match column {
+            Column::Name => {
+                // Only the top-level items of the recycle bin are renamed
+                if info.name.starts_with("$R") {
+                    if let Some(original) = self.original_path(&entry.path) {
+                        return original
+                            .rsplit_once('\\')
+                            .map_or(original.as_str(), |(_, name)| name)
+                            .into();
+                    }
+                }
+                info.name.as_str().into()
+            }
+            Column::Path => {
+                let path = self
+                    .original_path(&entry.path)
+                    .unwrap_or_else(|| entry.path.clone());
+                path.rsplit_once('\\')
+                    .map_or(path.as_str(), |(parent, _)| parent)
+                    .into()
+            }
+            Column::Size => format::format_size(info.size(), self.size_format).into(),
+            Column::SizeOnDisk => format::format_size(info.size_on_disk(), self.size_format).into(),
+            Column::DateModified => format::format_time(info.times().modified).into(),
+            Column::DateCreated => format::format_time(info.times().created).into(),
+            Column::DateAccessed => format::format_time(info.times().accessed).into(),
+            Column::Attributes => {
+                format::format_attributes(info.attributes(), info.is_directory()).into()
+            }
+            Column::Extension => info.extension().unwrap_or_default().into(),
+            Column::Owner => owner::owner(&entry.path)
+                .as_deref()
+                .unwrap_or_default()
+                .into(),
+        }
+    }
 }
 
 impl Model for NtfsIndexTableModel {
-    type Data = ModelRc<StandardListViewItem>;
+    type Data = ResultRow;
 
     fn row_count(&self) -> usize {
-        self.filtered_files.borrow().len()
+        self.source.borrow().row_count()
     }
 
     fn row_data(&self, row: usize) -> Option<Self::Data> {
-        let ntfs_index = self.ntfs_index.lock().unwrap();
-        let file_info = ntfs_index.find_by_index(self.filtered_files.borrow()[row])?;
-
-        Some(
-            Rc::new(VecModel::from(vec![
-                StandardListViewItem::from(slint::format!(
-                    "{}",
-                    ntfs_index.compute_full_path(file_info)
-                )),
-                StandardListViewItem::from(slint::format!("{}", file_info.size())),
-            ]))
-            .into(),
-        )
+        let mut source = self.source.borrow_mut();
+        let entry = source.entry(row)?;
+        let group_count = source.group_count(row);
+        drop(source);
+
+        let columns = self.columns.borrow();
+        let cells = columns
+            .iter()
+            .map(|&column| match (column, group_count) {
+                (Column::Name, Some(count)) => format!(
+                    "{} ({} {})",
+                    self.cell(&entry, column),
+                    count,
+                    if count == 1 { "result" } else { "results" }
+                )
+                .into(),
+                _ => self.cell(&entry, column),
+            })
+            .collect::<Vec<_>>();
+        // Icons are only shown next to the name
+        let icon = if columns.contains(&Column::Name) {
+            self.icons
+                .get(IconKey::new(&entry.info, || entry.path.clone()))
+        } else {
+            Default::default()
+        };
+
+        Some(ResultRow {
+            cells: ModelRc::from(Rc::new(VecModel::from(cells))),
+            icon,
+            selected: group_count.is_none() && self.selection.borrow().contains(&entry.id),
+            group_count: group_count.unwrap_or(0) as i32,
+            expanded: group_count.is_some() && self.expanded_groups.borrow().contains(&entry.id),
+            nested: group_count.is_none() && self.group_by_folder.get(),
+        })
     }
 
     fn set_row_data(&self, _row: usize, _data: Self::Data) {
@@ -140,3 +1765,77 @@ impl Model for NtfsIndexTableModel {
         self
     }
 }
+
+/// Sets of duplicate files, flattened into one row per file.
+struct DuplicatesModel {
+    sets: RefCell<Vec<DuplicateSet>>,
+    /// Index of the set and of the path within the set for every row
+    rows: RefCell<Vec<(usize, usize)>>,
+    size_format: SizeFormat,
+    notify: ModelNotify,
+}
+
+impl DuplicatesModel {
+    fn set_sets(&self, sets: Vec<DuplicateSet>) {
+        *self.rows.borrow_mut() = sets
+            .iter()
+            .enumerate()
+            .flat_map(|(i, set)| (0..set.paths.len()).map(move |j| (i, j)))
+            .collect();
+        *self.sets.borrow_mut() = sets;
+        self.notify.reset();
+    }
+
+    fn path_at(&self, row: usize) -> Option<String> {
+        let (set, path) = *self.rows.borrow().get(row)?;
+        Some(self.sets.borrow()[set].paths[path].clone())
+    }
+
+    /// Removes the set containing the given row and returns all of its files except the one in
+    /// that row.
+    fn take_others(&self, row: usize) -> Vec<String> {
+        let Some(&(set, keep)) = self.rows.borrow().get(row) else {
+            return Vec::new();
+        };
+
+        let mut sets = self.sets.take();
+        let paths = sets.remove(set).paths;
+        self.set_sets(sets);
+
+        paths
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| *i != keep)
+            .map(|(_, path)| path)
+            .collect()
+    }
+}
+
+impl Model for DuplicatesModel {
+    type Data = ModelRc<StandardListViewItem>;
+
+    fn row_count(&self) -> usize {
+        self.rows.borrow().len()
+    }
+
+    fn row_data(&self, row: usize) -> Option<Self::Data> {
+        let (set, path) = *self.rows.borrow().get(row)?;
+        let sets = self.sets.borrow();
+
+        Some(ModelRc::new(VecModel::from(vec![
+            StandardListViewItem::from((set + 1).to_string().as_str()),
+            StandardListViewItem::from(
+                format::format_size(sets[set].size, self.size_format).as_str(),
+            ),
+            StandardListViewItem::from(sets[set].paths[path].as_str()),
+        ])))
+    }
+
+    fn model_tracker(&self) -> &dyn ModelTracker {
+        &self.notify
+    }
+
+    fn as_any(&self) -> &dyn core::any::Any {
+        self
+    }
+}
@@ -0,0 +1,205 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use eyre::{Context, Result};
+use tracing::error;
+use windows::core::{w, PCWSTR};
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::Shell::{
+    Shell_NotifyIconW, NIF_ICON, NIF_INFO, NIF_TIP, NIIF_INFO, NIM_ADD, NIM_DELETE, NIM_MODIFY,
+    NOTIFYICONDATAW,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, DestroyWindow, LoadIconW, RegisterClassW, HMENU, HWND_MESSAGE,
+    IDI_INFORMATION, WINDOW_EX_STYLE, WINDOW_STYLE, WNDCLASSW,
+};
+
+use crate::config::WatchRule;
+use crate::format;
+use crate::ntfs::index::{FileChange, NtfsVolumeIndex};
+use crate::query::{Query, SearchOptions};
+
+const NOTIFICATION_WINDOW_CLASS: PCWSTR = w!("ReverythingNotifications");
+
+/// Matches changes from the journal against the user's watch rules and shows a notification for
+/// each rule which matched.
+pub struct Watchers {
+    rules: Vec<(WatchRule, Query)>,
+    notifications: Option<Sender<Notification>>,
+}
+
+struct Notification {
+    title: String,
+    message: String,
+}
+
+impl Watchers {
+    /// Rules with an invalid pattern are reported and ignored.
+    pub fn new(rules: Vec<WatchRule>) -> Self {
+        // Like paths on Windows, patterns should not care about case
+        let options = SearchOptions {
+            fold: true,
+            ..Default::default()
+        };
+        let rules = rules
+            .into_iter()
+            .filter_map(|rule| match Query::parse(&rule.pattern, options) {
+                Ok(query) => Some((rule, query)),
+                Err(e) => {
+                    error!(
+                        "{:?}",
+                        e.wrap_err(format!("Invalid watch rule '{}'", rule.name))
+                    );
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Self {
+            notifications: (!rules.is_empty()).then(start_notification_thread),
+            rules,
+        }
+    }
+
+    pub fn process(&self, ntfs_index: &NtfsVolumeIndex, changes: &[FileChange]) {
+        let Some(notifications) = &self.notifications else {
+            return;
+        };
+
+        for (rule, query) in &self.rules {
+            let matches = changes
+                .iter()
+                .filter(|change| {
+                    is_below(&change.path, &rule.path_prefix)
+                        && query.matches(ntfs_index, change.id.mft_index, &change.file)
+                        && query.matches_owner(&change.path)
+                })
+                .collect::<Vec<_>>();
+
+            // Bursts like extracting an archive are summarized instead of flooding the user
+            let message = match matches.as_slice() {
+                [] => continue,
+                [change] => format!(
+                    "{} {}",
+                    change.kind.name(),
+                    format::format_change_path(&change.path, change.old_path.as_deref())
+                ),
+                [.., last] => format!(
+                    "{} files changed, last: {} {}",
+                    matches.len(),
+                    last.kind.name().to_lowercase(),
+                    format::format_change_path(&last.path, last.old_path.as_deref())
+                ),
+            };
+            let _ = notifications.send(Notification {
+                title: rule.name.clone(),
+                message,
+            });
+        }
+    }
+}
+
+/// Returns true if `path` is `directory` or inside of it. An empty directory contains everything.
+fn is_below(path: &str, directory: &str) -> bool {
+    let directory = directory.trim_end_matches('\\');
+    if directory.is_empty() {
+        return true;
+    }
+
+    path.get(..directory.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(directory))
+        && matches!(path.as_bytes().get(directory.len()), None | Some(b'\\'))
+}
+
+/// Notifications are shown as balloons of a tray icon, which Windows 10 and newer display as
+/// toasts. The icon is owned by a hidden window, which has to live on its own thread.
+fn start_notification_thread() -> Sender<Notification> {
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        if let Err(e) = unsafe { run_notification_thread(receiver) } {
+            error!("{:?}", e);
+        }
+    });
+
+    sender
+}
+
+unsafe fn run_notification_thread(receiver: Receiver<Notification>) -> Result<()> {
+    let hwnd = create_notification_window().with_context(|| "Failed to create window")?;
+
+    let mut data = NOTIFYICONDATAW {
+        cbSize: size_of::<NOTIFYICONDATAW>() as u32,
+        hWnd: hwnd,
+        uFlags: NIF_ICON | NIF_TIP,
+        hIcon: LoadIconW(None, IDI_INFORMATION)?,
+        ..Default::default()
+    };
+    copy_truncated(&mut data.szTip, "Reverything");
+
+    let res = (|| {
+        Shell_NotifyIconW(NIM_ADD, &data)
+            .ok()
+            .with_context(|| "Failed to add notification icon")?;
+
+        for notification in receiver {
+            data.uFlags = NIF_INFO;
+            data.dwInfoFlags = NIIF_INFO;
+            copy_truncated(&mut data.szInfoTitle, &notification.title);
+            copy_truncated(&mut data.szInfo, &notification.message);
+            if Shell_NotifyIconW(NIM_MODIFY, &data).ok().is_err() {
+                error!("Failed to show notification: {}", notification.message);
+            }
+        }
+
+        Ok(())
+    })();
+    let _ = Shell_NotifyIconW(NIM_DELETE, &data);
+    let _ = DestroyWindow(hwnd);
+
+    res
+}
+
+unsafe fn create_notification_window() -> Result<HWND> {
+    let instance = GetModuleHandleW(None)?;
+    let class = WNDCLASSW {
+        lpfnWndProc: Some(notification_window_proc),
+        hInstance: instance.into(),
+        lpszClassName: NOTIFICATION_WINDOW_CLASS,
+        ..Default::default()
+    };
+    RegisterClassW(&class);
+
+    // Message-only windows are invisible and don't receive broadcasts
+    Ok(CreateWindowExW(
+        WINDOW_EX_STYLE::default(),
+        NOTIFICATION_WINDOW_CLASS,
+        PCWSTR::null(),
+        WINDOW_STYLE::default(),
+        0,
+        0,
+        0,
+        0,
+        HWND_MESSAGE,
+        HMENU::default(),
+        instance,
+        None,
+    )?)
+}
+
+unsafe extern "system" fn notification_window_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    DefWindowProcW(hwnd, msg, wparam, lparam)
+}
+
+/// Copies `text` into a fixed size, null terminated buffer, cutting it off if it is too long.
+fn copy_truncated(buffer: &mut [u16], text: &str) {
+    buffer.fill(0);
+    let len = buffer.len() - 1;
+    for (dst, src) in buffer[..len].iter_mut().zip(text.encode_utf16()) {
+        *dst = src;
+    }
+}